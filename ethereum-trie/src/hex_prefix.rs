@@ -0,0 +1,114 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hex-prefix encoding of partial keys.
+//!
+//! The first nibble is a flag: bit 1 is set for leaves, bit 0 for an odd number of
+//! nibbles. With an even number of nibbles the flag is followed by a zero nibble.
+
+use crate::rstd::vec::Vec;
+use rlp::DecoderError;
+use trie_db::{
+	nibble_ops::{NibbleOps, Radix16},
+	node::NibbleSlicePlan,
+	Partial,
+};
+
+const LEAF_FLAG: u8 = 0b10;
+const ODD_FLAG: u8 = 0b01;
+
+fn flag(leaf: bool, odd: bool) -> u8 {
+	let mut flag = if leaf { LEAF_FLAG } else { 0 };
+	if odd {
+		flag |= ODD_FLAG;
+	}
+	flag << Radix16::BIT_PER_NIBBLE
+}
+
+/// Length of the encoding of a partial key of `nibble_count` nibbles.
+pub(crate) fn encoded_len(nibble_count: usize) -> usize {
+	1 + nibble_count / Radix16::NIBBLE_PER_BYTE
+}
+
+/// Encode a partial key given as right aligned bytes, as produced by
+/// `NibbleSlice::right_iter`.
+pub(crate) fn encode(
+	partial: impl Iterator<Item = u8>,
+	nibble_count: usize,
+	leaf: bool,
+) -> Vec<u8> {
+	let mut output = Vec::with_capacity(encoded_len(nibble_count));
+	encode_into(partial, nibble_count, leaf, &mut output);
+	output
+}
+
+/// Append the encoding of a partial key given as right aligned bytes to `output`.
+pub(crate) fn encode_into(
+	mut partial: impl Iterator<Item = u8>,
+	nibble_count: usize,
+	leaf: bool,
+	output: &mut Vec<u8>,
+) {
+	let odd = nibble_count % Radix16::NIBBLE_PER_BYTE == 1;
+	if odd {
+		output.push(flag(leaf, odd) | partial.next().unwrap_or(0));
+	} else {
+		output.push(flag(leaf, odd));
+	}
+	output.extend(partial);
+}
+
+/// Right aligned bytes and number of nibbles of a `Partial` key, as taken by `encode`.
+pub(crate) fn partial_nibbles(partial: Partial<'_>) -> (impl Iterator<Item = u8> + '_, usize) {
+	let ((padded, first), bytes) = partial;
+	let nibble_count = padded as usize + bytes.len() * Radix16::NIBBLE_PER_BYTE;
+	let first = if padded > 0 { Some(first) } else { None };
+	(first.into_iter().chain(bytes.iter().cloned()), nibble_count)
+}
+
+/// Encode a key given as one nibble per byte.
+pub(crate) fn encode_nibbles(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+	let odd = nibbles.len() % Radix16::NIBBLE_PER_BYTE == 1;
+	let (first, rest) = nibbles.split_at(if odd { 1 } else { 0 });
+	encode(
+		first.iter().cloned().chain(
+			rest.chunks(Radix16::NIBBLE_PER_BYTE)
+				.map(|pair| (pair[0] << Radix16::BIT_PER_NIBBLE) | pair[1]),
+		),
+		nibbles.len(),
+		leaf,
+	)
+}
+
+/// Decode an encoded partial key located at `offset` in the node, returning
+/// whether it belongs to a leaf and the plan of its nibbles.
+pub(crate) fn decode(
+	data: &[u8],
+	offset: usize,
+) -> Result<(bool, NibbleSlicePlan<Radix16>), DecoderError> {
+	let first = *data.first().ok_or(DecoderError::Custom("Empty hex-prefix key"))?;
+	let flag = first >> Radix16::BIT_PER_NIBBLE;
+	if flag > LEAF_FLAG | ODD_FLAG {
+		return Err(DecoderError::Custom("Invalid hex-prefix flag"))
+	}
+	let end = offset + data.len();
+	let plan = if flag & ODD_FLAG == ODD_FLAG {
+		NibbleSlicePlan::new(offset..end, 1)
+	} else if Radix16::pad_right(1, first) == 0 {
+		NibbleSlicePlan::new(offset + 1..end, 0)
+	} else {
+		return Err(DecoderError::Custom("Invalid hex-prefix padding"))
+	};
+	Ok((flag & LEAF_FLAG == LEAF_FLAG, plan))
+}
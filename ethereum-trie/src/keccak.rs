@@ -0,0 +1,39 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keccak-256 hasher.
+
+use hash256_std_hasher::Hash256StdHasher;
+use hash_db::Hasher;
+use tiny_keccak::{Hasher as _, Keccak};
+
+/// Keccak-256 `Hasher`, hashing nodes and secure keys of the Ethereum trie.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct KeccakHasher;
+
+impl Hasher for KeccakHasher {
+	type Out = [u8; 32];
+
+	type StdHasher = Hash256StdHasher;
+
+	const LENGTH: usize = 32;
+
+	fn hash(x: &[u8]) -> Self::Out {
+		let mut keccak = Keccak::v256();
+		keccak.update(x);
+		let mut out = [0u8; 32];
+		keccak.finalize(&mut out);
+		out
+	}
+}
@@ -0,0 +1,485 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ethereum Merkle Patricia trie format for `trie-db` and `trie-root`.
+//!
+//! Nodes are RLP encoded, partial keys use hex-prefix encoding and hashing is Keccak-256,
+//! so roots match the `stateRoot`, `transactionsRoot` and `receiptsRoot` of Ethereum blocks.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod rstd {
+	pub use std::{borrow, collections::BTreeMap, marker, ops, vec};
+}
+
+#[cfg(not(feature = "std"))]
+mod rstd {
+	pub use alloc::{collections::BTreeMap, vec};
+	pub use core::{borrow, marker, ops};
+}
+
+mod hex_prefix;
+mod keccak;
+mod node_codec;
+mod trie_stream;
+
+pub use keccak::KeccakHasher;
+pub use node_codec::RlpNodeCodec;
+pub use trie_stream::RlpTrieStream;
+
+use hash_db::Hasher;
+use rstd::{vec::Vec, BTreeMap};
+use trie_db::{nibble_ops::Radix16, TrieConfiguration, TrieLayout};
+
+/// Encoding of the empty node: the empty RLP string.
+const EMPTY_NODE: [u8; 1] = [0x80];
+
+/// Hash output of the Ethereum trie.
+pub type H256 = <KeccakHasher as Hasher>::Out;
+
+/// Root of the empty trie, `keccak256(rlp(""))`.
+pub const KECCAK_NULL_RLP: H256 = [
+	0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+	0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// Ethereum trie layout: extension nodes, Keccak-256 hashing and RLP nodes.
+#[derive(Default, Clone)]
+pub struct EthereumLayout;
+
+impl TrieLayout for EthereumLayout {
+	const USE_EXTENSION: bool = true;
+	const ALLOW_EMPTY: bool = false;
+	const MAX_INLINE_VALUE: Option<u32> = None;
+
+	type Hash = KeccakHasher;
+	type Codec = RlpNodeCodec<KeccakHasher>;
+	type Nibble = Radix16;
+}
+
+impl TrieConfiguration for EthereumLayout {
+	/// Indexed tries (transactions, receipts) are keyed by the RLP encoded index.
+	fn encode_index(input: u32) -> Vec<u8> {
+		rlp::encode(&(input as usize)).to_vec()
+	}
+
+	/// RLP encoded indices do not sort like the indices themselves, so the items are
+	/// sorted by key before building the trie.
+	fn ordered_trie_root<I, A>(input: I) -> H256
+	where
+		I: IntoIterator<Item = A>,
+		A: AsRef<[u8]>,
+	{
+		let input: BTreeMap<_, _> = input
+			.into_iter()
+			.enumerate()
+			.map(|(i, v)| (Self::encode_index(i as u32), v))
+			.collect();
+		Self::trie_root(input)
+	}
+}
+
+/// Read-only Ethereum trie.
+pub type TrieDB<'db> = trie_db::TrieDB<'db, EthereumLayout>;
+/// Mutable Ethereum trie.
+pub type TrieDBMut<'db> = trie_db::TrieDBMut<'db, EthereumLayout>;
+/// Read-only Ethereum trie keyed by the Keccak-256 hash of the keys.
+pub type SecTrieDB<'db> = trie_db::SecTrieDB<'db, EthereumLayout>;
+/// Mutable Ethereum trie keyed by the Keccak-256 hash of the keys.
+pub type SecTrieDBMut<'db> = trie_db::SecTrieDBMut<'db, EthereumLayout>;
+/// Read-only Ethereum trie keyed by hashed keys, also storing the original keys.
+pub type FatDB<'db> = trie_db::FatDB<'db, EthereumLayout>;
+/// Mutable Ethereum trie keyed by hashed keys, also storing the original keys.
+pub type FatDBMut<'db> = trie_db::FatDBMut<'db, EthereumLayout>;
+
+/// Root of a trie with the given keys and values.
+pub fn trie_root<I, A, B>(input: I) -> H256
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]> + Ord,
+	B: AsRef<[u8]>,
+{
+	trie_root::trie_root::<KeccakHasher, RlpTrieStream, _, _, _>(input, None)
+}
+
+/// Root of a trie keyed by the Keccak-256 hash of the given keys, as used by the state
+/// (keyed by address) and storage (keyed by slot) tries.
+///
+/// Values are stored as is: account and storage values must already be RLP encoded.
+pub fn sec_trie_root<I, A, B>(input: I) -> H256
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+{
+	trie_root::sec_trie_root::<KeccakHasher, RlpTrieStream, _, _, _>(input, None)
+}
+
+/// Root of a trie keyed by the RLP encoded position of each value, as used by the
+/// transactions and receipts tries.
+pub fn ordered_trie_root<I, A>(input: I) -> H256
+where
+	I: IntoIterator<Item = A>,
+	A: AsRef<[u8]>,
+{
+	EthereumLayout::ordered_trie_root(input)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hash_db::{HashDB, EMPTY_PREFIX};
+	use hex_literal::hex;
+	use memory_db::{HashKey, MemoryDB};
+	use trie_db::{node::Value, proof, ChildReference, DBValue, NodeCodec, Trie, TrieMut};
+
+	type MemoryDBEth = MemoryDB<KeccakHasher, HashKey<KeccakHasher>, DBValue>;
+
+	fn memory_db() -> MemoryDBEth {
+		MemoryDB::from_null_node(&EMPTY_NODE, EMPTY_NODE.to_vec())
+	}
+
+	/// Check a root with `trie-root`, `trie_visit` and `TrieDBMut`.
+	fn check_root(input: &[(&[u8], &[u8])], expected: H256) {
+		assert_eq!(trie_root(input.iter().cloned()), expected);
+		assert_eq!(EthereumLayout::trie_root(input.iter().cloned()), expected);
+
+		let mut db = memory_db();
+		let mut root = Default::default();
+		{
+			let mut trie = TrieDBMut::new(&mut db, &mut root);
+			for (key, value) in input.iter().rev() {
+				trie.insert(key, value).unwrap();
+			}
+		}
+		assert_eq!(root, expected);
+		let trie = TrieDB::new(&db, &root);
+		for (key, value) in input {
+			assert_eq!(trie.get(key).unwrap().as_deref(), Some(*value));
+		}
+	}
+
+	#[test]
+	fn empty_root() {
+		assert_eq!(RlpNodeCodec::<KeccakHasher>::hashed_null_node(), KECCAK_NULL_RLP);
+		assert_eq!(trie_root(Vec::<(Vec<u8>, Vec<u8>)>::new()), KECCAK_NULL_RLP);
+		assert_eq!(ordered_trie_root(Vec::<Vec<u8>>::new()), KECCAK_NULL_RLP);
+		check_root(&[], KECCAK_NULL_RLP);
+	}
+
+	// Vectors from the `TrieTests/trieanyorder.json` file of the ethereum/tests repository.
+	#[test]
+	fn ethereum_test_vectors() {
+		check_root(
+			&[(b"doe", b"reindeer"), (b"dog", b"puppy"), (b"dogglesworth", b"cat")],
+			hex!("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"),
+		);
+		check_root(
+			&[(b"do", b"verb"), (b"dog", b"puppy"), (b"doge", b"coin"), (b"horse", b"stallion")],
+			hex!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"),
+		);
+		check_root(
+			&[(b"foo", b"bar"), (b"food", b"bass")],
+			hex!("17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3"),
+		);
+		check_root(
+			&[(b"be", b"e"), (b"bed", b"d"), (b"dog", b"puppy")],
+			hex!("3f67c7a47520f79faa29255d2d3c084a7a6df0453116ed7232ff10277a8be68b"),
+		);
+		check_root(
+			&[(b"te", b"testy"), (b"test", b"test")],
+			hex!("8452568af70d8d140f58d941338542f645fcca50094b20f3c3d8c3df49337928"),
+		);
+		check_root(
+			&[(&hex!("0045"), &hex!("0123456789")), (&hex!("4500"), &hex!("9876543210"))],
+			hex!("285505fcabe84badc8aa310e2aae17eddc7d120aabec8a476902c8184b3a3503"),
+		);
+	}
+
+	// Roots from mainnet block headers, built from the RLP of their transactions and receipts.
+	#[test]
+	fn mainnet_vectors() {
+		// Block 46147, first transaction on mainnet.
+		let mut stream = rlp::RlpStream::new_list(9);
+		stream.append(&0u8);
+		stream.append(&hex!("2d79883d2000").as_ref());
+		stream.append(&21000u32);
+		stream.append(&hex!("5df9b87991262f6ba471f09758cde1c0fc1de734").as_ref());
+		stream.append(&31337u32);
+		stream.append_empty_data();
+		stream.append(&0x1cu8);
+		stream.append(
+			&hex!("88ff6cf0fefd94db46111149ae4bfc179e9b94721fffd821d38d16464b3f71d0").as_ref(),
+		);
+		stream.append(
+			&hex!("45e0aff800961cfce805daef7016b9b675c137a6a41a548f7b60a3484c06a33a").as_ref(),
+		);
+		let transaction = stream.out().to_vec();
+		assert_eq!(
+			KeccakHasher::hash(&transaction),
+			hex!("5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"),
+		);
+		let transactions_root =
+			hex!("4513310fcb9f6f616972a3b948dc5d547f280849a87ebb5af0191f98b87be598");
+		assert_eq!(ordered_trie_root([&transaction]), transactions_root);
+		check_root(&[(&EthereumLayout::encode_index(0), &transaction)], transactions_root);
+
+		// Receipts root of post-Byzantium blocks holding a single successful transfer.
+		let mut stream = rlp::RlpStream::new_list(4);
+		stream.append(&1u8);
+		stream.append(&21000u32);
+		stream.append(&[0u8; 256].as_ref());
+		stream.begin_list(0);
+		let receipt = stream.out().to_vec();
+		let receipts_root =
+			hex!("056b23fbba480696b65fe5a59b8f2148a1299103c4f57df839233af2cf4ca2d2");
+		assert_eq!(ordered_trie_root([&receipt]), receipts_root);
+		check_root(&[(&EthereumLayout::encode_index(0), &receipt)], receipts_root);
+	}
+
+	fn from_hex(hex: &str) -> Vec<u8> {
+		let padded = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_string() };
+		(0..padded.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&padded[i..i + 2], 16).unwrap())
+			.collect()
+	}
+
+	/// Parse a genesis allocation, one `address balance [code [slot=value,...]]` line in hex
+	/// per account. Returns the address, storage root and RLP encoded account of each entry.
+	fn genesis_accounts(alloc: &str) -> Vec<(Vec<u8>, H256, Vec<u8>)> {
+		alloc
+			.lines()
+			.map(|line| {
+				let mut fields = line.split(' ');
+				let address = from_hex(fields.next().unwrap());
+				let balance = u128::from_str_radix(fields.next().unwrap(), 16).unwrap();
+				let code = from_hex(fields.next().unwrap_or(""));
+				let storage =
+					fields.next().unwrap_or("").split(',').filter(|s| !s.is_empty()).map(|slot| {
+						let (key, value) = slot.split_once('=').unwrap();
+						let value = from_hex(value);
+						let value = &value[value.iter().take_while(|b| **b == 0).count()..];
+						(from_hex(key), rlp::encode(&value).to_vec())
+					});
+				let storage_root = sec_trie_root(storage);
+
+				let mut stream = rlp::RlpStream::new_list(4);
+				stream.append(&0u8);
+				stream.append(&balance);
+				stream.append(&storage_root.as_ref());
+				stream.append(&KeccakHasher::hash(&code).as_ref());
+				(address, storage_root, stream.out().to_vec())
+			})
+			.collect()
+	}
+
+	// Allocations as dumped by go-ethereum's `dumpgenesis`, checked against the `stateRoot` of
+	// the genesis block headers.
+	#[test]
+	fn genesis_state_roots() {
+		let mainnet_root = hex!("d7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544");
+		let accounts = genesis_accounts(include_str!("../test-data/mainnet-genesis.txt"));
+		assert_eq!(accounts.len(), 8893);
+		assert!(accounts.iter().all(|(_, storage_root, _)| *storage_root == KECCAK_NULL_RLP));
+		assert_eq!(
+			sec_trie_root(accounts.iter().map(|(address, _, account)| (address, account))),
+			mainnet_root,
+		);
+		let mut db = memory_db();
+		let mut root = Default::default();
+		{
+			let mut trie = SecTrieDBMut::new(&mut db, &mut root);
+			for (address, _, account) in &accounts {
+				trie.insert(address, account).unwrap();
+			}
+		}
+		assert_eq!(root, mainnet_root);
+
+		// Mainnet genesis has no contract, the Holesky one holds the deposit contract with the
+		// zero hashes of its deposit tree in storage.
+		let accounts = genesis_accounts(include_str!("../test-data/holesky-genesis.txt"));
+		let deposit_contract = hex!("4242424242424242424242424242424242424242");
+		let storage_roots: Vec<_> = accounts
+			.iter()
+			.filter(|(_, storage_root, _)| *storage_root != KECCAK_NULL_RLP)
+			.map(|(address, storage_root, _)| (&address[..], *storage_root))
+			.collect();
+		assert_eq!(
+			storage_roots,
+			vec![(
+				&deposit_contract[..],
+				hex!("556a482068355939c95a3412bdb21213a301483edb1b64402fb66ac9f3583599"),
+			)],
+		);
+		assert_eq!(
+			sec_trie_root(accounts.iter().map(|(address, _, account)| (address, account))),
+			hex!("69d8c9d72f6fa4ad42d4702b433707212f90db395eb54dc20bc85de253788783"),
+		);
+	}
+
+	#[test]
+	fn secure_and_ordered_roots() {
+		assert_eq!(
+			sec_trie_root(vec![("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]),
+			hex!("d4cd937e4a4368d7931a9cf51686b7e10abb3dce38a39000fd7902a092b64585"),
+		);
+		assert_eq!(
+			ordered_trie_root(["doe", "reindeer"]),
+			hex!("e766d5d51b89dc39d981b41bda63248d7abce4f0225eefd023792a540bcffee3"),
+		);
+		assert_eq!(EthereumLayout::encode_index(0), vec![0x80]);
+		assert_eq!(EthereumLayout::encode_index(127), vec![0x7f]);
+		assert_eq!(EthereumLayout::encode_index(128), vec![0x81, 0x80]);
+
+		let values: Vec<Vec<u8>> =
+			(0..300u32).map(|i| vec![i as u8; 1 + i as usize % 70]).collect();
+		assert_eq!(ordered_trie_root(&values), EthereumLayout::ordered_trie_root(&values));
+
+		let mut db = memory_db();
+		let mut root = Default::default();
+		{
+			let mut trie = SecTrieDBMut::new(&mut db, &mut root);
+			trie.insert(b"dog", b"puppy").unwrap();
+			trie.insert(b"doe", b"reindeer").unwrap();
+			trie.insert(b"dogglesworth", b"cat").unwrap();
+		}
+		assert_eq!(root, hex!("d4cd937e4a4368d7931a9cf51686b7e10abb3dce38a39000fd7902a092b64585"));
+	}
+
+	#[test]
+	fn proof_round_trip() {
+		let items: Vec<(Vec<u8>, Vec<u8>)> = (0..200u32)
+			.map(|i| (rlp::encode(&i).to_vec(), vec![i as u8; i as usize % 50]))
+			.collect();
+		let items: Vec<_> = items.into_iter().filter(|(_, v)| !v.is_empty()).collect();
+		let mut db = memory_db();
+		let root = EthereumLayout::trie_build(&mut db, items.iter().cloned());
+		assert_eq!(root, trie_root(items.iter().cloned()));
+
+		let keys: Vec<_> = items.iter().step_by(7).map(|(k, _)| k.clone()).collect();
+		let trie = TrieDB::new(&db, &root);
+		let proof = proof::generate_proof::<_, EthereumLayout, _, _>(&trie, &keys).unwrap();
+		let expected: Vec<_> =
+			items.iter().step_by(7).map(|(k, v)| (k.clone(), Some(v.clone()))).collect();
+		proof::verify_proof::<EthereumLayout, _, _, _>(&root, &proof, expected.iter()).unwrap();
+
+		let compact = trie_db::encode_compact::<EthereumLayout>(&trie).unwrap();
+		let mut decoded = memory_db();
+		let (decoded_root, _) =
+			trie_db::decode_compact::<EthereumLayout, _>(&mut decoded, &compact).unwrap();
+		assert_eq!(decoded_root, root);
+		let trie = TrieDB::new(&decoded, &root);
+		for (key, value) in &items {
+			assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+		}
+
+		// Branch node values are omitted from proofs as well.
+		let items = [(b"do".to_vec(), b"verb".to_vec()), (b"dog".to_vec(), b"puppy".to_vec())];
+		let mut db = memory_db();
+		let root = EthereumLayout::trie_build(&mut db, items.iter().cloned());
+		let trie = TrieDB::new(&db, &root);
+		let proof = proof::generate_proof::<_, EthereumLayout, _, _>(&trie, &[b"do"]).unwrap();
+		let expected = [(b"do".to_vec(), Some(b"verb".to_vec()))];
+		proof::verify_proof::<EthereumLayout, _, _, _>(&root, &proof, expected.iter()).unwrap();
+	}
+
+	#[test]
+	fn encode_into_appends() {
+		type Codec = RlpNodeCodec<KeccakHasher>;
+		let hash = [0x11; 32];
+		let inline = {
+			let mut inline = [0; 32];
+			inline[..3].copy_from_slice(&[0xc2, 0x20, 0x01]);
+			inline
+		};
+		let children = [
+			Some(ChildReference::Hash(hash)),
+			None,
+			Some(ChildReference::Inline(inline, 3)),
+			Some(ChildReference::Hash(hash)),
+		];
+		for value in [&b""[..], &[0x01], &[0x81], &[0x42; 100]] {
+			let partial = ((1, 0x02), &[0x34, 0x56][..]);
+			let mut output = b"prefix".to_vec();
+			Codec::leaf_node_into(partial, Value::Inline(value), &mut output);
+			let mut stream = rlp::RlpStream::new_list(2);
+			stream.append(&hex!("323456").as_ref()).append(&value);
+			assert_eq!(output, [&b"prefix"[..], &stream.out()].concat());
+			assert_eq!(&output[6..], &Codec::leaf_node(partial, Value::Inline(value))[..]);
+
+			let mut output = b"prefix".to_vec();
+			Codec::branch_node_into(children.iter(), Some(Value::Inline(value)), &mut output);
+			let mut stream = rlp::RlpStream::new_list(17);
+			stream.append(&hash.as_ref()).append_empty_data().append_raw(&inline[..3], 1);
+			stream.append(&hash.as_ref());
+			for _ in children.len()..16 {
+				stream.append_empty_data();
+			}
+			if value.is_empty() {
+				stream.begin_list(0);
+			} else {
+				stream.append(&value);
+			}
+			assert_eq!(output, [&b"prefix"[..], &stream.out()].concat());
+			assert!(Codec::decode_plan(&output[6..]).is_ok());
+		}
+
+		let mut output = b"prefix".to_vec();
+		Codec::extension_node_into([0x34].iter().cloned(), 2, children[0].unwrap(), &mut output);
+		let mut stream = rlp::RlpStream::new_list(2);
+		stream.append(&hex!("0034").as_ref()).append(&hash.as_ref());
+		assert_eq!(output, [&b"prefix"[..], &stream.out()].concat());
+		assert_eq!(
+			&output[6..],
+			&Codec::extension_node([0x34].iter().cloned(), 2, children[0].unwrap())[..]
+		);
+	}
+
+	#[test]
+	fn rejects_non_canonical_nodes() {
+		type Codec = RlpNodeCodec<KeccakHasher>;
+		let mut db = memory_db();
+		let root = EthereumLayout::trie_build(
+			&mut db,
+			vec![(b"doe".to_vec(), b"reindeer".to_vec()), (b"dog".to_vec(), b"puppy".to_vec())],
+		);
+		let node = db.get(&root, EMPTY_PREFIX).unwrap();
+		assert!(Codec::decode_plan(&node).is_ok());
+
+		let mut trailing = node.clone();
+		trailing.push(0);
+		assert!(Codec::decode_plan(&trailing).is_err());
+
+		// Leaf with an even key whose padding nibble is not zero.
+		assert!(Codec::decode_plan(&[0xc4, 0x82, 0x21, 0x23, 0x01]).is_err());
+		assert!(Codec::decode_plan(&[0xc4, 0x82, 0x20, 0x23, 0x01]).is_ok());
+		// Single byte value encoded as a string.
+		assert!(Codec::decode_plan(&[0xc5, 0x82, 0x20, 0x23, 0x81, 0x01]).is_err());
+		// Invalid item after the leaf items.
+		assert!(Codec::decode_plan(&[0xc5, 0x82, 0x20, 0x23, 0x01, 0xb9]).is_err());
+		// Invalid hex-prefix flag.
+		assert!(Codec::decode_plan(&[0xc4, 0x82, 0x40, 0x23, 0x01]).is_err());
+		// Branch child that is neither empty, inline nor a hash.
+		let mut branch = vec![0xd2, 0x81, 0x01];
+		branch.extend_from_slice(&[0x80; 16]);
+		assert!(Codec::decode_plan(&branch).is_err());
+		// Lists of other sizes.
+		assert!(Codec::decode_plan(&[0xc3, 0x80, 0x80, 0x80]).is_err());
+	}
+}
@@ -0,0 +1,331 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `NodeCodec` implementation for the Ethereum RLP node format.
+
+use crate::{
+	hex_prefix,
+	rstd::{borrow::Borrow, marker::PhantomData, ops::Range, vec::Vec},
+	EMPTY_NODE,
+};
+use hash_db::Hasher;
+use rlp::{DecoderError, Prototype, Rlp, EMPTY_LIST_RLP};
+use trie_db::{
+	nibble_ops::{NibbleOps, Radix16},
+	node::{Children, NodeHandlePlan, NodePlan, Value, ValuePlan},
+	ChildReference, NodeCodec, Partial,
+};
+
+/// Number of items in an encoded branch node: one per nibble, then the value.
+const BRANCH_ITEMS: usize = Radix16::NIBBLE_LENGTH + 1;
+
+/// First byte of the header of an RLP string.
+const STRING_OFFSET: u8 = 0x80;
+/// First byte of the header of an RLP list.
+const LIST_OFFSET: u8 = 0xc0;
+
+/// Length of the header of an RLP item with a payload of `len` bytes.
+fn header_len(len: usize) -> usize {
+	if len < 56 {
+		1
+	} else {
+		1 + (len as u64).to_be_bytes().iter().skip_while(|b| **b == 0).count()
+	}
+}
+
+/// Append the header of an RLP string or list with a payload of `len` bytes.
+fn append_header(offset: u8, len: usize, output: &mut Vec<u8>) {
+	if len < 56 {
+		output.push(offset + len as u8);
+	} else {
+		let len = (len as u64).to_be_bytes();
+		let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+		output.push(offset + 55 + len.len() as u8);
+		output.extend_from_slice(len);
+	}
+}
+
+/// Length of `data` encoded as an RLP string.
+fn data_len(data: &[u8]) -> usize {
+	match data {
+		[byte] if *byte < STRING_OFFSET => 1,
+		_ => header_len(data.len()) + data.len(),
+	}
+}
+
+/// Append `data` encoded as an RLP string.
+fn append_data(data: &[u8], output: &mut Vec<u8>) {
+	match data {
+		[byte] if *byte < STRING_OFFSET => output.push(*byte),
+		_ => {
+			append_header(STRING_OFFSET, data.len(), output);
+			output.extend_from_slice(data);
+		},
+	}
+}
+
+/// Length of a hex-prefix encoded partial key of `nibble_count` nibbles as an RLP string.
+fn partial_len(nibble_count: usize) -> usize {
+	match hex_prefix::encoded_len(nibble_count) {
+		// A single byte only holds the flag nibble and is its own encoding.
+		1 => 1,
+		len => header_len(len) + len,
+	}
+}
+
+/// Append a hex-prefix encoded partial key as an RLP string.
+fn append_partial(
+	partial: impl Iterator<Item = u8>,
+	nibble_count: usize,
+	leaf: bool,
+	output: &mut Vec<u8>,
+) {
+	let len = hex_prefix::encoded_len(nibble_count);
+	if len > 1 {
+		append_header(STRING_OFFSET, len, output);
+	}
+	hex_prefix::encode_into(partial, nibble_count, leaf, output);
+}
+
+/// Ethereum node codec.
+///
+/// Leaves and extensions are two item lists of a hex-prefix encoded partial key and
+/// respectively the value or the child reference. Branches are seventeen item lists of
+/// child references followed by the value. A child is referenced by the hash of its
+/// encoding, or embedded directly when it is shorter than the hash.
+///
+/// Proofs and compact encodings omit some children and values by using empty inline
+/// children and empty values. These placeholders have no RLP node representation and are
+/// encoded as empty lists.
+#[derive(Default, Clone)]
+pub struct RlpNodeCodec<H>(PhantomData<H>);
+
+impl<H: Hasher> RlpNodeCodec<H> {
+	fn decode_child(item: &Rlp, offset: usize) -> Result<Option<NodeHandlePlan>, DecoderError> {
+		let info = item.payload_info()?;
+		let range = offset..offset + info.total();
+		if item.is_list() {
+			if info.value_len == 0 {
+				return Ok(Some(NodeHandlePlan::Inline(range.end..range.end)))
+			}
+			if info.total() >= H::LENGTH {
+				return Err(DecoderError::Custom("Inline child node too long"))
+			}
+			Ok(Some(NodeHandlePlan::Inline(range)))
+		} else if info.value_len == H::LENGTH {
+			Ok(Some(NodeHandlePlan::Hash(range.start + info.header_len..range.end)))
+		} else if item.is_empty() {
+			Ok(None)
+		} else {
+			Err(DecoderError::Custom("Invalid child reference"))
+		}
+	}
+
+	fn data_range(item: &Rlp, offset: usize) -> Result<Range<usize>, DecoderError> {
+		if !item.is_data() {
+			return Err(DecoderError::RlpExpectedToBeData)
+		}
+		let info = item.payload_info()?;
+		if info.header_len == 1 && info.value_len == 1 && item.as_raw()[1] < 0x80 {
+			// Single bytes below `0x80` are their own encoding.
+			return Err(DecoderError::RlpInvalidIndirection)
+		}
+		Ok(offset + info.header_len..offset + info.total())
+	}
+
+	fn child_len(child: Option<&ChildReference<H::Out>>) -> usize {
+		match child {
+			Some(ChildReference::Hash(hash)) => data_len(hash.as_ref()),
+			Some(ChildReference::Inline(_, 0)) => EMPTY_LIST_RLP.len(),
+			Some(ChildReference::Inline(_, len)) => *len,
+			None => 1,
+		}
+	}
+
+	fn append_child(child: Option<&ChildReference<H::Out>>, output: &mut Vec<u8>) {
+		match child {
+			Some(ChildReference::Hash(hash)) => append_data(hash.as_ref(), output),
+			Some(ChildReference::Inline(_, 0)) => output.extend_from_slice(&EMPTY_LIST_RLP),
+			Some(ChildReference::Inline(data, len)) =>
+				output.extend_from_slice(&data.as_ref()[..*len]),
+			None => output.push(STRING_OFFSET),
+		}
+	}
+
+	fn value_data(value: Value<'_>) -> &[u8] {
+		match value {
+			Value::Inline(value) => value,
+			Value::Node(..) => unreachable!("Ethereum trie values are always inline"),
+		}
+	}
+}
+
+impl<H: Hasher> NodeCodec for RlpNodeCodec<H> {
+	type Error = DecoderError;
+	type HashOut = H::Out;
+	type Nibble = Radix16;
+
+	fn hashed_null_node() -> H::Out {
+		H::hash(&EMPTY_NODE)
+	}
+
+	fn decode_plan(data: &[u8]) -> Result<NodePlan<Radix16>, Self::Error> {
+		let r = Rlp::new(data);
+		if r.payload_info()?.total() != data.len() {
+			return Err(DecoderError::RlpIsTooBig)
+		}
+		let prototype = r.prototype()?;
+		if let Prototype::List(count @ 1..=BRANCH_ITEMS) = prototype {
+			// Item count stops at the first invalid item, the items must cover the payload.
+			let (last, offset) = r.at_with_offset(count - 1)?;
+			if offset + last.payload_info()?.total() != data.len() {
+				return Err(DecoderError::RlpInconsistentLengthAndData)
+			}
+		}
+		match prototype {
+			Prototype::Data(0) => Ok(NodePlan::Empty),
+			Prototype::List(2) => {
+				let (path, offset) = r.at_with_offset(0)?;
+				let path = Self::data_range(&path, offset)?;
+				let (is_leaf, partial) = hex_prefix::decode(&data[path.clone()], path.start)?;
+				let (item, offset) = r.at_with_offset(1)?;
+				if is_leaf {
+					let value = Self::data_range(&item, offset)?;
+					Ok(NodePlan::Leaf { partial, value: ValuePlan::Inline(value) })
+				} else {
+					if partial.len() == 0 {
+						return Err(DecoderError::Custom("Empty extension partial key"))
+					}
+					let child = Self::decode_child(&item, offset)?
+						.ok_or(DecoderError::Custom("Extension without child"))?;
+					Ok(NodePlan::Extension { partial, child })
+				}
+			},
+			Prototype::List(BRANCH_ITEMS) => {
+				let mut children = Children::<Radix16, _>::default();
+				for (i, child) in children.iter_mut().enumerate() {
+					let (item, offset) = r.at_with_offset(i)?;
+					*child = Self::decode_child(&item, offset)?;
+				}
+				let (item, offset) = r.at_with_offset(Radix16::NIBBLE_LENGTH)?;
+				let value = if item.is_list() && item.is_empty() {
+					let end = offset + EMPTY_LIST_RLP.len();
+					Some(ValuePlan::Inline(end..end))
+				} else {
+					let value = Self::data_range(&item, offset)?;
+					if value.is_empty() {
+						None
+					} else {
+						Some(ValuePlan::Inline(value))
+					}
+				};
+				Ok(NodePlan::Branch { value, children })
+			},
+			_ => Err(DecoderError::Custom("Rlp is not a valid trie node")),
+		}
+	}
+
+	fn is_empty_node(data: &[u8]) -> bool {
+		data == EMPTY_NODE
+	}
+
+	fn empty_node() -> &'static [u8] {
+		&EMPTY_NODE
+	}
+
+	fn leaf_node(partial: Partial, value: Value) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::leaf_node_into(partial, value, &mut output);
+		output
+	}
+
+	fn extension_node(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		child_ref: ChildReference<Self::HashOut>,
+	) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::extension_node_into(partial, number_nibble, child_ref, &mut output);
+		output
+	}
+
+	fn branch_node(
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		maybe_value: Option<Value>,
+	) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::branch_node_into(children, maybe_value, &mut output);
+		output
+	}
+
+	fn branch_node_nibbled(
+		_partial: impl Iterator<Item = u8>,
+		_number_nibble: usize,
+		_children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		_maybe_value: Option<Value>,
+	) -> Vec<u8> {
+		unreachable!("Ethereum trie uses extension nodes")
+	}
+
+	fn leaf_node_into(partial: Partial, value: Value, output: &mut Vec<u8>) {
+		let value = Self::value_data(value);
+		let (partial, nibble_count) = hex_prefix::partial_nibbles(partial);
+		append_header(LIST_OFFSET, partial_len(nibble_count) + data_len(value), output);
+		append_partial(partial, nibble_count, true, output);
+		append_data(value, output);
+	}
+
+	fn extension_node_into(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		child_ref: ChildReference<Self::HashOut>,
+		output: &mut Vec<u8>,
+	) {
+		let payload_len = partial_len(number_nibble) + Self::child_len(Some(&child_ref));
+		append_header(LIST_OFFSET, payload_len, output);
+		append_partial(partial, number_nibble, false, output);
+		Self::append_child(Some(&child_ref), output);
+	}
+
+	fn branch_node_into(
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		maybe_value: Option<Value>,
+		output: &mut Vec<u8>,
+	) {
+		// Children are read twice, to compute the list length then to encode them.
+		let mut child_refs = [None; Radix16::NIBBLE_LENGTH];
+		for (child_ref, child) in child_refs.iter_mut().zip(children) {
+			*child_ref = *child.borrow();
+		}
+		let value = maybe_value.map(Self::value_data);
+		let value_len = match value {
+			// Empty values are placeholders for omitted values.
+			Some([]) => EMPTY_LIST_RLP.len(),
+			Some(value) => data_len(value),
+			None => 1,
+		};
+		let payload_len =
+			child_refs.iter().map(|child| Self::child_len(child.as_ref())).sum::<usize>() +
+				value_len;
+		append_header(LIST_OFFSET, payload_len, output);
+		for child in child_refs.iter() {
+			Self::append_child(child.as_ref(), output);
+		}
+		match value {
+			Some([]) => output.extend_from_slice(&EMPTY_LIST_RLP),
+			Some(value) => append_data(value, output),
+			None => output.push(STRING_OFFSET),
+		}
+	}
+}
@@ -0,0 +1,99 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `TrieStream` implementation for the Ethereum RLP node format.
+
+use crate::{hex_prefix, rstd::vec::Vec};
+use hash_db::Hasher;
+use rlp::RlpStream;
+use trie_root::{TrieStream, Value};
+
+/// Number of items in an encoded branch node.
+const BRANCH_ITEMS: usize = 17;
+
+/// Ethereum `TrieStream`, producing the same encoding as
+/// [`RlpNodeCodec`](crate::RlpNodeCodec).
+#[derive(Default)]
+pub struct RlpTrieStream {
+	stream: RlpStream,
+}
+
+impl RlpTrieStream {
+	fn append_value(&mut self, value: Value) {
+		match value {
+			Value::Inline(value) => {
+				self.stream.append(&value);
+			},
+			Value::Node(..) => unreachable!("Ethereum trie values are always inline"),
+		}
+	}
+}
+
+impl TrieStream for RlpTrieStream {
+	fn new() -> Self {
+		RlpTrieStream { stream: RlpStream::new() }
+	}
+
+	fn append_empty_data(&mut self) {
+		self.stream.append_empty_data();
+	}
+
+	fn begin_branch(
+		&mut self,
+		_maybe_key: Option<&[u8]>,
+		_maybe_value: Option<Value>,
+		_has_children: impl Iterator<Item = bool>,
+	) {
+		self.stream.begin_list(BRANCH_ITEMS);
+	}
+
+	fn append_empty_child(&mut self) {
+		self.stream.append_empty_data();
+	}
+
+	fn end_branch(&mut self, value: Option<Value>) {
+		match value {
+			Some(value) => self.append_value(value),
+			None => {
+				self.stream.append_empty_data();
+			},
+		}
+	}
+
+	fn append_leaf(&mut self, key: &[u8], value: Value) {
+		self.stream.begin_list(2);
+		self.stream.append(&&hex_prefix::encode_nibbles(key, true)[..]);
+		self.append_value(value);
+	}
+
+	fn append_extension(&mut self, key: &[u8]) {
+		self.stream.begin_list(2);
+		self.stream.append(&&hex_prefix::encode_nibbles(key, false)[..]);
+	}
+
+	fn append_encoded_substream<H: Hasher>(&mut self, data: &[u8]) -> Option<H::Out> {
+		if data.len() < H::LENGTH {
+			self.stream.append_raw(data, 1);
+			None
+		} else {
+			let hash = H::hash(data);
+			self.stream.append(&hash.as_ref());
+			Some(hash)
+		}
+	}
+
+	fn out(self) -> Vec<u8> {
+		self.stream.out().to_vec()
+	}
+}
@@ -0,0 +1,794 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference-counted persistent `HashDB` implementation.
+//!
+//! Content is stored in a directory containing two files:
+//! - `log`: an append-only sequence of batches. Each batch contains a list of `(key, reference
+//!   count delta, value)` entries and ends with the hash of its content, so a batch is either fully
+//!   applied or ignored.
+//! - `index`: a snapshot of the in-memory index (key to reference count and value position in the
+//!   log) valid up to a given log offset. It only speeds up opening the database: batches written
+//!   after the snapshot are replayed from the log.
+//!
+//! When opening, an incomplete or corrupted batch at the end of the log (torn write)
+//! is discarded and the log truncated to the last complete batch.
+
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher as KeyHasher, Prefix};
+use memory_db::{KeyFunction, MemTracker, MemoryDB, NoopTracker};
+use std::{
+	collections::HashMap,
+	convert::TryInto,
+	fs::{self, File, OpenOptions},
+	io::{self, BufReader, Read, Seek, SeekFrom, Write},
+	marker::PhantomData,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+/// Database value.
+pub type DBValue = Vec<u8>;
+
+const LOG_FILE: &str = "log";
+const INDEX_FILE: &str = "index";
+const TMP_SUFFIX: &str = ".tmp";
+
+const BATCH_MAGIC: &[u8; 4] = b"FDBB";
+const INDEX_MAGIC: &[u8; 4] = b"FDBI";
+/// Magic, then payload length.
+const BATCH_HEADER_LEN: usize = 4 + 8;
+
+/// Position of a value in the log and its reference count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IndexEntry {
+	offset: u64,
+	len: u32,
+	rc: i32,
+}
+
+/// In-memory index, keyed by the `KeyFunction` output.
+type Index = HashMap<Vec<u8>, IndexEntry>;
+
+/// Pending writes, committed with `FileDB::commit`.
+type Overlay<H, KF> = MemoryDB<H, KF, DBValue, NoopTracker<DBValue>>;
+
+/// Reference-counted `HashDB` persisted in a directory.
+///
+/// Writes done through the `HashDB` trait are kept in memory until `commit` is called,
+/// then written as a single atomic batch. Reference counts follow the rules of
+/// `MemoryDB::consolidate`: committing a batch adds its reference counts to the stored
+/// ones and entries whose count drops to zero are purged.
+///
+/// Keys are stored using the byte representation of the `KeyFunction` output, so a
+/// database must always be opened with the same key function.
+///
+/// # Example
+/// ```rust
+///   use hash_db::{HashDB, EMPTY_PREFIX};
+///   use keccak_hasher::KeccakHasher;
+///   use memory_db::HashKey;
+///   use file_db::FileDB;
+///
+///   let dir = tempfile::tempdir().unwrap();
+///   let key = {
+///     let mut db = FileDB::<KeccakHasher, HashKey<_>>::open(dir.path()).unwrap();
+///     let key = db.insert(EMPTY_PREFIX, b"Hello world!");
+///     db.commit().unwrap();
+///     key
+///   };
+///
+///   let db = FileDB::<KeccakHasher, HashKey<_>>::open(dir.path()).unwrap();
+///   assert_eq!(db.get(&key, EMPTY_PREFIX).unwrap(), b"Hello world!");
+/// ```
+pub struct FileDB<H, KF>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+{
+	path: PathBuf,
+	log: Mutex<File>,
+	log_len: u64,
+	index: Index,
+	overlay: Overlay<H, KF>,
+	hashed_null_node: H::Out,
+	null_node_data: DBValue,
+	_kf: PhantomData<KF>,
+}
+
+impl<H, KF> FileDB<H, KF>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	KF::Key: AsRef<[u8]>,
+{
+	/// Open or create a database in the `path` directory, using `[0u8]` as null node
+	/// (the default of `MemoryDB`).
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		Self::open_with_null_node(path, &[0u8][..])
+	}
+
+	/// Open or create a database in the `path` directory, with a given null node.
+	pub fn open_with_null_node(path: impl AsRef<Path>, null_node: &[u8]) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		fs::create_dir_all(&path)?;
+		let mut log = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path.join(LOG_FILE))?;
+		let file_len = log.metadata()?.len();
+
+		let (mut index, start) = match read_index::<H>(&path.join(INDEX_FILE))? {
+			Some((index, offset)) if offset <= file_len => (index, offset),
+			Some(_) => {
+				log::warn!(target: "file-db", "Index is ahead of the log, replaying whole log");
+				(HashMap::new(), 0)
+			},
+			None => (HashMap::new(), 0),
+		};
+
+		let log_len = replay::<H>(&mut log, start, file_len, &mut index)?;
+		if log_len < file_len {
+			log::warn!(
+				target: "file-db",
+				"Discarding {} bytes of incomplete batch at the end of the log",
+				file_len - log_len,
+			);
+			log.set_len(log_len)?;
+			log.sync_all()?;
+		}
+
+		Ok(FileDB {
+			path,
+			log: Mutex::new(log),
+			log_len,
+			index,
+			overlay: Overlay::from_null_node(null_node, null_node.into()),
+			hashed_null_node: H::hash(null_node),
+			null_node_data: null_node.into(),
+			_kf: PhantomData,
+		})
+	}
+
+	/// Directory of the database.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Write all pending changes in a single batch.
+	///
+	/// Pending changes are only discarded once written: on error they are kept and
+	/// the commit can be retried.
+	pub fn commit(&mut self) -> io::Result<()> {
+		let pending = self.overlay.clone();
+		self.apply(pending)?;
+		self.overlay.clear();
+		Ok(())
+	}
+
+	/// Discard all pending changes.
+	pub fn revert(&mut self) {
+		self.overlay.clear();
+	}
+
+	/// Write the content of a `MemoryDB` in a single batch, as `MemoryDB::consolidate`
+	/// would merge it.
+	pub fn apply<M: MemTracker<DBValue> + Default>(
+		&mut self,
+		mut batch: MemoryDB<H, KF, DBValue, M>,
+	) -> io::Result<()> {
+		let entries: Vec<_> = batch
+			.drain()
+			.into_iter()
+			.filter(|(_, (_, rc))| *rc != 0)
+			.map(|(key, (value, rc))| (key.as_ref().to_vec(), value, rc))
+			.collect();
+		if entries.is_empty() {
+			return Ok(())
+		}
+
+		let mut payload = Vec::new();
+		payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+		// Offset of each value within the payload.
+		let mut value_offsets = Vec::with_capacity(entries.len());
+		for (key, value, rc) in entries.iter() {
+			// Values of pending removals are meaningless.
+			let value: &[u8] = if *rc > 0 { value } else { &[] };
+			payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+			payload.extend_from_slice(key);
+			payload.extend_from_slice(&rc.to_le_bytes());
+			payload.extend_from_slice(&(value.len() as u32).to_le_bytes());
+			value_offsets.push(payload.len() as u64);
+			payload.extend_from_slice(value);
+		}
+
+		let batch_start = self.log_len;
+		{
+			let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+			log.seek(SeekFrom::Start(batch_start))?;
+			let mut buf = Vec::with_capacity(BATCH_HEADER_LEN + payload.len() + H::LENGTH);
+			buf.extend_from_slice(BATCH_MAGIC);
+			buf.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+			buf.extend_from_slice(&payload);
+			buf.extend_from_slice(H::hash(&payload).as_ref());
+			log.write_all(&buf)?;
+			log.sync_data()?;
+			self.log_len += buf.len() as u64;
+		}
+
+		let payload_start = batch_start + BATCH_HEADER_LEN as u64;
+		for ((key, value, rc), offset) in entries.into_iter().zip(value_offsets) {
+			let len = if rc > 0 { value.len() as u32 } else { 0 };
+			apply_entry(
+				&mut self.index,
+				key,
+				IndexEntry { offset: payload_start + offset, len, rc },
+			);
+		}
+		Ok(())
+	}
+
+	/// Get the stored value and reference count of a key, ignoring pending changes.
+	pub fn raw(&self, key: &H::Out, prefix: Prefix) -> io::Result<Option<(DBValue, i32)>> {
+		if key == &self.hashed_null_node {
+			return Ok(Some((self.null_node_data.clone(), 1)))
+		}
+		match self.index.get(KF::key(key, prefix).as_ref()) {
+			Some(entry) => Ok(Some((self.read_value(entry)?, entry.rc))),
+			None => Ok(None),
+		}
+	}
+
+	/// Number of stored entries, ignoring pending changes.
+	pub fn len(&self) -> usize {
+		self.index.len()
+	}
+
+	/// Does the database contain no stored entries?
+	pub fn is_empty(&self) -> bool {
+		self.index.is_empty()
+	}
+
+	/// Write a snapshot of the index, so that the next opening does not need to
+	/// replay the whole log.
+	pub fn write_index(&self) -> io::Result<()> {
+		let mut buf = Vec::new();
+		buf.extend_from_slice(INDEX_MAGIC);
+		buf.extend_from_slice(&self.log_len.to_le_bytes());
+		buf.extend_from_slice(&(self.index.len() as u64).to_le_bytes());
+		for (key, entry) in self.index.iter() {
+			buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+			buf.extend_from_slice(key);
+			buf.extend_from_slice(&entry.offset.to_le_bytes());
+			buf.extend_from_slice(&entry.len.to_le_bytes());
+			buf.extend_from_slice(&entry.rc.to_le_bytes());
+		}
+		let checksum = H::hash(&buf);
+		buf.extend_from_slice(checksum.as_ref());
+		write_atomically(&self.path, INDEX_FILE, &buf)
+	}
+
+	/// Rewrite the log with only the live entries, and write a new index.
+	///
+	/// Pending changes are left untouched.
+	pub fn compact(&mut self) -> io::Result<()> {
+		let mut entries: Vec<_> = self.index.iter().map(|(k, e)| (k.clone(), *e)).collect();
+		entries.sort_by_key(|(_, e)| e.offset);
+
+		let mut payload = Vec::new();
+		payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+		let mut new_index = HashMap::with_capacity(entries.len());
+		for (key, entry) in entries {
+			let value = self.read_value(&entry)?;
+			payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+			payload.extend_from_slice(&key);
+			payload.extend_from_slice(&entry.rc.to_le_bytes());
+			payload.extend_from_slice(&(value.len() as u32).to_le_bytes());
+			let offset = (BATCH_HEADER_LEN + payload.len()) as u64;
+			payload.extend_from_slice(&value);
+			new_index.insert(key, IndexEntry { offset, ..entry });
+		}
+
+		let mut buf = Vec::with_capacity(BATCH_HEADER_LEN + payload.len() + H::LENGTH);
+		if !new_index.is_empty() {
+			buf.extend_from_slice(BATCH_MAGIC);
+			buf.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+			buf.extend_from_slice(&payload);
+			buf.extend_from_slice(H::hash(&payload).as_ref());
+		}
+
+		// A stale index must never be used with the new log: remove it first, then
+		// replace the log. Crashing in between leaves a consistent log without index.
+		match fs::remove_file(self.path.join(INDEX_FILE)) {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+			_ => (),
+		}
+		sync_dir(&self.path)?;
+		write_atomically(&self.path, LOG_FILE, &buf)?;
+
+		let log = OpenOptions::new().read(true).write(true).open(self.path.join(LOG_FILE))?;
+		self.log = Mutex::new(log);
+		self.log_len = buf.len() as u64;
+		self.index = new_index;
+		self.write_index()
+	}
+
+	fn read_value(&self, entry: &IndexEntry) -> io::Result<DBValue> {
+		let mut value = vec![0; entry.len as usize];
+		if entry.len > 0 {
+			let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+			log.seek(SeekFrom::Start(entry.offset))?;
+			log.read_exact(&mut value)?;
+		}
+		Ok(value)
+	}
+
+	fn get_value(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+		if key == &self.hashed_null_node {
+			return Some(self.null_node_data.clone())
+		}
+
+		let (pending_value, pending_rc) = match self.overlay.raw(key, prefix) {
+			Some((value, rc)) => (Some(value), rc),
+			None => (None, 0),
+		};
+		let stored = self.index.get(KF::key(key, prefix).as_ref());
+		if pending_rc + stored.map_or(0, |e| e.rc) <= 0 {
+			return None
+		}
+		match (pending_value, stored) {
+			(Some(value), _) if pending_rc > 0 => Some(value.clone()),
+			(_, Some(entry)) if entry.rc > 0 => match self.read_value(entry) {
+				Ok(value) => Some(value),
+				Err(e) => {
+					log::error!(target: "file-db", "Error reading value: {}", e);
+					None
+				},
+			},
+			(Some(value), _) => Some(value.clone()),
+			(None, _) => None,
+		}
+	}
+}
+
+/// Merge an entry in the index the way `MemoryDB::consolidate` merges entries.
+fn apply_entry(index: &mut Index, key: Vec<u8>, new: IndexEntry) {
+	match index.get_mut(&key) {
+		Some(entry) => {
+			if entry.rc < 0 {
+				entry.offset = new.offset;
+				entry.len = new.len;
+			}
+			entry.rc += new.rc;
+			if entry.rc == 0 {
+				index.remove(&key);
+			}
+		},
+		None => {
+			index.insert(key, new);
+		},
+	}
+}
+
+/// Replay the batches from `start`, returning the end offset of the last valid batch.
+fn replay<H: KeyHasher>(
+	log: &mut File,
+	start: u64,
+	file_len: u64,
+	index: &mut Index,
+) -> io::Result<u64> {
+	log.seek(SeekFrom::Start(start))?;
+	let mut reader = BufReader::new(log);
+	let mut pos = start;
+	while let Some(payload) = read_batch::<H, _>(&mut reader, file_len - pos)? {
+		let payload_start = pos + BATCH_HEADER_LEN as u64;
+		match decode_batch(&payload) {
+			Some(entries) =>
+				for BatchEntry { key, offset, len, rc } in entries {
+					let offset = payload_start + offset as u64;
+					apply_entry(index, key.to_vec(), IndexEntry { offset, len, rc });
+				},
+			None => break,
+		}
+		pos = payload_start + payload.len() as u64 + H::LENGTH as u64;
+	}
+	Ok(pos)
+}
+
+/// Read the next batch of at most `remaining` bytes, checking its header and checksum
+/// and returning its payload.
+fn read_batch<H: KeyHasher, R: Read>(
+	reader: &mut R,
+	remaining: u64,
+) -> io::Result<Option<Vec<u8>>> {
+	if remaining < BATCH_HEADER_LEN as u64 {
+		return Ok(None)
+	}
+	let mut header = [0u8; BATCH_HEADER_LEN];
+	reader.read_exact(&mut header)?;
+	if &header[..4] != BATCH_MAGIC {
+		return Ok(None)
+	}
+	let payload_len = u64::from_le_bytes(header[4..].try_into().expect("8 bytes"));
+	let batch_len = (BATCH_HEADER_LEN as u64)
+		.checked_add(payload_len)
+		.and_then(|len| len.checked_add(H::LENGTH as u64));
+	match batch_len {
+		Some(len) if len <= remaining => (),
+		_ => return Ok(None),
+	}
+	let mut payload = vec![0; payload_len as usize];
+	reader.read_exact(&mut payload)?;
+	let mut checksum = vec![0; H::LENGTH];
+	reader.read_exact(&mut checksum)?;
+	if H::hash(&payload).as_ref() != &checksum[..] {
+		return Ok(None)
+	}
+	Ok(Some(payload))
+}
+
+/// Entry of a batch, with the position of its value in the batch payload.
+struct BatchEntry<'a> {
+	key: &'a [u8],
+	offset: usize,
+	len: u32,
+	rc: i32,
+}
+
+/// Decode the entries of a batch payload.
+fn decode_batch(payload: &[u8]) -> Option<Vec<BatchEntry<'_>>> {
+	let mut input = Input(payload, 0);
+	let count = input.u32()?;
+	let mut entries = Vec::new();
+	for _ in 0..count {
+		let key_len = input.u32()? as usize;
+		let key = input.take(key_len)?;
+		let rc = input.u32()? as i32;
+		let len = input.u32()?;
+		let offset = input.1;
+		input.take(len as usize)?;
+		entries.push(BatchEntry { key, offset, len, rc });
+	}
+	Some(entries)
+}
+
+/// Read an index snapshot, returning `None` if missing or invalid.
+fn read_index<H: KeyHasher>(path: &Path) -> io::Result<Option<(Index, u64)>> {
+	let data = match fs::read(path) {
+		Ok(data) => data,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e),
+	};
+	if data.len() < H::LENGTH {
+		return Ok(None)
+	}
+	let (content, checksum) = data.split_at(data.len() - H::LENGTH);
+	if H::hash(content).as_ref() != checksum {
+		log::warn!(target: "file-db", "Ignoring corrupted index");
+		return Ok(None)
+	}
+	let decode = || {
+		let mut input = Input(content, 0);
+		if input.take(4)? != INDEX_MAGIC {
+			return None
+		}
+		let log_offset = input.u64()?;
+		let count = input.u64()?;
+		let mut index = HashMap::new();
+		for _ in 0..count {
+			let key_len = input.u32()? as usize;
+			let key = input.take(key_len)?.to_vec();
+			let offset = input.u64()?;
+			let len = input.u32()?;
+			let rc = input.u32()? as i32;
+			index.insert(key, IndexEntry { offset, len, rc });
+		}
+		Some((index, log_offset))
+	};
+	Ok(decode())
+}
+
+/// Write a file by writing a temporary file then renaming it.
+fn write_atomically(dir: &Path, name: &str, data: &[u8]) -> io::Result<()> {
+	let tmp = dir.join(format!("{}{}", name, TMP_SUFFIX));
+	{
+		let mut file = File::create(&tmp)?;
+		file.write_all(data)?;
+		file.sync_all()?;
+	}
+	fs::rename(&tmp, dir.join(name))?;
+	sync_dir(dir)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+	File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+/// Cursor over a byte slice.
+struct Input<'a>(&'a [u8], usize);
+
+impl<'a> Input<'a> {
+	fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+		let end = self.1.checked_add(len)?;
+		let result = self.0.get(self.1..end)?;
+		self.1 = end;
+		Some(result)
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		self.take(4).map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes")))
+	}
+
+	fn u64(&mut self) -> Option<u64> {
+		self.take(8).map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes")))
+	}
+}
+
+impl<H, KF> HashDB<H, DBValue> for FileDB<H, KF>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: AsRef<[u8]>,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+		self.get_value(key, prefix)
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		self.get_value(key, prefix).is_some()
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		self.overlay.insert(prefix, value)
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: DBValue) {
+		self.overlay.emplace(key, prefix, value)
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		self.overlay.remove(key, prefix)
+	}
+}
+
+impl<H, KF> HashDBRef<H, DBValue> for FileDB<H, KF>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: AsRef<[u8]>,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+		self.get_value(key, prefix)
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		self.get_value(key, prefix).is_some()
+	}
+}
+
+impl<H, KF> AsHashDB<H, DBValue> for FileDB<H, KF>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: AsRef<[u8]>,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<H, DBValue> {
+		self
+	}
+	fn as_hash_db_mut(&mut self) -> &mut dyn HashDB<H, DBValue> {
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{FileDB, INDEX_FILE, LOG_FILE};
+	use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+	use keccak_hasher::KeccakHasher;
+	use memory_db::{HashKey, MemoryDB, PrefixedKey};
+	use reference_trie::{NoExtensionLayout, RefTrieDBMutNoExt};
+	use std::{
+		fs::{File, OpenOptions},
+		io::Write,
+		sync::Mutex,
+	};
+	use trie_db::{Trie, TrieDB, TrieMut};
+
+	type DB = FileDB<KeccakHasher, HashKey<KeccakHasher>>;
+
+	#[test]
+	fn refcount_matches_memorydb() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut db = DB::open(dir.path()).unwrap();
+		let mut memdb = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+
+		let doggo = db.insert(EMPTY_PREFIX, b"doggo");
+		memdb.insert(EMPTY_PREFIX, b"doggo");
+		let arf = db.insert(EMPTY_PREFIX, b"arf");
+		memdb.insert(EMPTY_PREFIX, b"arf");
+		db.commit().unwrap();
+
+		let mut batch = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+		batch.remove(&doggo, EMPTY_PREFIX);
+		batch.insert(EMPTY_PREFIX, b"arf");
+		let negative = KeccakHasher::hash(b"negative");
+		batch.remove(&negative, EMPTY_PREFIX);
+		memdb.consolidate(batch.clone());
+		db.apply(batch).unwrap();
+		memdb.purge();
+
+		for key in [doggo, arf, negative].iter() {
+			assert_eq!(
+				db.raw(key, EMPTY_PREFIX).unwrap().map(|(_, rc)| rc),
+				memdb.raw(key, EMPTY_PREFIX).map(|(_, rc)| rc),
+			);
+			assert_eq!(db.contains(key, EMPTY_PREFIX), memdb.contains(key, EMPTY_PREFIX));
+		}
+
+		// A negative entry gets its value from a later insertion.
+		db.insert(EMPTY_PREFIX, b"negative");
+		db.insert(EMPTY_PREFIX, b"negative");
+		assert_eq!(db.get(&negative, EMPTY_PREFIX), Some(b"negative".to_vec()));
+		db.commit().unwrap();
+		assert_eq!(db.raw(&negative, EMPTY_PREFIX).unwrap(), Some((b"negative".to_vec(), 1)));
+	}
+
+	#[test]
+	fn pending_changes() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut db = DB::open(dir.path()).unwrap();
+		let key = db.insert(EMPTY_PREFIX, b"value");
+		db.commit().unwrap();
+
+		db.remove(&key, EMPTY_PREFIX);
+		assert!(!db.contains(&key, EMPTY_PREFIX));
+		db.revert();
+		assert!(db.contains(&key, EMPTY_PREFIX));
+
+		db.remove(&key, EMPTY_PREFIX);
+		drop(db);
+		let db = DB::open(dir.path()).unwrap();
+		assert!(db.contains(&key, EMPTY_PREFIX));
+	}
+
+	#[test]
+	fn failed_commit_keeps_changes() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut db = DB::open(dir.path()).unwrap();
+		let key = db.insert(EMPTY_PREFIX, b"value");
+
+		// Writing to a read-only handle fails.
+		let log = dir.path().join(LOG_FILE);
+		db.log = Mutex::new(File::open(&log).unwrap());
+		assert!(db.commit().is_err());
+		assert!(db.contains(&key, EMPTY_PREFIX));
+		assert!(db.is_empty());
+
+		db.log = Mutex::new(OpenOptions::new().read(true).write(true).open(&log).unwrap());
+		db.commit().unwrap();
+		drop(db);
+		let db = DB::open(dir.path()).unwrap();
+		assert_eq!(db.get(&key, EMPTY_PREFIX), Some(b"value".to_vec()));
+	}
+
+	#[test]
+	fn recover_torn_write() {
+		let dir = tempfile::tempdir().unwrap();
+		let (first, second) = {
+			let mut db = DB::open(dir.path()).unwrap();
+			let first = db.insert(EMPTY_PREFIX, b"first");
+			db.commit().unwrap();
+			let second = db.insert(EMPTY_PREFIX, b"second");
+			db.commit().unwrap();
+			(first, second)
+		};
+
+		// Cut the last batch.
+		let log = dir.path().join(LOG_FILE);
+		let len = std::fs::metadata(&log).unwrap().len();
+		OpenOptions::new().write(true).open(&log).unwrap().set_len(len - 5).unwrap();
+
+		let mut db = DB::open(dir.path()).unwrap();
+		assert!(db.contains(&first, EMPTY_PREFIX));
+		assert!(!db.contains(&second, EMPTY_PREFIX));
+
+		// Writing after recovery works and garbage at the end is discarded.
+		let third = db.insert(EMPTY_PREFIX, b"third");
+		db.commit().unwrap();
+		drop(db);
+		let len = std::fs::metadata(&log).unwrap().len();
+		OpenOptions::new()
+			.append(true)
+			.open(&log)
+			.unwrap()
+			.write_all(b"FDBBgarbage")
+			.unwrap();
+		let db = DB::open(dir.path()).unwrap();
+		assert!(db.contains(&first, EMPTY_PREFIX));
+		assert!(!db.contains(&second, EMPTY_PREFIX));
+		assert!(db.contains(&third, EMPTY_PREFIX));
+		assert_eq!(std::fs::metadata(&log).unwrap().len(), len);
+	}
+
+	#[test]
+	fn index_and_compaction() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut db = DB::open(dir.path()).unwrap();
+		let keys: Vec<_> = (0u8..16).map(|i| db.insert(EMPTY_PREFIX, &[i; 40])).collect();
+		db.commit().unwrap();
+		db.write_index().unwrap();
+		for key in keys.iter().take(8) {
+			db.remove(key, EMPTY_PREFIX);
+		}
+		db.commit().unwrap();
+		drop(db);
+
+		// Index is used, then the batch written after it is replayed.
+		let mut db = DB::open(dir.path()).unwrap();
+		assert_eq!(db.len(), 8);
+		let len = std::fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+		db.compact().unwrap();
+		assert!(std::fs::metadata(dir.path().join(LOG_FILE)).unwrap().len() < len);
+		assert!(dir.path().join(INDEX_FILE).exists());
+		drop(db);
+
+		let db = DB::open(dir.path()).unwrap();
+		for (i, key) in keys.iter().enumerate() {
+			assert_eq!(
+				db.get(key, EMPTY_PREFIX),
+				if i < 8 { None } else { Some(vec![i as u8; 40]) }
+			);
+		}
+	}
+
+	#[test]
+	fn persist_trie() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut root = Default::default();
+		{
+			let mut db = FileDB::<KeccakHasher, PrefixedKey<_>>::open(dir.path()).unwrap();
+			{
+				let mut t = RefTrieDBMutNoExt::new(&mut db, &mut root);
+				for i in 0u8..100 {
+					t.insert(&[i, i / 10], &[i; 33]).unwrap();
+				}
+			}
+			db.commit().unwrap();
+		}
+
+		let mut db = FileDB::<KeccakHasher, PrefixedKey<_>>::open(dir.path()).unwrap();
+		{
+			let mut t = RefTrieDBMutNoExt::from_existing(&mut db, &mut root);
+			for i in 0u8..50 {
+				t.remove(&[i, i / 10]).unwrap();
+			}
+		}
+		db.commit().unwrap();
+		drop(db);
+
+		let db = FileDB::<KeccakHasher, PrefixedKey<_>>::open(dir.path()).unwrap();
+		let t = TrieDB::<NoExtensionLayout>::new(&db, &root);
+		assert_eq!(t.iter().unwrap().count(), 50);
+		assert_eq!(t.get(&[60, 6]).unwrap(), Some(vec![60; 33]));
+		// Removed nodes are pruned.
+		assert!(db.len() < 100);
+	}
+}
@@ -0,0 +1,392 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `MemoryDB` with a memory budget, flushing its content to a sink when
+//! the budget is exceeded.
+
+use super::{mem, DefaultMemTracker, HashKey, KeyFunction, MemTracker, MemoryDB};
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher as KeyHasher, Prefix, EMPTY_PREFIX};
+
+/// Destination of the entries flushed by a [`BoundedMemoryDB`].
+pub trait FlushSink<H: KeyHasher, KF: KeyFunction<H>, T> {
+	/// Receive an entry together with the number of references to add
+	/// (negative for pending removals).
+	fn flush(&mut self, key: KF::Key, value: T, rc: i32);
+
+	/// Look up a previously flushed value together with its reference count.
+	fn get(&self, key: &KF::Key) -> Option<(T, i32)>;
+}
+
+impl<H, KF, T, M> FlushSink<H, KF, T> for MemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: for<'a> From<&'a [u8]> + Clone,
+	KF: KeyFunction<H>,
+	M: MemTracker<T> + Default,
+{
+	fn flush(&mut self, key: KF::Key, value: T, rc: i32) {
+		self.consolidate_entry(key, value, rc);
+	}
+
+	fn get(&self, key: &KF::Key) -> Option<(T, i32)> {
+		self.data.get(key).map(|(d, rc)| (d.clone(), *rc))
+	}
+}
+
+/// Flush into any `HashDB`, for databases keyed by hash only.
+///
+/// As with `HashKey`, the prefix is not used: entries are written with `EMPTY_PREFIX`.
+/// `HashDB` does not expose reference counts, so a value found in it is read back as
+/// a single reference.
+pub struct HashDBSink<DB>(pub DB);
+
+impl<H, T, DB> FlushSink<H, HashKey<H>, T> for HashDBSink<DB>
+where
+	H: KeyHasher,
+	T: Clone,
+	DB: HashDB<H, T>,
+{
+	fn flush(&mut self, key: H::Out, value: T, rc: i32) {
+		for _ in rc..0 {
+			self.0.remove(&key, EMPTY_PREFIX);
+		}
+		for _ in 0..rc {
+			self.0.emplace(key, EMPTY_PREFIX, value.clone());
+		}
+	}
+
+	fn get(&self, key: &H::Out) -> Option<(T, i32)> {
+		self.0.get(key, EMPTY_PREFIX).map(|value| (value, 1))
+	}
+}
+
+/// Flush through a pair of closures, the first one receiving flushed entries and
+/// the second one reading them back with their reference count.
+pub struct FlushFn<F, G>(pub F, pub G);
+
+impl<H, KF, T, F, G> FlushSink<H, KF, T> for FlushFn<F, G>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	F: FnMut(KF::Key, T, i32),
+	G: Fn(&KF::Key) -> Option<(T, i32)>,
+{
+	fn flush(&mut self, key: KF::Key, value: T, rc: i32) {
+		(self.0)(key, value, rc)
+	}
+
+	fn get(&self, key: &KF::Key) -> Option<(T, i32)> {
+		(self.1)(key)
+	}
+}
+
+/// `MemoryDB` that moves its entries to a [`FlushSink`] whenever the size reported by
+/// its `MemTracker` exceeds a byte budget.
+///
+/// Flushing moves every referenced entry, pending removals (negative reference counts)
+/// included, so the sink has to accept removals of entries it does not hold yet. A value
+/// is found when its reference count in memory plus its reference count in the sink is
+/// positive, as it would be in a single `MemoryDB`.
+///
+/// The budget is only enforced when using a tracker that counts memory (`MemCounter`),
+/// with `NoopTracker` nothing is flushed before `commit`.
+///
+/// # Example
+/// ```rust
+///   use hash_db::{HashDB, EMPTY_PREFIX};
+///   use keccak_hasher::KeccakHasher;
+///   use memory_db::{BoundedMemoryDB, MemoryDB, HashKey};
+///
+///   type DB = MemoryDB<KeccakHasher, HashKey<KeccakHasher>, Vec<u8>>;
+///   let mut db = BoundedMemoryDB::new(DB::default(), DB::default(), 1024);
+///   let keys: Vec<_> = (0u32..64).map(|i| db.insert(EMPTY_PREFIX, &[i as u8; 64])).collect();
+///   assert!(db.memory_db().keys().len() < 64);
+///   assert!(keys.iter().all(|k| db.contains(k, EMPTY_PREFIX)));
+///
+///   let sink = db.commit();
+///   assert_eq!(sink.keys().len(), 64);
+/// ```
+pub struct BoundedMemoryDB<H, KF, T, S, M = DefaultMemTracker<T>>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	M: MemTracker<T>,
+{
+	db: MemoryDB<H, KF, T, M>,
+	sink: S,
+	budget: usize,
+}
+
+impl<H, KF, T, S, M> BoundedMemoryDB<H, KF, T, S, M>
+where
+	H: KeyHasher,
+	T: Default + for<'a> From<&'a [u8]>,
+	KF: KeyFunction<H>,
+	S: FlushSink<H, KF, T>,
+	M: MemTracker<T> + Default,
+{
+	/// Create a new `BoundedMemoryDB` from a memory db, a flush sink and the
+	/// maximum size in bytes of the values kept in memory.
+	pub fn new(db: MemoryDB<H, KF, T, M>, sink: S, budget: usize) -> Self {
+		BoundedMemoryDB { db, sink, budget }
+	}
+
+	/// Access the in-memory layer.
+	pub fn memory_db(&self) -> &MemoryDB<H, KF, T, M> {
+		&self.db
+	}
+
+	/// Access the flush sink.
+	pub fn sink(&self) -> &S {
+		&self.sink
+	}
+
+	/// Maximum size in bytes of the values kept in memory.
+	pub fn budget(&self) -> usize {
+		self.budget
+	}
+
+	/// Change the memory budget, flushing if it is already exceeded.
+	pub fn set_budget(&mut self, budget: usize) {
+		self.budget = budget;
+		self.flush_if_over_budget();
+	}
+
+	/// Move all entries with a non-zero reference count, pending removals included,
+	/// to the sink, and purge zero-referenced entries.
+	pub fn flush(&mut self) {
+		self.db.malloc_tracker.on_clear();
+		for (key, (value, rc)) in mem::take(&mut self.db.data) {
+			if rc != 0 {
+				self.sink.flush(key, value, rc);
+			}
+		}
+	}
+
+	/// Flush all remaining entries to the sink and return it.
+	pub fn commit(mut self) -> S {
+		self.flush();
+		self.sink
+	}
+
+	/// Split into the in-memory layer and the sink, without flushing.
+	pub fn into_inner(self) -> (MemoryDB<H, KF, T, M>, S) {
+		(self.db, self.sink)
+	}
+
+	fn flush_if_over_budget(&mut self) {
+		if self.db.malloc_tracker.get_size() > self.budget {
+			self.flush();
+		}
+	}
+}
+
+impl<H, KF, T, S, M> HashDB<H, T> for BoundedMemoryDB<H, KF, T, S, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	S: FlushSink<H, KF, T> + Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		let memory = self.db.raw(key, prefix);
+		let flushed = self.sink.get(&KF::key(key, prefix));
+		let rc = memory.map_or(0, |(_, rc)| rc) + flushed.as_ref().map_or(0, |(_, rc)| *rc);
+		if rc <= 0 {
+			return None
+		}
+		match memory {
+			Some((value, rc)) if rc > 0 => Some(value.clone()),
+			_ => flushed.map(|(value, _)| value),
+		}
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		HashDB::get(self, key, prefix).is_some()
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: T) {
+		HashDB::emplace(&mut self.db, key, prefix, value);
+		self.flush_if_over_budget();
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		let key = HashDB::insert(&mut self.db, prefix, value);
+		self.flush_if_over_budget();
+		key
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		HashDB::remove(&mut self.db, key, prefix);
+	}
+}
+
+impl<H, KF, T, S, M> HashDBRef<H, T> for BoundedMemoryDB<H, KF, T, S, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	S: FlushSink<H, KF, T> + Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		HashDB::get(self, key, prefix)
+	}
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		HashDB::contains(self, key, prefix)
+	}
+}
+
+impl<H, KF, T, S, M> AsHashDB<H, T> for BoundedMemoryDB<H, KF, T, S, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	S: FlushSink<H, KF, T> + Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<H, T> {
+		self
+	}
+	fn as_hash_db_mut(&mut self) -> &mut dyn HashDB<H, T> {
+		self
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::{BoundedMemoryDB, FlushFn, HashDBSink};
+	use crate::{HashKey, MemoryDB, PrefixedKey};
+	use hash_db::{HashDB, EMPTY_PREFIX};
+	use keccak_hasher::KeccakHasher;
+	use std::{
+		collections::HashMap,
+		sync::{Arc, Mutex},
+	};
+
+	type DB = MemoryDB<KeccakHasher, HashKey<KeccakHasher>, Vec<u8>>;
+
+	#[test]
+	fn flushes_over_budget() {
+		let mut db = BoundedMemoryDB::new(DB::default(), DB::default(), 256);
+		let mut reference = DB::default();
+		let mut keys = Vec::new();
+		for i in 0u8..100 {
+			keys.push(db.insert(EMPTY_PREFIX, &[i; 20]));
+			reference.insert(EMPTY_PREFIX, &[i; 20]);
+		}
+		assert!(!db.sink().keys().is_empty());
+		assert!(db.memory_db().keys().len() < 100);
+		for (i, key) in keys.iter().enumerate() {
+			assert_eq!(db.get(key, EMPTY_PREFIX), Some(vec![i as u8; 20]));
+		}
+
+		// Removing a flushed entry is only written to the sink on the next flush.
+		db.remove(&keys[0], EMPTY_PREFIX);
+		reference.remove(&keys[0], EMPTY_PREFIX);
+		assert!(!db.contains(&keys[0], EMPTY_PREFIX));
+		assert!(db.sink().contains(&keys[0], EMPTY_PREFIX));
+		db.insert(EMPTY_PREFIX, &[1; 20]);
+		reference.insert(EMPTY_PREFIX, &[1; 20]);
+
+		let sink = db.commit();
+		assert_eq!(sink.keys(), reference.keys());
+		assert!(!sink.contains(&keys[0], EMPTY_PREFIX));
+	}
+
+	#[test]
+	fn remove_after_flush() {
+		let mut db = BoundedMemoryDB::new(DB::default(), DB::default(), usize::MAX);
+		let mut reference = DB::default();
+		let key = db.insert(EMPTY_PREFIX, b"value");
+		reference.insert(EMPTY_PREFIX, b"value");
+		db.flush();
+		db.remove(&key, EMPTY_PREFIX);
+		reference.remove(&key, EMPTY_PREFIX);
+		assert_eq!(db.get(&key, EMPTY_PREFIX), reference.get(&key, EMPTY_PREFIX));
+		assert_eq!(db.get(&key, EMPTY_PREFIX), None);
+
+		db.insert(EMPTY_PREFIX, b"value");
+		reference.insert(EMPTY_PREFIX, b"value");
+		assert_eq!(db.get(&key, EMPTY_PREFIX), Some(b"value".to_vec()));
+		assert_eq!(db.commit().keys(), reference.keys());
+	}
+
+	#[test]
+	fn remove_with_references_in_sink() {
+		let mut db = BoundedMemoryDB::new(DB::default(), DB::default(), usize::MAX);
+		let key = db.insert(EMPTY_PREFIX, b"value");
+		db.flush();
+		db.insert(EMPTY_PREFIX, b"value");
+		db.flush();
+		db.remove(&key, EMPTY_PREFIX);
+		assert_eq!(db.memory_db().raw(&key, EMPTY_PREFIX).map(|(_, rc)| rc), Some(-1));
+		assert_eq!(db.get(&key, EMPTY_PREFIX), Some(b"value".to_vec()));
+		db.remove(&key, EMPTY_PREFIX);
+		assert_eq!(db.get(&key, EMPTY_PREFIX), None);
+	}
+
+	#[test]
+	fn flushes_removals() {
+		let mut db = BoundedMemoryDB::new(DB::default(), DB::default(), usize::MAX);
+		let key = db.insert(EMPTY_PREFIX, b"value");
+		db.flush();
+		db.remove(&key, EMPTY_PREFIX);
+		db.remove(&key, EMPTY_PREFIX);
+		db.flush();
+		assert!(db.memory_db().keys().is_empty());
+		assert_eq!(db.sink().raw(&key, EMPTY_PREFIX).map(|(_, rc)| rc), Some(-1));
+		assert!(!db.contains(&key, EMPTY_PREFIX));
+		db.insert(EMPTY_PREFIX, b"value");
+		assert!(!db.contains(&key, EMPTY_PREFIX));
+		db.insert(EMPTY_PREFIX, b"value");
+		assert_eq!(db.get(&key, EMPTY_PREFIX), Some(b"value".to_vec()));
+	}
+
+	#[test]
+	fn hash_db_sink() {
+		let mut db = BoundedMemoryDB::new(DB::default(), HashDBSink(DB::default()), 0);
+		let key = db.insert(EMPTY_PREFIX, b"value");
+		db.insert(EMPTY_PREFIX, b"value");
+		assert!(db.memory_db().keys().is_empty());
+		assert_eq!(db.sink().0.raw(&key, EMPTY_PREFIX), Some((&b"value".to_vec(), 2)));
+		db.remove(&key, EMPTY_PREFIX);
+		db.remove(&key, EMPTY_PREFIX);
+		assert!(!db.contains(&key, EMPTY_PREFIX));
+		let sink = db.commit();
+		assert!(!sink.0.contains(&key, EMPTY_PREFIX));
+	}
+
+	#[test]
+	fn closure_sink() {
+		let store = Arc::new(Mutex::new(HashMap::new()));
+		let (write, read) = (store.clone(), store.clone());
+		let sink = FlushFn(
+			move |key: Vec<u8>, value: Vec<u8>, rc: i32| {
+				write.lock().unwrap().insert(key, (value, rc));
+			},
+			move |key: &Vec<u8>| read.lock().unwrap().get(key).cloned(),
+		);
+		let mut db = BoundedMemoryDB::<_, PrefixedKey<KeccakHasher>, _, _>::new(
+			MemoryDB::default(),
+			sink,
+			0,
+		);
+		let key = HashDB::insert(&mut db, (&[0x12][..], Some(0x30)), b"value");
+		assert_eq!(store.lock().unwrap().len(), 1);
+		assert_eq!(HashDB::get(&db, &key, (&[0x12][..], Some(0x30))), Some(b"value".to_vec()));
+		assert_eq!(HashDB::get(&db, &key, EMPTY_PREFIX), None);
+	}
+}
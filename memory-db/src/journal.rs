@@ -0,0 +1,381 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `MemoryDB` keeping removals in a per-block journal, so that recent states
+//! stay available until they are deep enough to be pruned.
+
+use super::{BTreeMap, DefaultMemTracker, KeyFunction, MemTracker, MemoryDB};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher as KeyHasher, Prefix};
+
+/// Error returned by [`JournalDB::mark_canonical`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalError {
+	/// The era is less than `history_size` blocks behind the latest journaled era.
+	NotDeepEnough,
+	/// An older era is still in the journal and must be canonicalized first.
+	OutOfOrder,
+	/// No block with this id was journaled under this era.
+	UnknownBlock,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for JournalError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			JournalError::NotDeepEnough => write!(f, "Era is still within the pruning window"),
+			JournalError::OutOfOrder => write!(f, "An older era must be canonicalized first"),
+			JournalError::UnknownBlock => write!(f, "No such block in journal"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for JournalError {}
+
+/// Changes made by one block.
+struct JournalRecord<K, I> {
+	id: I,
+	inserts: Vec<(K, i32)>,
+	removals: Vec<(K, i32)>,
+}
+
+/// Journal records, by era.
+type Journal<K, I> = BTreeMap<u64, Vec<JournalRecord<K, I>>>;
+
+/// Reference-counted database with a delayed pruning window.
+///
+/// Changes are first written to an overlay through the `HashDB` interface (typically by
+/// `TrieDBMut::commit`). [`JournalDB::journal_under`] then records them under a block
+/// number (era) and block id: insertions are applied to the backing `MemoryDB` immediately,
+/// while removals only go to the journal. Several blocks can be journaled under the same era
+/// when the chain forks.
+///
+/// Once an era is at least `history_size` blocks behind the latest journaled era,
+/// [`JournalDB::mark_canonical`] applies the removals of the canonical block and reverts the
+/// insertions of all the other blocks of that era. Until then, the states of every journaled
+/// block remain readable.
+///
+/// # Example
+/// ```rust
+///   use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+///   use keccak_hasher::KeccakHasher;
+///   use memory_db::{JournalDB, MemoryDB, HashKey};
+///
+///   type DB = MemoryDB<KeccakHasher, HashKey<KeccakHasher>, Vec<u8>>;
+///   let mut db = JournalDB::new(DB::default(), 1);
+///   let old = db.insert(EMPTY_PREFIX, b"old");
+///   db.journal_under(0, KeccakHasher::hash(b"block0"));
+///
+///   db.remove(&old, EMPTY_PREFIX);
+///   db.journal_under(1, KeccakHasher::hash(b"block1"));
+///   assert!(db.contains(&old, EMPTY_PREFIX));
+///
+///   db.mark_canonical(0, &KeccakHasher::hash(b"block0")).unwrap();
+///   db.mark_canonical(1, &KeccakHasher::hash(b"block1")).unwrap_err();
+///   db.journal_under(2, KeccakHasher::hash(b"block2"));
+///   db.mark_canonical(1, &KeccakHasher::hash(b"block1")).unwrap();
+///   assert!(!db.contains(&old, EMPTY_PREFIX));
+/// ```
+pub struct JournalDB<H, KF, T, M = DefaultMemTracker<T>>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	M: MemTracker<T>,
+{
+	backing: MemoryDB<H, KF, T, M>,
+	overlay: MemoryDB<H, KF, T, M>,
+	journal: Journal<KF::Key, H::Out>,
+	history_size: u64,
+	latest_era: Option<u64>,
+}
+
+impl<H, KF, T, M> JournalDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + for<'a> From<&'a [u8]> + Clone,
+	KF: KeyFunction<H>,
+	M: MemTracker<T> + Default,
+{
+	/// Create a new `JournalDB` over a backing database, keeping removals for
+	/// `history_size` blocks.
+	pub fn new(backing: MemoryDB<H, KF, T, M>, history_size: u64) -> Self {
+		let overlay = MemoryDB {
+			data: Default::default(),
+			malloc_tracker: M::default(),
+			hashed_null_node: backing.hashed_null_node,
+			null_node_data: backing.null_node_data.clone(),
+			_kf: Default::default(),
+		};
+		JournalDB { backing, overlay, journal: BTreeMap::new(), history_size, latest_era: None }
+	}
+
+	/// Access the backing database.
+	pub fn backing(&self) -> &MemoryDB<H, KF, T, M> {
+		&self.backing
+	}
+
+	/// Access the changes not journaled yet.
+	pub fn overlay(&self) -> &MemoryDB<H, KF, T, M> {
+		&self.overlay
+	}
+
+	/// Number of blocks removals are kept for.
+	pub fn history_size(&self) -> u64 {
+		self.history_size
+	}
+
+	/// Oldest era still in the journal.
+	pub fn earliest_era(&self) -> Option<u64> {
+		self.journal.keys().next().cloned()
+	}
+
+	/// Latest era passed to `journal_under`.
+	pub fn latest_era(&self) -> Option<u64> {
+		self.latest_era
+	}
+
+	/// Ids of the blocks journaled under `era`.
+	pub fn journaled(&self, era: u64) -> Vec<H::Out> {
+		self.journal
+			.get(&era)
+			.map(|records| records.iter().map(|r| r.id).collect())
+			.unwrap_or_default()
+	}
+
+	/// Discard the changes not journaled yet.
+	pub fn revert(&mut self) {
+		self.overlay.clear();
+	}
+
+	/// Record the pending changes as the block `id` at `era`. Insertions are applied to the
+	/// backing database, removals are delayed until the era is canonicalized.
+	pub fn journal_under(&mut self, era: u64, id: H::Out) {
+		let mut record = JournalRecord { id, inserts: Vec::new(), removals: Vec::new() };
+		for (key, (value, rc)) in self.overlay.drain() {
+			if rc > 0 {
+				self.backing.consolidate_entry(key.clone(), value, rc);
+				record.inserts.push((key, rc));
+			} else if rc < 0 {
+				record.removals.push((key, -rc));
+			}
+		}
+		self.journal.entry(era).or_default().push(record);
+		self.latest_era = Some(self.latest_era.map_or(era, |latest| latest.max(era)));
+	}
+
+	/// Make block `id` the canonical block of `era`: its removals are applied and the other
+	/// blocks journaled under `era` are reverted.
+	///
+	/// Eras must be canonicalized in order, and only once they are `history_size` blocks
+	/// behind the latest journaled era.
+	pub fn mark_canonical(&mut self, era: u64, id: &H::Out) -> Result<(), JournalError> {
+		if self.journal.range(..era).next().is_some() {
+			return Err(JournalError::OutOfOrder)
+		}
+		match self.latest_era {
+			Some(latest) if era.saturating_add(self.history_size) <= latest => (),
+			_ => return Err(JournalError::NotDeepEnough),
+		}
+		if !matches!(self.journal.get(&era), Some(records) if records.iter().any(|r| r.id == *id)) {
+			return Err(JournalError::UnknownBlock)
+		}
+
+		for record in self.journal.remove(&era).unwrap_or_default() {
+			let released = if record.id == *id { record.removals } else { record.inserts };
+			for (key, rc) in released {
+				self.release(key, rc);
+			}
+		}
+		Ok(())
+	}
+
+	/// Revert a block that was journaled but not canonicalized yet, undoing its insertions.
+	///
+	/// Returns `false` if no such block is in the journal.
+	pub fn revert_block(&mut self, era: u64, id: &H::Out) -> bool {
+		let records = match self.journal.get_mut(&era) {
+			Some(records) => records,
+			None => return false,
+		};
+		let record = match records.iter().position(|r| r.id == *id) {
+			Some(index) => records.remove(index),
+			None => return false,
+		};
+		if records.is_empty() {
+			self.journal.remove(&era);
+		}
+		for (key, rc) in record.inserts {
+			self.release(key, rc);
+		}
+		true
+	}
+
+	/// Remove `rc` references from a backing entry, purging it when none are left.
+	fn release(&mut self, key: KF::Key, rc: i32) {
+		self.backing.consolidate_entry(key.clone(), T::default(), -rc);
+		if let Some((value, 0)) = self.backing.data.get(&key) {
+			self.backing.malloc_tracker.on_remove(value);
+			self.backing.data.remove(&key);
+		}
+	}
+}
+
+impl<H, KF, T, M> HashDB<H, T> for JournalDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		match self.overlay.raw(key, prefix) {
+			Some((value, rc)) if rc > 0 => Some(value.clone()),
+			_ => HashDB::get(&self.backing, key, prefix),
+		}
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		HashDB::get(self, key, prefix).is_some()
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: T) {
+		HashDB::emplace(&mut self.overlay, key, prefix, value)
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		HashDB::insert(&mut self.overlay, prefix, value)
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		HashDB::remove(&mut self.overlay, key, prefix)
+	}
+}
+
+impl<H, KF, T, M> HashDBRef<H, T> for JournalDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		HashDB::get(self, key, prefix)
+	}
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		HashDB::contains(self, key, prefix)
+	}
+}
+
+impl<H, KF, T, M> AsHashDB<H, T> for JournalDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	KF::Key: Send + Sync,
+	M: MemTracker<T> + Default + Send + Sync,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<H, T> {
+		self
+	}
+	fn as_hash_db_mut(&mut self) -> &mut dyn HashDB<H, T> {
+		self
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::{JournalDB, JournalError};
+	use crate::{HashKey, MemoryDB};
+	use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+	use keccak_hasher::KeccakHasher;
+
+	type DB = MemoryDB<KeccakHasher, HashKey<KeccakHasher>, Vec<u8>>;
+
+	fn id(n: u8) -> <KeccakHasher as Hasher>::Out {
+		KeccakHasher::hash(&[n])
+	}
+
+	#[test]
+	fn removals_are_delayed() {
+		let mut db = JournalDB::new(DB::default(), 2);
+		let a = db.insert(EMPTY_PREFIX, b"a");
+		db.journal_under(0, id(0));
+		assert!(db.overlay().keys().is_empty());
+
+		db.remove(&a, EMPTY_PREFIX);
+		let b = db.insert(EMPTY_PREFIX, b"b");
+		db.journal_under(1, id(1));
+		assert!(db.contains(&a, EMPTY_PREFIX));
+		assert!(db.contains(&b, EMPTY_PREFIX));
+
+		db.journal_under(2, id(2));
+		assert_eq!(db.mark_canonical(1, &id(1)), Err(JournalError::OutOfOrder));
+		assert_eq!(db.mark_canonical(0, &id(9)), Err(JournalError::UnknownBlock));
+		db.mark_canonical(0, &id(0)).unwrap();
+		assert_eq!(db.mark_canonical(1, &id(1)), Err(JournalError::NotDeepEnough));
+		assert!(db.contains(&a, EMPTY_PREFIX));
+
+		db.journal_under(3, id(3));
+		db.mark_canonical(1, &id(1)).unwrap();
+		assert!(!db.contains(&a, EMPTY_PREFIX));
+		assert!(db.contains(&b, EMPTY_PREFIX));
+		assert_eq!(db.backing().keys().len(), 1);
+		assert_eq!(db.earliest_era(), Some(2));
+	}
+
+	#[test]
+	fn non_canonical_blocks_are_reverted() {
+		let mut db = JournalDB::new(DB::default(), 1);
+		let shared = db.insert(EMPTY_PREFIX, b"shared");
+		db.journal_under(0, id(0));
+
+		// Two competing blocks at era 1, both re-inserting the shared value.
+		db.insert(EMPTY_PREFIX, b"shared");
+		let fork_a = db.insert(EMPTY_PREFIX, b"fork a");
+		db.journal_under(1, id(1));
+		db.remove(&shared, EMPTY_PREFIX);
+		let fork_b = db.insert(EMPTY_PREFIX, b"fork b");
+		db.journal_under(1, id(2));
+		assert_eq!(db.journaled(1), vec![id(1), id(2)]);
+		assert_eq!(db.backing().raw(&shared, EMPTY_PREFIX).unwrap().1, 2);
+
+		db.journal_under(2, id(3));
+		db.mark_canonical(0, &id(0)).unwrap();
+		db.mark_canonical(1, &id(2)).unwrap();
+		assert!(!db.contains(&fork_a, EMPTY_PREFIX));
+		assert!(db.contains(&fork_b, EMPTY_PREFIX));
+		assert!(!db.contains(&shared, EMPTY_PREFIX));
+		assert!(db.backing().raw(&shared, EMPTY_PREFIX).is_none());
+	}
+
+	#[test]
+	fn revert_block_and_overlay() {
+		let mut db = JournalDB::new(DB::default(), 4);
+		let a = db.insert(EMPTY_PREFIX, b"a");
+		db.journal_under(0, id(0));
+		assert!(!db.revert_block(0, &id(1)));
+		assert!(db.revert_block(0, &id(0)));
+		assert!(!db.contains(&a, EMPTY_PREFIX));
+		assert_eq!(db.earliest_era(), None);
+
+		let b = db.insert(EMPTY_PREFIX, b"b");
+		db.revert();
+		assert!(!db.contains(&b, EMPTY_PREFIX));
+		assert!(db.backing().keys().is_empty());
+	}
+}
@@ -21,6 +21,16 @@ extern crate alloc;
 
 mod malloc_size_of;
 pub use malloc_size_of::*;
+mod bounded;
+mod journal;
+mod storage;
+pub use bounded::{BoundedMemoryDB, FlushFn, FlushSink, HashDBSink};
+pub use journal::{JournalDB, JournalError};
+pub use storage::Storage;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+pub use sharded::{ShardedMemoryDB, DEFAULT_SHARDS, MAX_SHARDS};
 
 use hash_db::{
 	AsHashDB, AsPlainDB, HashDB, HashDBRef, Hasher as KeyHasher, PlainDB, PlainDBRef, Prefix,
@@ -28,18 +38,32 @@ use hash_db::{
 use parity_util_mem::{MallocShallowSizeOf, MallocSizeOf, MallocSizeOfOps};
 #[cfg(feature = "std")]
 use std::{
-	borrow::Borrow, cmp::Eq, collections::hash_map::Entry, collections::HashMap, hash,
-	marker::PhantomData, mem,
+	borrow::Borrow,
+	cmp::Eq,
+	collections::{
+		btree_map,
+		hash_map::{self, Entry},
+		BTreeMap, HashMap,
+	},
+	hash,
+	marker::PhantomData,
+	mem,
 };
 
 #[cfg(not(feature = "std"))]
-use hashbrown::{hash_map::Entry, HashMap};
+use hashbrown::{
+	hash_map::{self, Entry},
+	HashMap,
+};
 
 #[cfg(not(feature = "std"))]
 use core::{borrow::Borrow, cmp::Eq, hash, marker::PhantomData, mem};
 
 #[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
+use alloc::{
+	collections::{btree_map, BTreeMap},
+	vec::Vec,
+};
 
 #[cfg(feature = "std")]
 pub trait MaybeDebug: std::fmt::Debug {}
@@ -67,6 +91,10 @@ pub type DefaultMemTracker<T> = NoopTracker<T>;
 /// If you're not using the `MallocSizeOf` implementation to track memory usage,
 /// set the `M` type parameter to `NoopTracker`.
 ///
+/// Content is stored in a `HashMap` by default. The `S` type parameter allows using
+/// another [`Storage`], such as `BTreeMap` (see [`OrderedMemoryDB`]) to get a deterministic
+/// iteration order.
+///
 /// # Example
 /// ```rust
 ///   use hash_db::{Hasher, HashDB, EMPTY_PREFIX};
@@ -102,13 +130,18 @@ pub type DefaultMemTracker<T> = NoopTracker<T>;
 ///   m.remove(&k, EMPTY_PREFIX);
 ///   assert!(!m.contains(&k, EMPTY_PREFIX));
 /// ```
-pub struct MemoryDB<H, KF, T, M = DefaultMemTracker<T>>
-where
+pub struct MemoryDB<
+	H,
+	KF,
+	T,
+	M = DefaultMemTracker<T>,
+	S = HashMap<<KF as KeyFunction<H>>::Key, (T, i32)>,
+> where
 	H: KeyHasher,
 	KF: KeyFunction<H>,
 	M: MemTracker<T>,
 {
-	data: HashMap<KF::Key, (T, i32)>,
+	data: S,
 	// We cache `size_of(data) - shallow_size_of(data)` to compute
 	// `size_of(data)` incrementally and avoid iterating over the `data`.
 	malloc_tracker: M,
@@ -117,12 +150,13 @@ where
 	_kf: PhantomData<KF>,
 }
 
-impl<H, KF, T, M> Clone for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> Clone for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	KF: KeyFunction<H>,
 	T: Clone,
 	M: MemTracker<T> + Copy,
+	S: Clone,
 {
 	fn clone(&self) -> Self {
 		Self {
@@ -135,17 +169,18 @@ where
 	}
 }
 
-impl<H, KF, T, M> PartialEq<MemoryDB<H, KF, T, M>> for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> PartialEq<MemoryDB<H, KF, T, M, S>> for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	KF: KeyFunction<H>,
 	<KF as KeyFunction<H>>::Key: Eq + MaybeDebug,
 	T: Eq + MaybeDebug,
 	M: MemTracker<T> + PartialEq,
+	S: Storage<KF::Key, (T, i32)>,
 {
-	fn eq(&self, other: &MemoryDB<H, KF, T, M>) -> bool {
+	fn eq(&self, other: &MemoryDB<H, KF, T, M, S>) -> bool {
 		for a in self.data.iter() {
-			match other.data.get(&a.0) {
+			match other.data.get(a.0) {
 				Some(v) if v != a.1 => return false,
 				None => return false,
 				_ => (),
@@ -155,13 +190,14 @@ where
 	}
 }
 
-impl<H, KF, T, M> Eq for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> Eq for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	KF: KeyFunction<H>,
 	<KF as KeyFunction<H>>::Key: Eq + MaybeDebug,
 	T: Eq + MaybeDebug,
 	M: MemTracker<T> + Eq,
+	S: Storage<KF::Key, (T, i32)>,
 {
 }
 
@@ -270,12 +306,13 @@ pub fn legacy_prefixed_key<H: KeyHasher>(key: &H::Out, prefix: Prefix) -> Vec<u8
 	prefixed_key
 }
 
-impl<H, KF, T, M> Default for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> Default for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: for<'a> From<&'a [u8]>,
 	KF: KeyFunction<H>,
 	M: MemTracker<T> + Default,
+	S: Storage<KF::Key, (T, i32)>,
 {
 	fn default() -> Self {
 		Self::from_null_node(&[0u8][..], [0u8][..].into())
@@ -283,12 +320,13 @@ where
 }
 
 /// Create a new `MemoryDB` from a given null key/data
-impl<H, KF, T, M> MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: Default,
 	KF: KeyFunction<H>,
 	M: MemTracker<T>,
+	S: Storage<KF::Key, (T, i32)>,
 {
 	/// Remove an element and delete it from storage if reference count reaches zero.
 	/// If the value was purged, return the old value.
@@ -297,20 +335,20 @@ where
 			return None
 		}
 		let key = KF::key(key, prefix);
-		match self.data.entry(key) {
-			Entry::Occupied(mut entry) =>
-				if entry.get().1 == 1 {
-					let (value, _) = entry.remove();
-					self.malloc_tracker.on_remove(&value);
-					Some(value)
-				} else {
-					entry.get_mut().1 -= 1;
-					None
-				},
-			Entry::Vacant(entry) => {
+		match self.data.get_mut(&key) {
+			Some((_, rc)) if *rc != 1 => {
+				*rc -= 1;
+				None
+			},
+			Some(_) => {
+				let (value, _) = self.data.remove(&key).expect("Entry checked above");
+				self.malloc_tracker.on_remove(&value);
+				Some(value)
+			},
+			None => {
 				let value = T::default();
 				self.malloc_tracker.on_insert(&value);
-				entry.insert((value, -1));
+				self.data.upsert(key, |_| Some((value, -1)));
 				None
 			},
 		}
@@ -325,17 +363,18 @@ where
 	}
 }
 
-impl<H, KF, T, M> MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: for<'a> From<&'a [u8]>,
 	KF: KeyFunction<H>,
 	M: MemTracker<T> + Default,
+	S: Storage<KF::Key, (T, i32)>,
 {
 	/// Create a new `MemoryDB` from a given null key/data
 	pub fn from_null_node(null_key: &[u8], null_node_data: T) -> Self {
 		MemoryDB {
-			data: HashMap::default(),
+			data: S::default(),
 			hashed_null_node: H::hash(null_key),
 			null_node_data,
 			malloc_tracker: M::default(),
@@ -394,8 +433,8 @@ where
 		});
 	}
 
-	/// Return the internal key-value HashMap, clearing the current state.
-	pub fn drain(&mut self) -> HashMap<KF::Key, (T, i32)> {
+	/// Return the internal key-value storage, clearing the current state.
+	pub fn drain(&mut self) -> S {
 		self.malloc_tracker.on_clear();
 		mem::take(&mut self.data)
 	}
@@ -413,26 +452,37 @@ where
 	}
 
 	/// Consolidate all the entries of `other` into `self`.
-	pub fn consolidate(&mut self, mut other: Self) {
+	pub fn consolidate(&mut self, mut other: Self)
+	where
+		S: IntoIterator<Item = (KF::Key, (T, i32))>,
+	{
 		for (key, (value, rc)) in other.drain() {
-			match self.data.entry(key) {
-				Entry::Occupied(mut entry) => {
-					if entry.get().1 < 0 {
-						self.malloc_tracker.on_insert(&value);
-						self.malloc_tracker.on_remove(&entry.get().0);
-						entry.get_mut().0 = value;
-					}
-
-					entry.get_mut().1 += rc;
-				},
-				Entry::Vacant(entry) => {
-					self.malloc_tracker.on_insert(&value);
-					entry.insert((value, rc));
-				},
-			}
+			self.consolidate_entry(key, value, rc);
 		}
 	}
 
+	/// Add `rc` references to `key`, replacing its value if the current
+	/// reference count is negative.
+	pub(crate) fn consolidate_entry(&mut self, key: KF::Key, value: T, rc: i32) {
+		let malloc_tracker = &mut self.malloc_tracker;
+		self.data.upsert(key, |entry| match entry {
+			Some((old_value, old_rc)) => {
+				if *old_rc < 0 {
+					malloc_tracker.on_insert(&value);
+					malloc_tracker.on_remove(old_value);
+					*old_value = value;
+				}
+
+				*old_rc += rc;
+				None
+			},
+			None => {
+				malloc_tracker.on_insert(&value);
+				Some((value, rc))
+			},
+		});
+	}
+
 	/// Get the keys in the database together with number of underlying references.
 	pub fn keys(&self) -> HashMap<KF::Key, i32> {
 		self.data
@@ -440,9 +490,52 @@ where
 			.filter_map(|(k, v)| if v.1 != 0 { Some((k.clone(), v.1)) } else { None })
 			.collect()
 	}
+
+	/// Get the keys in the database together with number of underlying references,
+	/// ordered by key.
+	pub fn sorted_keys(&self) -> BTreeMap<KF::Key, i32>
+	where
+		KF::Key: Ord,
+	{
+		self.data
+			.iter()
+			.filter_map(|(k, v)| if v.1 != 0 { Some((k.clone(), v.1)) } else { None })
+			.collect()
+	}
+
+	/// Iterate over all entries and their number of references, ordered by key.
+	///
+	/// With `PrefixedKey` entries are ordered by prefix then hash, with `HashKey` by hash. The
+	/// entries of an unordered storage are collected and sorted first.
+	pub fn iter_sorted<'a>(&'a self) -> impl Iterator<Item = (&'a KF::Key, &'a T, i32)>
+	where
+		KF::Key: Ord,
+	{
+		let entry = |(k, (v, rc)): (&'a KF::Key, &'a (T, i32))| (k, v, *rc);
+		let mut sorted = Vec::new();
+		if !S::ORDERED {
+			sorted = self.data.iter().map(entry).collect();
+			sorted.sort_unstable_by(|a, b| a.0.cmp(b.0));
+		}
+		let ordered = if S::ORDERED { Some(self.data.iter().map(entry)) } else { None };
+		ordered.into_iter().flatten().chain(sorted)
+	}
+
+	/// Return the content ordered by key, clearing the current state.
+	pub fn drain_sorted(&mut self) -> BTreeMap<KF::Key, (T, i32)>
+	where
+		KF::Key: Ord,
+		S: IntoIterator<Item = (KF::Key, (T, i32))>,
+	{
+		self.drain().into_iter().collect()
+	}
 }
 
-impl<H, KF, T, M> MallocSizeOf for MemoryDB<H, KF, T, M>
+/// `MemoryDB` stored in a `BTreeMap`, iterating and draining in key order.
+pub type OrderedMemoryDB<H, KF, T, M = DefaultMemTracker<T>> =
+	MemoryDB<H, KF, T, M, BTreeMap<<KF as KeyFunction<H>>::Key, (T, i32)>>;
+
+impl<H, KF, T, M, S> MallocSizeOf for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	H::Out: MallocSizeOf,
@@ -450,6 +543,7 @@ where
 	KF: KeyFunction<H>,
 	KF::Key: MallocSizeOf,
 	M: MemTracker<T>,
+	S: MallocShallowSizeOf,
 {
 	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
 		self.data.shallow_size_of(ops) +
@@ -530,12 +624,13 @@ where
 	}
 }
 
-impl<H, KF, T, M> HashDB<H, T> for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> HashDB<H, T> for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
 	KF: KeyFunction<H> + Send + Sync,
 	M: MemTracker<T> + Send + Sync,
+	S: Storage<KF::Key, (T, i32)> + Send + Sync,
 {
 	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
 		if key == &self.hashed_null_node {
@@ -567,21 +662,22 @@ where
 		}
 
 		let key = KF::key(&key, prefix);
-		match self.data.entry(key) {
-			Entry::Occupied(mut entry) => {
-				let &mut (ref mut old_value, ref mut rc) = entry.get_mut();
+		let malloc_tracker = &mut self.malloc_tracker;
+		self.data.upsert(key, |entry| match entry {
+			Some((old_value, rc)) => {
 				if *rc <= 0 {
-					self.malloc_tracker.on_insert(&value);
-					self.malloc_tracker.on_remove(old_value);
+					malloc_tracker.on_insert(&value);
+					malloc_tracker.on_remove(old_value);
 					*old_value = value;
 				}
 				*rc += 1;
+				None
 			},
-			Entry::Vacant(entry) => {
-				self.malloc_tracker.on_insert(&value);
-				entry.insert((value, 1));
+			None => {
+				malloc_tracker.on_insert(&value);
+				Some((value, 1))
 			},
-		}
+		});
 	}
 
 	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
@@ -600,26 +696,28 @@ where
 		}
 
 		let key = KF::key(key, prefix);
-		match self.data.entry(key) {
-			Entry::Occupied(mut entry) => {
-				let &mut (_, ref mut rc) = entry.get_mut();
+		let malloc_tracker = &mut self.malloc_tracker;
+		self.data.upsert(key, |entry| match entry {
+			Some((_, rc)) => {
 				*rc -= 1;
+				None
 			},
-			Entry::Vacant(entry) => {
+			None => {
 				let value = T::default();
-				self.malloc_tracker.on_insert(&value);
-				entry.insert((value, -1));
+				malloc_tracker.on_insert(&value);
+				Some((value, -1))
 			},
-		}
+		});
 	}
 }
 
-impl<H, KF, T, M> HashDBRef<H, T> for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> HashDBRef<H, T> for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
 	KF: KeyFunction<H> + Send + Sync,
 	M: MemTracker<T> + Send + Sync,
+	S: Storage<KF::Key, (T, i32)> + Send + Sync,
 {
 	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
 		HashDB::get(self, key, prefix)
@@ -645,12 +743,13 @@ where
 	}
 }
 
-impl<H, KF, T, M> AsHashDB<H, T> for MemoryDB<H, KF, T, M>
+impl<H, KF, T, M, S> AsHashDB<H, T> for MemoryDB<H, KF, T, M, S>
 where
 	H: KeyHasher,
 	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
 	KF: KeyFunction<H> + Send + Sync,
 	M: MemTracker<T> + Send + Sync,
+	S: Storage<KF::Key, (T, i32)> + Send + Sync,
 {
 	fn as_hash_db(&self) -> &dyn HashDB<H, T> {
 		self
@@ -662,7 +761,7 @@ where
 
 #[cfg(test)]
 mod tests {
-	use super::{HashDB, HashKey, KeyHasher, MemoryDB};
+	use super::{HashDB, HashKey, KeyHasher, MemoryDB, OrderedMemoryDB, PrefixedKey};
 	use hash_db::EMPTY_PREFIX;
 	use keccak_hasher::KeccakHasher;
 	use parity_util_mem::malloc_size;
@@ -744,4 +843,36 @@ mod tests {
 				malloc_size(&db.hashed_null_node)
 		);
 	}
+
+	#[test]
+	fn ordered_storage() {
+		let mut db = OrderedMemoryDB::<KeccakHasher, PrefixedKey<_>, Vec<u8>>::default();
+		let mut reference = MemoryDB::<KeccakHasher, PrefixedKey<_>, Vec<u8>>::default();
+		for i in (0u32..256).rev() {
+			let bytes = i.to_be_bytes();
+			let prefix = (&bytes[2..], None);
+			db.insert(prefix, &bytes);
+			reference.insert(prefix, &bytes);
+			if i % 3 == 0 {
+				let key = KeccakHasher::hash(&bytes);
+				db.remove(&key, prefix);
+				reference.remove(&key, prefix);
+			}
+		}
+		db.remove_and_purge(&KeccakHasher::hash(&1u32.to_be_bytes()), (&[0, 1], None));
+		reference.remove_and_purge(&KeccakHasher::hash(&1u32.to_be_bytes()), (&[0, 1], None));
+
+		let keys: Vec<_> = db.iter_sorted().map(|(k, _, _)| k.clone()).collect();
+		assert!(keys.windows(2).all(|w| w[0] < w[1]));
+		assert_eq!(keys, reference.iter_sorted().map(|(k, _, _)| k.clone()).collect::<Vec<_>>());
+		assert_eq!(db.sorted_keys(), reference.sorted_keys());
+		assert_eq!(
+			db.sorted_keys().into_iter().collect::<std::collections::HashMap<_, _>>(),
+			reference.keys(),
+		);
+
+		let exported = reference.drain_sorted();
+		assert_eq!(db.drain(), exported);
+		assert!(reference.keys().is_empty());
+	}
 }
@@ -0,0 +1,612 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Thread-safe reference-counted memory-based `HashDB` implementation.
+
+use crate::{DefaultMemTracker, KeyFunction, MemTracker, MemoryDB};
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher as KeyHasher, Prefix};
+use parity_util_mem::{MallocShallowSizeOf, MallocSizeOf, MallocSizeOfOps};
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	marker::PhantomData,
+	mem,
+	sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Default number of shards of a [`ShardedMemoryDB`].
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Maximum number of shards of a [`ShardedMemoryDB`]: shards are selected
+/// by the first byte of the hash.
+pub const MAX_SHARDS: usize = 256;
+
+struct Shard<K, T, M> {
+	data: HashMap<K, (T, i32)>,
+	malloc_tracker: M,
+}
+
+/// Reference-counted memory-based `HashDB` implementation that can be written
+/// concurrently.
+///
+/// Content is split into shards selected by the first byte of the hash, each
+/// shard being protected by its own lock. All operations are available from
+/// `&self`, and `HashDB` is implemented for `&ShardedMemoryDB` so that multiple
+/// threads can share the same database as a `TrieBuilder` or `TrieDBMut` sink.
+///
+/// Reference counting follows the same rules as [`MemoryDB`].
+///
+/// # Example
+/// ```rust
+///   use hash_db::{HashDB, EMPTY_PREFIX};
+///   use keccak_hasher::KeccakHasher;
+///   use memory_db::{ShardedMemoryDB, HashKey};
+///
+///   let db = ShardedMemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+///   std::thread::scope(|s| {
+///     for i in 0u8..4 {
+///       let mut db = &db;
+///       s.spawn(move || {
+///         db.insert(EMPTY_PREFIX, &[i; 40]);
+///       });
+///     }
+///   });
+///   assert_eq!(db.keys().len(), 4);
+/// ```
+pub struct ShardedMemoryDB<H, KF, T, M = DefaultMemTracker<T>>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	M: MemTracker<T>,
+{
+	shards: Vec<RwLock<Shard<KF::Key, T, M>>>,
+	hashed_null_node: H::Out,
+	null_node_data: T,
+	_kf: PhantomData<KF>,
+}
+
+impl<H, KF, T, M> Default for ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: for<'a> From<&'a [u8]>,
+	KF: KeyFunction<H>,
+	M: MemTracker<T> + Default,
+{
+	fn default() -> Self {
+		Self::from_null_node(&[0u8][..], [0u8][..].into())
+	}
+}
+
+impl<H, KF, T, M> ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	KF: KeyFunction<H>,
+	M: MemTracker<T>,
+{
+	/// Number of shards.
+	pub fn shard_count(&self) -> usize {
+		self.shards.len()
+	}
+
+	fn shard_index(&self, key: &H::Out) -> usize {
+		key.as_ref().first().map_or(0, |b| *b as usize) & (self.shards.len() - 1)
+	}
+
+	fn read_shard(&self, key: &H::Out) -> RwLockReadGuard<'_, Shard<KF::Key, T, M>> {
+		// Shard content stays consistent on panic, so poisoning is ignored.
+		self.shards[self.shard_index(key)].read().unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn write_shard(&self, key: &H::Out) -> RwLockWriteGuard<'_, Shard<KF::Key, T, M>> {
+		self.shards[self.shard_index(key)].write().unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn for_each_shard(&self, mut f: impl FnMut(&mut Shard<KF::Key, T, M>)) {
+		for shard in self.shards.iter() {
+			f(&mut shard.write().unwrap_or_else(|e| e.into_inner()))
+		}
+	}
+}
+
+impl<H, KF, T, M> ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: for<'a> From<&'a [u8]>,
+	KF: KeyFunction<H>,
+	M: MemTracker<T> + Default,
+{
+	/// Create a new `ShardedMemoryDB` from a given null key/data, with
+	/// `DEFAULT_SHARDS` shards.
+	pub fn from_null_node(null_key: &[u8], null_node_data: T) -> Self {
+		Self::from_null_node_with_shards(null_key, null_node_data, DEFAULT_SHARDS)
+	}
+
+	/// Create a new `ShardedMemoryDB` from a given null key/data and a number of shards.
+	///
+	/// Panics if `shards` is not a power of two between 1 and `MAX_SHARDS`.
+	pub fn from_null_node_with_shards(null_key: &[u8], null_node_data: T, shards: usize) -> Self {
+		assert!(
+			shards.is_power_of_two() && shards <= MAX_SHARDS,
+			"Shard count must be a power of two no bigger than {}",
+			MAX_SHARDS,
+		);
+		ShardedMemoryDB {
+			shards: (0..shards)
+				.map(|_| {
+					RwLock::new(Shard { data: HashMap::default(), malloc_tracker: M::default() })
+				})
+				.collect(),
+			hashed_null_node: H::hash(null_key),
+			null_node_data,
+			_kf: Default::default(),
+		}
+	}
+
+	/// Create a new instance of `Self`.
+	pub fn new(data: &[u8]) -> Self {
+		Self::from_null_node(data, data.into())
+	}
+
+	/// Create a new default instance of `Self` and returns `Self` and the root hash.
+	pub fn default_with_root() -> (Self, H::Out) {
+		let db = Self::default();
+		let root = db.hashed_null_node;
+
+		(db, root)
+	}
+
+	/// Clear all data from the database.
+	pub fn clear(&self) {
+		self.for_each_shard(|shard| {
+			shard.malloc_tracker.on_clear();
+			shard.data.clear();
+		});
+	}
+
+	/// Purge all zero-referenced data from the database.
+	pub fn purge(&self) {
+		self.for_each_shard(|shard| {
+			let malloc_tracker = &mut shard.malloc_tracker;
+			shard.data.retain(|_, (v, rc)| {
+				let keep = *rc != 0;
+				if !keep {
+					malloc_tracker.on_remove(v);
+				}
+				keep
+			});
+		});
+	}
+
+	/// Return the internal key-value HashMap, clearing the current state.
+	pub fn drain(&self) -> HashMap<KF::Key, (T, i32)> {
+		let mut result = HashMap::default();
+		self.for_each_shard(|shard| {
+			shard.malloc_tracker.on_clear();
+			result.extend(mem::take(&mut shard.data));
+		});
+		result
+	}
+
+	/// Get the keys in the database together with number of underlying references.
+	pub fn keys(&self) -> HashMap<KF::Key, i32> {
+		let mut result = HashMap::default();
+		for shard in self.shards.iter() {
+			let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+			result.extend(shard.data.iter().filter_map(|(k, v)| {
+				if v.1 != 0 {
+					Some((k.clone(), v.1))
+				} else {
+					None
+				}
+			}));
+		}
+		result
+	}
+
+	/// Convert into a single threaded `MemoryDB` with the same content.
+	pub fn into_memory_db(self) -> MemoryDB<H, KF, T, M> {
+		let mut malloc_tracker = M::default();
+		let mut data = HashMap::default();
+		for shard in self.shards.into_iter() {
+			let shard = shard.into_inner().unwrap_or_else(|e| e.into_inner());
+			for (key, value) in shard.data.into_iter() {
+				malloc_tracker.on_insert(&value.0);
+				data.insert(key, value);
+			}
+		}
+		MemoryDB {
+			data,
+			malloc_tracker,
+			hashed_null_node: self.hashed_null_node,
+			null_node_data: self.null_node_data,
+			_kf: Default::default(),
+		}
+	}
+}
+
+impl<H, KF, T, M> ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + for<'a> From<&'a [u8]> + Clone,
+	KF: KeyFunction<H>,
+	M: MemTracker<T>,
+{
+	/// Grab the raw information associated with a key. Returns None if the key
+	/// doesn't exist.
+	///
+	/// Even when Some is returned, the data is only guaranteed to be useful
+	/// when the refs > 0.
+	pub fn raw(&self, key: &H::Out, prefix: Prefix) -> Option<(T, i32)> {
+		if key == &self.hashed_null_node {
+			return Some((self.null_node_data.clone(), 1))
+		}
+		self.read_shard(key).data.get(&KF::key(key, prefix)).cloned()
+	}
+
+	/// Look up a given hash, see `HashDB::get`.
+	pub fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		if key == &self.hashed_null_node {
+			return Some(self.null_node_data.clone())
+		}
+
+		match self.read_shard(key).data.get(&KF::key(key, prefix)) {
+			Some(&(ref d, rc)) if rc > 0 => Some(d.clone()),
+			_ => None,
+		}
+	}
+
+	/// Check for the existence of a hash-key, see `HashDB::contains`.
+	pub fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		if key == &self.hashed_null_node {
+			return true
+		}
+
+		matches!(self.read_shard(key).data.get(&KF::key(key, prefix)), Some(&(_, x)) if x > 0)
+	}
+
+	/// Insert a datum item with a given key, see `HashDB::emplace`.
+	pub fn emplace(&self, key: H::Out, prefix: Prefix, value: T) {
+		if value == self.null_node_data {
+			return
+		}
+
+		let mut shard = self.write_shard(&key);
+		let Shard { data, malloc_tracker } = &mut *shard;
+		match data.entry(KF::key(&key, prefix)) {
+			Entry::Occupied(mut entry) => {
+				let &mut (ref mut old_value, ref mut rc) = entry.get_mut();
+				if *rc <= 0 {
+					malloc_tracker.on_insert(&value);
+					malloc_tracker.on_remove(old_value);
+					*old_value = value;
+				}
+				*rc += 1;
+			},
+			Entry::Vacant(entry) => {
+				malloc_tracker.on_insert(&value);
+				entry.insert((value, 1));
+			},
+		}
+	}
+
+	/// Insert a datum item and return its hash, see `HashDB::insert`.
+	pub fn insert(&self, prefix: Prefix, value: &[u8]) -> H::Out {
+		if T::from(value) == self.null_node_data {
+			return self.hashed_null_node
+		}
+
+		let key = H::hash(value);
+		self.emplace(key, prefix, value.into());
+		key
+	}
+
+	/// Remove a datum previously inserted, see `HashDB::remove`.
+	pub fn remove(&self, key: &H::Out, prefix: Prefix) {
+		if key == &self.hashed_null_node {
+			return
+		}
+
+		let mut shard = self.write_shard(key);
+		let Shard { data, malloc_tracker } = &mut *shard;
+		match data.entry(KF::key(key, prefix)) {
+			Entry::Occupied(mut entry) => {
+				entry.get_mut().1 -= 1;
+			},
+			Entry::Vacant(entry) => {
+				let value = T::default();
+				malloc_tracker.on_insert(&value);
+				entry.insert((value, -1));
+			},
+		}
+	}
+
+	/// Remove an element and delete it from storage if reference count reaches zero.
+	/// If the value was purged, return the old value.
+	pub fn remove_and_purge(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		if key == &self.hashed_null_node {
+			return None
+		}
+
+		let mut shard = self.write_shard(key);
+		let Shard { data, malloc_tracker } = &mut *shard;
+		match data.entry(KF::key(key, prefix)) {
+			Entry::Occupied(mut entry) =>
+				if entry.get().1 == 1 {
+					let (value, _) = entry.remove();
+					malloc_tracker.on_remove(&value);
+					Some(value)
+				} else {
+					entry.get_mut().1 -= 1;
+					None
+				},
+			Entry::Vacant(entry) => {
+				let value = T::default();
+				malloc_tracker.on_insert(&value);
+				entry.insert((value, -1));
+				None
+			},
+		}
+	}
+
+	/// Consolidate all the entries of `other` into `self`.
+	///
+	/// Panics if both databases do not use the same number of shards.
+	pub fn consolidate(&self, other: Self) {
+		assert_eq!(self.shards.len(), other.shards.len(), "Shard count mismatch");
+		for (shard, other) in self.shards.iter().zip(other.shards) {
+			let other = other.into_inner().unwrap_or_else(|e| e.into_inner());
+			let mut shard = shard.write().unwrap_or_else(|e| e.into_inner());
+			let Shard { data, malloc_tracker } = &mut *shard;
+			for (key, (value, rc)) in other.data {
+				match data.entry(key) {
+					Entry::Occupied(mut entry) => {
+						if entry.get().1 < 0 {
+							malloc_tracker.on_insert(&value);
+							malloc_tracker.on_remove(&entry.get().0);
+							entry.get_mut().0 = value;
+						}
+
+						entry.get_mut().1 += rc;
+					},
+					Entry::Vacant(entry) => {
+						malloc_tracker.on_insert(&value);
+						entry.insert((value, rc));
+					},
+				}
+			}
+		}
+	}
+}
+
+impl<H, KF, T, M> MallocSizeOf for ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	H::Out: MallocSizeOf,
+	T: MallocSizeOf,
+	KF: KeyFunction<H>,
+	KF::Key: MallocSizeOf,
+	M: MemTracker<T>,
+{
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		let mut size = self.shards.shallow_size_of(ops);
+		for shard in self.shards.iter() {
+			let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+			size += shard.data.shallow_size_of(ops) + shard.malloc_tracker.get_size();
+		}
+		size + self.null_node_data.size_of(ops) + self.hashed_null_node.size_of(ops)
+	}
+}
+
+impl<H, KF, T, M> HashDB<H, T> for ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	M: MemTracker<T> + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		ShardedMemoryDB::get(self, key, prefix)
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		ShardedMemoryDB::contains(self, key, prefix)
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: T) {
+		ShardedMemoryDB::emplace(self, key, prefix, value)
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		ShardedMemoryDB::insert(self, prefix, value)
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		ShardedMemoryDB::remove(self, key, prefix)
+	}
+}
+
+impl<H, KF, T, M> HashDB<H, T> for &ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	M: MemTracker<T> + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		ShardedMemoryDB::get(self, key, prefix)
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		ShardedMemoryDB::contains(self, key, prefix)
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: T) {
+		ShardedMemoryDB::emplace(self, key, prefix, value)
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		ShardedMemoryDB::insert(self, prefix, value)
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		ShardedMemoryDB::remove(self, key, prefix)
+	}
+}
+
+impl<H, KF, T, M> HashDBRef<H, T> for ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	M: MemTracker<T> + Send + Sync,
+{
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<T> {
+		ShardedMemoryDB::get(self, key, prefix)
+	}
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		ShardedMemoryDB::contains(self, key, prefix)
+	}
+}
+
+impl<H, KF, T, M> AsHashDB<H, T> for ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	M: MemTracker<T> + Send + Sync,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<H, T> {
+		self
+	}
+	fn as_hash_db_mut(&mut self) -> &mut dyn HashDB<H, T> {
+		self
+	}
+}
+
+impl<H, KF, T, M> AsHashDB<H, T> for &ShardedMemoryDB<H, KF, T, M>
+where
+	H: KeyHasher,
+	T: Default + PartialEq<T> + AsRef<[u8]> + for<'a> From<&'a [u8]> + Clone + Send + Sync,
+	KF: KeyFunction<H> + Send + Sync,
+	M: MemTracker<T> + Send + Sync,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<H, T> {
+		self
+	}
+	fn as_hash_db_mut(&mut self) -> &mut dyn HashDB<H, T> {
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ShardedMemoryDB;
+	use crate::{HashKey, MemoryDB, PrefixedKey};
+	use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+	use keccak_hasher::KeccakHasher;
+	use parity_util_mem::malloc_size;
+
+	type ShardedDB = ShardedMemoryDB<KeccakHasher, HashKey<KeccakHasher>, Vec<u8>>;
+
+	#[test]
+	fn refcount_matches_memorydb() {
+		let sharded = ShardedDB::default();
+		let mut memory = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+		let hello_key = KeccakHasher::hash(b"Hello world!");
+
+		sharded.remove(&hello_key, EMPTY_PREFIX);
+		memory.remove(&hello_key, EMPTY_PREFIX);
+		assert_eq!(sharded.raw(&hello_key, EMPTY_PREFIX).unwrap().1, -1);
+		sharded.insert(EMPTY_PREFIX, b"Hello world!");
+		memory.insert(EMPTY_PREFIX, b"Hello world!");
+		assert_eq!(sharded.raw(&hello_key, EMPTY_PREFIX).unwrap().1, 0);
+		assert!(!sharded.contains(&hello_key, EMPTY_PREFIX));
+		sharded.insert(EMPTY_PREFIX, b"Hello world!");
+		memory.insert(EMPTY_PREFIX, b"Hello world!");
+		assert_eq!(sharded.get(&hello_key, EMPTY_PREFIX).unwrap(), b"Hello world!".to_vec());
+
+		assert_eq!(sharded.keys(), memory.keys());
+		assert!(sharded.into_memory_db() == memory);
+	}
+
+	#[test]
+	fn remove_and_purge() {
+		let db = ShardedDB::default();
+		let hello_key = db.insert(EMPTY_PREFIX, b"Hello world!");
+		db.insert(EMPTY_PREFIX, b"Hello world!");
+		assert!(db.remove_and_purge(&hello_key, EMPTY_PREFIX).is_none());
+		assert_eq!(&db.remove_and_purge(&hello_key, EMPTY_PREFIX).unwrap()[..], b"Hello world!");
+		assert_eq!(db.raw(&hello_key, EMPTY_PREFIX), None);
+
+		db.remove(&hello_key, EMPTY_PREFIX);
+		db.insert(EMPTY_PREFIX, b"Hello world!");
+		db.purge();
+		assert_eq!(db.raw(&hello_key, EMPTY_PREFIX), None);
+	}
+
+	#[test]
+	fn consolidate() {
+		let main = ShardedDB::default();
+		let other = ShardedDB::default();
+		let remove_key = other.insert(EMPTY_PREFIX, b"doggo");
+		main.remove(&remove_key, EMPTY_PREFIX);
+		let insert_key = other.insert(EMPTY_PREFIX, b"arf");
+		main.emplace(insert_key, EMPTY_PREFIX, b"arf".to_vec());
+
+		main.consolidate(other);
+
+		assert_eq!(main.raw(&remove_key, EMPTY_PREFIX).unwrap(), (b"doggo".to_vec(), 0));
+		assert_eq!(main.raw(&insert_key, EMPTY_PREFIX).unwrap(), (b"arf".to_vec(), 2));
+	}
+
+	#[test]
+	fn concurrent_inserts() {
+		let db =
+			ShardedMemoryDB::<KeccakHasher, PrefixedKey<_>, Vec<u8>>::from_null_node_with_shards(
+				&[0u8][..],
+				vec![0u8],
+				4,
+			);
+		std::thread::scope(|s| {
+			for t in 0u32..4 {
+				let mut db = &db;
+				s.spawn(move || {
+					for i in 0u32..256 {
+						let bytes = i.to_be_bytes();
+						HashDB::insert(&mut db, (&bytes[..], None), &bytes);
+						let own = (t * 1000 + i).to_be_bytes();
+						HashDB::insert(&mut db, EMPTY_PREFIX, &own);
+					}
+				});
+			}
+		});
+
+		let keys = db.keys();
+		assert_eq!(keys.len(), 256 + 4 * 256);
+		assert_eq!(keys.values().filter(|rc| **rc == 4).count(), 256);
+		assert_eq!(keys.values().filter(|rc| **rc == 1).count(), 4 * 256);
+	}
+
+	#[test]
+	fn malloc_size_of() {
+		let db = ShardedDB::default();
+		let empty = malloc_size(&db);
+		for i in 0u32..1024 {
+			let bytes = i.to_be_bytes();
+			db.insert(EMPTY_PREFIX, &bytes);
+		}
+		assert!(malloc_size(&db) > empty);
+		db.clear();
+		assert!(db.keys().is_empty());
+	}
+}
@@ -0,0 +1,166 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Maps that can be used as `MemoryDB` storage.
+
+use super::{btree_map, hash, hash_map, BTreeMap, Entry, HashMap};
+
+/// Map used to store the content of a [`MemoryDB`](crate::MemoryDB).
+///
+/// `HashMap` is the default and fastest storage, `BTreeMap` keeps entries
+/// ordered by key so that iterating over the database is deterministic.
+pub trait Storage<K, V>: Default {
+	/// Iterator over the entries of the storage.
+	type Iter<'a>: Iterator<Item = (&'a K, &'a V)>
+	where
+		Self: 'a,
+		K: 'a,
+		V: 'a;
+
+	/// Whether `iter` yields the entries ordered by key.
+	const ORDERED: bool = false;
+
+	/// Look up the value stored for a key.
+	fn get(&self, key: &K) -> Option<&V>;
+
+	/// Look up the value stored for a key, mutably.
+	fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+
+	/// Call `f` with the value stored for `key`, inserting the value it returns
+	/// when there was none.
+	fn upsert(&mut self, key: K, f: impl FnOnce(Option<&mut V>) -> Option<V>);
+
+	/// Remove a key from the map, returning its value.
+	fn remove(&mut self, key: &K) -> Option<V>;
+
+	/// Only keep the entries for which `f` returns true.
+	fn retain(&mut self, f: impl FnMut(&K, &mut V) -> bool);
+
+	/// Remove all entries.
+	fn clear(&mut self);
+
+	/// Number of entries.
+	fn len(&self) -> usize;
+
+	/// Is the map empty?
+	fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Iterate over all entries, ordered by key if the storage is ordered.
+	fn iter(&self) -> Self::Iter<'_>;
+
+	/// Shrink the allocated capacity when supported.
+	fn shrink_to_fit(&mut self) {}
+}
+
+impl<K: Eq + hash::Hash, V, S: hash::BuildHasher + Default> Storage<K, V> for HashMap<K, V, S> {
+	type Iter<'a>
+		= hash_map::Iter<'a, K, V>
+	where
+		Self: 'a;
+
+	fn get(&self, key: &K) -> Option<&V> {
+		HashMap::get(self, key)
+	}
+
+	fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+		HashMap::get_mut(self, key)
+	}
+
+	fn upsert(&mut self, key: K, f: impl FnOnce(Option<&mut V>) -> Option<V>) {
+		match self.entry(key) {
+			Entry::Occupied(mut entry) => {
+				f(Some(entry.get_mut()));
+			},
+			Entry::Vacant(entry) =>
+				if let Some(value) = f(None) {
+					entry.insert(value);
+				},
+		}
+	}
+
+	fn remove(&mut self, key: &K) -> Option<V> {
+		HashMap::remove(self, key)
+	}
+
+	fn retain(&mut self, f: impl FnMut(&K, &mut V) -> bool) {
+		HashMap::retain(self, f)
+	}
+
+	fn clear(&mut self) {
+		HashMap::clear(self)
+	}
+
+	fn len(&self) -> usize {
+		HashMap::len(self)
+	}
+
+	fn iter(&self) -> Self::Iter<'_> {
+		HashMap::iter(self)
+	}
+
+	fn shrink_to_fit(&mut self) {
+		HashMap::shrink_to_fit(self)
+	}
+}
+
+impl<K: Ord, V> Storage<K, V> for BTreeMap<K, V> {
+	type Iter<'a>
+		= btree_map::Iter<'a, K, V>
+	where
+		Self: 'a;
+
+	const ORDERED: bool = true;
+
+	fn get(&self, key: &K) -> Option<&V> {
+		BTreeMap::get(self, key)
+	}
+
+	fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+		BTreeMap::get_mut(self, key)
+	}
+
+	fn upsert(&mut self, key: K, f: impl FnOnce(Option<&mut V>) -> Option<V>) {
+		match self.entry(key) {
+			btree_map::Entry::Occupied(mut entry) => {
+				f(Some(entry.get_mut()));
+			},
+			btree_map::Entry::Vacant(entry) =>
+				if let Some(value) = f(None) {
+					entry.insert(value);
+				},
+		}
+	}
+
+	fn remove(&mut self, key: &K) -> Option<V> {
+		BTreeMap::remove(self, key)
+	}
+
+	fn retain(&mut self, f: impl FnMut(&K, &mut V) -> bool) {
+		BTreeMap::retain(self, f)
+	}
+
+	fn clear(&mut self) {
+		BTreeMap::clear(self)
+	}
+
+	fn len(&self) -> usize {
+		BTreeMap::len(self)
+	}
+
+	fn iter(&self) -> Self::Iter<'_> {
+		BTreeMap::iter(self)
+	}
+}
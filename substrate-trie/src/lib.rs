@@ -0,0 +1,418 @@
+// Copyright 2017-2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Substrate trie format for `trie-db` and `trie-root`.
+//!
+//! Tries have no extension nodes: branches carry their own partial key. Node headers
+//! and lengths are SCALE encoded. With state version 1 ([`LayoutV1`]), values of at least
+//! [`TRIE_VALUE_NODE_THRESHOLD`] bytes are stored as a separate node and referenced by hash.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod rstd {
+	pub use std::{borrow, cmp, iter, marker, ops, vec};
+}
+
+#[cfg(not(feature = "std"))]
+mod rstd {
+	pub use alloc::vec;
+	pub use core::{borrow, cmp, iter, marker, ops};
+}
+
+mod node_codec;
+mod node_header;
+mod trie_stream;
+
+pub use node_codec::NodeCodec;
+pub use node_header::NodeHeader;
+pub use trie_stream::TrieStream;
+
+use hash_db::Hasher;
+use parity_scale_codec::{Compact, Encode};
+use rstd::{marker::PhantomData, vec::Vec};
+use trie_db::{nibble_ops::Radix16, TrieConfiguration, TrieLayout};
+
+/// Constants used by the node header encoding.
+pub mod trie_constants {
+	const FIRST_PREFIX: u8 = 0b_00 << 6;
+	/// Maximum number of nibbles of a partial key.
+	pub const NIBBLE_SIZE_BOUND: usize = u16::MAX as usize;
+	/// Header prefix of a leaf.
+	pub const LEAF_PREFIX_MASK: u8 = 0b_01 << 6;
+	/// Header prefix of a branch without value.
+	pub const BRANCH_WITHOUT_MASK: u8 = 0b_10 << 6;
+	/// Header prefix of a branch with a value.
+	pub const BRANCH_WITH_MASK: u8 = 0b_11 << 6;
+	/// Encoding of the empty node.
+	pub const EMPTY_TRIE: u8 = FIRST_PREFIX;
+	/// Header prefix of a leaf with a hashed value.
+	pub const ALT_HASHING_LEAF_PREFIX_MASK: u8 = FIRST_PREFIX | (0b_1 << 5);
+	/// Header prefix of a branch with a hashed value.
+	pub const ALT_HASHING_BRANCH_WITH_MASK: u8 = FIRST_PREFIX | (0b_01 << 4);
+	/// Header used by compact encodings to escape a node whose value is attached.
+	pub const ESCAPE_COMPACT_HEADER: u8 = EMPTY_TRIE | 0b_00_01;
+}
+
+/// Size from which values are stored as a separate node with state version 1.
+pub const TRIE_VALUE_NODE_THRESHOLD: u32 = 33;
+
+/// Trie layout of state version 0: all values are stored inline.
+pub struct LayoutV0<H>(PhantomData<H>);
+
+/// Trie layout of state version 1: values of at least [`TRIE_VALUE_NODE_THRESHOLD`] bytes
+/// are stored as a separate node.
+pub struct LayoutV1<H>(PhantomData<H>);
+
+impl<H> Default for LayoutV0<H> {
+	fn default() -> Self {
+		LayoutV0(PhantomData)
+	}
+}
+
+impl<H> Clone for LayoutV0<H> {
+	fn clone(&self) -> Self {
+		LayoutV0(PhantomData)
+	}
+}
+
+impl<H> Default for LayoutV1<H> {
+	fn default() -> Self {
+		LayoutV1(PhantomData)
+	}
+}
+
+impl<H> Clone for LayoutV1<H> {
+	fn clone(&self) -> Self {
+		LayoutV1(PhantomData)
+	}
+}
+
+impl<H: Hasher> TrieLayout for LayoutV0<H> {
+	const USE_EXTENSION: bool = false;
+	const ALLOW_EMPTY: bool = true;
+	const MAX_INLINE_VALUE: Option<u32> = None;
+
+	type Hash = H;
+	type Codec = NodeCodec<H>;
+	type Nibble = Radix16;
+}
+
+impl<H: Hasher> TrieLayout for LayoutV1<H> {
+	const USE_EXTENSION: bool = false;
+	const ALLOW_EMPTY: bool = true;
+	const MAX_INLINE_VALUE: Option<u32> = Some(TRIE_VALUE_NODE_THRESHOLD);
+
+	type Hash = H;
+	type Codec = NodeCodec<H>;
+	type Nibble = Radix16;
+}
+
+macro_rules! impl_trie_configuration {
+	($layout:ident) => {
+		impl<H> TrieConfiguration for $layout<H>
+		where
+			H: Hasher,
+			H::Out: Ord,
+		{
+			fn trie_root<I, A, B>(input: I) -> <Self::Hash as Hasher>::Out
+			where
+				I: IntoIterator<Item = (A, B)>,
+				A: AsRef<[u8]> + Ord,
+				B: AsRef<[u8]>,
+			{
+				trie_root::trie_root_no_extension::<H, TrieStream, _, _, _>(
+					input,
+					Self::MAX_INLINE_VALUE,
+				)
+			}
+
+			fn trie_root_unhashed<I, A, B>(input: I) -> Vec<u8>
+			where
+				I: IntoIterator<Item = (A, B)>,
+				A: AsRef<[u8]> + Ord,
+				B: AsRef<[u8]>,
+			{
+				trie_root::unhashed_trie_no_extension::<H, TrieStream, _, _, _>(
+					input,
+					Self::MAX_INLINE_VALUE,
+				)
+			}
+
+			/// Indexed tries are keyed by the compact SCALE encoding of the index.
+			fn encode_index(input: u32) -> Vec<u8> {
+				Compact(input).encode()
+			}
+		}
+	};
+}
+
+impl_trie_configuration!(LayoutV0);
+impl_trie_configuration!(LayoutV1);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hex_literal::hex;
+	use keccak_hasher::KeccakHasher;
+	use memory_db::{HashKey, MemoryDB, PrefixedKey};
+	use std::convert::TryInto;
+	use trie_db::{
+		node::Value, proof, trie_visit, ChildReference, DBValue, NodeCodec as NodeCodecT, Trie,
+		TrieDB, TrieDBMut, TrieMut, TrieRoot,
+	};
+
+	/// Blake2-256, the hasher used by Substrate chains.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+	struct Blake2Hasher;
+
+	impl Hasher for Blake2Hasher {
+		type Out = [u8; 32];
+		type StdHasher = hash256_std_hasher::Hash256StdHasher;
+		const LENGTH: usize = 32;
+
+		fn hash(x: &[u8]) -> Self::Out {
+			blake2b_simd::Params::new()
+				.hash_length(32)
+				.hash(x)
+				.as_bytes()
+				.try_into()
+				.unwrap()
+		}
+	}
+
+	fn dogs() -> Vec<(Vec<u8>, Vec<u8>)> {
+		vec![
+			(b"doe".to_vec(), b"reindeer".to_vec()),
+			(b"dog".to_vec(), b"puppy".to_vec()),
+			(b"dogglesworth".to_vec(), b"cat".to_vec()),
+		]
+	}
+
+	/// Inline and hashed values with state version 1, including branch values and a
+	/// value of exactly the threshold size.
+	fn mixed() -> Vec<(Vec<u8>, Vec<u8>)> {
+		vec![
+			(b"a".to_vec(), vec![0x05; 100]),
+			(b"alfa".to_vec(), vec![0x01; 40]),
+			(b"alfa-romeo".to_vec(), vec![0x02; 8]),
+			(b"bravo".to_vec(), vec![0x03; 33]),
+			(b"charlie".to_vec(), vec![0x04; 32]),
+		]
+	}
+
+	/// Values of every size from 0 to 63 bytes, on both sides of the threshold of state
+	/// version 1, under branches of 16 children.
+	fn sizes() -> Vec<(Vec<u8>, Vec<u8>)> {
+		(0..64u8).map(|i| (vec![i / 16, i], vec![i; i as usize])).collect()
+	}
+
+	/// Check a root with `trie-root`, `trie_visit` and `TrieDBMut`.
+	fn check_root<L>(input: Vec<(Vec<u8>, Vec<u8>)>, expected: [u8; 32])
+	where
+		L: TrieConfiguration,
+		<L::Hash as Hasher>::Out: AsRef<[u8]>,
+	{
+		assert_eq!(L::trie_root(input.clone()).as_ref(), &expected[..]);
+
+		let mut cb = TrieRoot::<L>::default();
+		trie_visit::<L, _, _, _, _>(input.iter().cloned(), &mut cb);
+		assert_eq!(cb.root.unwrap().as_ref(), &expected[..]);
+
+		let mut db = MemoryDB::<L::Hash, PrefixedKey<_>, DBValue>::default();
+		let mut root = Default::default();
+		{
+			let mut trie = TrieDBMut::<L>::new(&mut db, &mut root);
+			for (key, value) in input.iter().rev() {
+				trie.insert(key, value).unwrap();
+			}
+		}
+		assert_eq!(root.as_ref(), &expected[..]);
+		let trie = TrieDB::<L>::new(&db, &root);
+		for (key, value) in &input {
+			assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+		}
+	}
+
+	#[test]
+	fn blake2_empty_root() {
+		// Empty storage root of Substrate chains.
+		let empty = hex!("03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314");
+		assert_eq!(NodeCodec::<Blake2Hasher>::hashed_null_node(), empty);
+		check_root::<LayoutV0<Blake2Hasher>>(vec![], empty);
+		check_root::<LayoutV1<Blake2Hasher>>(vec![], empty);
+	}
+
+	// Roots computed with `sp-trie` 47.0.0 and the `sp-core` hashers, from `trie_root` and
+	// `TrieDBMut` alike.
+	#[test]
+	fn blake2_sp_trie_roots() {
+		let root = hex!("39245109cef3758c2eed2ccba8d9b370a917850af3824bc8348d505df2c298fa");
+		check_root::<LayoutV0<Blake2Hasher>>(dogs(), root);
+		check_root::<LayoutV1<Blake2Hasher>>(dogs(), root);
+
+		check_root::<LayoutV0<Blake2Hasher>>(
+			mixed(),
+			hex!("d1d2a6213ea69d11e9e92018b9e182fb68d9acdcbf90ecc2492ed520e32df80f"),
+		);
+		check_root::<LayoutV1<Blake2Hasher>>(
+			mixed(),
+			hex!("a4673fa9650caa776e592954e2fcdf17f7fafb34db6f2472419b0e31bdd52ae1"),
+		);
+
+		check_root::<LayoutV0<Blake2Hasher>>(
+			sizes(),
+			hex!("070767b9592ece35a19f1a15604887fb6f798c591cebedb583967de145ce5dec"),
+		);
+		check_root::<LayoutV1<Blake2Hasher>>(
+			sizes(),
+			hex!("afdfdbefb27fc96c74fd2c5a1a973c579eb06955d02bbe4a20075affbf4e0458"),
+		);
+	}
+
+	// Roots computed with `sp-trie` 47.0.0, see `blake2_sp_trie_roots`.
+	#[test]
+	fn keccak_sp_trie_roots() {
+		let empty = hex!("bc36789e7a1e281436464229828f817d6612f7b477d66591ff96a9e064bcc98a");
+		check_root::<LayoutV0<KeccakHasher>>(vec![], empty);
+		check_root::<LayoutV1<KeccakHasher>>(vec![], empty);
+
+		let root = hex!("f05ecdaf8ee7d69f542bdd59f320ad2ab0632624a409f8cf8360bcfe589c4128");
+		check_root::<LayoutV0<KeccakHasher>>(dogs(), root);
+		check_root::<LayoutV1<KeccakHasher>>(dogs(), root);
+
+		check_root::<LayoutV0<KeccakHasher>>(
+			mixed(),
+			hex!("d6650f7e37a8672f7c434cf6e93387e87fe7bd36c9db8557be6bf57d3efa6485"),
+		);
+		check_root::<LayoutV1<KeccakHasher>>(
+			mixed(),
+			hex!("196d2496a98361b2f3c8ec40eae3edeb54ced23ac52730794d6637ae5781f88e"),
+		);
+
+		check_root::<LayoutV0<KeccakHasher>>(
+			sizes(),
+			hex!("4e0497cff4f9e9199d6cfdfad4cd1e2a7aff13f2b0d88192de3564eaebc0cbf4"),
+		);
+		check_root::<LayoutV1<KeccakHasher>>(
+			sizes(),
+			hex!("9e39e0bb8acc35db715c474dcbe9ec33f2766c7f9a8e4040c1fa6530aea55a16"),
+		);
+	}
+
+	#[test]
+	fn ordered_root() {
+		type L = LayoutV1<Blake2Hasher>;
+		let values: Vec<Vec<u8>> = (0..300u32).map(|i| vec![i as u8; i as usize % 70]).collect();
+		let expected = L::trie_root(
+			values.iter().enumerate().map(|(i, v)| (Compact(i as u32).encode(), v.clone())),
+		);
+		assert_eq!(L::ordered_trie_root(&values), expected);
+	}
+
+	#[test]
+	fn proof_and_compact_round_trip() {
+		type L = LayoutV1<Blake2Hasher>;
+		let items = mixed();
+		let mut db = MemoryDB::<Blake2Hasher, HashKey<_>, DBValue>::default();
+		let root = L::trie_build(&mut db, items.iter().cloned());
+		let trie = TrieDB::<L>::new(&db, &root);
+
+		let keys = [b"alfa".to_vec(), b"charlie".to_vec()];
+		let proof = proof::generate_proof::<_, L, _, _>(&trie, &keys).unwrap();
+		let expected: Vec<_> = items
+			.iter()
+			.filter(|(k, _)| keys.contains(k))
+			.map(|(k, v)| (k.clone(), Some(v.clone())))
+			.collect();
+		proof::verify_proof::<L, _, _, _>(&root, &proof, expected.iter()).unwrap();
+
+		let compact = trie_db::encode_compact::<L>(&trie).unwrap();
+		assert!(compact.iter().any(|node| node[0] == trie_constants::ESCAPE_COMPACT_HEADER));
+		let mut decoded = MemoryDB::<Blake2Hasher, HashKey<_>, DBValue>::default();
+		let (decoded_root, _) = trie_db::decode_compact::<L, _>(&mut decoded, &compact).unwrap();
+		assert_eq!(decoded_root, root);
+		let trie = TrieDB::<L>::new(&decoded, &root);
+		for (key, value) in &items {
+			assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+		}
+	}
+
+	#[test]
+	fn encode_into_appends() {
+		type Codec = NodeCodec<Blake2Hasher>;
+		let partial = ((1, 0x02), &[0x34, 0x56][..]);
+		let hash = [0x11; 32];
+		let children =
+			[Some(ChildReference::Inline([0x22; 32], 3)), None, Some(ChildReference::Hash(hash))];
+		for value in [Value::Inline(&b"value"[..]), Value::Node(&hash[..], None)] {
+			let mut output = b"prefix".to_vec();
+			Codec::leaf_node_into(partial, value.clone(), &mut output);
+			assert_eq!(
+				output,
+				[&b"prefix"[..], &Codec::leaf_node(partial, value.clone())].concat()
+			);
+
+			let nibbled = || {
+				Codec::branch_node_nibbled(
+					partial.1.iter().cloned(),
+					4,
+					children.iter(),
+					Some(value.clone()),
+				)
+			};
+			let mut output = b"prefix".to_vec();
+			Codec::branch_node_nibbled_into(
+				partial.1.iter().cloned(),
+				4,
+				children.iter(),
+				Some(value.clone()),
+				&mut output,
+			);
+			assert_eq!(output, [&b"prefix"[..], &nibbled()].concat());
+			assert!(Codec::decode_plan(&output[6..]).is_ok());
+		}
+	}
+
+	#[test]
+	fn rejects_non_canonical_nodes() {
+		type Codec = NodeCodec<Blake2Hasher>;
+		let root_node = LayoutV0::<Blake2Hasher>::trie_root_unhashed(dogs());
+		assert!(Codec::decode_plan(&root_node).is_ok());
+
+		let mut trailing = root_node.clone();
+		trailing.push(0);
+		assert!(Codec::decode_plan(&trailing).is_err());
+
+		// Leaf with one nibble and a non zero padding.
+		assert!(Codec::decode_plan(&[0x41, 0x01, 0x04, 0x01]).is_ok());
+		assert!(Codec::decode_plan(&[0x41, 0x11, 0x04, 0x01]).is_err());
+		// Truncated leaf.
+		assert!(Codec::decode_plan(&[0x41]).is_err());
+		// Branch without children.
+		assert!(Codec::decode_plan(&[0x80, 0x00, 0x00]).is_err());
+		// Inline child longer than a hash.
+		let mut branch = vec![0x80, 0x01, 0x00, 0x84];
+		branch.extend_from_slice(&[0x00; 33]);
+		assert!(Codec::decode_plan(&branch).is_err());
+		branch[3] = 0x80;
+		branch.truncate(4 + 32);
+		assert!(Codec::decode_plan(&branch).is_ok());
+		// Reserved header.
+		assert!(Codec::decode_plan(&[0x02]).is_err());
+	}
+}
@@ -0,0 +1,358 @@
+// Copyright 2017-2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `NodeCodec` implementation for Substrate's trie format.
+
+use crate::{
+	node_header::{NodeHeader, NodeKind},
+	rstd::{borrow::Borrow, cmp, marker::PhantomData, ops::Range, vec::Vec},
+	trie_constants,
+};
+use hash_db::Hasher;
+use parity_scale_codec::{Compact, Decode, Encode, Error, Input};
+use trie_db::{
+	nibble_ops::{NibbleOps, Radix16},
+	node::{Children, NibbleSlicePlan, NodeHandlePlan, NodePlan, Value, ValuePlan},
+	ChildReference, NodeCodec as NodeCodecT, Partial,
+};
+
+/// Length of the children bitmap of a branch.
+pub(crate) const BITMAP_LENGTH: usize = 2;
+
+/// Children bitmap codec for radix 16 trie.
+pub(crate) struct Bitmap(u16);
+
+impl Bitmap {
+	fn decode(data: &[u8]) -> Result<Self, Error> {
+		let value = u16::decode(&mut &data[..])?;
+		if value == 0 {
+			Err("Bitmap without a child.".into())
+		} else {
+			Ok(Bitmap(value))
+		}
+	}
+
+	fn value_at(&self, i: usize) -> bool {
+		self.0 & (1u16 << i) != 0
+	}
+
+	pub(crate) fn encode<I: Iterator<Item = bool>>(has_children: I, dest: &mut [u8]) {
+		let mut bitmap: u16 = 0;
+		let mut cursor: u16 = 1;
+		for v in has_children {
+			if v {
+				bitmap |= cursor
+			}
+			cursor <<= 1;
+		}
+		dest[0] = (bitmap % 256) as u8;
+		dest[1] = (bitmap / 256) as u8;
+	}
+}
+
+/// Input over a byte slice, keeping track of the decoded offset.
+struct ByteSliceInput<'a> {
+	data: &'a [u8],
+	offset: usize,
+}
+
+impl<'a> ByteSliceInput<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		ByteSliceInput { data, offset: 0 }
+	}
+
+	fn take(&mut self, count: usize) -> Result<Range<usize>, Error> {
+		if self.offset + count > self.data.len() {
+			return Err("out of data".into())
+		}
+
+		let range = self.offset..(self.offset + count);
+		self.offset += count;
+		Ok(range)
+	}
+}
+
+impl<'a> Input for ByteSliceInput<'a> {
+	fn remaining_len(&mut self) -> Result<Option<usize>, Error> {
+		Ok(Some(self.data.len().saturating_sub(self.offset)))
+	}
+
+	fn read(&mut self, into: &mut [u8]) -> Result<(), Error> {
+		let range = self.take(into.len())?;
+		into.copy_from_slice(&self.data[range]);
+		Ok(())
+	}
+
+	fn read_byte(&mut self) -> Result<u8, Error> {
+		if self.offset + 1 > self.data.len() {
+			return Err("out of data".into())
+		}
+
+		let byte = self.data[self.offset];
+		self.offset += 1;
+		Ok(byte)
+	}
+}
+
+/// Substrate node codec: no extension nodes, SCALE encoded headers and lengths, and
+/// values optionally stored as a hash (`HashedValueLeaf`/`HashedValueBranch` headers).
+#[derive(Default, Clone)]
+pub struct NodeCodec<H>(PhantomData<H>);
+
+impl<H: Hasher> NodeCodec<H> {
+	fn decode_plan_inner_hashed(data: &[u8]) -> Result<NodePlan<Radix16>, Error> {
+		let mut input = ByteSliceInput::new(data);
+
+		let header = NodeHeader::decode(&mut input)?;
+		let contains_hash = header.contains_hash_of_value();
+
+		let branch_has_value = if let NodeHeader::Branch(has_value, _) = &header {
+			*has_value
+		} else {
+			// hashed value branch
+			true
+		};
+
+		let plan = match header {
+			NodeHeader::Null => NodePlan::Empty,
+			NodeHeader::HashedValueBranch(nibble_count) | NodeHeader::Branch(_, nibble_count) => {
+				let padding = nibble_count % Radix16::NIBBLE_PER_BYTE != 0;
+				let partial = input.take(nibble_count.div_ceil(Radix16::NIBBLE_PER_BYTE))?;
+				// check that the padding is valid (if any)
+				if padding && Radix16::pad_left(1, data[partial.start]) != 0 {
+					return Err(Error::from("Bad format"))
+				}
+				let partial_padding = Radix16::number_padding(nibble_count);
+				let bitmap_range = input.take(BITMAP_LENGTH)?;
+				let bitmap = Bitmap::decode(&data[bitmap_range])?;
+				let value = if branch_has_value {
+					Some(if contains_hash {
+						ValuePlan::Node(input.take(H::LENGTH)?)
+					} else {
+						let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
+						ValuePlan::Inline(input.take(count)?)
+					})
+				} else {
+					None
+				};
+				let mut children = Children::<Radix16, _>::default();
+				for (i, child) in children.iter_mut().enumerate() {
+					if bitmap.value_at(i) {
+						let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
+						if count > H::LENGTH {
+							return Err(Error::from("Inline child node too long"))
+						}
+						let range = input.take(count)?;
+						*child = Some(if count == H::LENGTH {
+							NodeHandlePlan::Hash(range)
+						} else {
+							NodeHandlePlan::Inline(range)
+						});
+					}
+				}
+				NodePlan::NibbledBranch {
+					partial: NibbleSlicePlan::new(partial, partial_padding),
+					value,
+					children,
+				}
+			},
+			NodeHeader::HashedValueLeaf(nibble_count) | NodeHeader::Leaf(nibble_count) => {
+				let padding = nibble_count % Radix16::NIBBLE_PER_BYTE != 0;
+				let partial = input.take(nibble_count.div_ceil(Radix16::NIBBLE_PER_BYTE))?;
+				// check that the padding is valid (if any)
+				if padding && Radix16::pad_left(1, data[partial.start]) != 0 {
+					return Err(Error::from("Bad format"))
+				}
+				let partial_padding = Radix16::number_padding(nibble_count);
+				let value = if contains_hash {
+					ValuePlan::Node(input.take(H::LENGTH)?)
+				} else {
+					let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
+					ValuePlan::Inline(input.take(count)?)
+				};
+
+				NodePlan::Leaf { partial: NibbleSlicePlan::new(partial, partial_padding), value }
+			},
+		};
+		if input.offset != data.len() {
+			return Err("Trailing bytes after node".into())
+		}
+		Ok(plan)
+	}
+}
+
+impl<H> NodeCodecT for NodeCodec<H>
+where
+	H: Hasher,
+{
+	const ESCAPE_HEADER: Option<u8> = Some(trie_constants::ESCAPE_COMPACT_HEADER);
+	type Error = Error;
+	type HashOut = H::Out;
+	type Nibble = Radix16;
+
+	fn hashed_null_node() -> <H as Hasher>::Out {
+		H::hash(<Self as NodeCodecT>::empty_node())
+	}
+
+	fn decode_plan(data: &[u8]) -> Result<NodePlan<Radix16>, Self::Error> {
+		Self::decode_plan_inner_hashed(data)
+	}
+
+	fn is_empty_node(data: &[u8]) -> bool {
+		data == <Self as NodeCodecT>::empty_node()
+	}
+
+	fn empty_node() -> &'static [u8] {
+		&[trie_constants::EMPTY_TRIE]
+	}
+
+	fn leaf_node(partial: Partial, value: Value) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::leaf_node_into(partial, value, &mut output);
+		output
+	}
+
+	fn extension_node(
+		_partial: impl Iterator<Item = u8>,
+		_nbnibble: usize,
+		_child: ChildReference<<H as Hasher>::Out>,
+	) -> Vec<u8> {
+		unreachable!("Codec without extension.")
+	}
+
+	fn branch_node(
+		_children: impl Iterator<Item = impl Borrow<Option<ChildReference<<H as Hasher>::Out>>>>,
+		_maybe_value: Option<Value>,
+	) -> Vec<u8> {
+		unreachable!("Codec without extension.")
+	}
+
+	fn branch_node_nibbled(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<<H as Hasher>::Out>>>>,
+		value: Option<Value>,
+	) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::branch_node_nibbled_into(partial, number_nibble, children, value, &mut output);
+		output
+	}
+
+	fn leaf_node_into(partial: Partial, value: Value, output: &mut Vec<u8>) {
+		let contains_hash = matches!(&value, Value::Node(..));
+		if contains_hash {
+			partial_encode(partial, NodeKind::HashedValueLeaf, output)
+		} else {
+			partial_encode(partial, NodeKind::Leaf, output)
+		}
+		match value {
+			Value::Inline(value) => {
+				Compact(value.len() as u32).encode_to(output);
+				output.extend_from_slice(value);
+			},
+			Value::Node(hash, _) => {
+				debug_assert!(hash.len() == H::LENGTH);
+				output.extend_from_slice(hash);
+			},
+		}
+	}
+
+	fn branch_node_nibbled_into(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<<H as Hasher>::Out>>>>,
+		value: Option<Value>,
+		output: &mut Vec<u8>,
+	) {
+		let contains_hash = matches!(&value, Some(Value::Node(..)));
+		let node_kind = match (&value, contains_hash) {
+			(&None, _) => NodeKind::BranchNoValue,
+			(_, false) => NodeKind::BranchWithValue,
+			(_, true) => NodeKind::HashedValueBranch,
+		};
+		partial_from_iterator_encode(partial, number_nibble, node_kind, output);
+
+		let bitmap_index = output.len();
+		let mut bitmap: [u8; BITMAP_LENGTH] = [0; BITMAP_LENGTH];
+		(0..BITMAP_LENGTH).for_each(|_| output.push(0));
+		match value {
+			Some(Value::Inline(value)) => {
+				Compact(value.len() as u32).encode_to(output);
+				output.extend_from_slice(value);
+			},
+			Some(Value::Node(hash, _)) => {
+				debug_assert!(hash.len() == H::LENGTH);
+				output.extend_from_slice(hash);
+			},
+			None => (),
+		}
+		Bitmap::encode(
+			children.map(|maybe_child| match maybe_child.borrow() {
+				Some(ChildReference::Hash(h)) => {
+					h.as_ref().encode_to(output);
+					true
+				},
+				&Some(ChildReference::Inline(inline_data, len)) => {
+					inline_data.as_ref()[..len].encode_to(output);
+					true
+				},
+				None => false,
+			}),
+			bitmap.as_mut(),
+		);
+		output[bitmap_index..bitmap_index + BITMAP_LENGTH]
+			.copy_from_slice(&bitmap[..BITMAP_LENGTH]);
+	}
+}
+
+/// Append node type header (type and size), and partial value to `output`.
+/// It uses an iterator over encoded partial bytes as input.
+fn partial_from_iterator_encode<I: Iterator<Item = u8>>(
+	partial: I,
+	nibble_count: usize,
+	node_kind: NodeKind,
+	output: &mut Vec<u8>,
+) {
+	let nibble_count = cmp::min(trie_constants::NIBBLE_SIZE_BOUND, nibble_count);
+
+	output.reserve(4 + (nibble_count / Radix16::NIBBLE_PER_BYTE));
+	node_header(node_kind, nibble_count).encode_to(output);
+	output.extend(partial);
+}
+
+/// Append node type header (type and size), and partial value to `output`.
+/// Same as `partial_from_iterator_encode` but uses non encoded `Partial` as input.
+fn partial_encode(partial: Partial, node_kind: NodeKind, output: &mut Vec<u8>) {
+	let number_nibble_encoded = (partial.0).0 as usize;
+	let nibble_count = partial.1.len() * Radix16::NIBBLE_PER_BYTE + number_nibble_encoded;
+
+	let nibble_count = cmp::min(trie_constants::NIBBLE_SIZE_BOUND, nibble_count);
+
+	output.reserve(4 + partial.1.len());
+	node_header(node_kind, nibble_count).encode_to(output);
+	if number_nibble_encoded > 0 {
+		output.push(Radix16::pad_right(1, (partial.0).1));
+	}
+	output.extend_from_slice(partial.1);
+}
+
+fn node_header(node_kind: NodeKind, nibble_count: usize) -> NodeHeader {
+	match node_kind {
+		NodeKind::Leaf => NodeHeader::Leaf(nibble_count),
+		NodeKind::BranchWithValue => NodeHeader::Branch(true, nibble_count),
+		NodeKind::BranchNoValue => NodeHeader::Branch(false, nibble_count),
+		NodeKind::HashedValueLeaf => NodeHeader::HashedValueLeaf(nibble_count),
+		NodeKind::HashedValueBranch => NodeHeader::HashedValueBranch(nibble_count),
+	}
+}
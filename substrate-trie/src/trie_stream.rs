@@ -0,0 +1,129 @@
+// Copyright 2017-2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `TrieStream` implementation for Substrate's trie format.
+
+use crate::{
+	node_codec::{Bitmap, BITMAP_LENGTH},
+	node_header::{size_and_prefix_iterator, NodeKind},
+	rstd::{cmp, vec::Vec},
+	trie_constants,
+};
+use hash_db::Hasher;
+use parity_scale_codec::{Compact, Encode};
+use trie_root::Value as TrieStreamValue;
+
+/// `TrieStream` producing the same encoding as [`NodeCodec`](crate::NodeCodec).
+#[derive(Default, Clone)]
+pub struct TrieStream {
+	/// Current node buffer.
+	buffer: Vec<u8>,
+}
+
+/// Create a leaf/branch node, encoding a number of nibbles.
+fn fuse_nibbles_node(nibbles: &[u8], kind: NodeKind) -> impl Iterator<Item = u8> + '_ {
+	let size = cmp::min(trie_constants::NIBBLE_SIZE_BOUND, nibbles.len());
+
+	let iter_start = match kind {
+		NodeKind::Leaf => size_and_prefix_iterator(size, trie_constants::LEAF_PREFIX_MASK, 2),
+		NodeKind::BranchNoValue =>
+			size_and_prefix_iterator(size, trie_constants::BRANCH_WITHOUT_MASK, 2),
+		NodeKind::BranchWithValue =>
+			size_and_prefix_iterator(size, trie_constants::BRANCH_WITH_MASK, 2),
+		NodeKind::HashedValueLeaf =>
+			size_and_prefix_iterator(size, trie_constants::ALT_HASHING_LEAF_PREFIX_MASK, 3),
+		NodeKind::HashedValueBranch =>
+			size_and_prefix_iterator(size, trie_constants::ALT_HASHING_BRANCH_WITH_MASK, 4),
+	};
+	iter_start
+		.chain(if nibbles.len() % 2 == 1 { Some(nibbles[0]) } else { None })
+		.chain(nibbles[nibbles.len() % 2..].chunks(2).map(|ch| ch[0] << 4 | ch[1]))
+}
+
+impl TrieStream {
+	fn append_value(&mut self, value: TrieStreamValue) {
+		match value {
+			TrieStreamValue::Inline(value) => {
+				Compact(value.len() as u32).encode_to(&mut self.buffer);
+				self.buffer.extend_from_slice(value);
+			},
+			TrieStreamValue::Node(hash) => {
+				self.buffer.extend_from_slice(hash.as_slice());
+			},
+		}
+	}
+}
+
+impl trie_root::TrieStream for TrieStream {
+	fn new() -> Self {
+		Self { buffer: Vec::new() }
+	}
+
+	fn append_empty_data(&mut self) {
+		self.buffer.push(trie_constants::EMPTY_TRIE);
+	}
+
+	fn append_leaf(&mut self, key: &[u8], value: TrieStreamValue) {
+		let kind = match &value {
+			TrieStreamValue::Inline(..) => NodeKind::Leaf,
+			TrieStreamValue::Node(..) => NodeKind::HashedValueLeaf,
+		};
+		self.buffer.extend(fuse_nibbles_node(key, kind));
+		self.append_value(value);
+	}
+
+	fn begin_branch(
+		&mut self,
+		maybe_partial: Option<&[u8]>,
+		maybe_value: Option<TrieStreamValue>,
+		has_children: impl Iterator<Item = bool>,
+	) {
+		if let Some(partial) = maybe_partial {
+			let kind = match &maybe_value {
+				None => NodeKind::BranchNoValue,
+				Some(TrieStreamValue::Inline(..)) => NodeKind::BranchWithValue,
+				Some(TrieStreamValue::Node(..)) => NodeKind::HashedValueBranch,
+			};
+
+			self.buffer.extend(fuse_nibbles_node(partial, kind));
+			let mut bitmap = [0u8; BITMAP_LENGTH];
+			Bitmap::encode(has_children, &mut bitmap);
+			self.buffer.extend_from_slice(&bitmap);
+		} else {
+			unreachable!("trie stream codec only for no extension trie");
+		}
+		if let Some(value) = maybe_value {
+			self.append_value(value);
+		}
+	}
+
+	fn append_extension(&mut self, _key: &[u8]) {
+		unreachable!("trie stream codec only for no extension trie");
+	}
+
+	fn append_encoded_substream<H: Hasher>(&mut self, data: &[u8]) -> Option<H::Out> {
+		if data.len() < H::LENGTH {
+			data.encode_to(&mut self.buffer);
+			None
+		} else {
+			let hash = H::hash(data);
+			hash.as_ref().encode_to(&mut self.buffer);
+			Some(hash)
+		}
+	}
+
+	fn out(self) -> Vec<u8> {
+		self.buffer
+	}
+}
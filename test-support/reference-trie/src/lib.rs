@@ -17,9 +17,10 @@
 mod substrate_like;
 
 use parity_scale_codec::{Compact, Decode, Encode, Error as CodecError, Input, Output};
-use std::{borrow::Borrow, fmt, iter::once, marker::PhantomData, ops::Range};
+use std::{borrow::Borrow, cmp, fmt, iter::once, marker::PhantomData, ops::Range};
 use trie_db::{
-	node::{NibbleSlicePlan, NodeHandlePlan, NodePlan, Value, ValuePlan},
+	nibble_ops::{NibbleOps, Radix16, Radix2, Radix256, Radix4},
+	node::{Children, NibbleSlicePlan, NodeHandlePlan, NodePlan, Value, ValuePlan},
 	trie_visit,
 	triedbmut::ChildReference,
 	DBValue, Partial, TrieBuilder, TrieRoot,
@@ -27,7 +28,7 @@ use trie_db::{
 use trie_root::Hasher;
 
 use trie_db::{
-	nibble_ops, NodeCodec, Trie, TrieConfiguration, TrieDB, TrieDBMut, TrieLayout, TrieMut,
+	NodeCodec as NodeCodecT, Trie, TrieConfiguration, TrieDB, TrieDBMut, TrieLayout, TrieMut,
 };
 pub use trie_root::TrieStream;
 use trie_root::Value as TrieStreamValue;
@@ -35,9 +36,9 @@ pub mod node {
 	pub use trie_db::node::Node;
 }
 
-pub use substrate_like::{
-	trie_constants, HashedValueNoExt, HashedValueNoExtThreshold,
-	NodeCodec as ReferenceNodeCodecNoExtMeta, ReferenceTrieStreamNoExt,
+pub use substrate_like::{HashedValueNoExt, HashedValueNoExtThreshold};
+pub use substrate_trie::{
+	trie_constants, LayoutV0, LayoutV1, NodeCodec, TrieStream as ReferenceTrieStreamNoExt,
 };
 
 /// Reference hasher is a keccak hasher.
@@ -53,6 +54,9 @@ macro_rules! test_layouts {
 			$test_internal::<reference_trie::HashedValueNoExt>();
 			$test_internal::<reference_trie::NoExtensionLayout>();
 			$test_internal::<reference_trie::ExtensionLayout>();
+			$test_internal::<reference_trie::Radix2Layout>();
+			$test_internal::<reference_trie::Radix4Layout>();
+			$test_internal::<reference_trie::Radix256Layout>();
 		}
 	};
 }
@@ -79,32 +83,34 @@ impl TrieLayout for ExtensionLayout {
 	const MAX_INLINE_VALUE: Option<u32> = None;
 	type Hash = RefHasher;
 	type Codec = ReferenceNodeCodec<RefHasher>;
+	type Nibble = Radix16;
 }
 
 impl TrieConfiguration for ExtensionLayout {}
 
 /// Trie layout without extension nodes, allowing
-/// generic hasher.
-pub struct GenericNoExtensionLayout<H>(PhantomData<H>);
+/// generic hasher and radix.
+pub struct GenericNoExtensionLayout<H, N = Radix16>(PhantomData<(H, N)>);
 
-impl<H> Default for GenericNoExtensionLayout<H> {
+impl<H, N> Default for GenericNoExtensionLayout<H, N> {
 	fn default() -> Self {
 		GenericNoExtensionLayout(PhantomData)
 	}
 }
 
-impl<H> Clone for GenericNoExtensionLayout<H> {
+impl<H, N> Clone for GenericNoExtensionLayout<H, N> {
 	fn clone(&self) -> Self {
 		GenericNoExtensionLayout(PhantomData)
 	}
 }
 
-impl<H: Hasher> TrieLayout for GenericNoExtensionLayout<H> {
+impl<H: Hasher, N: NibbleOps> TrieLayout for GenericNoExtensionLayout<H, N> {
 	const USE_EXTENSION: bool = false;
 	const ALLOW_EMPTY: bool = false;
 	const MAX_INLINE_VALUE: Option<u32> = None;
 	type Hash = H;
-	type Codec = ReferenceNodeCodecNoExt<H>;
+	type Codec = ReferenceNodeCodecNoExt<H, N>;
+	type Nibble = N;
 }
 
 /// Trie that allows empty values.
@@ -117,38 +123,53 @@ impl TrieLayout for AllowEmptyLayout {
 	const MAX_INLINE_VALUE: Option<u32> = None;
 	type Hash = RefHasher;
 	type Codec = ReferenceNodeCodec<RefHasher>;
+	type Nibble = Radix16;
 }
 
-impl<H: Hasher> TrieConfiguration for GenericNoExtensionLayout<H> {}
+impl<H: Hasher, N: NibbleOps> TrieConfiguration for GenericNoExtensionLayout<H, N> {}
 
 /// Trie layout without extension nodes.
 pub type NoExtensionLayout = GenericNoExtensionLayout<RefHasher>;
 
-/// Children bitmap codec for radix 16 trie.
-pub struct Bitmap(u16);
+/// Binary trie layout without extension nodes.
+pub type Radix2Layout = GenericNoExtensionLayout<RefHasher, Radix2>;
+
+/// Radix 4 trie layout without extension nodes.
+pub type Radix4Layout = GenericNoExtensionLayout<RefHasher, Radix4>;
+
+/// Radix 256 trie layout without extension nodes.
+pub type Radix256Layout = GenericNoExtensionLayout<RefHasher, Radix256>;
+
+/// Children bitmap codec, a bit per child in little endian order.
+/// For radix 16, this is a little endian `u16`.
+pub struct Bitmap<'a>(&'a [u8]);
 
 const BITMAP_LENGTH: usize = 2;
 
-impl Bitmap {
-	fn decode(data: &[u8]) -> Result<Self, CodecError> {
-		Ok(u16::decode(&mut &data[..]).map(|v| Bitmap(v))?)
+/// Biggest children bitmap length, for radix 256.
+const MAX_BITMAP_LENGTH: usize = 32;
+
+/// Length of the children bitmap of a branch for radix `N`.
+fn bitmap_length<N: NibbleOps>() -> usize {
+	cmp::max(N::NIBBLE_LENGTH / 8, 1)
+}
+
+impl<'a> Bitmap<'a> {
+	fn decode(data: &'a [u8]) -> Self {
+		Bitmap(data)
 	}
 
 	fn value_at(&self, i: usize) -> bool {
-		self.0 & (1u16 << i) != 0
+		self.0[i / 8] & (1u8 << (i % 8)) != 0
 	}
 
 	fn encode<I: Iterator<Item = bool>>(has_children: I, output: &mut [u8]) {
-		let mut bitmap: u16 = 0;
-		let mut cursor: u16 = 1;
-		for v in has_children {
+		output.iter_mut().for_each(|b| *b = 0);
+		for (i, v) in has_children.enumerate() {
 			if v {
-				bitmap |= cursor
+				output[i / 8] |= 1u8 << (i % 8);
 			}
-			cursor <<= 1;
 		}
-		output[0] = (bitmap % 256) as u8;
-		output[1] = (bitmap / 256) as u8;
 	}
 }
 
@@ -171,6 +192,9 @@ where
 {
 	if T::USE_EXTENSION {
 		trie_root::trie_root::<T::Hash, ReferenceTrieStream, _, _, _>(input, Default::default())
+	} else if T::Nibble::NIBBLE_LENGTH != Radix16::NIBBLE_LENGTH {
+		// trie streams are radix 16 only.
+		reference_trie_root_iter_build::<T, _, _, _>(input)
 	} else {
 		trie_root::trie_root_no_extension::<T::Hash, ReferenceTrieStreamNoExt, _, _, _>(
 			input,
@@ -283,20 +307,6 @@ fn branch_node_buffered<I: Iterator<Item = bool>>(
 	Bitmap::encode(has_children, &mut output[1..]);
 }
 
-/// Encoding of children bitmap (for trie stream radix 16).
-/// For stream variant without extension.
-fn branch_node_bit_mask(has_children: impl Iterator<Item = bool>) -> (u8, u8) {
-	let mut bitmap: u16 = 0;
-	let mut cursor: u16 = 1;
-	for v in has_children {
-		if v {
-			bitmap |= cursor
-		}
-		cursor <<= 1;
-	}
-	((bitmap % 256) as u8, (bitmap / 256) as u8)
-}
-
 /// Reference implementation of a `TrieStream` with extension nodes.
 #[derive(Default, Clone)]
 pub struct ReferenceTrieStream {
@@ -341,11 +351,17 @@ impl TrieStream for ReferenceTrieStream {
 		self.buffer.extend(fuse_nibbles_node(key, false));
 	}
 
-	fn append_substream<H: Hasher>(&mut self, other: Self) {
-		let data = other.out();
+	fn append_encoded_substream<H: Hasher>(&mut self, data: &[u8]) -> Option<H::Out> {
 		match data.len() {
-			0..=31 => data.encode_to(&mut self.buffer),
-			_ => H::hash(&data).as_ref().encode_to(&mut self.buffer),
+			0..=31 => {
+				data.encode_to(&mut self.buffer);
+				None
+			},
+			_ => {
+				let hash = H::hash(data);
+				hash.as_ref().encode_to(&mut self.buffer);
+				Some(hash)
+			},
 		}
 	}
 
@@ -485,19 +501,20 @@ pub struct ReferenceNodeCodec<H>(PhantomData<H>);
 /// Even if implementation follows initial specification of
 /// https://github.com/w3f/polkadot-re-spec/issues/8, this may
 /// not follow it in the future, it is mainly the testing codec without extension node.
+/// The radix is `N`, the children bitmap of a branch holding a bit per child.
 #[derive(Default, Clone)]
-pub struct ReferenceNodeCodecNoExt<H>(PhantomData<H>);
+pub struct ReferenceNodeCodecNoExt<H, N = Radix16>(PhantomData<(H, N)>);
 
-fn partial_to_key(partial: Partial, offset: u8, over: u8) -> Vec<u8> {
+fn partial_to_key(partial: Partial, offset: u8, over: u8, output: &mut Vec<u8>) {
 	let number_nibble_encoded = (partial.0).0 as usize;
-	let nibble_count = partial.1.len() * nibble_ops::NIBBLE_PER_BYTE + number_nibble_encoded;
+	let nibble_count = partial.1.len() * Radix16::NIBBLE_PER_BYTE + number_nibble_encoded;
 	assert!(nibble_count < over as usize);
-	let mut output = vec![offset + nibble_count as u8];
+	output.reserve(2 + partial.1.len());
+	output.push(offset + nibble_count as u8);
 	if number_nibble_encoded > 0 {
-		output.push(nibble_ops::pad_right((partial.0).1));
+		output.push(Radix16::pad_right(number_nibble_encoded, (partial.0).1));
 	}
-	output.extend_from_slice(&partial.1[..]);
-	output
+	output.extend_from_slice(partial.1);
 }
 
 fn partial_from_iterator_to_key<I: Iterator<Item = u8>>(
@@ -505,52 +522,47 @@ fn partial_from_iterator_to_key<I: Iterator<Item = u8>>(
 	nibble_count: usize,
 	offset: u8,
 	over: u8,
-) -> Vec<u8> {
+	output: &mut Vec<u8>,
+) {
 	assert!(nibble_count < over as usize);
-	let mut output = Vec::with_capacity(1 + (nibble_count / nibble_ops::NIBBLE_PER_BYTE));
+	output.reserve(1 + (nibble_count / Radix16::NIBBLE_PER_BYTE));
 	output.push(offset + nibble_count as u8);
 	output.extend(partial);
-	output
 }
 
-fn partial_from_iterator_encode<I: Iterator<Item = u8>>(
+fn partial_from_iterator_encode<N: NibbleOps, I: Iterator<Item = u8>>(
 	partial: I,
 	nibble_count: usize,
 	node_kind: NodeKindNoExt,
-) -> Vec<u8> {
+	output: &mut Vec<u8>,
+) {
 	let nibble_count = ::std::cmp::min(NIBBLE_SIZE_BOUND_NO_EXT, nibble_count);
 
-	let mut output = Vec::with_capacity(3 + (nibble_count / nibble_ops::NIBBLE_PER_BYTE));
-	match node_kind {
-		NodeKindNoExt::Leaf => NodeHeaderNoExt::Leaf(nibble_count).encode_to(&mut output),
-		NodeKindNoExt::BranchWithValue =>
-			NodeHeaderNoExt::Branch(true, nibble_count).encode_to(&mut output),
-		NodeKindNoExt::BranchNoValue =>
-			NodeHeaderNoExt::Branch(false, nibble_count).encode_to(&mut output),
-	};
+	output.reserve(3 + (nibble_count / N::NIBBLE_PER_BYTE));
+	node_header_no_ext(node_kind, nibble_count).encode_to(output);
 	output.extend(partial);
-	output
 }
 
-fn partial_encode(partial: Partial, node_kind: NodeKindNoExt) -> Vec<u8> {
+fn partial_encode<N: NibbleOps>(partial: Partial, node_kind: NodeKindNoExt, output: &mut Vec<u8>) {
 	let number_nibble_encoded = (partial.0).0 as usize;
-	let nibble_count = partial.1.len() * nibble_ops::NIBBLE_PER_BYTE + number_nibble_encoded;
+	let nibble_count = partial.1.len() * N::NIBBLE_PER_BYTE + number_nibble_encoded;
 
 	let nibble_count = ::std::cmp::min(NIBBLE_SIZE_BOUND_NO_EXT, nibble_count);
 
-	let mut output = Vec::with_capacity(3 + partial.1.len());
-	match node_kind {
-		NodeKindNoExt::Leaf => NodeHeaderNoExt::Leaf(nibble_count).encode_to(&mut output),
-		NodeKindNoExt::BranchWithValue =>
-			NodeHeaderNoExt::Branch(true, nibble_count).encode_to(&mut output),
-		NodeKindNoExt::BranchNoValue =>
-			NodeHeaderNoExt::Branch(false, nibble_count).encode_to(&mut output),
-	};
+	output.reserve(3 + partial.1.len());
+	node_header_no_ext(node_kind, nibble_count).encode_to(output);
 	if number_nibble_encoded > 0 {
-		output.push(nibble_ops::pad_right((partial.0).1));
+		output.push(N::pad_right(number_nibble_encoded, (partial.0).1));
+	}
+	output.extend_from_slice(partial.1);
+}
+
+fn node_header_no_ext(node_kind: NodeKindNoExt, nibble_count: usize) -> NodeHeaderNoExt {
+	match node_kind {
+		NodeKindNoExt::Leaf => NodeHeaderNoExt::Leaf(nibble_count),
+		NodeKindNoExt::BranchWithValue => NodeHeaderNoExt::Branch(true, nibble_count),
+		NodeKindNoExt::BranchNoValue => NodeHeaderNoExt::Branch(false, nibble_count),
 	}
-	output.extend_from_slice(&partial.1[..]);
-	output
 }
 
 struct ByteSliceInput<'a> {
@@ -603,21 +615,22 @@ impl<'a> Input for ByteSliceInput<'a> {
 // but due to the current limitations of Rust const evaluation we can't do
 // `const HASHED_NULL_NODE: <KeccakHasher as Hasher>::Out = <KeccakHasher as Hasher>::Out( … … )`.
 // Perhaps one day soon?
-impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
+impl<H: Hasher> NodeCodecT for ReferenceNodeCodec<H> {
 	type Error = CodecError;
 	type HashOut = H::Out;
+	type Nibble = Radix16;
 
 	fn hashed_null_node() -> <H as Hasher>::Out {
-		H::hash(<Self as NodeCodec>::empty_node())
+		H::hash(<Self as NodeCodecT>::empty_node())
 	}
 
-	fn decode_plan(data: &[u8]) -> ::std::result::Result<NodePlan, Self::Error> {
+	fn decode_plan(data: &[u8]) -> ::std::result::Result<NodePlan<Radix16>, Self::Error> {
 		let mut input = ByteSliceInput::new(data);
 		match NodeHeader::decode(&mut input)? {
 			NodeHeader::Null => Ok(NodePlan::Empty),
 			NodeHeader::Branch(has_value) => {
 				let bitmap_range = input.take(BITMAP_LENGTH)?;
-				let bitmap = Bitmap::decode(&data[bitmap_range])?;
+				let bitmap = Bitmap::decode(&data[bitmap_range]);
 
 				let value = if has_value {
 					let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
@@ -625,11 +638,8 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 				} else {
 					None
 				};
-				let mut children = [
-					None, None, None, None, None, None, None, None, None, None, None, None, None,
-					None, None, None,
-				];
-				for i in 0..nibble_ops::NIBBLE_LENGTH {
+				let mut children = Children::<Radix16, _>::default();
+				for i in 0..Radix16::NIBBLE_LENGTH {
 					if bitmap.value_at(i) {
 						let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 						let range = input.take(count)?;
@@ -644,10 +654,9 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 			},
 			NodeHeader::Extension(nibble_count) => {
 				let partial = input.take(
-					(nibble_count + (nibble_ops::NIBBLE_PER_BYTE - 1)) /
-						nibble_ops::NIBBLE_PER_BYTE,
+					(nibble_count + (Radix16::NIBBLE_PER_BYTE - 1)) / Radix16::NIBBLE_PER_BYTE,
 				)?;
-				let partial_padding = nibble_ops::number_padding(nibble_count);
+				let partial_padding = Radix16::number_padding(nibble_count);
 				let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 				let range = input.take(count)?;
 				let child = if count == H::LENGTH {
@@ -662,10 +671,9 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 			},
 			NodeHeader::Leaf(nibble_count) => {
 				let partial = input.take(
-					(nibble_count + (nibble_ops::NIBBLE_PER_BYTE - 1)) /
-						nibble_ops::NIBBLE_PER_BYTE,
+					(nibble_count + (Radix16::NIBBLE_PER_BYTE - 1)) / Radix16::NIBBLE_PER_BYTE,
 				)?;
-				let partial_padding = nibble_ops::number_padding(nibble_count);
+				let partial_padding = Radix16::number_padding(nibble_count);
 				let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 				let value = input.take(count)?;
 				Ok(NodePlan::Leaf {
@@ -677,7 +685,7 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 	}
 
 	fn is_empty_node(data: &[u8]) -> bool {
-		data == <Self as NodeCodec>::empty_node()
+		data == <Self as NodeCodecT>::empty_node()
 	}
 
 	fn empty_node() -> &'static [u8] {
@@ -685,45 +693,81 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 	}
 
 	fn leaf_node(partial: Partial, value: Value) -> Vec<u8> {
-		let mut output = partial_to_key(partial, LEAF_NODE_OFFSET, LEAF_NODE_OVER);
+		let mut output = Vec::new();
+		Self::leaf_node_into(partial, value, &mut output);
+		output
+	}
+
+	fn extension_node(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		child: ChildReference<Self::HashOut>,
+	) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::extension_node_into(partial, number_nibble, child, &mut output);
+		output
+	}
+
+	fn branch_node(
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		maybe_value: Option<Value>,
+	) -> Vec<u8> {
+		let mut output = Vec::new();
+		Self::branch_node_into(children, maybe_value, &mut output);
+		output
+	}
+
+	fn branch_node_nibbled(
+		_partial: impl Iterator<Item = u8>,
+		_number_nibble: usize,
+		_children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		_maybe_value: Option<Value>,
+	) -> Vec<u8> {
+		unreachable!("codec with extension branch")
+	}
+
+	fn leaf_node_into(partial: Partial, value: Value, output: &mut Vec<u8>) {
+		partial_to_key(partial, LEAF_NODE_OFFSET, LEAF_NODE_OVER, output);
 		match value {
 			Value::Inline(value) => {
-				Compact(value.len() as u32).encode_to(&mut output);
+				Compact(value.len() as u32).encode_to(output);
 				output.extend_from_slice(value);
 			},
 			_ => unimplemented!("unsupported"),
 		}
-		output
 	}
 
-	fn extension_node(
+	fn extension_node_into(
 		partial: impl Iterator<Item = u8>,
 		number_nibble: usize,
 		child: ChildReference<Self::HashOut>,
-	) -> Vec<u8> {
-		let mut output = partial_from_iterator_to_key(
+		output: &mut Vec<u8>,
+	) {
+		partial_from_iterator_to_key(
 			partial,
 			number_nibble,
 			EXTENSION_NODE_OFFSET,
 			EXTENSION_NODE_OVER,
+			output,
 		);
 		match child {
-			ChildReference::Hash(h) => h.as_ref().encode_to(&mut output),
+			ChildReference::Hash(h) => h.as_ref().encode_to(output),
 			ChildReference::Inline(inline_data, len) =>
-				(&AsRef::<[u8]>::as_ref(&inline_data)[..len]).encode_to(&mut output),
+				AsRef::<[u8]>::as_ref(&inline_data)[..len].encode_to(output),
 		};
-		output
 	}
 
-	fn branch_node(
+	fn branch_node_into(
 		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
 		maybe_value: Option<Value>,
-	) -> Vec<u8> {
-		let mut output = vec![0; BITMAP_LENGTH + 1];
+		output: &mut Vec<u8>,
+	) {
+		let prefix_index = output.len();
 		let mut prefix: [u8; 3] = [0; 3];
+		(0..BITMAP_LENGTH + 1).for_each(|_| output.push(0));
 		let have_value = match maybe_value {
 			Some(Value::Inline(value)) => {
-				Compact(value.len() as u32).encode_to(&mut output);
+				Compact(value.len() as u32).encode_to(output);
 				output.extend_from_slice(value);
 				true
 			},
@@ -732,39 +776,30 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
 		};
 		let has_children = children.map(|maybe_child| match maybe_child.borrow() {
 			Some(ChildReference::Hash(h)) => {
-				h.as_ref().encode_to(&mut output);
+				h.as_ref().encode_to(output);
 				true
 			},
 			&Some(ChildReference::Inline(inline_data, len)) => {
-				inline_data.as_ref()[..len].encode_to(&mut output);
+				inline_data.as_ref()[..len].encode_to(output);
 				true
 			},
 			None => false,
 		});
 		branch_node_buffered(have_value, has_children, prefix.as_mut());
-		output[0..BITMAP_LENGTH + 1].copy_from_slice(prefix.as_ref());
-		output
-	}
-
-	fn branch_node_nibbled(
-		_partial: impl Iterator<Item = u8>,
-		_number_nibble: usize,
-		_children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
-		_maybe_value: Option<Value>,
-	) -> Vec<u8> {
-		unreachable!("codec with extension branch")
+		output[prefix_index..prefix_index + BITMAP_LENGTH + 1].copy_from_slice(prefix.as_ref());
 	}
 }
 
-impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
+impl<H: Hasher, N: NibbleOps> NodeCodecT for ReferenceNodeCodecNoExt<H, N> {
 	type Error = CodecError;
 	type HashOut = <H as Hasher>::Out;
+	type Nibble = N;
 
 	fn hashed_null_node() -> <H as Hasher>::Out {
-		H::hash(<Self as NodeCodec>::empty_node())
+		H::hash(<Self as NodeCodecT>::empty_node())
 	}
 
-	fn decode_plan(data: &[u8]) -> Result<NodePlan, Self::Error> {
+	fn decode_plan(data: &[u8]) -> Result<NodePlan<N>, Self::Error> {
 		if data.len() < 1 {
 			return Err(CodecError::from("Empty encoded node."))
 		}
@@ -773,29 +808,23 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 		Ok(match NodeHeaderNoExt::decode(&mut input)? {
 			NodeHeaderNoExt::Null => NodePlan::Empty,
 			NodeHeaderNoExt::Branch(has_value, nibble_count) => {
-				let padding = nibble_count % nibble_ops::NIBBLE_PER_BYTE != 0;
+				let partial_padding = N::number_padding(nibble_count);
 				// check that the padding is valid (if any)
-				if padding && nibble_ops::pad_left(data[input.offset]) != 0 {
+				if partial_padding > 0 && N::pad_left(partial_padding, data[input.offset]) != 0 {
 					return Err(CodecError::from("Bad format"))
 				}
-				let partial = input.take(
-					(nibble_count + (nibble_ops::NIBBLE_PER_BYTE - 1)) /
-						nibble_ops::NIBBLE_PER_BYTE,
-				)?;
-				let partial_padding = nibble_ops::number_padding(nibble_count);
-				let bitmap_range = input.take(BITMAP_LENGTH)?;
-				let bitmap = Bitmap::decode(&data[bitmap_range])?;
+				let partial =
+					input.take((nibble_count + (N::NIBBLE_PER_BYTE - 1)) / N::NIBBLE_PER_BYTE)?;
+				let bitmap_range = input.take(bitmap_length::<N>())?;
+				let bitmap = Bitmap::decode(&data[bitmap_range]);
 				let value = if has_value {
 					let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 					Some(ValuePlan::Inline(input.take(count)?))
 				} else {
 					None
 				};
-				let mut children = [
-					None, None, None, None, None, None, None, None, None, None, None, None, None,
-					None, None, None,
-				];
-				for i in 0..nibble_ops::NIBBLE_LENGTH {
+				let mut children = Children::<N, _>::default();
+				for i in 0..N::NIBBLE_LENGTH {
 					if bitmap.value_at(i) {
 						let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 						let range = input.take(count)?;
@@ -813,16 +842,13 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 				}
 			},
 			NodeHeaderNoExt::Leaf(nibble_count) => {
-				let padding = nibble_count % nibble_ops::NIBBLE_PER_BYTE != 0;
+				let partial_padding = N::number_padding(nibble_count);
 				// check that the padding is valid (if any)
-				if padding && nibble_ops::pad_left(data[input.offset]) != 0 {
+				if partial_padding > 0 && N::pad_left(partial_padding, data[input.offset]) != 0 {
 					return Err(CodecError::from("Bad format"))
 				}
-				let partial = input.take(
-					(nibble_count + (nibble_ops::NIBBLE_PER_BYTE - 1)) /
-						nibble_ops::NIBBLE_PER_BYTE,
-				)?;
-				let partial_padding = nibble_ops::number_padding(nibble_count);
+				let partial =
+					input.take((nibble_count + (N::NIBBLE_PER_BYTE - 1)) / N::NIBBLE_PER_BYTE)?;
 				let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
 				let value = ValuePlan::Inline(input.take(count)?);
 
@@ -832,7 +858,7 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 	}
 
 	fn is_empty_node(data: &[u8]) -> bool {
-		data == <Self as NodeCodec>::empty_node()
+		data == <Self as NodeCodecT>::empty_node()
 	}
 
 	fn empty_node() -> &'static [u8] {
@@ -840,14 +866,8 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 	}
 
 	fn leaf_node(partial: Partial, value: Value) -> Vec<u8> {
-		let mut output = partial_encode(partial, NodeKindNoExt::Leaf);
-		match value {
-			Value::Inline(value) => {
-				Compact(value.len() as u32).encode_to(&mut output);
-				output.extend_from_slice(value);
-			},
-			Value::Node(..) => unimplemented!("No support for inner hashed value"),
-		}
+		let mut output = Vec::new();
+		Self::leaf_node_into(partial, value, &mut output);
 		output
 	}
 
@@ -872,17 +892,42 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
 		maybe_value: Option<Value>,
 	) -> Vec<u8> {
-		let mut output = if maybe_value.is_none() {
-			partial_from_iterator_encode(partial, number_nibble, NodeKindNoExt::BranchNoValue)
+		let mut output = Vec::new();
+		Self::branch_node_nibbled_into(partial, number_nibble, children, maybe_value, &mut output);
+		output
+	}
+
+	fn leaf_node_into(partial: Partial, value: Value, output: &mut Vec<u8>) {
+		partial_encode::<N>(partial, NodeKindNoExt::Leaf, output);
+		match value {
+			Value::Inline(value) => {
+				Compact(value.len() as u32).encode_to(output);
+				output.extend_from_slice(value);
+			},
+			Value::Node(..) => unimplemented!("No support for inner hashed value"),
+		}
+	}
+
+	fn branch_node_nibbled_into(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		maybe_value: Option<Value>,
+		output: &mut Vec<u8>,
+	) {
+		let node_kind = if maybe_value.is_none() {
+			NodeKindNoExt::BranchNoValue
 		} else {
-			partial_from_iterator_encode(partial, number_nibble, NodeKindNoExt::BranchWithValue)
+			NodeKindNoExt::BranchWithValue
 		};
+		partial_from_iterator_encode::<N, _>(partial, number_nibble, node_kind, output);
+		let bitmap_length = bitmap_length::<N>();
 		let bitmap_index = output.len();
-		let mut bitmap: [u8; BITMAP_LENGTH] = [0; BITMAP_LENGTH];
-		(0..BITMAP_LENGTH).for_each(|_| output.push(0));
+		let mut bitmap: [u8; MAX_BITMAP_LENGTH] = [0; MAX_BITMAP_LENGTH];
+		(0..bitmap_length).for_each(|_| output.push(0));
 		match maybe_value {
 			Some(Value::Inline(value)) => {
-				Compact(value.len() as u32).encode_to(&mut output);
+				Compact(value.len() as u32).encode_to(output);
 				output.extend_from_slice(value);
 			},
 			Some(Value::Node(..)) => unimplemented!("No support for inner hashed value"),
@@ -892,20 +937,19 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodecNoExt<H> {
 		Bitmap::encode(
 			children.map(|maybe_child| match maybe_child.borrow() {
 				Some(ChildReference::Hash(h)) => {
-					h.as_ref().encode_to(&mut output);
+					h.as_ref().encode_to(output);
 					true
 				},
 				&Some(ChildReference::Inline(inline_data, len)) => {
-					inline_data.as_ref()[..len].encode_to(&mut output);
+					inline_data.as_ref()[..len].encode_to(output);
 					true
 				},
 				None => false,
 			}),
-			bitmap.as_mut(),
+			&mut bitmap[..bitmap_length],
 		);
-		output[bitmap_index..bitmap_index + BITMAP_LENGTH]
-			.copy_from_slice(&bitmap.as_ref()[..BITMAP_LENGTH]);
-		output
+		output[bitmap_index..bitmap_index + bitmap_length]
+			.copy_from_slice(&bitmap[..bitmap_length]);
 	}
 }
 
@@ -1139,11 +1183,11 @@ mod tests {
 	fn too_big_nibble_length() {
 		// + 1 for 0 added byte of nibble encode
 		let input = vec![0u8; (NIBBLE_SIZE_BOUND_NO_EXT as usize + 1) / 2 + 1];
-		let enc = <ReferenceNodeCodecNoExt<RefHasher> as NodeCodec>::leaf_node(
+		let enc = <ReferenceNodeCodecNoExt<RefHasher> as NodeCodecT>::leaf_node(
 			((0, 0), &input),
 			Value::Inline(&[1]),
 		);
-		let dec = <ReferenceNodeCodecNoExt<RefHasher> as NodeCodec>::decode(&enc).unwrap();
+		let dec = <ReferenceNodeCodecNoExt<RefHasher> as NodeCodecT>::decode(&enc).unwrap();
 		let o_sl = if let Node::Leaf(sl, _) = dec { Some(sl) } else { None };
 		assert!(o_sl.is_some());
 	}
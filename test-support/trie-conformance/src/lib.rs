@@ -0,0 +1,427 @@
+// Copyright 2017-2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conformance test suite for `NodeCodec` and `TrieStream` implementations.
+//!
+//! Every check panics with a description of the offending node or input, so the
+//! functions can be called directly from a `#[test]`. [`check_layout`] runs all of them
+//! on [`standard_inputs`]; the individual checks can be used to run a subset, or to run
+//! on other inputs.
+//!
+//! Inputs are lists of key value pairs with distinct keys and non empty values, so that
+//! they describe the same trie whether or not the layout allows empty values.
+
+use hash_db::Hasher;
+use memory_db::{HashKey, MemoryDB};
+use std::{collections::BTreeMap, convert::TryFrom};
+use trie_db::{
+	decode_compact, encode_compact,
+	node::{Node, NodeHandle, NodePlan, Value},
+	proof::{generate_proof, verify_proof},
+	trie_visit, ChildReference, DBValue, NodeCodec, Trie, TrieDB, TrieDBMut, TrieDBNodeIterator,
+	TrieHash, TrieLayout, TrieMut, TrieRoot, TrieRootUnhashed,
+};
+use trie_root::TrieStream;
+use trie_standardmap::{Alphabet, StandardMap, ValueMode};
+
+type Input = [(Vec<u8>, Vec<u8>)];
+
+type MemoryDBFor<L> = MemoryDB<<L as TrieLayout>::Hash, HashKey<<L as TrieLayout>::Hash>, DBValue>;
+
+/// Byte masks applied to each byte of an encoded node by [`check_canonical_encoding`].
+const MUTATION_MASKS: [u8; 4] = [0x01, 0x0f, 0x10, 0x80];
+
+/// Inputs used by [`check_layout`].
+///
+/// They cover the empty trie, single leaves, keys prefixing other keys, inline and hashed
+/// child nodes, and value sizes on both sides of common inline value thresholds.
+pub fn standard_inputs() -> Vec<Vec<(Vec<u8>, Vec<u8>)>> {
+	let with_values = |keys: Vec<(Vec<u8>, Vec<u8>)>| {
+		keys.into_iter()
+			.enumerate()
+			.map(|(i, (key, _))| (key, vec![i as u8; 1 + i % 70]))
+			.collect::<Vec<_>>()
+	};
+	let inputs = [
+		vec![],
+		vec![(b"a".to_vec(), b"b".to_vec())],
+		vec![(vec![0x12, 0x34], vec![0x42; 100])],
+		vec![
+			(b"do".to_vec(), b"verb".to_vec()),
+			(b"dog".to_vec(), b"puppy".to_vec()),
+			(b"doge".to_vec(), vec![0; 32]),
+			(b"dogglesworth".to_vec(), vec![1; 40]),
+			(b"horse".to_vec(), b"stallion".to_vec()),
+			(b"house".to_vec(), vec![2; 33]),
+		],
+		StandardMap {
+			alphabet: Alphabet::All,
+			min_key: 1,
+			journal_key: 3,
+			value_mode: ValueMode::Index,
+			count: 200,
+		}
+		.make(),
+		with_values(
+			StandardMap {
+				alphabet: Alphabet::Low,
+				min_key: 3,
+				journal_key: 5,
+				value_mode: ValueMode::Index,
+				count: 150,
+			}
+			.make(),
+		),
+		with_values(
+			StandardMap {
+				alphabet: Alphabet::Mid,
+				min_key: 16,
+				journal_key: 16,
+				value_mode: ValueMode::Index,
+				count: 100,
+			}
+			.make(),
+		),
+	];
+	inputs.iter().map(|input| sorted(input)).collect()
+}
+
+/// Run every check on every input of [`standard_inputs`].
+///
+/// `S` is the `TrieStream` producing the same nodes as the layout's codec.
+pub fn check_layout<L: TrieLayout, S: TrieStream>() {
+	check_null_node::<L, S>();
+	for input in standard_inputs() {
+		check_node_round_trips::<L>(&input);
+		check_canonical_encoding::<L>(&input);
+		check_root_agreement::<L, S>(&input);
+		check_proofs::<L>(&input);
+	}
+}
+
+/// Check the empty node and its hash.
+pub fn check_null_node<L: TrieLayout, S: TrieStream>() {
+	let empty = L::Codec::empty_node();
+	assert!(L::Codec::is_empty_node(empty), "`is_empty_node` rejects `empty_node`");
+	assert_eq!(
+		L::Codec::hashed_null_node(),
+		L::Hash::hash(empty),
+		"`hashed_null_node` is not the hash of `empty_node`",
+	);
+	match L::Codec::decode_plan(empty) {
+		Ok(NodePlan::Empty) => (),
+		other => panic!("`empty_node` decodes to {:?}", other),
+	}
+
+	let mut db = new_db::<L>();
+	let mut root = Default::default();
+	TrieDBMut::<L>::new(&mut db, &mut root).commit();
+	assert_eq!(root, L::Codec::hashed_null_node(), "root of an empty `TrieDBMut`");
+	assert_eq!(stream_root::<L, S>(&[]), root, "root of an empty `TrieStream`");
+}
+
+/// Check that every node of the trie built from `input` decodes, and encodes back to the
+/// same bytes.
+pub fn check_node_round_trips<L: TrieLayout>(input: &Input) {
+	let (db, root) = build::<L>(input);
+	for encoded in trie_nodes::<L>(&db, &root) {
+		match reencode::<L>(&encoded) {
+			Ok(reencoded) => assert_eq!(
+				reencoded, encoded,
+				"node {:?} is encoded back as {:?}",
+				encoded, reencoded,
+			),
+			Err(e) => panic!("node {:?} does not round trip: {}", encoded, e),
+		}
+	}
+}
+
+/// Check that the node encoding is canonical.
+///
+/// Truncations, extensions and single byte mutations of every node of the trie built from
+/// `input` must either be rejected by `decode_plan` or encode back to the same bytes, so
+/// that no two encodings decode to the same node.
+pub fn check_canonical_encoding<L: TrieLayout>(input: &Input) {
+	let (db, root) = build::<L>(input);
+	let mut nodes = trie_nodes::<L>(&db, &root);
+	nodes.push(L::Codec::empty_node().to_vec());
+	for encoded in nodes {
+		let mut candidates = Vec::new();
+		for len in 0..encoded.len() {
+			candidates.push(encoded[..len].to_vec());
+		}
+		for extra in &[0x00, 0x01, 0x80, 0xff] {
+			let mut extended = encoded.clone();
+			extended.push(*extra);
+			candidates.push(extended);
+		}
+		for i in 0..encoded.len() {
+			for mask in &MUTATION_MASKS {
+				let mut mutated = encoded.clone();
+				mutated[i] ^= mask;
+				candidates.push(mutated);
+			}
+		}
+		for candidate in candidates {
+			if L::Codec::decode_plan(&candidate).is_err() {
+				continue
+			}
+			match reencode::<L>(&candidate) {
+				Ok(reencoded) => assert_eq!(
+					reencoded, candidate,
+					"non canonical encoding {:?} is accepted for node {:?}",
+					candidate, reencoded,
+				),
+				Err(e) => panic!("invalid encoding {:?} is accepted: {}", candidate, e),
+			}
+		}
+	}
+}
+
+/// Check that `trie-root` with `S`, `trie_visit` and `TrieDBMut` agree on the root of
+/// `input`, and that the unhashed roots agree too.
+pub fn check_root_agreement<L: TrieLayout, S: TrieStream>(input: &Input) {
+	let input = sorted(input);
+	let (_, root) = build::<L>(&input);
+
+	let mut visited = TrieRoot::<L>::default();
+	trie_visit::<L, _, _, _, _>(input.iter().cloned(), &mut visited);
+	assert_eq!(visited.root.unwrap_or_default(), root, "`trie_visit` and `TrieDBMut` roots");
+	assert_eq!(stream_root::<L, S>(&input), root, "`TrieStream` and `TrieDBMut` roots");
+
+	let unhashed = if L::USE_EXTENSION {
+		trie_root::unhashed_trie::<L::Hash, S, _, _, _>(input.clone(), L::MAX_INLINE_VALUE)
+	} else {
+		trie_root::unhashed_trie_no_extension::<L::Hash, S, _, _, _>(
+			input.clone(),
+			L::MAX_INLINE_VALUE,
+		)
+	};
+	let mut visited = TrieRootUnhashed::<L>::default();
+	trie_visit::<L, _, _, _, _>(input.iter().cloned(), &mut visited);
+	assert_eq!(
+		visited.root.unwrap_or_else(|| L::Codec::empty_node().to_vec()),
+		unhashed,
+		"`trie_visit` and `TrieStream` unhashed roots",
+	);
+}
+
+/// Check that `trie_visit` and `TrieDBMut` agree on the root of `input`, for layouts
+/// without a `TrieStream` such as those of a radix other than 16.
+pub fn check_visit_agreement<L: TrieLayout>(input: &Input) {
+	let input = sorted(input);
+	let (_, root) = build::<L>(&input);
+
+	let mut visited = TrieRoot::<L>::default();
+	trie_visit::<L, _, _, _, _>(input.iter().cloned(), &mut visited);
+	assert_eq!(visited.root.unwrap_or_default(), root, "`trie_visit` and `TrieDBMut` roots");
+}
+
+/// Check that proofs of present and absent keys of `input` verify, that proofs do not
+/// verify wrong values, and that compact encodings decode to the same trie.
+pub fn check_proofs<L: TrieLayout>(input: &Input) {
+	let (db, root) = build::<L>(input);
+	let trie = TrieDB::<L>::new(&db, &root);
+
+	let mut items: BTreeMap<Vec<u8>, Option<Vec<u8>>> = input
+		.iter()
+		.step_by(3)
+		.map(|(key, value)| (key.clone(), Some(value.clone())))
+		.collect();
+	for (key, _) in input.iter().step_by(5) {
+		for absent in [[&key[..], &[0x00]].concat(), key[..key.len().saturating_sub(1)].to_vec()] {
+			if !input.iter().any(|(k, _)| *k == absent) {
+				items.insert(absent, None);
+			}
+		}
+	}
+	if input.is_empty() {
+		items.insert(b"absent".to_vec(), None);
+	}
+	let items: Vec<_> = items.into_iter().collect();
+
+	let keys: Vec<_> = items.iter().map(|(key, _)| key.clone()).collect();
+	let proof = generate_proof::<_, L, _, _>(&trie, &keys)
+		.unwrap_or_else(|e| panic!("proof generation failed: {:?}", e));
+	if let Err(e) = verify_proof::<L, _, _, _>(&root, &proof, items.iter()) {
+		panic!("proof of {:?} does not verify: {:?}", keys, e);
+	}
+	if let Some((key, value)) = items.iter().find(|(_, value)| value.is_some()) {
+		let mut value = value.clone().expect("found a value; qed");
+		value.push(0);
+		let wrong = [(key.clone(), Some(value))];
+		assert!(
+			verify_proof::<L, _, _, _>(&root, &proof, wrong.iter()).is_err(),
+			"proof verifies a wrong value for {:?}",
+			key,
+		);
+	}
+
+	let compact =
+		encode_compact::<L>(&trie).unwrap_or_else(|e| panic!("compact encoding failed: {:?}", e));
+	let mut decoded = new_db::<L>();
+	let (decoded_root, _) = decode_compact::<L, _>(&mut decoded, &compact)
+		.unwrap_or_else(|e| panic!("compact decoding failed: {:?}", e));
+	assert_eq!(decoded_root, root, "root of the decoded compact encoding");
+	let decoded = TrieDB::<L>::new(&decoded, &root);
+	for (key, value) in input {
+		assert_eq!(
+			decoded.get(key).unwrap_or_else(|e| panic!("compact lookup failed: {:?}", e)),
+			Some(value.clone()),
+			"value of {:?} in the decoded compact encoding",
+			key,
+		);
+	}
+}
+
+fn new_db<L: TrieLayout>() -> MemoryDBFor<L> {
+	MemoryDB::new(L::Codec::empty_node())
+}
+
+fn sorted(input: &Input) -> Vec<(Vec<u8>, Vec<u8>)> {
+	input.iter().cloned().collect::<BTreeMap<_, _>>().into_iter().collect()
+}
+
+fn build<L: TrieLayout>(input: &Input) -> (MemoryDBFor<L>, TrieHash<L>) {
+	let mut db = new_db::<L>();
+	let mut root = Default::default();
+	{
+		let mut trie = TrieDBMut::<L>::new(&mut db, &mut root);
+		for (key, value) in input {
+			trie.insert(key, value).unwrap_or_else(|e| panic!("insertion failed: {:?}", e));
+		}
+	}
+	(db, root)
+}
+
+fn stream_root<L: TrieLayout, S: TrieStream>(input: &Input) -> TrieHash<L> {
+	let input = sorted(input);
+	if L::USE_EXTENSION {
+		trie_root::trie_root::<L::Hash, S, _, _, _>(input, L::MAX_INLINE_VALUE)
+	} else {
+		trie_root::trie_root_no_extension::<L::Hash, S, _, _, _>(input, L::MAX_INLINE_VALUE)
+	}
+}
+
+/// Encodings of all the nodes of a trie, inline nodes included.
+fn trie_nodes<L: TrieLayout>(db: &MemoryDBFor<L>, root: &TrieHash<L>) -> Vec<Vec<u8>> {
+	let trie = TrieDB::<L>::new(db, root);
+	let iter =
+		TrieDBNodeIterator::new(&trie).unwrap_or_else(|e| panic!("node iteration failed: {:?}", e));
+	iter.map(|item| match item {
+		Ok((_, _, node)) => node.data().to_vec(),
+		Err(e) => panic!("node iteration failed: {:?}", e),
+	})
+	.collect()
+}
+
+fn child_reference<L: TrieLayout>(
+	handle: NodeHandle,
+) -> Result<ChildReference<TrieHash<L>>, String> {
+	ChildReference::try_from(handle)
+		.map_err(|data| format!("child reference {:?} has an invalid length", data))
+}
+
+/// Decode a node and encode it back with the codec of the layout.
+fn reencode<L: TrieLayout>(encoded: &[u8]) -> Result<Vec<u8>, String> {
+	let node = L::Codec::decode(encoded).map_err(|e| format!("decoding failed: {:?}", e))?;
+	if let Some(Value::Node(hash, _)) = match &node {
+		Node::Leaf(_, value) => Some(value),
+		Node::Branch(_, value) | Node::NibbledBranch(_, _, value) => value.as_ref(),
+		Node::Empty | Node::Extension(..) => None,
+	} {
+		if hash.len() != L::Hash::LENGTH {
+			return Err(format!("value hash {:?} has an invalid length", hash))
+		}
+	}
+	let mut children = Vec::new();
+	if let Node::Branch(handles, _) | Node::NibbledBranch(_, handles, _) = &node {
+		for handle in handles.iter() {
+			children.push(handle.map(child_reference::<L>).transpose()?);
+		}
+	}
+	Ok(match node {
+		Node::Empty => L::Codec::empty_node().to_vec(),
+		Node::Leaf(partial, value) => L::Codec::leaf_node(partial.right(), value),
+		Node::Extension(partial, child) => L::Codec::extension_node(
+			partial.right_iter(),
+			partial.len(),
+			child_reference::<L>(child)?,
+		),
+		Node::Branch(_, value) => L::Codec::branch_node(children.into_iter(), value),
+		Node::NibbledBranch(partial, _, value) => L::Codec::branch_node_nibbled(
+			partial.right_iter(),
+			partial.len(),
+			children.into_iter(),
+			value,
+		),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use keccak_hasher::KeccakHasher;
+	use reference_trie::{
+		ExtensionLayout, HashedValueNoExt, HashedValueNoExtThreshold, Radix256Layout, Radix2Layout,
+		Radix4Layout, ReferenceTrieStream, ReferenceTrieStreamNoExt,
+	};
+
+	#[test]
+	fn reference_layouts_roots_and_proofs() {
+		fn check<L: TrieLayout, S: TrieStream>() {
+			check_null_node::<L, S>();
+			for input in standard_inputs() {
+				check_node_round_trips::<L>(&input);
+				check_root_agreement::<L, S>(&input);
+				check_proofs::<L>(&input);
+			}
+		}
+		check::<ExtensionLayout, ReferenceTrieStream>();
+		check::<HashedValueNoExt, ReferenceTrieStreamNoExt>();
+		check::<HashedValueNoExtThreshold, ReferenceTrieStreamNoExt>();
+	}
+
+	#[test]
+	fn reference_radix_layouts() {
+		fn check<L: TrieLayout>() {
+			for input in standard_inputs() {
+				check_node_round_trips::<L>(&input);
+				check_visit_agreement::<L>(&input);
+				check_proofs::<L>(&input);
+			}
+		}
+		check::<Radix2Layout>();
+		check::<Radix4Layout>();
+		check::<Radix256Layout>();
+	}
+
+	#[test]
+	#[should_panic(expected = "non canonical encoding")]
+	fn detects_trailing_bytes() {
+		// The reference codec ignores bytes following a node.
+		check_canonical_encoding::<ExtensionLayout>(&standard_inputs()[3]);
+	}
+
+	#[test]
+	fn ethereum_layout() {
+		check_layout::<ethereum_trie::EthereumLayout, ethereum_trie::RlpTrieStream>();
+	}
+
+	#[test]
+	fn substrate_layouts() {
+		check_layout::<substrate_trie::LayoutV0<KeccakHasher>, substrate_trie::TrieStream>();
+		check_layout::<substrate_trie::LayoutV1<KeccakHasher>, substrate_trie::TrieStream>();
+	}
+}
@@ -0,0 +1,338 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Trie construction from unsorted input, sorting in bounded memory.
+//!
+//! Input is buffered until `ExternalSort::run_size` bytes are held, then sorted and written
+//! to a run file. Runs are merged while being fed to `trie_visit`, first merging groups of
+//! `ExternalSort::max_merge_runs` runs into longer ones while there are more, so memory use and
+//! open files depend on these settings, not on the input size.
+
+use crate::{
+	iter_build::{trie_visit, ProcessEncodedNode, TrieBuilder, TrieRoot},
+	DBValue, HashDB, TrieHash, TrieLayout,
+};
+use std::{
+	cmp::Ordering,
+	collections::BinaryHeap,
+	fs::{self, File},
+	io::{self, BufReader, BufWriter, Read, Write},
+	mem,
+	path::{Path, PathBuf},
+	process,
+	sync::atomic::{self, AtomicUsize},
+};
+
+/// Default number of bytes buffered before writing a sorted run.
+pub const DEFAULT_RUN_SIZE: usize = 256 * 1024 * 1024;
+
+/// Default number of runs merged at once.
+pub const DEFAULT_MAX_MERGE_RUNS: usize = 64;
+
+/// Bytes counted for each buffered entry on top of its key and value: the two `Vec` headers and
+/// an allowance for the allocator bookkeeping of their buffers.
+const ENTRY_OVERHEAD: usize = 2 * mem::size_of::<Vec<u8>>() + 2 * 16;
+
+/// Counter making run file names unique within the process.
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Settings for sorting input that may not fit in memory.
+#[derive(Clone, Debug)]
+pub struct ExternalSort {
+	/// Existing directory where sorted runs are written. Runs are removed once merged.
+	pub dir: PathBuf,
+	/// Number of bytes held in memory before entries are sorted and written as a run. Each
+	/// entry counts its key and value bytes plus a fixed overhead for their allocations.
+	pub run_size: usize,
+	/// Maximum number of runs read at once. More runs are first merged in groups of this size
+	/// into longer runs. Values under 2 are treated as 2.
+	pub max_merge_runs: usize,
+}
+
+impl ExternalSort {
+	/// Sort writing runs of `DEFAULT_RUN_SIZE` bytes in `dir`, merging at most
+	/// `DEFAULT_MAX_MERGE_RUNS` runs at once.
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		ExternalSort {
+			dir: dir.into(),
+			run_size: DEFAULT_RUN_SIZE,
+			max_merge_runs: DEFAULT_MAX_MERGE_RUNS,
+		}
+	}
+
+	/// Set the number of bytes buffered before writing a run.
+	pub fn with_run_size(mut self, run_size: usize) -> Self {
+		self.run_size = run_size;
+		self
+	}
+
+	/// Set the maximum number of runs merged at once.
+	pub fn with_max_merge_runs(mut self, max_merge_runs: usize) -> Self {
+		self.max_merge_runs = max_merge_runs;
+		self
+	}
+}
+
+/// Function visiting the trie of unsorted `input`, see `trie_visit`.
+///
+/// When a key appears more than once, the last value is used. If an io error is returned,
+/// `callback` may have processed the nodes of a partial trie.
+pub fn trie_visit_unsorted<T, I, A, B, F>(
+	input: I,
+	sort: &ExternalSort,
+	callback: &mut F,
+) -> io::Result<()>
+where
+	T: TrieLayout,
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	F: ProcessEncodedNode<TrieHash<T>>,
+{
+	let mut buffer = Vec::new();
+	let mut buffered = 0;
+	let mut runs = Vec::new();
+	for (key, value) in input {
+		let (key, value) = (key.as_ref().to_vec(), value.as_ref().to_vec());
+		buffered += key.len() + value.len() + ENTRY_OVERHEAD;
+		buffer.push((key, value));
+		if buffered >= sort.run_size {
+			sort_run(&mut buffer);
+			runs.push(Run::write(&sort.dir, buffer.drain(..))?);
+			buffered = 0;
+		}
+	}
+	sort_run(&mut buffer);
+	if runs.is_empty() {
+		trie_visit::<T, _, _, _, _>(buffer, callback);
+		return Ok(())
+	}
+	if !buffer.is_empty() {
+		runs.push(Run::write(&sort.dir, buffer.drain(..))?);
+	}
+	drop(buffer);
+
+	let runs = merge_runs(runs, sort)?;
+	let mut merge = Merge::new(&runs)?;
+	trie_visit::<T, _, _, _, _>(&mut merge, callback);
+	merge.error.map_or(Ok(()), Err)
+}
+
+/// Trie root of unsorted `input`, see `trie_visit_unsorted`.
+pub fn trie_root_unsorted<T, I, A, B>(input: I, sort: &ExternalSort) -> io::Result<TrieHash<T>>
+where
+	T: TrieLayout,
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+{
+	let mut cb = TrieRoot::<T>::default();
+	trie_visit_unsorted::<T, _, _, _, _>(input, sort, &mut cb)?;
+	Ok(cb.root.unwrap_or_default())
+}
+
+/// Insert the trie of unsorted `input` in `db` and return its root, see
+/// `trie_visit_unsorted`.
+pub fn trie_build_unsorted<T, DB, I, A, B>(
+	db: &mut DB,
+	input: I,
+	sort: &ExternalSort,
+) -> io::Result<TrieHash<T>>
+where
+	T: TrieLayout,
+	DB: HashDB<T::Hash, DBValue>,
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+{
+	let mut cb = TrieBuilder::<T, DB>::new(db);
+	trie_visit_unsorted::<T, _, _, _, _>(input, sort, &mut cb)?;
+	Ok(cb.root.unwrap_or_default())
+}
+
+/// Sort entries by key, keeping the last value of duplicated keys.
+fn sort_run(buffer: &mut Vec<(Vec<u8>, Vec<u8>)>) {
+	buffer.reverse();
+	buffer.sort_by(|a, b| a.0.cmp(&b.0));
+	buffer.dedup_by(|a, b| a.0 == b.0);
+}
+
+/// Merge `runs` in groups of `sort.max_merge_runs` until no more than that many are left.
+/// Groups keep the order of the runs, so the last value of a key still wins.
+fn merge_runs(mut runs: Vec<Run>, sort: &ExternalSort) -> io::Result<Vec<Run>> {
+	let max_merge_runs = sort.max_merge_runs.max(2);
+	while runs.len() > max_merge_runs {
+		let mut merged = Vec::with_capacity(runs.len().div_ceil(max_merge_runs));
+		for group in runs.chunks(max_merge_runs) {
+			let mut merge = Merge::new(group)?;
+			let run = Run::write(&sort.dir, &mut merge)?;
+			if let Some(e) = merge.error {
+				return Err(e)
+			}
+			merged.push(run);
+		}
+		runs = merged;
+	}
+	Ok(runs)
+}
+
+/// Sorted entries written to a file, removed on drop.
+struct Run {
+	path: PathBuf,
+	len: usize,
+}
+
+impl Run {
+	fn write(
+		dir: &Path,
+		entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+	) -> io::Result<Self> {
+		let counter = RUN_COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+		let path = dir.join(format!("trie-sort-{}-{}.run", process::id(), counter));
+		let mut run = Run { path, len: 0 };
+		let mut file = BufWriter::new(File::create(&run.path)?);
+		for (key, value) in entries {
+			write_bytes(&mut file, &key)?;
+			write_bytes(&mut file, &value)?;
+			run.len += 1;
+		}
+		file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+		Ok(run)
+	}
+}
+
+impl Drop for Run {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+fn write_bytes(file: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+	file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+	file.write_all(bytes)
+}
+
+fn read_bytes(file: &mut impl Read) -> io::Result<Vec<u8>> {
+	let mut len = [0u8; 8];
+	file.read_exact(&mut len)?;
+	let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+	file.read_exact(&mut bytes)?;
+	Ok(bytes)
+}
+
+struct RunReader {
+	file: BufReader<File>,
+	remaining: usize,
+}
+
+impl RunReader {
+	fn next_entry(&mut self) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+		if self.remaining == 0 {
+			return Ok(None)
+		}
+		self.remaining -= 1;
+		let key = read_bytes(&mut self.file)?;
+		let value = read_bytes(&mut self.file)?;
+		Ok(Some((key, value)))
+	}
+}
+
+/// Next entry of a run, ordered for `BinaryHeap` to pop the smallest key first and, for equal
+/// keys, the entry of the latest run.
+struct HeapEntry {
+	key: Vec<u8>,
+	value: Vec<u8>,
+	run: usize,
+}
+
+impl Ord for HeapEntry {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.key.cmp(&self.key).then(self.run.cmp(&other.run))
+	}
+}
+
+impl PartialOrd for HeapEntry {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl PartialEq for HeapEntry {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for HeapEntry {}
+
+/// Iterator merging sorted runs. Stops at the first io error, which is kept in `error`.
+struct Merge {
+	readers: Vec<RunReader>,
+	heap: BinaryHeap<HeapEntry>,
+	error: Option<io::Error>,
+}
+
+impl Merge {
+	fn new(runs: &[Run]) -> io::Result<Self> {
+		let mut readers = Vec::with_capacity(runs.len());
+		for run in runs {
+			readers.push(RunReader {
+				file: BufReader::new(File::open(&run.path)?),
+				remaining: run.len,
+			});
+		}
+		let mut merge = Merge { readers, heap: BinaryHeap::new(), error: None };
+		for run in 0..runs.len() {
+			merge.refill(run);
+		}
+		match merge.error.take() {
+			Some(e) => Err(e),
+			None => Ok(merge),
+		}
+	}
+
+	fn refill(&mut self, run: usize) {
+		match self.readers[run].next_entry() {
+			Ok(Some((key, value))) => self.heap.push(HeapEntry { key, value, run }),
+			Ok(None) => (),
+			Err(e) => {
+				self.heap.clear();
+				self.error = Some(e);
+			},
+		}
+	}
+}
+
+impl Iterator for Merge {
+	type Item = (Vec<u8>, Vec<u8>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.error.is_some() {
+			return None
+		}
+		let entry = self.heap.pop()?;
+		self.refill(entry.run);
+		while let Some(next) = self.heap.peek() {
+			if next.key != entry.key {
+				break
+			}
+			let duplicate = self.heap.pop().expect("peeked entry; qed");
+			self.refill(duplicate.run);
+		}
+		if self.error.is_some() {
+			return None
+		}
+		Some((entry.key, entry.value))
+	}
+}
@@ -18,8 +18,8 @@
 //! See `trie_visit` function.
 
 use crate::{
-	nibble::{nibble_ops, NibbleSlice},
-	node::Value,
+	nibble::{nibble_ops::NibbleOps, NibbleSlice},
+	node::{Children, Value},
 	node_codec::NodeCodec,
 	rstd::{cmp::max, marker::PhantomData, vec::Vec},
 	triedbmut::ChildReference,
@@ -27,27 +27,17 @@ use crate::{
 };
 use hash_db::{HashDB, Hasher, Prefix};
 
-macro_rules! exponential_out {
-	(@3, [$($inpp:expr),*]) => { exponential_out!(@2, [$($inpp,)* $($inpp),*]) };
-	(@2, [$($inpp:expr),*]) => { exponential_out!(@1, [$($inpp,)* $($inpp),*]) };
-	(@1, [$($inpp:expr),*]) => { [$($inpp,)* $($inpp),*] };
-}
-
 type CacheNode<HO> = Option<ChildReference<HO>>;
 
-#[inline(always)]
-fn new_vec_slice_buffer<HO>() -> [CacheNode<HO>; 16] {
-	exponential_out!(@3, [None, None])
-}
-
-type ArrayNode<T> = [CacheNode<TrieHash<T>>; 16];
+type ArrayNode<T> = Children<<T as TrieLayout>::Nibble, CacheNode<TrieHash<T>>>;
 
 /// Struct containing iteration cache, can be at most the length of the lowest nibble.
 ///
 /// Note that it is not memory optimal (all depth are allocated even if some are empty due
 /// to node partial).
 /// Three field are used, a cache over the children, an optional associated value and the depth.
-struct CacheAccum<T: TrieLayout, V>(Vec<(ArrayNode<T>, Option<V>, usize)>);
+/// The second field is a buffer reused to encode every node.
+struct CacheAccum<T: TrieLayout, V>(Vec<(ArrayNode<T>, Option<V>, usize)>, Vec<u8>);
 
 /// Initially allocated cache depth.
 const INITIAL_DEPTH: usize = 10;
@@ -59,13 +49,13 @@ where
 {
 	fn new() -> Self {
 		let v = Vec::with_capacity(INITIAL_DEPTH);
-		CacheAccum(v)
+		CacheAccum(v, Vec::new())
 	}
 
 	#[inline(always)]
 	fn set_cache_value(&mut self, depth: usize, value: Option<V>) {
 		if self.0.is_empty() || self.0[self.0.len() - 1].2 < depth {
-			self.0.push((new_vec_slice_buffer(), None, depth));
+			self.0.push((Default::default(), None, depth));
 		}
 		let last = self.0.len() - 1;
 		debug_assert!(self.0[last].2 <= depth);
@@ -75,7 +65,7 @@ where
 	#[inline(always)]
 	fn set_node(&mut self, depth: usize, nibble_index: usize, node: CacheNode<TrieHash<T>>) {
 		if self.0.is_empty() || self.0[self.0.len() - 1].2 < depth {
-			self.0.push((new_vec_slice_buffer(), None, depth));
+			self.0.push((Default::default(), None, depth));
 		}
 
 		let last = self.0.len() - 1;
@@ -121,12 +111,12 @@ where
 		target_depth: usize,
 		(k2, v2): &(impl AsRef<[u8]>, impl AsRef<[u8]>),
 	) {
-		let nibble_value = nibble_ops::left_nibble_at(&k2.as_ref()[..], target_depth);
+		let nibble_value = T::Nibble::left_nibble_at(&k2.as_ref()[..], target_depth);
 		// is it a branch value (two candidate same ix)
-		let nkey = NibbleSlice::new_offset(&k2.as_ref()[..], target_depth + 1);
-		let pr = NibbleSlice::new_offset(
+		let nkey = NibbleSlice::<T::Nibble>::new_offset(&k2.as_ref()[..], target_depth + 1);
+		let pr = NibbleSlice::<T::Nibble>::new_offset(
 			&k2.as_ref()[..],
-			k2.as_ref().len() * nibble_ops::NIBBLE_PER_BYTE - nkey.len(),
+			k2.as_ref().len() * T::Nibble::NIBBLE_PER_BYTE - nkey.len(),
 		);
 
 		let hashed;
@@ -136,8 +126,9 @@ where
 			hashed = callback.process_inner_hashed_value((k2.as_ref(), None), v2.as_ref());
 			Value::Node(hashed.as_ref(), None)
 		};
-		let encoded = T::Codec::leaf_node(nkey.right(), value);
-		let hash = callback.process(pr.left(), encoded, false);
+		self.1.clear();
+		T::Codec::leaf_node_into(nkey.right(), value, &mut self.1);
+		let hash = callback.process(pr.left(), &self.1, false);
 
 		// insert hash in branch (first level branch only at this point)
 		self.set_node(target_depth, nibble_value as usize, Some(hash));
@@ -170,7 +161,7 @@ where
 			};
 			if !is_root {
 				// put hash in parent
-				let nibble: u8 = nibble_ops::left_nibble_at(&ref_branch.as_ref()[..], llix);
+				let nibble: u8 = T::Nibble::left_nibble_at(&ref_branch.as_ref()[..], llix);
 				self.set_node(llix, nibble as usize, Some(h));
 			}
 		}
@@ -191,14 +182,14 @@ where
 		let (children, v, depth) = self.0.pop().expect("checked");
 
 		debug_assert!(branch_d == depth);
-		let pr = NibbleSlice::new_offset(&key_branch, branch_d);
+		let pr = NibbleSlice::<T::Nibble>::new_offset(&key_branch, branch_d);
 
 		let hashed;
 		let value = if let Some(v) = v.as_ref() {
 			Some(if let Some(value) = Value::new_inline(v.as_ref(), T::MAX_INLINE_VALUE) {
 				value
 			} else {
-				let mut prefix = NibbleSlice::new_offset(&key_branch, 0);
+				let mut prefix = NibbleSlice::<T::Nibble>::new_offset(&key_branch, 0);
 				prefix.advance(branch_d);
 				hashed = callback.process_inner_hashed_value(prefix.left(), v.as_ref());
 				Value::Node(hashed.as_ref(), None)
@@ -208,14 +199,16 @@ where
 		};
 
 		// encode branch
-		let encoded = T::Codec::branch_node(children.iter(), value);
-		let branch_hash = callback.process(pr.left(), encoded, is_root && nkey.is_none());
+		self.1.clear();
+		T::Codec::branch_node_into(children.iter(), value, &mut self.1);
+		let branch_hash = callback.process(pr.left(), &self.1, is_root && nkey.is_none());
 
 		if let Some(nkeyix) = nkey {
-			let pr = NibbleSlice::new_offset(&key_branch, nkeyix.0);
+			let pr = NibbleSlice::<T::Nibble>::new_offset(&key_branch, nkeyix.0);
 			let nib = pr.right_range_iter(nkeyix.1);
-			let encoded = T::Codec::extension_node(nib, nkeyix.1, branch_hash);
-			callback.process(pr.left(), encoded, is_root)
+			self.1.clear();
+			T::Codec::extension_node_into(nib, nkeyix.1, branch_hash, &mut self.1);
+			callback.process(pr.left(), &self.1, is_root)
 		} else {
 			branch_hash
 		}
@@ -235,13 +228,13 @@ where
 		debug_assert!(branch_d == depth);
 		// encode branch
 		let nkeyix = nkey.unwrap_or((branch_d, 0));
-		let pr = NibbleSlice::new_offset(&key_branch, nkeyix.0);
+		let pr = NibbleSlice::<T::Nibble>::new_offset(&key_branch, nkeyix.0);
 		let hashed;
 		let value = if let Some(v) = v.as_ref() {
 			Some(if let Some(value) = Value::new_inline(v.as_ref(), T::MAX_INLINE_VALUE) {
 				value
 			} else {
-				let mut prefix = NibbleSlice::new_offset(&key_branch, 0);
+				let mut prefix = NibbleSlice::<T::Nibble>::new_offset(&key_branch, 0);
 				prefix.advance(branch_d);
 				hashed = callback.process_inner_hashed_value(prefix.left(), v.as_ref());
 				Value::Node(hashed.as_ref(), None)
@@ -250,13 +243,15 @@ where
 			None
 		};
 
-		let encoded = T::Codec::branch_node_nibbled(
+		self.1.clear();
+		T::Codec::branch_node_nibbled_into(
 			pr.right_range_iter(nkeyix.1),
 			nkeyix.1,
 			children.iter(),
 			value,
+			&mut self.1,
 		);
-		callback.process(pr.left(), encoded, is_root)
+		callback.process(pr.left(), &self.1, is_root)
 	}
 }
 
@@ -283,10 +278,10 @@ where
 		for (k, v) in iter_input {
 			single = false;
 			let common_depth =
-				nibble_ops::biggest_depth(&previous_value.0.as_ref()[..], &k.as_ref()[..]);
+				T::Nibble::biggest_depth(&previous_value.0.as_ref()[..], &k.as_ref()[..]);
 			// 0 is a reserved value : could use option
 			let depth_item = common_depth;
-			if common_depth == previous_value.0.as_ref().len() * nibble_ops::NIBBLE_PER_BYTE {
+			if common_depth == previous_value.0.as_ref().len() * T::Nibble::NIBBLE_PER_BYTE {
 				// the new key include the previous one : branch value case
 				// just stored value at branch depth
 				depth_queue.set_cache_value(common_depth, Some(previous_value.1));
@@ -307,10 +302,10 @@ where
 		if single {
 			// one single element corner case
 			let (k2, v2) = previous_value;
-			let nkey = NibbleSlice::new_offset(&k2.as_ref()[..], last_depth);
-			let pr = NibbleSlice::new_offset(
+			let nkey = NibbleSlice::<T::Nibble>::new_offset(&k2.as_ref()[..], last_depth);
+			let pr = NibbleSlice::<T::Nibble>::new_offset(
 				&k2.as_ref()[..],
-				k2.as_ref().len() * nibble_ops::NIBBLE_PER_BYTE - nkey.len(),
+				k2.as_ref().len() * T::Nibble::NIBBLE_PER_BYTE - nkey.len(),
 			);
 
 			let hashed;
@@ -321,8 +316,8 @@ where
 				Value::Node(hashed.as_ref(), None)
 			};
 
-			let encoded = T::Codec::leaf_node(nkey.right(), value);
-			callback.process(pr.left(), encoded, true);
+			T::Codec::leaf_node_into(nkey.right(), value, &mut depth_queue.1);
+			callback.process(pr.left(), &depth_queue.1, true);
 		} else {
 			depth_queue.flush_value(callback, last_depth, &previous_value);
 			let ref_branches = previous_value.0;
@@ -330,7 +325,7 @@ where
 		}
 	} else {
 		// nothing null root corner case
-		callback.process(hash_db::EMPTY_PREFIX, T::Codec::empty_node().to_vec(), true);
+		callback.process(hash_db::EMPTY_PREFIX, T::Codec::empty_node(), true);
 	}
 }
 
@@ -343,12 +338,8 @@ pub trait ProcessEncodedNode<HO> {
 	/// but usually it should be the Hash of encoded node.
 	/// This is not something direcly related to encoding but is here for
 	/// optimisation purpose (builder hash_db does return this value).
-	fn process(
-		&mut self,
-		prefix: Prefix,
-		encoded_node: Vec<u8>,
-		is_root: bool,
-	) -> ChildReference<HO>;
+	fn process(&mut self, prefix: Prefix, encoded_node: &[u8], is_root: bool)
+		-> ChildReference<HO>;
 
 	/// Callback for hashed value in encoded node.
 	fn process_inner_hashed_value(&mut self, prefix: Prefix, value: &[u8]) -> HO;
@@ -376,7 +367,7 @@ where
 	fn process(
 		&mut self,
 		prefix: Prefix,
-		encoded_node: Vec<u8>,
+		encoded_node: &[u8],
 		is_root: bool,
 	) -> ChildReference<TrieHash<T>> {
 		let len = encoded_node.len();
@@ -386,7 +377,7 @@ where
 
 			return ChildReference::Inline(h, len)
 		}
-		let hash = self.db.insert(prefix, &encoded_node[..]);
+		let hash = self.db.insert(prefix, encoded_node);
 		if is_root {
 			self.root = Some(hash);
 		};
@@ -414,7 +405,7 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRoot<T> {
 	fn process(
 		&mut self,
 		_: Prefix,
-		encoded_node: Vec<u8>,
+		encoded_node: &[u8],
 		is_root: bool,
 	) -> ChildReference<TrieHash<T>> {
 		let len = encoded_node.len();
@@ -424,7 +415,7 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRoot<T> {
 
 			return ChildReference::Inline(h, len)
 		}
-		let hash = <T::Hash as Hasher>::hash(encoded_node.as_slice());
+		let hash = <T::Hash as Hasher>::hash(encoded_node);
 		if is_root {
 			self.root = Some(hash);
 		};
@@ -470,7 +461,7 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRootPrint<T> {
 	fn process(
 		&mut self,
 		p: Prefix,
-		encoded_node: Vec<u8>,
+		encoded_node: &[u8],
 		is_root: bool,
 	) -> ChildReference<TrieHash<T>> {
 		println!("Encoded node: {:x?}", &encoded_node);
@@ -483,7 +474,7 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRootPrint<T> {
 			println!("	inline len {}", len);
 			return ChildReference::Inline(h, len)
 		}
-		let hash = <T::Hash as Hasher>::hash(encoded_node.as_slice());
+		let hash = <T::Hash as Hasher>::hash(encoded_node);
 		if is_root {
 			self.root = Some(hash);
 		};
@@ -501,7 +492,7 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRootUnhashed<T> {
 	fn process(
 		&mut self,
 		_: Prefix,
-		encoded_node: Vec<u8>,
+		encoded_node: &[u8],
 		is_root: bool,
 	) -> ChildReference<<T::Hash as Hasher>::Out> {
 		let len = encoded_node.len();
@@ -511,10 +502,10 @@ impl<T: TrieLayout> ProcessEncodedNode<TrieHash<T>> for TrieRootUnhashed<T> {
 
 			return ChildReference::Inline(h, len)
 		}
-		let hash = <T::Hash as Hasher>::hash(encoded_node.as_slice());
+		let hash = <T::Hash as Hasher>::hash(encoded_node);
 
 		if is_root {
-			self.root = Some(encoded_node);
+			self.root = Some(encoded_node.to_vec());
 		};
 		ChildReference::Hash(hash)
 	}
@@ -14,7 +14,7 @@
 
 use super::{CError, DBValue, Result, Trie, TrieHash, TrieIterator, TrieLayout};
 use crate::{
-	nibble::{nibble_ops, NibbleSlice, NibbleVec},
+	nibble::{nibble_ops::NibbleOps, NibbleSlice, NibbleVec},
 	node::{NodeHandle, NodePlan, OwnedNode},
 	triedb::TrieDB,
 };
@@ -33,13 +33,13 @@ enum Status {
 
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Eq, PartialEq)]
-struct Crumb<H: Hasher> {
+struct Crumb<H: Hasher, N: NibbleOps> {
 	hash: Option<H::Out>,
-	node: Rc<OwnedNode<DBValue>>,
+	node: Rc<OwnedNode<DBValue, N>>,
 	status: Status,
 }
 
-impl<H: Hasher> Crumb<H> {
+impl<H: Hasher, N: NibbleOps> Crumb<H, N> {
 	/// Move on to next status in the node's sequence.
 	fn increment(&mut self) {
 		self.status = match (self.status, self.node.node_plan()) {
@@ -50,7 +50,7 @@ impl<H: Hasher> Crumb<H> {
 			(Status::At, NodePlan::NibbledBranch { .. }) => Status::AtChild(0),
 			(Status::AtChild(x), NodePlan::Branch { .. }) |
 			(Status::AtChild(x), NodePlan::NibbledBranch { .. })
-				if x < (nibble_ops::NIBBLE_LENGTH - 1) =>
+				if x < (N::NIBBLE_LENGTH - 1) =>
 				Status::AtChild(x + 1),
 			_ => Status::Exiting,
 		}
@@ -60,15 +60,15 @@ impl<H: Hasher> Crumb<H> {
 /// Iterator for going through all nodes in the trie in pre-order traversal order.
 pub struct TrieDBNodeIterator<'a, L: TrieLayout> {
 	db: &'a TrieDB<'a, L>,
-	trail: Vec<Crumb<L::Hash>>,
-	key_nibbles: NibbleVec,
+	trail: Vec<Crumb<L::Hash, L::Nibble>>,
+	key_nibbles: NibbleVec<L::Nibble>,
 }
 
 /// When there is guaranties the storage backend do not change,
 /// this can be use to suspend and restore the iterator.
 pub struct SuspendedTrieDBNodeIterator<L: TrieLayout> {
-	trail: Vec<Crumb<L::Hash>>,
-	key_nibbles: NibbleVec,
+	trail: Vec<Crumb<L::Hash, L::Nibble>>,
+	key_nibbles: NibbleVec<L::Nibble>,
 }
 
 impl<L: TrieLayout> SuspendedTrieDBNodeIterator<L> {
@@ -90,7 +90,7 @@ impl<'a, L: TrieLayout> TrieDBNodeIterator<'a, L> {
 	}
 
 	/// Descend into a payload.
-	fn descend(&mut self, node: OwnedNode<DBValue>, node_hash: Option<TrieHash<L>>) {
+	fn descend(&mut self, node: OwnedNode<DBValue, L::Nibble>, node_hash: Option<TrieHash<L>>) {
 		self.trail
 			.push(Crumb { hash: node_hash, status: Status::Entering, node: Rc::new(node) });
 	}
@@ -119,7 +119,7 @@ impl<'a, L: TrieLayout> TrieDBNodeIterator<'a, L> {
 	fn seek_prefix(&mut self, key: &[u8]) -> Result<bool, TrieHash<L>, CError<L>> {
 		self.trail.clear();
 		self.key_nibbles.clear();
-		let key = NibbleSlice::new(key);
+		let key = NibbleSlice::<L::Nibble>::new(key);
 
 		let (mut node, mut node_hash) = self.db.get_raw_or_lookup(
 			<TrieHash<L>>::default(),
@@ -198,7 +198,7 @@ impl<'a, L: TrieLayout> TrieDBNodeIterator<'a, L> {
 							if slice < partial {
 								crumb.status = Status::Exiting;
 								self.key_nibbles.append_partial(slice.right());
-								self.key_nibbles.push((nibble_ops::NIBBLE_LENGTH - 1) as u8);
+								self.key_nibbles.push((L::Nibble::NIBBLE_LENGTH - 1) as u8);
 								return Ok(false)
 							}
 							return Ok(slice.starts_with(&partial))
@@ -269,7 +269,7 @@ impl<'a, L: TrieLayout> TrieDBNodeIterator<'a, L> {
 	) -> Result<(), TrieHash<L>, CError<L>> {
 		if seek.starts_with(prefix) {
 			self.seek_prefix(seek)?;
-			let prefix_len = prefix.len() * crate::nibble::nibble_ops::NIBBLE_PER_BYTE;
+			let prefix_len = prefix.len() * L::Nibble::NIBBLE_PER_BYTE;
 			let mut len = 0;
 			// look first prefix in trail
 			for i in 0..self.trail.len() {
@@ -313,15 +313,18 @@ impl<'a, L: TrieLayout> TrieIterator<L> for TrieDBNodeIterator<'a, L> {
 }
 
 impl<'a, L: TrieLayout> Iterator for TrieDBNodeIterator<'a, L> {
-	type Item =
-		Result<(NibbleVec, Option<TrieHash<L>>, Rc<OwnedNode<DBValue>>), TrieHash<L>, CError<L>>;
+	type Item = Result<
+		(NibbleVec<L::Nibble>, Option<TrieHash<L>>, Rc<OwnedNode<DBValue, L::Nibble>>),
+		TrieHash<L>,
+		CError<L>,
+	>;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		enum IterStep<O, E> {
+		enum IterStep<O, E, N: NibbleOps> {
 			YieldNode,
 			PopTrail,
 			Continue,
-			Descend(Result<(OwnedNode<DBValue>, Option<O>), O, E>),
+			Descend(Result<(OwnedNode<DBValue, N>, Option<O>), O, E>),
 		}
 		loop {
 			let iter_step = {
@@ -348,11 +351,13 @@ impl<'a, L: TrieLayout> Iterator for TrieDBNodeIterator<'a, L> {
 					(Status::At, NodePlan::Extension { partial: partial_plan, child }) => {
 						let partial = partial_plan.build(node_data);
 						self.key_nibbles.append_partial(partial.right());
-						IterStep::Descend::<TrieHash<L>, CError<L>>(self.db.get_raw_or_lookup(
-							b.hash.unwrap_or_default(),
-							child.build(node_data),
-							self.key_nibbles.as_prefix(),
-						))
+						IterStep::Descend::<TrieHash<L>, CError<L>, L::Nibble>(
+							self.db.get_raw_or_lookup(
+								b.hash.unwrap_or_default(),
+								child.build(node_data),
+								self.key_nibbles.as_prefix(),
+							),
+						)
 					},
 					(Status::At, NodePlan::Branch { .. }) => {
 						self.key_nibbles.push(0);
@@ -369,11 +374,13 @@ impl<'a, L: TrieLayout> Iterator for TrieDBNodeIterator<'a, L> {
 						if let Some(child) = &children[i] {
 							self.key_nibbles.pop();
 							self.key_nibbles.push(i as u8);
-							IterStep::Descend::<TrieHash<L>, CError<L>>(self.db.get_raw_or_lookup(
-								b.hash.unwrap_or_default(),
-								child.build(node_data),
-								self.key_nibbles.as_prefix(),
-							))
+							IterStep::Descend::<TrieHash<L>, CError<L>, L::Nibble>(
+								self.db.get_raw_or_lookup(
+									b.hash.unwrap_or_default(),
+									child.build(node_data),
+									self.key_nibbles.as_prefix(),
+								),
+							)
 						} else {
 							IterStep::Continue
 						}
@@ -407,10 +414,10 @@ impl<'a, L: TrieLayout> Iterator for TrieDBNodeIterator<'a, L> {
 					);
 					self.trail.last_mut()?.increment();
 				},
-				IterStep::Descend::<TrieHash<L>, CError<L>>(Ok((node, node_hash))) => {
+				IterStep::Descend::<TrieHash<L>, CError<L>, L::Nibble>(Ok((node, node_hash))) => {
 					self.descend(node, node_hash);
 				},
-				IterStep::Descend::<TrieHash<L>, CError<L>>(Err(err)) => {
+				IterStep::Descend::<TrieHash<L>, CError<L>, L::Nibble>(Err(err)) => {
 					// Increment here as there is an implicit PopTrail.
 					self.trail.last_mut()
 						.expect(
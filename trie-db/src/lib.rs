@@ -22,14 +22,14 @@ extern crate alloc;
 mod rstd {
 	pub use std::{
 		borrow, boxed, cmp, collections::VecDeque, convert, error::Error, fmt, hash, iter, marker,
-		mem, ops, rc, result, vec,
+		mem, ops, rc, result, slice, vec,
 	};
 }
 
 #[cfg(not(feature = "std"))]
 mod rstd {
 	pub use alloc::{boxed, collections::VecDeque, rc, vec};
-	pub use core::{borrow, cmp, convert, fmt, hash, iter, marker, mem, ops, result};
+	pub use core::{borrow, cmp, convert, fmt, hash, iter, marker, mem, ops, result, slice};
 	pub trait Error {}
 	impl<T> Error for T {}
 }
@@ -48,6 +48,8 @@ pub mod sectriedbmut;
 pub mod triedb;
 pub mod triedbmut;
 
+#[cfg(feature = "std")]
+mod external_sort;
 mod fatdb;
 mod fatdbmut;
 mod iter_build;
@@ -55,29 +57,51 @@ mod iterator;
 mod lookup;
 mod nibble;
 mod node_codec;
+mod ordered_root;
+mod partial_trie;
 mod trie_codec;
 
 pub use self::{
 	fatdb::{FatDB, FatDBIterator},
 	fatdbmut::FatDBMut,
 	lookup::Lookup,
-	nibble::{nibble_ops, NibbleSlice, NibbleVec},
-	recorder::{Record, Recorder},
+	nibble::{
+		nibble_ops::{self, NibbleOps, Radix16, Radix2, Radix256, Radix4},
+		NibbleSlice, NibbleVec,
+	},
+	recorder::{ProofBudget, Record, Recorder},
 	sectriedb::SecTrieDB,
 	sectriedbmut::SecTrieDBMut,
 	triedb::{TrieDB, TrieDBIterator, TrieDBKeyIterator},
-	triedbmut::{ChildReference, TrieDBMut, Value},
+	triedbmut::{ChildReference, PrefixRemoval, TrieDBMut, Value},
 };
 pub use crate::{
 	iter_build::{trie_visit, ProcessEncodedNode, TrieBuilder, TrieRoot, TrieRootUnhashed},
 	iterator::TrieDBNodeIterator,
 	node_codec::{NodeCodec, Partial},
-	trie_codec::{decode_compact, decode_compact_from_iter, encode_compact},
+	ordered_root::OrderedRootBuilder,
+	partial_trie::{PartialTrie, PartialTrieIterator, Proven},
+	trie_codec::{
+		decode_compact, decode_compact_from_iter, decode_compact_from_iter_with_limits,
+		decode_compact_with_limits, decode_compact_with_values,
+		decode_compact_with_values_with_limits, encode_compact, encode_compact_with_budget,
+		encode_compact_without_values,
+	},
 };
 pub use hash_db::{HashDB, HashDBRef, Hasher};
 
 #[cfg(feature = "std")]
-pub use crate::iter_build::TrieRootPrint;
+pub use crate::{
+	external_sort::{
+		trie_build_unsorted, trie_root_unsorted, trie_visit_unsorted, ExternalSort,
+		DEFAULT_MAX_MERGE_RUNS, DEFAULT_RUN_SIZE,
+	},
+	iter_build::TrieRootPrint,
+	trie_codec::{
+		decode_compact_from_reader, decode_compact_from_reader_with_limits,
+		encode_compact_to_writer, StreamError,
+	},
+};
 
 /// Database value
 pub type DBValue = Vec<u8>;
@@ -94,12 +118,18 @@ pub enum TrieError<T, E> {
 	IncompleteDatabase(T),
 	/// A value was found in the trie with a nibble key that was not byte-aligned.
 	/// The first parameter is the byte-aligned part of the prefix and the second parameter is the
-	/// remaining nibble.
+	/// last byte of the prefix, holding the remaining nibbles as in a `Prefix`.
 	ValueAtIncompleteKey(Vec<u8>, u8),
 	/// Corrupt Trie item.
 	DecoderError(T, E),
 	/// Hash is not value.
 	InvalidHash(T, Vec<u8>),
+	/// The proof being generated does not fit in this budget.
+	BudgetExceeded(ProofBudget),
+	/// The proof being decoded exceeds one of its verification limits.
+	LimitExceeded(proof::ExceededLimit),
+	/// The value of this key, omitted from a compact encoding, was not given back to the decoder.
+	MissingValue(Vec<u8>),
 }
 
 #[cfg(feature = "std")]
@@ -124,6 +154,13 @@ where
 				hash,
 				data.len()
 			),
+			TrieError::BudgetExceeded(ref budget) => write!(
+				f,
+				"Proof exceeds budget of {} nodes and {} bytes",
+				budget.max_nodes, budget.max_bytes
+			),
+			TrieError::LimitExceeded(ref limit) => write!(f, "Proof exceeds limit: {}", limit),
+			TrieError::MissingValue(ref key) => write!(f, "Missing value of key {:?}", key),
 		}
 	}
 }
@@ -160,6 +197,12 @@ pub trait Query<H: Hasher> {
 
 	/// Record that a node has been passed through.
 	fn record(&mut self, _hash: &H::Out, _data: &[u8], _depth: u32) {}
+
+	/// Check that the nodes recorded so far fit in the budget of the query, the lookup failing
+	/// with `TrieError::BudgetExceeded` otherwise.
+	fn check_budget(&self) -> rstd::result::Result<(), ProofBudget> {
+		Ok(())
+	}
 }
 
 impl<'a, H: Hasher> Query<H> for &'a mut Recorder<H::Out> {
@@ -170,6 +213,9 @@ impl<'a, H: Hasher> Query<H> for &'a mut Recorder<H::Out> {
 	fn record(&mut self, hash: &H::Out, data: &[u8], depth: u32) {
 		(&mut **self).record(hash, data, depth);
 	}
+	fn check_budget(&self) -> rstd::result::Result<(), ProofBudget> {
+		(**self).check_budget()
+	}
 }
 
 impl<F, T, H: Hasher> Query<H> for F
@@ -193,6 +239,9 @@ where
 	fn record(&mut self, hash: &H::Out, data: &[u8], depth: u32) {
 		self.0.record(hash, data, depth)
 	}
+	fn check_budget(&self) -> rstd::result::Result<(), ProofBudget> {
+		Recorder::check_budget(self.0)
+	}
 }
 
 /// A key-value datastore implemented as a database-backed modified Merkle tree.
@@ -442,8 +491,11 @@ pub trait TrieLayout {
 
 	/// Hasher to use for this trie.
 	type Hash: Hasher;
+	/// Nibble of the trie keys, selecting the trie radix: 1, 2, 4 or 8 bits
+	/// per key unit.
+	type Nibble: NibbleOps;
 	/// Codec to use (needs to match hasher and nibble ops).
-	type Codec: NodeCodec<HashOut = <Self::Hash as Hasher>::Out>;
+	type Codec: NodeCodec<HashOut = <Self::Hash as Hasher>::Out, Nibble = Self::Nibble>;
 }
 
 /// This trait associates a trie definition with preferred methods.
@@ -484,6 +536,55 @@ pub trait TrieConfiguration: Sized + TrieLayout {
 		trie_visit::<Self, _, _, _, _>(input.into_iter(), &mut cb);
 		cb.root.unwrap_or_default()
 	}
+	/// Operation to build a trie db from its key/values, keys being hashed with `Self::Hash`
+	/// as done by `SecTrieDBMut` and `FatDBMut`.
+	fn sec_trie_build<DB, I, A, B>(db: &mut DB, input: I) -> <Self::Hash as Hasher>::Out
+	where
+		<Self::Hash as Hasher>::Out: Ord,
+		DB: HashDB<Self::Hash, DBValue>,
+		I: IntoIterator<Item = (A, B)>,
+		A: AsRef<[u8]>,
+		B: AsRef<[u8]>,
+	{
+		Self::sec_trie_build_with_key_hasher::<Self::Hash, _, _, _, _>(db, input)
+	}
+	/// Operation to build a trie db from its key/values, keys being hashed with `K`.
+	fn sec_trie_build_with_key_hasher<K, DB, I, A, B>(
+		db: &mut DB,
+		input: I,
+	) -> <Self::Hash as Hasher>::Out
+	where
+		K: Hasher,
+		K::Out: Ord,
+		DB: HashDB<Self::Hash, DBValue>,
+		I: IntoIterator<Item = (A, B)>,
+		A: AsRef<[u8]>,
+		B: AsRef<[u8]>,
+	{
+		Self::trie_build(db, sorted_hashed_keys::<K, _, _, _>(input))
+	}
+	/// Determines a trie root given its key/values, keys being hashed with `Self::Hash` as
+	/// done by `SecTrieDBMut` and `FatDBMut`.
+	fn sec_trie_root<I, A, B>(input: I) -> <Self::Hash as Hasher>::Out
+	where
+		<Self::Hash as Hasher>::Out: Ord,
+		I: IntoIterator<Item = (A, B)>,
+		A: AsRef<[u8]>,
+		B: AsRef<[u8]>,
+	{
+		Self::sec_trie_root_with_key_hasher::<Self::Hash, _, _, _>(input)
+	}
+	/// Determines a trie root given its key/values, keys being hashed with `K`.
+	fn sec_trie_root_with_key_hasher<K, I, A, B>(input: I) -> <Self::Hash as Hasher>::Out
+	where
+		K: Hasher,
+		K::Out: Ord,
+		I: IntoIterator<Item = (A, B)>,
+		A: AsRef<[u8]>,
+		B: AsRef<[u8]>,
+	{
+		Self::trie_root(sorted_hashed_keys::<K, _, _, _>(input))
+	}
 	/// Encoding of index as a key (when reusing general trie for
 	/// indexed trie).
 	fn encode_index(input: u32) -> Vec<u8> {
@@ -503,6 +604,21 @@ pub trait TrieConfiguration: Sized + TrieLayout {
 	}
 }
 
+/// Hash keys with `K` and sort them, keeping the last value of duplicated keys.
+fn sorted_hashed_keys<K, I, A, B>(input: I) -> Vec<(K::Out, B)>
+where
+	K: Hasher,
+	K::Out: Ord,
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+{
+	let mut input: Vec<_> = input.into_iter().map(|(k, v)| (K::hash(k.as_ref()), v)).collect();
+	input.reverse();
+	input.sort_by_key(|a| a.0);
+	input.dedup_by(|a, b| a.0 == b.0);
+	input
+}
+
 /// Alias accessor to hasher hash output type from a `TrieLayout`.
 pub type TrieHash<L> = <<L as TrieLayout>::Hash as Hasher>::Out;
 /// Alias accessor to `NodeCodec` associated `Error` type from a `TrieLayout`.
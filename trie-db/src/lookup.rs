@@ -38,6 +38,10 @@ where
 	L: TrieLayout,
 	Q: Query<L::Hash>,
 {
+	fn check_budget(&self) -> Result<(), TrieHash<L>, CError<L>> {
+		self.query.check_budget().map_err(|budget| Box::new(TrieError::BudgetExceeded(budget)))
+	}
+
 	fn decode(
 		mut self,
 		v: Value,
@@ -52,6 +56,7 @@ where
 				res.as_mut().copy_from_slice(hash);
 				if let Some(value) = self.db.get(&res, prefix) {
 					self.query.record(&res, &value, depth);
+					self.check_budget()?;
 					Ok(self.query.decode(value.as_slice()))
 				} else {
 					Err(Box::new(TrieError::IncompleteDatabase(res)))
@@ -62,7 +67,10 @@ where
 
 	/// Look up the given key. If the value is found, it will be passed to the given
 	/// function to decode or copy.
-	pub fn look_up(mut self, key: NibbleSlice) -> Result<Option<Q::Item>, TrieHash<L>, CError<L>> {
+	pub fn look_up(
+		mut self,
+		key: NibbleSlice<L::Nibble>,
+	) -> Result<Option<Q::Item>, TrieHash<L>, CError<L>> {
 		let mut partial = key;
 		let mut key_nibbles = 0;
 
@@ -83,6 +91,7 @@ where
 			};
 
 			self.query.record(&hash, &node_data, depth);
+			self.check_budget()?;
 
 			// this loop iterates through all inline children (usually max 1)
 			// without incrementing the depth.
@@ -14,7 +14,11 @@
 
 //! Nibble oriented methods.
 
-use crate::{node::NodeKey, rstd::cmp};
+use crate::{
+	node::NodeKey,
+	rstd::{cmp, marker::PhantomData},
+};
+use hash_db::MaybeDebug;
 
 pub use self::leftnibbleslice::LeftNibbleSlice;
 
@@ -22,125 +26,191 @@ mod leftnibbleslice;
 mod nibbleslice;
 mod nibblevec;
 
-/// Utility methods to work on radix 16 nibble.
+/// Utility methods to work on nibbles of a configurable radix.
 pub mod nibble_ops {
 	use super::*;
 
-	/// Single nibble length in bit.
-	pub const BIT_PER_NIBBLE: usize = 4;
-	/// Number of nibble per byte.
-	pub const NIBBLE_PER_BYTE: usize = 2;
-	/// Number of child for a branch (trie radix).
-	pub const NIBBLE_LENGTH: usize = 16;
-	/// Nibble (half a byte).
-	pub const PADDING_BITMASK: u8 = 0x0F;
 	/// Size of header.
 	pub const CONTENT_HEADER_SIZE: u8 = 1;
 
-	/// Mask a byte, keeping left nibble.
-	#[inline(always)]
-	pub fn pad_left(b: u8) -> u8 {
-		b & !PADDING_BITMASK
-	}
+	/// Nibble size of a trie, with the methods working on nibbles of this size.
+	///
+	/// A nibble is the unit of a trie key: each byte of a key is split in
+	/// `NIBBLE_PER_BYTE` nibbles of `BIT_PER_NIBBLE` bits, and a branch has a child
+	/// for each of the `NIBBLE_LENGTH` possible nibble values. Implemented by
+	/// [`Radix2`], [`Radix4`], [`Radix16`] and [`Radix256`].
+	pub trait NibbleOps:
+		'static + Copy + Clone + Default + PartialEq + Eq + Send + Sync + MaybeDebug
+	{
+		/// Single nibble length in bit, one of 1, 2, 4 or 8.
+		const BIT_PER_NIBBLE: usize;
+		/// Number of nibble per byte.
+		const NIBBLE_PER_BYTE: usize = 8 / Self::BIT_PER_NIBBLE;
+		/// Number of child for a branch (trie radix).
+		const NIBBLE_LENGTH: usize = 1 << Self::BIT_PER_NIBBLE;
+		/// Mask of the last nibble of a byte.
+		const PADDING_BITMASK: u8 = 0xff >> (8 - Self::BIT_PER_NIBBLE);
 
-	/// Mask a byte, keeping right byte.
-	#[inline(always)]
-	pub fn pad_right(b: u8) -> u8 {
-		b & PADDING_BITMASK
-	}
+		/// Fixed size array with an item for each child of a branch,
+		/// that is `[T; NIBBLE_LENGTH]`.
+		type ChildArray<T>: AsRef<[T]> + AsMut<[T]> + IntoIterator<Item = T>;
 
-	/// Get u8 nibble value at a given index of a byte.
-	#[inline(always)]
-	pub fn at_left(ix: u8, b: u8) -> u8 {
-		if ix == 1 {
-			b & PADDING_BITMASK
-		} else {
-			b >> BIT_PER_NIBBLE
+		/// Build a `ChildArray` from the item of each child index.
+		fn child_array<T>(f: impl FnMut(usize) -> T) -> Self::ChildArray<T>;
+
+		/// Mask a byte, keeping its `nibbles` first (left) nibbles.
+		#[inline(always)]
+		fn pad_left(nibbles: usize, b: u8) -> u8 {
+			if nibbles >= Self::NIBBLE_PER_BYTE {
+				b
+			} else {
+				b & !(0xff >> (nibbles * Self::BIT_PER_NIBBLE))
+			}
 		}
-	}
 
-	/// Get u8 nibble value at a given index in a left aligned array.
-	#[inline(always)]
-	pub fn left_nibble_at(v1: &[u8], ix: usize) -> u8 {
-		at_left((ix % NIBBLE_PER_BYTE) as u8, v1[ix / NIBBLE_PER_BYTE])
-	}
+		/// Mask a byte, keeping its `nibbles` last (right) nibbles.
+		#[inline(always)]
+		fn pad_right(nibbles: usize, b: u8) -> u8 {
+			if nibbles >= Self::NIBBLE_PER_BYTE {
+				b
+			} else {
+				b & !(0xff << (nibbles * Self::BIT_PER_NIBBLE))
+			}
+		}
 
-	/// Get u8 nibble value at a given index in a `NibbleSlice`.
-	#[inline(always)]
-	pub fn at(s: &NibbleSlice, i: usize) -> u8 {
-		let ix = (s.offset + i) / NIBBLE_PER_BYTE;
-		let pad = (s.offset + i) % NIBBLE_PER_BYTE;
-		at_left(pad as u8, s.data[ix])
-	}
+		/// Last byte of a `Prefix` ending with the `nibbles` first nibbles of `b`,
+		/// `nibbles` being less than `NIBBLE_PER_BYTE`.
+		///
+		/// When a byte can end with more than one count of nibbles, a marker bit follows
+		/// the nibbles so the count can be read back with `prefix_last_len`.
+		#[inline(always)]
+		fn prefix_last(nibbles: usize, b: u8) -> u8 {
+			let padded = Self::pad_left(nibbles, b);
+			if Self::NIBBLE_PER_BYTE > 2 {
+				padded | 0x80 >> (nibbles * Self::BIT_PER_NIBBLE)
+			} else {
+				padded
+			}
+		}
 
-	/// Push u8 nibble value at a given index into an existing byte.
-	#[inline(always)]
-	pub fn push_at_left(ix: u8, v: u8, into: u8) -> u8 {
-		into | if ix == 1 { v } else { v << BIT_PER_NIBBLE }
-	}
+		/// Number of nibbles of a `Prefix` last byte built by `prefix_last`.
+		#[inline(always)]
+		fn prefix_last_len(b: u8) -> usize {
+			if Self::NIBBLE_PER_BYTE > 2 {
+				(7 - b.trailing_zeros() as usize) / Self::BIT_PER_NIBBLE
+			} else {
+				1
+			}
+		}
 
-	#[inline]
-	/// Calculate the number of needed padding a array of nibble length `i`.
-	pub fn number_padding(i: usize) -> usize {
-		i % NIBBLE_PER_BYTE
-	}
+		/// Get u8 nibble value at a given index of a byte.
+		#[inline(always)]
+		fn at_left(ix: u8, b: u8) -> u8 {
+			(b >> (8 - Self::BIT_PER_NIBBLE * (ix as usize + 1))) & Self::PADDING_BITMASK
+		}
 
-	/// The nibble shifts needed to align.
-	/// We use two value, one is a left shift and
-	/// the other is a right shift.
-	pub const SPLIT_SHIFTS: (usize, usize) = (4, 4);
-
-	/// Count the biggest common depth between two left aligned packed nibble slice.
-	pub fn biggest_depth(v1: &[u8], v2: &[u8]) -> usize {
-		let upper_bound = cmp::min(v1.len(), v2.len());
-		for a in 0..upper_bound {
-			if v1[a] != v2[a] {
-				return a * NIBBLE_PER_BYTE + left_common(v1[a], v2[a])
+		/// Get u8 nibble value at a given index in a left aligned array.
+		#[inline(always)]
+		fn left_nibble_at(v1: &[u8], ix: usize) -> u8 {
+			Self::at_left((ix % Self::NIBBLE_PER_BYTE) as u8, v1[ix / Self::NIBBLE_PER_BYTE])
+		}
+
+		/// Push u8 nibble value at a given index into an existing byte.
+		#[inline(always)]
+		fn push_at_left(ix: u8, v: u8, into: u8) -> u8 {
+			into | v << (8 - Self::BIT_PER_NIBBLE * (ix as usize + 1))
+		}
+
+		/// Calculate the number of needed padding a array of nibble length `i`.
+		#[inline]
+		fn number_padding(i: usize) -> usize {
+			(Self::NIBBLE_PER_BYTE - i % Self::NIBBLE_PER_BYTE) % Self::NIBBLE_PER_BYTE
+		}
+
+		/// Count the biggest common depth between two left aligned packed nibble slice.
+		fn biggest_depth(v1: &[u8], v2: &[u8]) -> usize {
+			let upper_bound = cmp::min(v1.len(), v2.len());
+			for a in 0..upper_bound {
+				if v1[a] != v2[a] {
+					return a * Self::NIBBLE_PER_BYTE + Self::left_common(v1[a], v2[a])
+				}
 			}
+			upper_bound * Self::NIBBLE_PER_BYTE
 		}
-		upper_bound * NIBBLE_PER_BYTE
-	}
 
-	/// Calculate the number of common nibble between two left aligned bytes.
-	#[inline(always)]
-	pub fn left_common(a: u8, b: u8) -> usize {
-		if a == b {
-			2
-		} else if pad_left(a) == pad_left(b) {
-			1
-		} else {
-			0
+		/// Calculate the number of common nibble between two left aligned bytes.
+		#[inline(always)]
+		fn left_common(a: u8, b: u8) -> usize {
+			(a ^ b).leading_zeros() as usize / Self::BIT_PER_NIBBLE
 		}
-	}
 
-	/// Shifts right aligned key to add a given left offset.
-	/// Resulting in possibly padding at both left and right
-	/// (example usage when combining two keys).
-	pub fn shift_key(key: &mut NodeKey, offset: usize) -> bool {
-		let old_offset = key.0;
-		key.0 = offset;
-		if old_offset > offset {
-			// shift left
-			let (s1, s2) = nibble_ops::SPLIT_SHIFTS;
-			let kl = key.1.len();
-			(0..kl - 1).for_each(|i| key.1[i] = key.1[i] << s2 | key.1[i + 1] >> s1);
-			key.1[kl - 1] = key.1[kl - 1] << s2;
-			true
-		} else if old_offset < offset {
-			// shift right
-			let (s1, s2) = nibble_ops::SPLIT_SHIFTS;
-			key.1.push(0);
-			(1..key.1.len())
-				.rev()
-				.for_each(|i| key.1[i] = key.1[i - 1] << s1 | key.1[i] >> s2);
-			key.1[0] = key.1[0] >> s2;
-			true
-		} else {
-			false
+		/// Shifts right aligned key to add a given left offset.
+		/// Resulting in possibly padding at both left and right
+		/// (example usage when combining two keys).
+		fn shift_key(key: &mut NodeKey, offset: usize) -> bool {
+			let old_offset = key.0;
+			key.0 = offset;
+			if old_offset > offset {
+				// shift left
+				let s2 = (old_offset - offset) * Self::BIT_PER_NIBBLE;
+				let s1 = 8 - s2;
+				let kl = key.1.len();
+				(0..kl - 1).for_each(|i| key.1[i] = key.1[i] << s2 | key.1[i + 1] >> s1);
+				key.1[kl - 1] = key.1[kl - 1] << s2;
+				true
+			} else if old_offset < offset {
+				// shift right
+				let s2 = (offset - old_offset) * Self::BIT_PER_NIBBLE;
+				let s1 = 8 - s2;
+				key.1.push(0);
+				(1..key.1.len())
+					.rev()
+					.for_each(|i| key.1[i] = key.1[i - 1] << s1 | key.1[i] >> s2);
+				key.1[0] = key.1[0] >> s2;
+				true
+			} else {
+				false
+			}
 		}
 	}
+
+	macro_rules! radix {
+		($(#[$doc:meta])* $name:ident, $bits:expr, $length:expr) => {
+			$(#[$doc])*
+			#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+			pub struct $name;
+
+			impl NibbleOps for $name {
+				const BIT_PER_NIBBLE: usize = $bits;
+				type ChildArray<T> = [T; $length];
+
+				fn child_array<T>(f: impl FnMut(usize) -> T) -> [T; $length] {
+					core::array::from_fn(f)
+				}
+			}
+		};
+	}
+
+	radix!(
+		/// Binary trie: one bit per nibble, two children per branch.
+		Radix2, 1, 2
+	);
+	radix!(
+		/// Quaternary trie: two bits per nibble, four children per branch.
+		Radix4, 2, 4
+	);
+	radix!(
+		/// Hexary trie: half a byte per nibble, sixteen children per branch.
+		Radix16, 4, 16
+	);
+	radix!(
+		/// Byte trie: a byte per nibble, 256 children per branch.
+		Radix256, 8, 256
+	);
 }
 
+use nibble_ops::NibbleOps;
+
 /// Backing storage for `NibbleVec`s.
 pub(crate) type BackingByteVec = smallvec::SmallVec<[u8; 36]>;
 
@@ -149,9 +219,10 @@ pub(crate) type BackingByteVec = smallvec::SmallVec<[u8; 36]>;
 /// a `NibbleSlice` can get costy.
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Clone, PartialEq, Eq)]
-pub struct NibbleVec {
+pub struct NibbleVec<N> {
 	inner: BackingByteVec,
 	len: usize,
+	_marker: PhantomData<N>,
 }
 
 /// Nibble-orientated view onto byte-slice, allowing nibble-precision offsets.
@@ -177,13 +248,14 @@ pub struct NibbleVec {
 /// }
 /// ```
 #[derive(Copy, Clone)]
-pub struct NibbleSlice<'a> {
+pub struct NibbleSlice<'a, N> {
 	data: &'a [u8],
 	offset: usize,
+	_marker: PhantomData<N>,
 }
 
 /// Iterator type for a nibble slice.
-pub struct NibbleSliceIterator<'a> {
-	p: &'a NibbleSlice<'a>,
+pub struct NibbleSliceIterator<'a, N: NibbleOps> {
+	p: &'a NibbleSlice<'a, N>,
 	i: usize,
 }
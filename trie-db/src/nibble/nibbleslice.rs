@@ -14,13 +14,17 @@
 
 //! Nibble-orientated view onto byte-slice, allowing nibble-precision offsets.
 
-use super::{nibble_ops, BackingByteVec, NibbleSlice, NibbleSliceIterator};
+use super::{nibble_ops::NibbleOps, BackingByteVec, NibbleSlice, NibbleSliceIterator};
 #[cfg(feature = "std")]
 use crate::rstd::fmt;
-use crate::{node::NodeKey, node_codec::Partial, rstd::cmp::*};
+use crate::{
+	node::NodeKey,
+	node_codec::Partial,
+	rstd::{cmp::*, marker::PhantomData},
+};
 use hash_db::Prefix;
 
-impl<'a> Iterator for NibbleSliceIterator<'a> {
+impl<'a, N: NibbleOps> Iterator for NibbleSliceIterator<'a, N> {
 	type Item = u8;
 	fn next(&mut self) -> Option<u8> {
 		self.i += 1;
@@ -31,7 +35,7 @@ impl<'a> Iterator for NibbleSliceIterator<'a> {
 	}
 }
 
-impl<'a> NibbleSlice<'a> {
+impl<'a, N: NibbleOps> NibbleSlice<'a, N> {
 	/// Create a new nibble slice with the given byte-slice.
 	pub fn new(data: &'a [u8]) -> Self {
 		NibbleSlice::new_slice(data, 0)
@@ -43,23 +47,23 @@ impl<'a> NibbleSlice<'a> {
 	}
 
 	fn new_slice(data: &'a [u8], offset: usize) -> Self {
-		NibbleSlice { data, offset }
+		NibbleSlice { data, offset, _marker: PhantomData }
 	}
 
 	/// Get an iterator for the series of nibbles.
-	pub fn iter(&'a self) -> NibbleSliceIterator<'a> {
+	pub fn iter(&'a self) -> NibbleSliceIterator<'a, N> {
 		NibbleSliceIterator { p: self, i: 0 }
 	}
 
 	/// Get nibble slice from a `NodeKey`.
-	pub fn from_stored(i: &NodeKey) -> NibbleSlice {
+	pub fn from_stored(i: &NodeKey) -> NibbleSlice<N> {
 		NibbleSlice::new_offset(&i.1[..], i.0)
 	}
 
 	/// Helper function to create a owned `NodeKey` from this `NibbleSlice`.
 	pub fn to_stored(&self) -> NodeKey {
-		let split = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-		let offset = self.offset % nibble_ops::NIBBLE_PER_BYTE;
+		let split = self.offset / N::NIBBLE_PER_BYTE;
+		let offset = self.offset % N::NIBBLE_PER_BYTE;
 		(offset, self.data[split..].into())
 	}
 
@@ -71,23 +75,20 @@ impl<'a> NibbleSlice<'a> {
 		if nb >= self.len() {
 			return self.to_stored()
 		}
-		if (self.offset + nb) % nibble_ops::NIBBLE_PER_BYTE == 0 {
+		if (self.offset + nb) % N::NIBBLE_PER_BYTE == 0 {
 			// aligned
-			let start = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-			let end = (self.offset + nb) / nibble_ops::NIBBLE_PER_BYTE;
-			(
-				self.offset % nibble_ops::NIBBLE_PER_BYTE,
-				BackingByteVec::from_slice(&self.data[start..end]),
-			)
+			let start = self.offset / N::NIBBLE_PER_BYTE;
+			let end = (self.offset + nb) / N::NIBBLE_PER_BYTE;
+			(self.offset % N::NIBBLE_PER_BYTE, BackingByteVec::from_slice(&self.data[start..end]))
 		} else {
 			// unaligned
-			let start = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-			let end = (self.offset + nb) / nibble_ops::NIBBLE_PER_BYTE;
+			let start = self.offset / N::NIBBLE_PER_BYTE;
+			let end = (self.offset + nb) / N::NIBBLE_PER_BYTE;
 			let ea = BackingByteVec::from_slice(&self.data[start..=end]);
-			let ea_offset = self.offset % nibble_ops::NIBBLE_PER_BYTE;
-			let n_offset = nibble_ops::number_padding(nb);
+			let ea_offset = self.offset % N::NIBBLE_PER_BYTE;
+			let n_offset = N::number_padding(nb);
 			let mut result = (ea_offset, ea);
-			nibble_ops::shift_key(&mut result, n_offset);
+			N::shift_key(&mut result, n_offset);
 			result.1.pop();
 			result
 		}
@@ -101,18 +102,18 @@ impl<'a> NibbleSlice<'a> {
 	/// Get the length (in nibbles, naturally) of this slice.
 	#[inline]
 	pub fn len(&self) -> usize {
-		self.data.len() * nibble_ops::NIBBLE_PER_BYTE - self.offset
+		self.data.len() * N::NIBBLE_PER_BYTE - self.offset
 	}
 
 	/// Get the nibble at position `i`.
 	#[inline(always)]
 	pub fn at(&self, i: usize) -> u8 {
-		nibble_ops::at(&self, i)
+		N::left_nibble_at(self.data, self.offset + i)
 	}
 
 	/// Return object which represents a view on to this slice (further) offset by `i` nibbles.
-	pub fn mid(&self, i: usize) -> NibbleSlice<'a> {
-		NibbleSlice { data: self.data, offset: self.offset + i }
+	pub fn mid(&self, i: usize) -> NibbleSlice<'a, N> {
+		NibbleSlice::new_slice(self.data, self.offset + i)
 	}
 
 	/// Advance the view on the slice by `i` nibbles.
@@ -122,8 +123,8 @@ impl<'a> NibbleSlice<'a> {
 	}
 
 	/// Move back to a previously valid fix offset position.
-	pub fn back(&self, i: usize) -> NibbleSlice<'a> {
-		NibbleSlice { data: self.data, offset: i }
+	pub fn back(&self, i: usize) -> NibbleSlice<'a, N> {
+		NibbleSlice::new_slice(self.data, i)
 	}
 
 	/// Do we start with the same nibbles as the whole of `them`?
@@ -147,10 +148,10 @@ impl<'a> NibbleSlice<'a> {
 	/// Return `Partial` representation of this slice:
 	/// first encoded byte and following slice.
 	pub fn right(&'a self) -> Partial {
-		let split = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-		let nb = (self.len() % nibble_ops::NIBBLE_PER_BYTE) as u8;
+		let split = self.offset / N::NIBBLE_PER_BYTE;
+		let nb = self.len() % N::NIBBLE_PER_BYTE;
 		if nb > 0 {
-			((nb, nibble_ops::pad_right(self.data[split])), &self.data[split + 1..])
+			((nb as u8, N::pad_right(nb, self.data[split])), &self.data[split + 1..])
 		} else {
 			((0, 0), &self.data[split..])
 		}
@@ -163,7 +164,7 @@ impl<'a> NibbleSlice<'a> {
 		crate::rstd::iter::from_fn(move || {
 			if first.0 > 0 {
 				first.0 = 0;
-				Some(nibble_ops::pad_right(first.1))
+				Some(first.1)
 			} else if ix < sl.len() {
 				ix += 1;
 				Some(sl[ix - 1])
@@ -176,54 +177,48 @@ impl<'a> NibbleSlice<'a> {
 	/// Return `Partial` bytes iterator over a range of byte..
 	/// Warning can be slow when unaligned (similar to `to_stored_range`).
 	pub fn right_range_iter(&'a self, to: usize) -> impl Iterator<Item = u8> + 'a {
-		let mut nib_res = to % nibble_ops::NIBBLE_PER_BYTE;
-		let aligned_i = (self.offset + to) % nibble_ops::NIBBLE_PER_BYTE;
-		let aligned = aligned_i == 0;
-		let mut ix = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-		let ix_lim = (self.offset + to) / nibble_ops::NIBBLE_PER_BYTE;
+		let end = self.offset + to;
+		let mut nib_res = to % N::NIBBLE_PER_BYTE;
+		// Nibble position where the next produced byte ends.
+		let mut pos = self.offset + if nib_res > 0 { nib_res } else { N::NIBBLE_PER_BYTE };
 		crate::rstd::iter::from_fn(move || {
-			if aligned {
-				if nib_res > 0 {
-					let v = nibble_ops::pad_right(self.data[ix]);
-					nib_res = 0;
-					ix += 1;
-					Some(v)
-				} else if ix < ix_lim {
-					ix += 1;
-					Some(self.data[ix - 1])
-				} else {
-					None
-				}
-			} else {
-				let (s1, s2) = nibble_ops::SPLIT_SHIFTS;
-				// unaligned
-				if nib_res > 0 {
-					let v = self.data[ix] >> s1;
-					let v = nibble_ops::pad_right(v);
-					nib_res = 0;
-					Some(v)
-				} else if ix < ix_lim {
-					ix += 1;
-					let b1 = self.data[ix - 1] << s2;
-					let b2 = self.data[ix] >> s1;
-					Some(b1 | b2)
-				} else {
-					None
-				}
+			if pos > end {
+				return None
+			}
+			let mut v = self.byte_ending_at(pos);
+			if nib_res > 0 {
+				v = N::pad_right(nib_res, v);
+				nib_res = 0;
 			}
+			pos += N::NIBBLE_PER_BYTE;
+			Some(v)
 		})
 	}
 
+	/// Byte made of the nibbles ending at nibble position `pos` of `data`,
+	/// zero padded on the left when `pos` is in the first byte.
+	fn byte_ending_at(&self, pos: usize) -> u8 {
+		let bit_pos = pos * N::BIT_PER_NIBBLE;
+		let ix = bit_pos / 8;
+		let shift = bit_pos % 8;
+		if shift == 0 {
+			self.data[ix - 1]
+		} else {
+			let high = if ix > 0 { self.data[ix - 1] << shift } else { 0 };
+			high | self.data[ix] >> (8 - shift)
+		}
+	}
+
 	/// Return left portion of `NibbleSlice`, if the slice
 	/// originates from a full key it will be the `Prefix of
 	/// the node`.
 	pub fn left(&'a self) -> Prefix {
-		let split = self.offset / nibble_ops::NIBBLE_PER_BYTE;
-		let ix = (self.offset % nibble_ops::NIBBLE_PER_BYTE) as u8;
+		let split = self.offset / N::NIBBLE_PER_BYTE;
+		let ix = self.offset % N::NIBBLE_PER_BYTE;
 		if ix == 0 {
 			(&self.data[..split], None)
 		} else {
-			(&self.data[..split], Some(nibble_ops::pad_left(self.data[split])))
+			(&self.data[..split], Some(N::prefix_last(ix, self.data[split])))
 		}
 	}
 
@@ -234,27 +229,27 @@ impl<'a> NibbleSlice<'a> {
 	}
 }
 
-impl<'a> Into<NodeKey> for NibbleSlice<'a> {
+impl<'a, N> Into<NodeKey> for NibbleSlice<'a, N> {
 	fn into(self) -> NodeKey {
 		(self.offset, self.data.into())
 	}
 }
 
-impl<'a> PartialEq for NibbleSlice<'a> {
+impl<'a, N: NibbleOps> PartialEq for NibbleSlice<'a, N> {
 	fn eq(&self, them: &Self) -> bool {
 		self.len() == them.len() && self.starts_with(them)
 	}
 }
 
-impl<'a> Eq for NibbleSlice<'a> {}
+impl<'a, N: NibbleOps> Eq for NibbleSlice<'a, N> {}
 
-impl<'a> PartialOrd for NibbleSlice<'a> {
+impl<'a, N: NibbleOps> PartialOrd for NibbleSlice<'a, N> {
 	fn partial_cmp(&self, them: &Self) -> Option<Ordering> {
 		Some(self.cmp(them))
 	}
 }
 
-impl<'a> Ord for NibbleSlice<'a> {
+impl<'a, N: NibbleOps> Ord for NibbleSlice<'a, N> {
 	fn cmp(&self, them: &Self) -> Ordering {
 		let s = min(self.len(), them.len());
 		let mut i = 0usize;
@@ -270,7 +265,7 @@ impl<'a> Ord for NibbleSlice<'a> {
 }
 
 #[cfg(feature = "std")]
-impl<'a> fmt::Debug for NibbleSlice<'a> {
+impl<'a, N: NibbleOps> fmt::Debug for NibbleSlice<'a, N> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		for i in 0..self.len() {
 			match i {
@@ -284,7 +279,11 @@ impl<'a> fmt::Debug for NibbleSlice<'a> {
 
 #[cfg(test)]
 mod tests {
-	use crate::nibble::{BackingByteVec, NibbleSlice};
+	use crate::nibble::{
+		nibble_ops::{NibbleOps, Radix16, Radix2, Radix256, Radix4},
+		BackingByteVec, NibbleVec,
+	};
+	type NibbleSlice<'a> = crate::nibble::NibbleSlice<'a, Radix16>;
 	static D: &'static [u8; 3] = &[0x01u8, 0x23, 0x45];
 
 	#[test]
@@ -417,4 +416,51 @@ mod tests {
 		assert!(n >= m.mid(4));
 		assert!(n <= m.mid(4));
 	}
+
+	fn radix_ranges<N: NibbleOps>() {
+		let data = &[0x01u8, 0x23, 0x45, 0xa7, 0xfe][..];
+		let n = crate::nibble::NibbleSlice::<N>::new(data);
+		assert_eq!(n.len(), data.len() * N::NIBBLE_PER_BYTE);
+		for offset in 0..n.len() {
+			let m = n.mid(offset);
+			for nb in 0..=m.len() {
+				let nibbles: Vec<u8> = m.iter().take(nb).collect();
+				let stored = m.to_stored_range(nb);
+				let from_stored = crate::nibble::NibbleSlice::<N>::from_stored(&stored);
+				assert_eq!(from_stored.iter().collect::<Vec<_>>(), nibbles);
+
+				let mut expected = NibbleVec::<N>::new();
+				nibbles.iter().for_each(|n| expected.push(*n));
+				let range: Vec<u8> = m.right_range_iter(nb).collect();
+				let mut from_range = NibbleVec::<N>::new();
+				let first = (nb % N::NIBBLE_PER_BYTE) as u8;
+				if first > 0 {
+					from_range.append_partial(((first, range[0]), &range[1..]));
+				} else {
+					from_range.append_partial(((0, 0), &range[..]));
+				}
+				assert_eq!(from_range, expected);
+			}
+			let right: Vec<u8> = m.right_iter().collect();
+			assert_eq!(right, m.right_range_iter(m.len()).collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn ranges_all_radix() {
+		radix_ranges::<Radix2>();
+		radix_ranges::<Radix4>();
+		radix_ranges::<Radix16>();
+		radix_ranges::<Radix256>();
+	}
+
+	#[test]
+	fn radix2_nibbles() {
+		let n = crate::nibble::NibbleSlice::<Radix2>::new(&[0b1010_0110]);
+		assert_eq!(n.iter().collect::<Vec<_>>(), vec![1, 0, 1, 0, 0, 1, 1, 0]);
+		// prefix last byte: three nibbles followed by the marker bit.
+		assert_eq!(n.mid(3).left(), (&[][..], Some(0b1011_0000)));
+		assert_eq!(Radix2::prefix_last_len(0b1011_0000), 3);
+		assert_eq!(n.mid(3).right(), ((5, 0b0000_0110), &[][..]));
+	}
 }
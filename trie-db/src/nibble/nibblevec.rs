@@ -16,21 +16,22 @@
 
 use super::NibbleVec;
 use crate::{
-	nibble::{nibble_ops, BackingByteVec, NibbleSlice},
+	nibble::{nibble_ops::NibbleOps, BackingByteVec, NibbleSlice},
 	node_codec::Partial,
+	rstd::marker::PhantomData,
 };
 use hash_db::Prefix;
 
-impl Default for NibbleVec {
+impl<N: NibbleOps> Default for NibbleVec<N> {
 	fn default() -> Self {
 		NibbleVec::new()
 	}
 }
 
-impl NibbleVec {
+impl<N: NibbleOps> NibbleVec<N> {
 	/// Make a new `NibbleVec`.
 	pub fn new() -> Self {
-		NibbleVec { inner: BackingByteVec::new(), len: 0 }
+		NibbleVec { inner: BackingByteVec::new(), len: 0, _marker: PhantomData }
 	}
 
 	/// Length of the `NibbleVec`.
@@ -47,23 +48,21 @@ impl NibbleVec {
 	/// Try to get the nibble at the given offset.
 	#[inline]
 	pub fn at(&self, idx: usize) -> u8 {
-		let ix = idx / nibble_ops::NIBBLE_PER_BYTE;
-		let pad = idx % nibble_ops::NIBBLE_PER_BYTE;
-		nibble_ops::at_left(pad as u8, self.inner[ix])
+		N::left_nibble_at(&self.inner[..], idx)
 	}
 
-	/// Push a nibble onto the `NibbleVec`. Ignores the high 4 bits.
+	/// Push a nibble onto the `NibbleVec`. Ignores the high bits.
 	pub fn push(&mut self, nibble: u8) {
-		let i = self.len % nibble_ops::NIBBLE_PER_BYTE;
+		let i = self.len % N::NIBBLE_PER_BYTE;
 
 		if i == 0 {
-			self.inner.push(nibble_ops::push_at_left(0, nibble, 0));
+			self.inner.push(N::push_at_left(0, nibble, 0));
 		} else {
 			let output = self
 				.inner
 				.last_mut()
-				.expect("len != 0 since len % 2 != 0; inner has a last element; qed");
-			*output = nibble_ops::push_at_left(i as u8, nibble, *output);
+				.expect("len != 0 since len % NIBBLE_PER_BYTE != 0; inner has a last element; qed");
+			*output = N::push_at_left(i as u8, nibble, *output);
 		}
 		self.len += 1;
 	}
@@ -75,11 +74,11 @@ impl NibbleVec {
 		}
 		let byte = self.inner.pop().expect("len != 0; inner has last elem; qed");
 		self.len -= 1;
-		let i_new = self.len % nibble_ops::NIBBLE_PER_BYTE;
+		let i_new = self.len % N::NIBBLE_PER_BYTE;
 		if i_new != 0 {
-			self.inner.push(nibble_ops::pad_left(byte));
+			self.inner.push(N::pad_left(i_new, byte));
 		}
-		Some(nibble_ops::at_left(i_new as u8, byte))
+		Some(N::at_left(i_new as u8, byte))
 	}
 
 	/// Remove then n last nibbles in a faster way than popping n times.
@@ -92,46 +91,61 @@ impl NibbleVec {
 			return
 		}
 		let end = self.len - n;
-		let end_index = end / nibble_ops::NIBBLE_PER_BYTE +
-			if end % nibble_ops::NIBBLE_PER_BYTE == 0 { 0 } else { 1 };
+		let end_index =
+			end / N::NIBBLE_PER_BYTE + if end % N::NIBBLE_PER_BYTE == 0 { 0 } else { 1 };
 		(end_index..self.inner.len()).for_each(|_| {
 			self.inner.pop();
 		});
 		self.len = end;
-		let pos = self.len % nibble_ops::NIBBLE_PER_BYTE;
+		let pos = self.len % N::NIBBLE_PER_BYTE;
 		if pos != 0 {
 			let kl = self.inner.len() - 1;
-			self.inner[kl] = nibble_ops::pad_left(self.inner[kl]);
+			self.inner[kl] = N::pad_left(pos, self.inner[kl]);
 		}
 	}
 
 	/// Get `Prefix` representation of this `NibbleVec`.
 	pub fn as_prefix(&self) -> Prefix {
-		let split = self.len / nibble_ops::NIBBLE_PER_BYTE;
-		let pos = (self.len % nibble_ops::NIBBLE_PER_BYTE) as u8;
+		let split = self.len / N::NIBBLE_PER_BYTE;
+		let pos = self.len % N::NIBBLE_PER_BYTE;
 		if pos == 0 {
 			(&self.inner[..split], None)
 		} else {
-			(&self.inner[..split], Some(nibble_ops::pad_left(self.inner[split])))
+			(&self.inner[..split], Some(N::prefix_last(pos, self.inner[split])))
 		}
 	}
 
+	/// Make a `NibbleVec` from the `left` portion of a `NibbleSlice`,
+	/// that is the nibbles before its offset.
+	pub(crate) fn from_left(s: &NibbleSlice<N>) -> Self {
+		let split = s.offset / N::NIBBLE_PER_BYTE;
+		let mut v = NibbleVec {
+			inner: BackingByteVec::from_slice(&s.data[..split]),
+			len: split * N::NIBBLE_PER_BYTE,
+			_marker: PhantomData,
+		};
+		for i in v.len..s.offset {
+			v.push(N::left_nibble_at(s.data, i));
+		}
+		v
+	}
+
 	/// Append another `NibbleVec`. Can be slow (alignement of second vec).
-	pub fn append(&mut self, v: &NibbleVec) {
+	pub fn append(&mut self, v: &NibbleVec<N>) {
 		if v.len == 0 {
 			return
 		}
 		let final_len = self.len + v.len;
-		let offset = self.len % nibble_ops::NIBBLE_PER_BYTE;
-		let final_offset = final_len % nibble_ops::NIBBLE_PER_BYTE;
-		let last_index = self.len / nibble_ops::NIBBLE_PER_BYTE;
+		let offset = self.len % N::NIBBLE_PER_BYTE;
+		let last_index = self.len / N::NIBBLE_PER_BYTE;
 		if offset > 0 {
-			let (s1, s2) = nibble_ops::SPLIT_SHIFTS;
+			let s2 = offset * N::BIT_PER_NIBBLE;
+			let s1 = 8 - s2;
 			self.inner[last_index] =
-				nibble_ops::pad_left(self.inner[last_index]) | (v.inner[0] >> s2);
+				N::pad_left(offset, self.inner[last_index]) | (v.inner[0] >> s2);
 			(0..v.inner.len() - 1)
 				.for_each(|i| self.inner.push(v.inner[i] << s1 | v.inner[i + 1] >> s2));
-			if final_offset > 0 {
+			if self.inner.len() * N::NIBBLE_PER_BYTE < final_len {
 				self.inner.push(v.inner[v.inner.len() - 1] << s1);
 			}
 		} else {
@@ -142,23 +156,25 @@ impl NibbleVec {
 
 	/// Append a `Partial`. Can be slow (alignement of partial).
 	pub fn append_partial(&mut self, (start_byte, sl): Partial) {
-		if start_byte.0 == 1 {
-			self.push(nibble_ops::at_left(1, start_byte.1));
+		for i in (N::NIBBLE_PER_BYTE - start_byte.0 as usize)..N::NIBBLE_PER_BYTE {
+			self.push(N::at_left(i as u8, start_byte.1));
 		}
-		let pad = self.inner.len() * nibble_ops::NIBBLE_PER_BYTE - self.len;
+		let pad = self.inner.len() * N::NIBBLE_PER_BYTE - self.len;
 		if pad == 0 {
 			self.inner.extend_from_slice(&sl[..]);
 		} else {
 			let kend = self.inner.len() - 1;
 			if sl.len() > 0 {
-				self.inner[kend] = nibble_ops::pad_left(self.inner[kend]);
-				let (s1, s2) = nibble_ops::SPLIT_SHIFTS;
+				let offset = N::NIBBLE_PER_BYTE - pad;
+				self.inner[kend] = N::pad_left(offset, self.inner[kend]);
+				let s1 = offset * N::BIT_PER_NIBBLE;
+				let s2 = 8 - s1;
 				self.inner[kend] |= sl[0] >> s1;
 				(0..sl.len() - 1).for_each(|i| self.inner.push(sl[i] << s2 | sl[i + 1] >> s1));
 				self.inner.push(sl[sl.len() - 1] << s2);
 			}
 		}
-		self.len += sl.len() * nibble_ops::NIBBLE_PER_BYTE;
+		self.len += sl.len() * N::NIBBLE_PER_BYTE;
 	}
 
 	/// Utility function for chaining two optional appending
@@ -166,7 +182,7 @@ impl NibbleVec {
 	/// Can be slow.
 	pub(crate) fn append_optional_slice_and_nibble(
 		&mut self,
-		o_slice: Option<&NibbleSlice>,
+		o_slice: Option<&NibbleSlice<N>>,
 		o_index: Option<u8>,
 	) -> usize {
 		let mut res = 0;
@@ -185,7 +201,7 @@ impl NibbleVec {
 	/// Can be slow.
 	pub(crate) fn clone_append_optional_slice_and_nibble(
 		&self,
-		o_slice: Option<&NibbleSlice>,
+		o_slice: Option<&NibbleSlice<N>>,
 		o_index: Option<u8>,
 	) -> Self {
 		let mut p = self.clone();
@@ -205,8 +221,8 @@ impl NibbleVec {
 	}
 
 	/// Try to treat this `NibbleVec` as a `NibbleSlice`. Works only if there is no padding.
-	pub fn as_nibbleslice(&self) -> Option<NibbleSlice> {
-		if self.len % nibble_ops::NIBBLE_PER_BYTE == 0 {
+	pub fn as_nibbleslice(&self) -> Option<NibbleSlice<N>> {
+		if self.len % N::NIBBLE_PER_BYTE == 0 {
 			Some(NibbleSlice::new(self.inner()))
 		} else {
 			None
@@ -218,13 +234,13 @@ impl NibbleVec {
 		if self.len() < other.len() {
 			return false
 		}
-		let byte_len = other.len() / nibble_ops::NIBBLE_PER_BYTE;
+		let byte_len = other.len() / N::NIBBLE_PER_BYTE;
 		if &self.inner[..byte_len] != &other.inner[..byte_len] {
 			return false
 		}
-		for pad in 0..(other.len() - byte_len * nibble_ops::NIBBLE_PER_BYTE) {
-			let self_nibble = nibble_ops::at_left(pad as u8, self.inner[byte_len]);
-			let other_nibble = nibble_ops::at_left(pad as u8, other.inner[byte_len]);
+		for pad in 0..(other.len() - byte_len * N::NIBBLE_PER_BYTE) {
+			let self_nibble = N::at_left(pad as u8, self.inner[byte_len]);
+			let other_nibble = N::at_left(pad as u8, other.inner[byte_len]);
 			if self_nibble != other_nibble {
 				return false
 			}
@@ -233,8 +249,8 @@ impl NibbleVec {
 	}
 }
 
-impl<'a> From<NibbleSlice<'a>> for NibbleVec {
-	fn from(s: NibbleSlice<'a>) -> Self {
+impl<'a, N: NibbleOps> From<NibbleSlice<'a, N>> for NibbleVec<N> {
+	fn from(s: NibbleSlice<'a, N>) -> Self {
 		let mut v = NibbleVec::new();
 		for i in 0..s.len() {
 			v.push(s.at(i));
@@ -245,21 +261,22 @@ impl<'a> From<NibbleSlice<'a>> for NibbleVec {
 
 #[cfg(test)]
 mod tests {
-	use crate::nibble::{nibble_ops, NibbleVec};
+	use crate::nibble::nibble_ops::{NibbleOps, Radix16, Radix2, Radix256, Radix4};
+	type NibbleVec = crate::nibble::NibbleVec<Radix16>;
 
 	#[test]
 	fn push_pop() {
 		let mut v = NibbleVec::new();
 
-		for i in 0..(nibble_ops::NIBBLE_PER_BYTE * 3) {
-			let iu8 = (i % nibble_ops::NIBBLE_PER_BYTE) as u8;
+		for i in 0..(Radix16::NIBBLE_PER_BYTE * 3) {
+			let iu8 = (i % Radix16::NIBBLE_PER_BYTE) as u8;
 			v.push(iu8);
 			assert_eq!(v.len() - 1, i);
 			assert_eq!(v.at(i), iu8);
 		}
 
-		for i in (0..(nibble_ops::NIBBLE_PER_BYTE * 3)).rev() {
-			let iu8 = (i % nibble_ops::NIBBLE_PER_BYTE) as u8;
+		for i in (0..(Radix16::NIBBLE_PER_BYTE * 3)).rev() {
+			let iu8 = (i % Radix16::NIBBLE_PER_BYTE) as u8;
 			let a = v.pop();
 			assert_eq!(a, Some(iu8));
 			assert_eq!(v.len(), i);
@@ -304,4 +321,36 @@ mod tests {
 		test_trun(&[1, 2, 3], 3, (&[], 0));
 		test_trun(&[1, 2, 3], 4, (&[], 0));
 	}
+
+	fn append_all_radix<N: NibbleOps>() {
+		let nibbles: Vec<u8> = (0..3 * N::NIBBLE_PER_BYTE + 1)
+			.map(|i| (i * 7 % N::NIBBLE_LENGTH) as u8)
+			.collect();
+		for split in 0..nibbles.len() {
+			let mut v = crate::nibble::NibbleVec::<N>::new();
+			nibbles[..split].iter().for_each(|n| v.push(*n));
+			let mut w = crate::nibble::NibbleVec::<N>::new();
+			nibbles[split..].iter().for_each(|n| w.push(*n));
+			v.append(&w);
+			assert_eq!(v.len(), nibbles.len());
+			assert_eq!((0..v.len()).map(|i| v.at(i)).collect::<Vec<_>>(), nibbles);
+			for drop in 0..nibbles.len() {
+				let mut d = v.clone();
+				d.drop_lasts(drop);
+				let mut popped = v.clone();
+				(0..drop).for_each(|_| {
+					popped.pop();
+				});
+				assert_eq!(d, popped);
+			}
+		}
+	}
+
+	#[test]
+	fn append_radix() {
+		append_all_radix::<Radix2>();
+		append_all_radix::<Radix4>();
+		append_all_radix::<Radix16>();
+		append_all_radix::<Radix256>();
+	}
 }
@@ -13,13 +13,20 @@
 // limitations under the License.
 
 use crate::{
-	nibble::{self, nibble_ops, NibbleSlice},
+	nibble::{self, nibble_ops::NibbleOps, NibbleSlice},
 	node_codec::NodeCodec,
 	DBValue,
 };
 use hash_db::Hasher;
 
-use crate::rstd::{borrow::Borrow, ops::Range};
+#[cfg(feature = "std")]
+use crate::rstd::fmt;
+use crate::rstd::{
+	borrow::Borrow,
+	marker::PhantomData,
+	ops::{Index, IndexMut, Range},
+	slice,
+};
 
 /// Partial node key type: offset and owned value of a nibbleslice.
 /// Offset is applied on first byte of array (bytes are right aligned).
@@ -67,25 +74,113 @@ impl<'a> Value<'a> {
 	}
 }
 
+/// Item of each child of a branch node, in child index order.
+/// The number of children is the radix `N::NIBBLE_LENGTH` of the trie.
+pub struct Children<N: NibbleOps, T>(N::ChildArray<T>);
+
+impl<N: NibbleOps, T> Children<N, T> {
+	/// Build children from the item of each child index.
+	pub fn from_fn(f: impl FnMut(usize) -> T) -> Self {
+		Children(N::child_array(f))
+	}
+
+	/// Iterate over the children items.
+	pub fn iter(&self) -> slice::Iter<'_, T> {
+		self.0.as_ref().iter()
+	}
+
+	/// Iterate mutably over the children items.
+	pub fn iter_mut(&mut self) -> slice::IterMut<'_, T> {
+		self.0.as_mut().iter_mut()
+	}
+}
+
+impl<N: NibbleOps, T> AsRef<[T]> for Children<N, T> {
+	fn as_ref(&self) -> &[T] {
+		self.0.as_ref()
+	}
+}
+
+impl<N: NibbleOps, T> AsMut<[T]> for Children<N, T> {
+	fn as_mut(&mut self) -> &mut [T] {
+		self.0.as_mut()
+	}
+}
+
+impl<N: NibbleOps, T> Index<usize> for Children<N, T> {
+	type Output = T;
+
+	fn index(&self, index: usize) -> &T {
+		&self.0.as_ref()[index]
+	}
+}
+
+impl<N: NibbleOps, T> IndexMut<usize> for Children<N, T> {
+	fn index_mut(&mut self, index: usize) -> &mut T {
+		&mut self.0.as_mut()[index]
+	}
+}
+
+impl<N: NibbleOps, T> IntoIterator for Children<N, T> {
+	type Item = T;
+	type IntoIter = <N::ChildArray<T> as IntoIterator>::IntoIter;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.0.into_iter()
+	}
+}
+
+impl<'a, N: NibbleOps, T> IntoIterator for &'a Children<N, T> {
+	type Item = &'a T;
+	type IntoIter = slice::Iter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<N: NibbleOps, T: Default> Default for Children<N, T> {
+	fn default() -> Self {
+		Children::from_fn(|_| T::default())
+	}
+}
+
+impl<N: NibbleOps, T: Clone> Clone for Children<N, T> {
+	fn clone(&self) -> Self {
+		Children::from_fn(|i| self[i].clone())
+	}
+}
+
+impl<N: NibbleOps, T: PartialEq> PartialEq for Children<N, T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.as_ref() == other.as_ref()
+	}
+}
+
+impl<N: NibbleOps, T: Eq> Eq for Children<N, T> {}
+
+#[cfg(feature = "std")]
+impl<N: NibbleOps, T: fmt::Debug> fmt::Debug for Children<N, T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.as_ref().fmt(f)
+	}
+}
+
 /// Type of node in the trie and essential information thereof.
 #[derive(Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "std", derive(Debug))]
-pub enum Node<'a> {
+pub enum Node<'a, N: NibbleOps> {
 	/// Null trie node; could be an empty root or an empty branch entry.
 	Empty,
 	/// Leaf node; has key slice and value. Value may not be empty.
-	Leaf(NibbleSlice<'a>, Value<'a>),
+	Leaf(NibbleSlice<'a, N>, Value<'a>),
 	/// Extension node; has key slice and node data. Data may not be null.
-	Extension(NibbleSlice<'a>, NodeHandle<'a>),
+	Extension(NibbleSlice<'a, N>, NodeHandle<'a>),
 	/// Branch node; has slice of child nodes (each possibly null)
 	/// and an optional immediate node data.
-	Branch([Option<NodeHandle<'a>>; nibble_ops::NIBBLE_LENGTH], Option<Value<'a>>),
+	Branch(Children<N, Option<NodeHandle<'a>>>, Option<Value<'a>>),
 	/// Branch node with support for a nibble (when extension nodes are not used).
-	NibbledBranch(
-		NibbleSlice<'a>,
-		[Option<NodeHandle<'a>>; nibble_ops::NIBBLE_LENGTH],
-		Option<Value<'a>>,
-	),
+	NibbledBranch(NibbleSlice<'a, N>, Children<N, Option<NodeHandle<'a>>>, Option<Value<'a>>),
 }
 
 /// A `NodeHandlePlan` is a decoding plan for constructing a `NodeHandle` from an encoded trie
@@ -112,26 +207,27 @@ impl NodeHandlePlan {
 /// `NibbleSlicePlan` is created by parsing a byte slice and can be reused multiple times.
 #[derive(Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "std", derive(Debug))]
-pub struct NibbleSlicePlan {
+pub struct NibbleSlicePlan<N> {
 	bytes: Range<usize>,
 	offset: usize,
+	_marker: PhantomData<N>,
 }
 
-impl NibbleSlicePlan {
+impl<N: NibbleOps> NibbleSlicePlan<N> {
 	/// Construct a nibble slice decode plan.
 	pub fn new(bytes: Range<usize>, offset: usize) -> Self {
-		NibbleSlicePlan { bytes, offset }
+		NibbleSlicePlan { bytes, offset, _marker: PhantomData }
 	}
 
 	/// Returns the nibble length of the slice.
 	pub fn len(&self) -> usize {
-		(self.bytes.end - self.bytes.start) * nibble_ops::NIBBLE_PER_BYTE - self.offset
+		(self.bytes.end - self.bytes.start) * N::NIBBLE_PER_BYTE - self.offset
 	}
 
 	/// Build a nibble slice by decoding a byte slice according to the plan. It is the
 	/// responsibility of the caller to ensure that the node plan was created for the argument
 	/// data, otherwise the call may decode incorrectly or panic.
-	pub fn build<'a, 'b>(&'a self, data: &'b [u8]) -> NibbleSlice<'b> {
+	pub fn build<'a, 'b>(&'a self, data: &'b [u8]) -> NibbleSlice<'b, N> {
 		NibbleSlice::new_offset(&data[self.bytes.clone()], self.offset)
 	}
 }
@@ -165,49 +261,42 @@ impl ValuePlan {
 /// ranges that can be used to index into a large byte slice.
 #[derive(Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "std", derive(Debug))]
-pub enum NodePlan {
+pub enum NodePlan<N: NibbleOps> {
 	/// Null trie node; could be an empty root or an empty branch entry.
 	Empty,
 	/// Leaf node; has a partial key plan and value.
-	Leaf { partial: NibbleSlicePlan, value: ValuePlan },
+	Leaf { partial: NibbleSlicePlan<N>, value: ValuePlan },
 	/// Extension node; has a partial key plan and child data.
-	Extension { partial: NibbleSlicePlan, child: NodeHandlePlan },
+	Extension { partial: NibbleSlicePlan<N>, child: NodeHandlePlan },
 	/// Branch node; has slice of child nodes (each possibly null)
 	/// and an optional immediate node data.
-	Branch {
-		value: Option<ValuePlan>,
-		children: [Option<NodeHandlePlan>; nibble_ops::NIBBLE_LENGTH],
-	},
+	Branch { value: Option<ValuePlan>, children: Children<N, Option<NodeHandlePlan>> },
 	/// Branch node with support for a nibble (when extension nodes are not used).
 	NibbledBranch {
-		partial: NibbleSlicePlan,
+		partial: NibbleSlicePlan<N>,
 		value: Option<ValuePlan>,
-		children: [Option<NodeHandlePlan>; nibble_ops::NIBBLE_LENGTH],
+		children: Children<N, Option<NodeHandlePlan>>,
 	},
 }
 
-impl NodePlan {
+impl<N: NibbleOps> NodePlan<N> {
 	/// Build a node by decoding a byte slice according to the node plan. It is the responsibility
 	/// of the caller to ensure that the node plan was created for the argument data, otherwise the
 	/// call may decode incorrectly or panic.
-	pub fn build<'a, 'b>(&'a self, data: &'b [u8]) -> Node<'b> {
+	pub fn build<'a, 'b>(&'a self, data: &'b [u8]) -> Node<'b, N> {
 		match self {
 			NodePlan::Empty => Node::Empty,
 			NodePlan::Leaf { partial, value } => Node::Leaf(partial.build(data), value.build(data)),
 			NodePlan::Extension { partial, child } =>
 				Node::Extension(partial.build(data), child.build(data)),
 			NodePlan::Branch { value, children } => {
-				let mut child_slices = [None; nibble_ops::NIBBLE_LENGTH];
-				for i in 0..nibble_ops::NIBBLE_LENGTH {
-					child_slices[i] = children[i].as_ref().map(|child| child.build(data));
-				}
+				let child_slices =
+					Children::from_fn(|i| children[i].as_ref().map(|child| child.build(data)));
 				Node::Branch(child_slices, value.as_ref().map(|v| v.build(data)))
 			},
 			NodePlan::NibbledBranch { partial, value, children } => {
-				let mut child_slices = [None; nibble_ops::NIBBLE_LENGTH];
-				for i in 0..nibble_ops::NIBBLE_LENGTH {
-					child_slices[i] = children[i].as_ref().map(|child| child.build(data));
-				}
+				let child_slices =
+					Children::from_fn(|i| children[i].as_ref().map(|child| child.build(data)));
 				Node::NibbledBranch(
 					partial.build(data),
 					child_slices,
@@ -244,14 +333,14 @@ impl NodePlan {
 /// the `OwnedNode`. This is useful for trie iterators.
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(PartialEq, Eq)]
-pub struct OwnedNode<D: Borrow<[u8]>> {
+pub struct OwnedNode<D: Borrow<[u8]>, N: NibbleOps> {
 	data: D,
-	plan: NodePlan,
+	plan: NodePlan<N>,
 }
 
-impl<D: Borrow<[u8]>> OwnedNode<D> {
+impl<D: Borrow<[u8]>, N: NibbleOps> OwnedNode<D, N> {
 	/// Construct an `OwnedNode` by decoding an owned data source according to some codec.
-	pub fn new<C: NodeCodec>(data: D) -> Result<Self, C::Error> {
+	pub fn new<C: NodeCodec<Nibble = N>>(data: D) -> Result<Self, C::Error> {
 		let plan = C::decode_plan(data.borrow())?;
 		Ok(OwnedNode { data, plan })
 	}
@@ -262,17 +351,17 @@ impl<D: Borrow<[u8]>> OwnedNode<D> {
 	}
 
 	/// Returns a reference to the node decode plan.
-	pub fn node_plan(&self) -> &NodePlan {
+	pub fn node_plan(&self) -> &NodePlan<N> {
 		&self.plan
 	}
 
 	/// Returns a mutable reference to the node decode plan.
-	pub fn node_plan_mut(&mut self) -> &mut NodePlan {
+	pub fn node_plan_mut(&mut self) -> &mut NodePlan<N> {
 		&mut self.plan
 	}
 
 	/// Construct a `Node` by borrowing data from this struct.
-	pub fn node(&self) -> Node {
+	pub fn node(&self) -> Node<N> {
 		self.plan.build(self.data.borrow())
 	}
 }
@@ -16,6 +16,7 @@
 //! to parametrize the hashes used in the codec.
 
 use crate::{
+	nibble::nibble_ops::NibbleOps,
 	node::{Node, NodePlan, Value},
 	ChildReference, MaybeDebug,
 };
@@ -24,7 +25,7 @@ use crate::rstd::{borrow::Borrow, hash, vec::Vec, Error};
 
 /// Representation of a nible slice (right aligned).
 /// It contains a right aligned padded first byte (first pair element is the number of nibbles
-/// (0 to max nb nibble - 1), second pair element is the padded nibble), and a slice over
+/// (0 to max nb nibble - 1), second pair element is the padded nibbles), and a slice over
 /// the remaining bytes.
 pub type Partial<'a> = ((u8, u8), &'a [u8]);
 
@@ -39,6 +40,9 @@ pub trait NodeCodec: Sized {
 	/// Codec error type.
 	type Error: Error;
 
+	/// Nibble of the encoded partial keys, and radix of the encoded branches.
+	type Nibble: NibbleOps;
+
 	/// Output type of encoded node hasher.
 	type HashOut: AsRef<[u8]>
 		+ AsMut<[u8]>
@@ -56,10 +60,10 @@ pub trait NodeCodec: Sized {
 	fn hashed_null_node() -> Self::HashOut;
 
 	/// Decode bytes to a `NodePlan`. Returns `Self::E` on failure.
-	fn decode_plan(data: &[u8]) -> Result<NodePlan, Self::Error>;
+	fn decode_plan(data: &[u8]) -> Result<NodePlan<Self::Nibble>, Self::Error>;
 
 	/// Decode bytes to a `Node`. Returns `Self::E` on failure.
-	fn decode<'a>(data: &'a [u8]) -> Result<Node<'a>, Self::Error> {
+	fn decode<'a>(data: &'a [u8]) -> Result<Node<'a, Self::Nibble>, Self::Error> {
 		Ok(Self::decode_plan(data)?.build(data))
 	}
 
@@ -97,4 +101,48 @@ pub trait NodeCodec: Sized {
 		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
 		value: Option<Value>,
 	) -> Vec<u8>;
+
+	/// Appends an encoded leaf node to `output`.
+	///
+	/// The default implementation copies the result of `leaf_node`, codecs should override
+	/// it to encode directly into `output`.
+	fn leaf_node_into(partial: Partial, value: Value, output: &mut Vec<u8>) {
+		output.extend_from_slice(&Self::leaf_node(partial, value));
+	}
+
+	/// Appends an encoded extension node to `output`, see `leaf_node_into`.
+	fn extension_node_into(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		child_ref: ChildReference<Self::HashOut>,
+		output: &mut Vec<u8>,
+	) {
+		output.extend_from_slice(&Self::extension_node(partial, number_nibble, child_ref));
+	}
+
+	/// Appends an encoded branch node to `output`, see `leaf_node_into`.
+	fn branch_node_into(
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		value: Option<Value>,
+		output: &mut Vec<u8>,
+	) {
+		output.extend_from_slice(&Self::branch_node(children, value));
+	}
+
+	/// Appends an encoded branch node with a possible partial path to `output`, see
+	/// `leaf_node_into`.
+	fn branch_node_nibbled_into(
+		partial: impl Iterator<Item = u8>,
+		number_nibble: usize,
+		children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+		value: Option<Value>,
+		output: &mut Vec<u8>,
+	) {
+		output.extend_from_slice(&Self::branch_node_nibbled(
+			partial,
+			number_nibble,
+			children,
+			value,
+		));
+	}
 }
@@ -0,0 +1,102 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Incremental root of an indexed trie.
+
+use crate::{
+	rstd::vec::Vec, CError, DBValue, NodeCodec, Result, TrieConfiguration, TrieDBMut, TrieHash,
+	TrieMut,
+};
+use hash_db::HashDB;
+
+/// Incremental version of `TrieConfiguration::ordered_trie_root`.
+///
+/// Items are pushed in index order and keyed with `TrieConfiguration::encode_index`, whose
+/// keys need not sort like the indices. Pushed items are inserted into the trie held in
+/// `db` when a root is requested, so only the nodes on the path of the new items are
+/// encoded and hashed again.
+///
+/// As for `TrieDBMut`, empty values are only stored by layouts with `ALLOW_EMPTY`.
+pub struct OrderedRootBuilder<L, DB>
+where
+	L: TrieConfiguration,
+{
+	db: DB,
+	root: TrieHash<L>,
+	len: usize,
+	pending: Vec<DBValue>,
+}
+
+impl<L, DB> OrderedRootBuilder<L, DB>
+where
+	L: TrieConfiguration,
+	DB: HashDB<L::Hash, DBValue>,
+{
+	/// Create an empty builder storing trie nodes in `db`.
+	pub fn new(db: DB) -> Self {
+		OrderedRootBuilder { db, root: L::Codec::hashed_null_node(), len: 0, pending: Vec::new() }
+	}
+
+	/// Append an item at the next index.
+	pub fn push(&mut self, value: &[u8]) {
+		self.pending.push(value.to_vec());
+		self.len += 1;
+	}
+
+	/// Number of items pushed.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Whether no item was pushed.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Root of the trie of all items pushed so far.
+	pub fn root(&mut self) -> Result<TrieHash<L>, TrieHash<L>, CError<L>> {
+		self.commit()?;
+		Ok(self.root)
+	}
+
+	/// Get the backing database, containing the nodes of the trie at the last requested root.
+	pub fn db(&self) -> &DB {
+		&self.db
+	}
+
+	/// Consume the builder, returning the backing database and the root of all items pushed.
+	pub fn into_inner(mut self) -> Result<(DB, TrieHash<L>), TrieHash<L>, CError<L>> {
+		self.commit()?;
+		Ok((self.db, self.root))
+	}
+
+	fn commit(&mut self) -> Result<(), TrieHash<L>, CError<L>> {
+		if self.pending.is_empty() {
+			return Ok(())
+		}
+		let first = self.len - self.pending.len();
+		let mut trie = if first == 0 {
+			TrieDBMut::<L>::new(&mut self.db, &mut self.root)
+		} else {
+			TrieDBMut::<L>::from_existing(&mut self.db, &mut self.root)
+		};
+		for (i, value) in self.pending.iter().enumerate() {
+			trie.insert(&L::encode_index((first + i) as u32), value)?;
+		}
+		trie.commit();
+		drop(trie);
+		self.pending.clear();
+		Ok(())
+	}
+}
@@ -0,0 +1,158 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read access to a trie of which only some nodes are known, as after decoding a proof.
+
+use core::cell::RefCell;
+
+use hash_db::{HashDBRef, Prefix};
+
+use crate::{
+	nibble::{nibble_ops::NibbleOps, NibbleVec},
+	rstd::boxed::Box,
+	triedb::TrieDBIterator,
+	CError, DBValue, Result, Trie, TrieDB, TrieError, TrieHash, TrieItem, TrieLayout,
+};
+
+/// Result of a lookup in a `PartialTrie`.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, PartialEq, Eq)]
+pub enum Proven<T, N> {
+	/// The answer follows from the known nodes.
+	Known(T),
+	/// The lookup needs the node at this key prefix, which is not known.
+	Unknown(NibbleVec<N>),
+}
+
+/// Result of a lookup in a `PartialTrie` of layout `L`.
+type ProvenResult<T, L> = Result<Proven<T, <L as TrieLayout>::Nibble>, TrieHash<L>, CError<L>>;
+
+/// Database recording the prefix of the first node it does not hold.
+struct MissingTracker<'a, L: TrieLayout> {
+	db: &'a dyn HashDBRef<L::Hash, DBValue>,
+	missing: RefCell<Option<NibbleVec<L::Nibble>>>,
+}
+
+impl<'a, L: TrieLayout> HashDBRef<L::Hash, DBValue> for MissingTracker<'a, L> {
+	fn get(&self, key: &TrieHash<L>, prefix: Prefix) -> Option<DBValue> {
+		let value = self.db.get(key, prefix);
+		if value.is_none() {
+			let mut missing = self.missing.borrow_mut();
+			if missing.is_none() {
+				let mut nibbles = NibbleVec::new();
+				nibbles.append_partial(((0, 0), prefix.0));
+				if let Some(last) = prefix.1 {
+					for i in 0..L::Nibble::prefix_last_len(last) {
+						nibbles.push(L::Nibble::at_left(i as u8, last));
+					}
+				}
+				*missing = Some(nibbles);
+			}
+		}
+		value
+	}
+
+	fn contains(&self, key: &TrieHash<L>, prefix: Prefix) -> bool {
+		self.get(key, prefix).is_some()
+	}
+}
+
+/// A trie backed by a database holding only part of its nodes, for instance the nodes of
+/// `decode_compact` output or of `Recorder` records.
+///
+/// Unlike `TrieDB`, lookups reaching a node missing from the database are not errors but
+/// `Proven::Unknown`, and iteration skips the subtrees of missing nodes.
+pub struct PartialTrie<'db, L: TrieLayout> {
+	db: &'db dyn HashDBRef<L::Hash, DBValue>,
+	root: &'db TrieHash<L>,
+	trie: TrieDB<'db, L>,
+}
+
+impl<'db, L: TrieLayout> PartialTrie<'db, L> {
+	/// Create a partial trie over the known nodes in `db` and `root`.
+	pub fn new(db: &'db dyn HashDBRef<L::Hash, DBValue>, root: &'db TrieHash<L>) -> Self {
+		PartialTrie { db, root, trie: TrieDB::new(db, root) }
+	}
+
+	/// Return the root of the trie.
+	pub fn root(&self) -> &TrieHash<L> {
+		self.root
+	}
+
+	/// Look up the value of `key`, or the prefix of the first missing node on its path.
+	///
+	/// Errors other than missing nodes, such as invalid node encodings, are returned as for
+	/// `TrieDB`.
+	pub fn get(&self, key: &[u8]) -> ProvenResult<Option<DBValue>, L> {
+		let tracker = MissingTracker::<L> { db: self.db, missing: RefCell::new(None) };
+		let result = TrieDB::<L>::new(&tracker, self.root).get(key);
+		match (result, tracker.missing.into_inner()) {
+			(Ok(value), _) => Ok(Proven::Known(value)),
+			(Err(e), Some(prefix)) => match *e {
+				TrieError::IncompleteDatabase(_) | TrieError::InvalidStateRoot(_) =>
+					Ok(Proven::Unknown(prefix)),
+				e => Err(Box::new(e)),
+			},
+			(Err(e), None) => Err(e),
+		}
+	}
+
+	/// Look up whether `key` is in the trie, see `get`.
+	pub fn contains(&self, key: &[u8]) -> ProvenResult<bool, L> {
+		self.get(key).map(|proven| match proven {
+			Proven::Known(value) => Proven::Known(value.is_some()),
+			Proven::Unknown(prefix) => Proven::Unknown(prefix),
+		})
+	}
+
+	/// Iterate over the known key-value pairs, in key order.
+	///
+	/// A missing root node gives an empty iteration, other errors reading the root node are
+	/// returned as the first item.
+	pub fn iter<'a>(&'a self) -> PartialTrieIterator<'a, L> {
+		match TrieDBIterator::new(&self.trie) {
+			Ok(inner) => PartialTrieIterator { inner: Some(inner), root_error: None },
+			Err(e) => match *e {
+				TrieError::InvalidStateRoot(_) | TrieError::IncompleteDatabase(_) =>
+					PartialTrieIterator { inner: None, root_error: None },
+				_ => PartialTrieIterator { inner: None, root_error: Some(e) },
+			},
+		}
+	}
+}
+
+/// Iterator over the key-value pairs of a `PartialTrie`.
+///
+/// Subtrees and values missing from the database are skipped, other errors are returned.
+pub struct PartialTrieIterator<'a, L: TrieLayout> {
+	inner: Option<TrieDBIterator<'a, L>>,
+	root_error: Option<Box<TrieError<TrieHash<L>, CError<L>>>>,
+}
+
+impl<'a, L: TrieLayout> Iterator for PartialTrieIterator<'a, L> {
+	type Item = TrieItem<'a, TrieHash<L>, CError<L>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(e) = self.root_error.take() {
+			return Some(Err(e))
+		}
+		for item in self.inner.as_mut()? {
+			match item {
+				Err(e) if matches!(*e, TrieError::IncompleteDatabase(_)) => continue,
+				item => return Some(item),
+			}
+		}
+		None
+	}
+}
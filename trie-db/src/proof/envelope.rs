@@ -0,0 +1,490 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioned envelope carrying a proof with the root and layout it is for.
+//!
+//! The binary encoding is the SCALE encoding of the tuple
+//! `(version: u8, layout: [u8; 8], kind: u8, root: Vec<u8>, nodes: Vec<Vec<u8>>)`. The hex-JSON
+//! encoding (std only) is an object with the same fields, where `version` is a number, `kind`
+//! is the name of the kind and the byte strings are `0x` prefixed hex:
+//!
+//! ```text
+//! {"version":1,"layout":"0x…","kind":"compact","root":"0x…","nodes":["0x…"]}
+//! ```
+
+use crate::rstd::{cmp, convert::TryFrom, result::Result, vec::Vec};
+
+use hash_db::Hasher;
+
+use crate::{node::Value, NodeCodec, TrieHash, TrieLayout};
+
+/// Version of the envelope encodings written by `ProofEnvelope`.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Identifier of a trie layout, see `layout_tag`.
+pub type LayoutTag = [u8; 8];
+
+/// Compute the tag identifying the layout `L` in envelopes.
+///
+/// The tag is a prefix of the hash, with the layout hasher, of the layout flags and of the
+/// encodings of the empty node and of a leaf node, so it differs between layouts with different
+/// hashers, codecs or flags.
+pub fn layout_tag<L: TrieLayout>() -> LayoutTag {
+	let mut preimage = Vec::new();
+	preimage.push(L::USE_EXTENSION as u8);
+	preimage.push(L::ALLOW_EMPTY as u8);
+	match L::MAX_INLINE_VALUE {
+		Some(max) => {
+			preimage.push(1);
+			preimage.extend_from_slice(&max.to_le_bytes());
+		},
+		None => preimage.push(0),
+	}
+	preimage.extend_from_slice(L::Codec::empty_node());
+	preimage.extend_from_slice(&L::Codec::leaf_node(((0, 0), &[0x12]), Value::Inline(&[0x34])));
+
+	let hash = L::Hash::hash(&preimage);
+	let mut tag = LayoutTag::default();
+	let len = cmp::min(tag.len(), hash.as_ref().len());
+	tag[..len].copy_from_slice(&hash.as_ref()[..len]);
+	tag
+}
+
+/// Kind of the proof in an envelope, telling which verifier accepts its nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofKind {
+	/// Compact nodes, as produced by `generate_proof` or `encode_compact`.
+	Compact,
+	/// Raw nodes on the path of a key, as produced by eip-1186 proof generation.
+	Eip1186,
+	/// Nodes proving a range of keys.
+	Range,
+}
+
+impl ProofKind {
+	fn code(self) -> u8 {
+		match self {
+			ProofKind::Compact => 0,
+			ProofKind::Eip1186 => 1,
+			ProofKind::Range => 2,
+		}
+	}
+
+	fn from_code(code: u8) -> Option<Self> {
+		match code {
+			0 => Some(ProofKind::Compact),
+			1 => Some(ProofKind::Eip1186),
+			2 => Some(ProofKind::Range),
+			_ => None,
+		}
+	}
+
+	#[cfg(feature = "std")]
+	fn name(self) -> &'static str {
+		match self {
+			ProofKind::Compact => "compact",
+			ProofKind::Eip1186 => "eip1186",
+			ProofKind::Range => "range",
+		}
+	}
+
+	#[cfg(feature = "std")]
+	fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"compact" => Some(ProofKind::Compact),
+			"eip1186" => Some(ProofKind::Eip1186),
+			"range" => Some(ProofKind::Range),
+			_ => None,
+		}
+	}
+}
+
+/// Errors decoding a `ProofEnvelope`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvelopeError {
+	/// The input ends before the envelope.
+	UnexpectedEnd,
+	/// The input continues after the envelope.
+	TrailingData,
+	/// A length prefix is not a canonical compact integer, or does not fit in memory.
+	InvalidLength,
+	/// The envelope version is not supported. The parameter is the version found.
+	UnsupportedVersion(u8),
+	/// The envelope is for another layout. The parameter is the tag found.
+	LayoutMismatch(LayoutTag),
+	/// The proof kind is unknown.
+	UnknownKind,
+	/// The root is not a hash of the layout hasher. The parameter is the length found.
+	InvalidRootLength(usize),
+	/// The hex-JSON encoding is malformed.
+	InvalidJson,
+	/// A byte string of the hex-JSON encoding is not `0x` prefixed hex.
+	InvalidHex,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for EnvelopeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			EnvelopeError::UnexpectedEnd => write!(f, "Proof envelope is truncated"),
+			EnvelopeError::TrailingData => write!(f, "Unexpected data after proof envelope"),
+			EnvelopeError::InvalidLength => write!(f, "Invalid length in proof envelope"),
+			EnvelopeError::UnsupportedVersion(version) =>
+				write!(f, "Unsupported proof envelope version {}", version),
+			EnvelopeError::LayoutMismatch(tag) =>
+				write!(f, "Proof envelope is for another layout: tag={:?}", tag),
+			EnvelopeError::UnknownKind => write!(f, "Unknown proof kind in envelope"),
+			EnvelopeError::InvalidRootLength(len) =>
+				write!(f, "Proof envelope root has invalid length {}", len),
+			EnvelopeError::InvalidJson => write!(f, "Malformed proof envelope JSON"),
+			EnvelopeError::InvalidHex => write!(f, "Malformed hex string in proof envelope"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EnvelopeError {}
+
+/// A proof of kind `kind` for the trie of `root`, with layout `L`.
+///
+/// Encodings hold the tag of `L`, and decoding fails unless it matches the layout decoded for,
+/// so that a proof cannot be verified with another layout by mistake.
+pub struct ProofEnvelope<L: TrieLayout> {
+	/// Root of the trie the proof is for.
+	pub root: TrieHash<L>,
+	/// Kind of the proof.
+	pub kind: ProofKind,
+	/// Nodes of the proof.
+	pub nodes: Vec<Vec<u8>>,
+}
+
+impl<L: TrieLayout> Clone for ProofEnvelope<L> {
+	fn clone(&self) -> Self {
+		ProofEnvelope { root: self.root, kind: self.kind, nodes: self.nodes.clone() }
+	}
+}
+
+impl<L: TrieLayout> PartialEq for ProofEnvelope<L> {
+	fn eq(&self, other: &Self) -> bool {
+		self.root == other.root && self.kind == other.kind && self.nodes == other.nodes
+	}
+}
+
+impl<L: TrieLayout> Eq for ProofEnvelope<L> {}
+
+#[cfg(feature = "std")]
+impl<L: TrieLayout> std::fmt::Debug for ProofEnvelope<L> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ProofEnvelope")
+			.field("root", &self.root)
+			.field("kind", &self.kind)
+			.field("nodes", &self.nodes)
+			.finish()
+	}
+}
+
+impl<L: TrieLayout> ProofEnvelope<L> {
+	/// Wrap the `nodes` of a proof of kind `kind` for the trie of `root`.
+	pub fn new(root: TrieHash<L>, kind: ProofKind, nodes: Vec<Vec<u8>>) -> Self {
+		ProofEnvelope { root, kind, nodes }
+	}
+
+	/// Encode the envelope in the binary format.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut output = Vec::new();
+		output.push(ENVELOPE_VERSION);
+		output.extend_from_slice(&layout_tag::<L>());
+		output.push(self.kind.code());
+		encode_bytes(&mut output, self.root.as_ref());
+		encode_length(&mut output, self.nodes.len());
+		for node in self.nodes.iter() {
+			encode_bytes(&mut output, node);
+		}
+		output
+	}
+
+	/// Decode an envelope in the binary format, checking its version, layout tag, kind and root.
+	pub fn decode(mut input: &[u8]) -> Result<Self, EnvelopeError> {
+		let input = &mut input;
+		check_version(take(input, 1)?[0])?;
+		let mut tag = LayoutTag::default();
+		let tag_len = tag.len();
+		tag.copy_from_slice(take(input, tag_len)?);
+		check_layout::<L>(tag)?;
+		let kind = ProofKind::from_code(take(input, 1)?[0]).ok_or(EnvelopeError::UnknownKind)?;
+		let root = decode_root::<L>(decode_bytes(input)?)?;
+
+		let count = decode_length(input)?;
+		// Each node takes at least a byte, which bounds the allocation.
+		if count > input.len() {
+			return Err(EnvelopeError::UnexpectedEnd)
+		}
+		let mut nodes = Vec::with_capacity(count);
+		for _ in 0..count {
+			nodes.push(decode_bytes(input)?.to_vec());
+		}
+		if !input.is_empty() {
+			return Err(EnvelopeError::TrailingData)
+		}
+		Ok(ProofEnvelope { root, kind, nodes })
+	}
+
+	/// Encode the envelope in the hex-JSON format.
+	#[cfg(feature = "std")]
+	pub fn to_hex_json(&self) -> String {
+		let nodes: Vec<_> = self.nodes.iter().map(|node| format!("\"{}\"", to_hex(node))).collect();
+		format!(
+			"{{\"version\":{},\"layout\":\"{}\",\"kind\":\"{}\",\"root\":\"{}\",\"nodes\":[{}]}}",
+			ENVELOPE_VERSION,
+			to_hex(&layout_tag::<L>()),
+			self.kind.name(),
+			to_hex(self.root.as_ref()),
+			nodes.join(","),
+		)
+	}
+
+	/// Decode an envelope in the hex-JSON format, checking it as `decode`.
+	///
+	/// Fields may come in any order and be separated by whitespace, but must all be present
+	/// exactly once.
+	#[cfg(feature = "std")]
+	pub fn from_hex_json(json: &str) -> Result<Self, EnvelopeError> {
+		let mut reader = json::Reader::new(json);
+		let (mut version, mut tag, mut kind, mut root, mut nodes) = (None, None, None, None, None);
+		reader.expect(b'{')?;
+		loop {
+			let key = reader.string()?;
+			reader.expect(b':')?;
+			let duplicate = match key {
+				"version" => version.replace(reader.number()?).is_some(),
+				"layout" => tag.replace(from_hex(reader.string()?)?).is_some(),
+				"kind" => kind.replace(reader.string()?).is_some(),
+				"root" => root.replace(from_hex(reader.string()?)?).is_some(),
+				"nodes" => {
+					let mut items = Vec::new();
+					reader.expect(b'[')?;
+					if !reader.next_is(b']') {
+						loop {
+							items.push(from_hex(reader.string()?)?);
+							if !reader.next_is(b',') {
+								break
+							}
+						}
+						reader.expect(b']')?;
+					}
+					nodes.replace(items).is_some()
+				},
+				_ => return Err(EnvelopeError::InvalidJson),
+			};
+			if duplicate {
+				return Err(EnvelopeError::InvalidJson)
+			}
+			if !reader.next_is(b',') {
+				break
+			}
+		}
+		reader.expect(b'}')?;
+		reader.end()?;
+
+		check_version(u8::try_from(required(version)?).map_err(|_| EnvelopeError::InvalidJson)?)?;
+		let tag =
+			LayoutTag::try_from(&required(tag)?[..]).map_err(|_| EnvelopeError::InvalidHex)?;
+		check_layout::<L>(tag)?;
+		let kind = ProofKind::from_name(required(kind)?).ok_or(EnvelopeError::UnknownKind)?;
+		let root = decode_root::<L>(&required(root)?)?;
+		Ok(ProofEnvelope { root, kind, nodes: required(nodes)? })
+	}
+}
+
+#[cfg(feature = "std")]
+fn required<T>(field: Option<T>) -> Result<T, EnvelopeError> {
+	field.ok_or(EnvelopeError::InvalidJson)
+}
+
+fn check_version(version: u8) -> Result<(), EnvelopeError> {
+	if version == ENVELOPE_VERSION {
+		Ok(())
+	} else {
+		Err(EnvelopeError::UnsupportedVersion(version))
+	}
+}
+
+fn check_layout<L: TrieLayout>(tag: LayoutTag) -> Result<(), EnvelopeError> {
+	if tag == layout_tag::<L>() {
+		Ok(())
+	} else {
+		Err(EnvelopeError::LayoutMismatch(tag))
+	}
+}
+
+fn decode_root<L: TrieLayout>(data: &[u8]) -> Result<TrieHash<L>, EnvelopeError> {
+	let mut root = TrieHash::<L>::default();
+	if data.len() != root.as_ref().len() {
+		return Err(EnvelopeError::InvalidRootLength(data.len()))
+	}
+	root.as_mut().copy_from_slice(data);
+	Ok(root)
+}
+
+/// Append `len` as a SCALE compact integer.
+pub(crate) fn encode_length(output: &mut Vec<u8>, len: usize) {
+	let len = len as u64;
+	match len {
+		0..=0x3f => output.push((len as u8) << 2),
+		0x40..=0x3fff => output.extend_from_slice(&((len as u16) << 2 | 0b01).to_le_bytes()),
+		0x4000..=0x3fff_ffff => output.extend_from_slice(&((len as u32) << 2 | 0b10).to_le_bytes()),
+		_ => {
+			let bytes = 8 - len.leading_zeros() as usize / 8;
+			output.push(((bytes - 4) as u8) << 2 | 0b11);
+			output.extend_from_slice(&len.to_le_bytes()[..bytes]);
+		},
+	}
+}
+
+fn encode_bytes(output: &mut Vec<u8>, data: &[u8]) {
+	encode_length(output, data.len());
+	output.extend_from_slice(data);
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], EnvelopeError> {
+	if input.len() < len {
+		return Err(EnvelopeError::UnexpectedEnd)
+	}
+	let (data, rest) = input.split_at(len);
+	*input = rest;
+	Ok(data)
+}
+
+/// Read a SCALE compact integer, rejecting encodings which are not the shortest.
+pub(crate) fn decode_length(input: &mut &[u8]) -> Result<usize, EnvelopeError> {
+	let first = take(input, 1)?[0];
+	let (len, min) = match first & 0b11 {
+		0b00 => (u64::from(first >> 2), 0),
+		0b01 => {
+			let mut bytes = [first, 0];
+			bytes[1..].copy_from_slice(take(input, 1)?);
+			(u64::from(u16::from_le_bytes(bytes) >> 2), 0x40)
+		},
+		0b10 => {
+			let mut bytes = [first, 0, 0, 0];
+			bytes[1..].copy_from_slice(take(input, 3)?);
+			(u64::from(u32::from_le_bytes(bytes) >> 2), 0x4000)
+		},
+		_ => {
+			let count = usize::from(first >> 2) + 4;
+			if count > 8 {
+				return Err(EnvelopeError::InvalidLength)
+			}
+			let mut bytes = [0; 8];
+			bytes[..count].copy_from_slice(take(input, count)?);
+			if bytes[count - 1] == 0 {
+				return Err(EnvelopeError::InvalidLength)
+			}
+			(u64::from_le_bytes(bytes), 0x4000_0000)
+		},
+	};
+	if len < min {
+		return Err(EnvelopeError::InvalidLength)
+	}
+	usize::try_from(len).map_err(|_| EnvelopeError::InvalidLength)
+}
+
+fn decode_bytes<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], EnvelopeError> {
+	let len = decode_length(input)?;
+	take(input, len)
+}
+
+#[cfg(feature = "std")]
+fn to_hex(data: &[u8]) -> String {
+	use rustc_hex::ToHex;
+	format!("0x{}", data.to_hex::<String>())
+}
+
+#[cfg(feature = "std")]
+fn from_hex(hex: &str) -> Result<Vec<u8>, EnvelopeError> {
+	use rustc_hex::FromHex;
+	hex.strip_prefix("0x")
+		.ok_or(EnvelopeError::InvalidHex)?
+		.from_hex()
+		.map_err(|_| EnvelopeError::InvalidHex)
+}
+
+/// Reader for the subset of JSON used by the hex-JSON encoding.
+#[cfg(feature = "std")]
+mod json {
+	use super::EnvelopeError;
+
+	pub(super) struct Reader<'a> {
+		input: &'a str,
+	}
+
+	impl<'a> Reader<'a> {
+		pub(super) fn new(input: &'a str) -> Self {
+			Reader { input }
+		}
+
+		fn skip_whitespace(&mut self) {
+			self.input = self.input.trim_start_matches([' ', '\t', '\n', '\r']);
+		}
+
+		/// Consume `byte` if it is the next token.
+		pub(super) fn next_is(&mut self, byte: u8) -> bool {
+			self.skip_whitespace();
+			if self.input.as_bytes().first() == Some(&byte) {
+				self.input = &self.input[1..];
+				true
+			} else {
+				false
+			}
+		}
+
+		pub(super) fn expect(&mut self, byte: u8) -> Result<(), EnvelopeError> {
+			if self.next_is(byte) {
+				Ok(())
+			} else {
+				Err(EnvelopeError::InvalidJson)
+			}
+		}
+
+		pub(super) fn end(&mut self) -> Result<(), EnvelopeError> {
+			self.skip_whitespace();
+			if self.input.is_empty() {
+				Ok(())
+			} else {
+				Err(EnvelopeError::TrailingData)
+			}
+		}
+
+		/// Read a string without escape sequences, which the encoding never needs.
+		pub(super) fn string(&mut self) -> Result<&'a str, EnvelopeError> {
+			self.expect(b'"')?;
+			let end = self.input.find(['"', '\\']).ok_or(EnvelopeError::InvalidJson)?;
+			let (string, rest) = self.input.split_at(end);
+			if !rest.starts_with('"') {
+				return Err(EnvelopeError::InvalidJson)
+			}
+			self.input = &rest[1..];
+			Ok(string)
+		}
+
+		/// Read a non-negative integer.
+		pub(super) fn number(&mut self) -> Result<u64, EnvelopeError> {
+			self.skip_whitespace();
+			let end = self.input.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.input.len());
+			let (number, rest) = self.input.split_at(end);
+			self.input = rest;
+			number.parse().map_err(|_| EnvelopeError::InvalidJson)
+		}
+	}
+}
@@ -19,18 +19,17 @@ use crate::rstd::{boxed::Box, convert::TryInto, marker::PhantomData, vec, vec::V
 use hash_db::Hasher;
 
 use crate::{
-	nibble::LeftNibbleSlice,
-	nibble_ops::NIBBLE_LENGTH,
-	node::{NodeHandle, NodeHandlePlan, NodePlan, OwnedNode, Value, ValuePlan},
-	CError, ChildReference, NibbleSlice, NodeCodec, Record, Recorder, Result as TrieResult, Trie,
-	TrieError, TrieHash, TrieLayout,
+	nibble::{nibble_ops::NibbleOps, LeftNibbleSlice},
+	node::{Children, NodeHandle, NodeHandlePlan, NodePlan, OwnedNode, Value, ValuePlan},
+	CError, ChildReference, NibbleSlice, NodeCodec, ProofBudget, Record, Recorder,
+	Result as TrieResult, Trie, TrieError, TrieHash, TrieLayout,
 };
 
 struct StackEntry<'a, C: NodeCodec> {
 	/// The prefix is the nibble path to the node in the trie.
-	prefix: LeftNibbleSlice<'a>,
+	prefix: LeftNibbleSlice<'a, C::Nibble>,
 	/// Stacked node.
-	node: OwnedNode<Vec<u8>>,
+	node: OwnedNode<Vec<u8>, C::Nibble>,
 	/// The hash of the node or None if it is referenced inline.
 	node_hash: Option<C::HashOut>,
 	/// Whether the value should be omitted in the generated proof.
@@ -47,7 +46,7 @@ struct StackEntry<'a, C: NodeCodec> {
 
 impl<'a, C: NodeCodec> StackEntry<'a, C> {
 	fn new(
-		prefix: LeftNibbleSlice<'a>,
+		prefix: LeftNibbleSlice<'a, C::Nibble>,
 		node_data: Vec<u8>,
 		node_hash: Option<C::HashOut>,
 		output_index: Option<usize>,
@@ -57,7 +56,7 @@ impl<'a, C: NodeCodec> StackEntry<'a, C> {
 		let children_len = match node.node_plan() {
 			NodePlan::Empty | NodePlan::Leaf { .. } => 0,
 			NodePlan::Extension { .. } => 1,
-			NodePlan::Branch { .. } | NodePlan::NibbledBranch { .. } => NIBBLE_LENGTH,
+			NodePlan::Branch { .. } | NodePlan::NibbledBranch { .. } => C::Nibble::NIBBLE_LENGTH,
 		};
 		Ok(StackEntry {
 			prefix,
@@ -137,11 +136,11 @@ impl<'a, C: NodeCodec> StackEntry<'a, C> {
 	/// - children has size NIBBLE_LENGTH.
 	fn complete_branch_children(
 		node_data: &[u8],
-		child_handles: &[Option<NodeHandlePlan>; NIBBLE_LENGTH],
+		child_handles: &Children<C::Nibble, Option<NodeHandlePlan>>,
 		child_index: usize,
 		children: &mut [Option<ChildReference<C::HashOut>>],
 	) -> TrieResult<(), C::HashOut, C::Error> {
-		for i in child_index..NIBBLE_LENGTH {
+		for i in child_index..C::Nibble::NIBBLE_LENGTH {
 			children[i] = child_handles[i]
 				.as_ref()
 				.map(|child_plan| {
@@ -175,7 +174,7 @@ impl<'a, C: NodeCodec> StackEntry<'a, C> {
 			},
 			NodePlan::Branch { children, .. } | NodePlan::NibbledBranch { children, .. } => {
 				assert!(
-					self.child_index < NIBBLE_LENGTH,
+					self.child_index < C::Nibble::NIBBLE_LENGTH,
 					"extension nodes have at most NIBBLE_LENGTH children; \
 					set_child is called when the only child is popped from the stack; \
 					child_index is <NIBBLE_LENGTH before child is pushed to the stack; qed"
@@ -221,6 +220,36 @@ pub fn generate_proof<'a, T, L, I, K>(
 	trie: &T,
 	keys: I,
 ) -> TrieResult<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	T: Trie<L>,
+	L: TrieLayout,
+	I: IntoIterator<Item = &'a K>,
+	K: 'a + AsRef<[u8]>,
+{
+	generate_proof_with_budget(trie, keys, ProofBudget::unlimited()).map(|proof| proof.proof)
+}
+
+/// Compact proof generated within a `ProofBudget`.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, PartialEq, Eq)]
+pub struct BudgetedProof {
+	/// Proof of the keys lower than `resume_from`, or of all keys if it is `None`.
+	pub proof: Vec<Vec<u8>>,
+	/// First key left out of the proof.
+	pub resume_from: Option<Vec<u8>>,
+}
+
+/// Generate a compact proof for key-value pairs in a trie given a set of keys, see
+/// `generate_proof`, with the nodes of the proof fitting in `budget`.
+///
+/// Keys are proven in order until the nodes needed for the next key exceed the budget. That key
+/// is returned as `resume_from`, to generate a proof for the remaining keys later. Fails with
+/// `TrieError::BudgetExceeded` if the first key does not fit in the budget.
+pub fn generate_proof_with_budget<'a, T, L, I, K>(
+	trie: &T,
+	keys: I,
+	budget: ProofBudget,
+) -> TrieResult<BudgetedProof, TrieHash<L>, CError<L>>
 where
 	T: Trie<L>,
 	L: TrieLayout,
@@ -232,6 +261,10 @@ where
 	keys.sort();
 	keys.dedup();
 
+	// Nodes and bytes used from the budget.
+	let (mut used_nodes, mut used_bytes) = (0, 0);
+	let mut resume_from = None;
+
 	// The stack of nodes through a path in the trie. Each entry is a child node of the preceding
 	// entry.
 	let mut stack = <Vec<StackEntry<L::Codec>>>::new();
@@ -267,6 +300,22 @@ where
 			}
 		}
 
+		// The remaining recorded nodes are added to the proof for this key.
+		let recorded_nodes: Vec<_> = recorded_nodes.collect();
+		let nodes = used_nodes + recorded_nodes.len();
+		let bytes =
+			used_bytes + recorded_nodes.iter().map(|record| record.data.len()).sum::<usize>();
+		if !budget.allows(nodes, bytes) {
+			if used_nodes == 0 {
+				return Err(Box::new(TrieError::BudgetExceeded(budget)))
+			}
+			resume_from = Some(key_bytes.to_vec());
+			break
+		}
+		used_nodes = nodes;
+		used_bytes = bytes;
+		let mut recorded_nodes = recorded_nodes.into_iter();
+
 		loop {
 			let step = match stack.last_mut() {
 				Some(entry) => match_key_to_node::<L::Codec>(
@@ -366,7 +415,7 @@ where
 	}
 
 	unwind_stack::<L::Codec>(&mut stack, &mut proof_nodes, None)?;
-	Ok(proof_nodes)
+	Ok(BudgetedProof { proof: proof_nodes, resume_from })
 }
 
 enum Step<'a> {
@@ -389,11 +438,11 @@ fn resolve_value<C: NodeCodec>(
 /// entry on the stack.
 fn match_key_to_node<'a, C: NodeCodec>(
 	node_data: &'a [u8],
-	node_plan: &NodePlan,
+	node_plan: &NodePlan<C::Nibble>,
 	omit_value: &mut bool,
 	child_index: &mut usize,
 	children: &mut [Option<ChildReference<C::HashOut>>],
-	key: &LeftNibbleSlice,
+	key: &LeftNibbleSlice<C::Nibble>,
 	prefix_len: usize,
 	recorded_nodes: &mut dyn Iterator<Item = Record<C::HashOut>>,
 ) -> TrieResult<Step<'a>, C::HashOut, C::Error> {
@@ -458,13 +507,13 @@ fn match_key_to_node<'a, C: NodeCodec>(
 fn match_key_to_branch_node<'a, 'b, C: NodeCodec>(
 	node_data: &'a [u8],
 	value_range: Option<&'b ValuePlan>,
-	child_handles: &'b [Option<NodeHandlePlan>; NIBBLE_LENGTH],
+	child_handles: &'b Children<C::Nibble, Option<NodeHandlePlan>>,
 	omit_value: &mut bool,
 	child_index: &mut usize,
 	children: &mut [Option<ChildReference<C::HashOut>>],
-	key: &'b LeftNibbleSlice<'b>,
+	key: &'b LeftNibbleSlice<'b, C::Nibble>,
 	prefix_len: usize,
-	partial: NibbleSlice<'b>,
+	partial: NibbleSlice<'b, C::Nibble>,
 	recorded_nodes: &mut dyn Iterator<Item = Record<C::HashOut>>,
 ) -> TrieResult<Step<'a>, C::HashOut, C::Error> {
 	if !key.contains(&partial, prefix_len) {
@@ -521,7 +570,7 @@ fn match_key_to_branch_node<'a, 'b, C: NodeCodec>(
 fn unwind_stack<C: NodeCodec>(
 	stack: &mut Vec<StackEntry<C>>,
 	proof_nodes: &mut Vec<Vec<u8>>,
-	maybe_key: Option<&LeftNibbleSlice>,
+	maybe_key: Option<&LeftNibbleSlice<C::Nibble>>,
 ) -> TrieResult<(), C::HashOut, C::Error> {
 	while let Some(entry) = stack.pop() {
 		match maybe_key {
@@ -0,0 +1,120 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the resources spent verifying untrusted proofs.
+
+use crate::{nibble::nibble_ops::NibbleOps, rstd::result::Result};
+
+/// Limits enforced while verifying or decoding a proof.
+///
+/// Proofs exceeding a limit are rejected before their nodes are hashed where possible, so that
+/// verifying a malicious proof is bounded by the limits rather than by the proof size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyLimits {
+	/// Maximum number of items in the proof, nodes and values included.
+	pub max_nodes: usize,
+	/// Maximum size of an item of the proof, in bytes.
+	pub max_node_size: usize,
+	/// Maximum length of a key, in bytes. For proofs of a whole partial trie, this bounds the
+	/// path to every node.
+	pub max_key_len: usize,
+	/// Maximum number of nodes on the path from the root to a node.
+	pub max_depth: usize,
+}
+
+/// The limit of a `VerifyLimits` exceeded by a proof, with its configured value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceededLimit {
+	/// The proof holds more items than `max_nodes`.
+	Nodes(usize),
+	/// An item of the proof is larger than `max_node_size`.
+	NodeSize(usize),
+	/// A key is longer than `max_key_len`.
+	KeyLength(usize),
+	/// A node is deeper than `max_depth`.
+	Depth(usize),
+}
+
+impl VerifyLimits {
+	/// Limits which accept any proof.
+	pub fn unlimited() -> Self {
+		VerifyLimits {
+			max_nodes: usize::MAX,
+			max_node_size: usize::MAX,
+			max_key_len: usize::MAX,
+			max_depth: usize::MAX,
+		}
+	}
+
+	/// Check that a proof of `count` items is within `max_nodes`.
+	pub fn check_nodes(&self, count: usize) -> Result<(), ExceededLimit> {
+		check(count, self.max_nodes, ExceededLimit::Nodes)
+	}
+
+	/// Check that an item of `size` bytes is within `max_node_size`.
+	pub fn check_node_size(&self, size: usize) -> Result<(), ExceededLimit> {
+		check(size, self.max_node_size, ExceededLimit::NodeSize)
+	}
+
+	/// Check that a key of `len` bytes is within `max_key_len`.
+	pub fn check_key_len(&self, len: usize) -> Result<(), ExceededLimit> {
+		check(len, self.max_key_len, ExceededLimit::KeyLength)
+	}
+
+	/// Check that a path of `len` nibbles of type `N` is within `max_key_len`.
+	pub fn check_nibble_len<N: NibbleOps>(&self, len: usize) -> Result<(), ExceededLimit> {
+		self.check_key_len(len.div_ceil(N::NIBBLE_PER_BYTE))
+	}
+
+	/// Check that a node at `depth`, counting the root as 1, is within `max_depth`.
+	pub fn check_depth(&self, depth: usize) -> Result<(), ExceededLimit> {
+		check(depth, self.max_depth, ExceededLimit::Depth)
+	}
+
+	/// Check the number and size of the items of `proof`.
+	pub fn check_proof<T: AsRef<[u8]>>(&self, proof: &[T]) -> Result<(), ExceededLimit> {
+		self.check_nodes(proof.len())?;
+		proof.iter().try_for_each(|item| self.check_node_size(item.as_ref().len()))
+	}
+}
+
+impl Default for VerifyLimits {
+	fn default() -> Self {
+		VerifyLimits::unlimited()
+	}
+}
+
+fn check(
+	value: usize,
+	max: usize,
+	exceeded: fn(usize) -> ExceededLimit,
+) -> Result<(), ExceededLimit> {
+	if value > max {
+		Err(exceeded(max))
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for ExceededLimit {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ExceededLimit::Nodes(max) => write!(f, "proof holds more than {} items", max),
+			ExceededLimit::NodeSize(max) => write!(f, "proof item is larger than {} bytes", max),
+			ExceededLimit::KeyLength(max) => write!(f, "key is longer than {} bytes", max),
+			ExceededLimit::Depth(max) => write!(f, "node is deeper than {} levels", max),
+		}
+	}
+}
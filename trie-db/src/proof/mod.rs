@@ -31,9 +31,134 @@
 //! pre-order traversal order, the construction can be done efficiently using a stack.
 
 pub use self::{
-	generate::generate_proof,
-	verify::{verify_proof, Error as VerifyError},
+	envelope::{layout_tag, EnvelopeError, LayoutTag, ProofEnvelope, ProofKind, ENVELOPE_VERSION},
+	generate::{generate_proof, generate_proof_with_budget, BudgetedProof},
+	limits::{ExceededLimit, VerifyLimits},
+	ordered::{generate_ordered_proof, verify_ordered_proof, verify_ordered_proof_with_limits},
+	recorded::{
+		compact_from_recorded, proof_from_recorded, recorded_from_compact,
+		recorded_from_compact_with_limits,
+	},
+	values::{generate_value_proof, verify_value_proof, verify_value_proof_with_limits},
+	verify::{verify_proof, verify_proof_with_limits, Error as VerifyError},
+	witness::{verify_update_witness, verify_update_witness_with_limits},
 };
 
+#[cfg(feature = "std")]
+pub use self::witness::generate_update_witness;
+
+#[cfg(feature = "std")]
+pub(crate) use self::envelope::{decode_length, encode_length};
+
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher, Prefix};
+use hashbrown::HashMap;
+
+use crate::{
+	rstd::vec::Vec, CError, DBValue, NodeCodec, Result as TrieResult, TrieHash, TrieLayout,
+};
+
+/// A trie root and a set of encoded nodes.
+type RootAndNodes<L> = TrieResult<(TrieHash<L>, Vec<Vec<u8>>), TrieHash<L>, CError<L>>;
+
+/// Reference counted nodes keyed by hash only, as in `memory_db::MemoryDB` with `HashKey`.
+///
+/// The empty node is always available under `NodeCodec::hashed_null_node`, without being
+/// stored.
+pub(crate) struct NodeStore<L: TrieLayout> {
+	nodes: HashMap<TrieHash<L>, (DBValue, i32)>,
+}
+
+impl<L: TrieLayout> NodeStore<L> {
+	pub(crate) fn new() -> Self {
+		NodeStore { nodes: HashMap::new() }
+	}
+
+	/// Store each of `nodes` under its hash.
+	pub(crate) fn from_nodes<I, A>(nodes: I) -> Self
+	where
+		I: IntoIterator<Item = A>,
+		A: AsRef<[u8]>,
+	{
+		let mut store = Self::new();
+		for node in nodes {
+			store.insert((&[], None), node.as_ref());
+		}
+		store
+	}
+
+	/// Hashes of the stored nodes.
+	pub(crate) fn hashes(&self) -> impl Iterator<Item = &TrieHash<L>> {
+		self.nodes.iter().filter(|(_, (_, rc))| *rc > 0).map(|(hash, _)| hash)
+	}
+
+	/// Stored nodes, each with its hash and number of references.
+	pub(crate) fn drain(self) -> impl Iterator<Item = (TrieHash<L>, DBValue, i32)> {
+		self.nodes
+			.into_iter()
+			.filter(|(_, (_, rc))| *rc > 0)
+			.map(|(hash, (node, rc))| (hash, node, rc))
+	}
+}
+
+impl<L: TrieLayout> HashDBRef<L::Hash, DBValue> for NodeStore<L> {
+	fn get(&self, key: &TrieHash<L>, _: Prefix) -> Option<DBValue> {
+		if *key == L::Codec::hashed_null_node() {
+			return Some(L::Codec::empty_node().to_vec())
+		}
+		match self.nodes.get(key) {
+			Some((value, rc)) if *rc > 0 => Some(value.clone()),
+			_ => None,
+		}
+	}
+
+	fn contains(&self, key: &TrieHash<L>, prefix: Prefix) -> bool {
+		HashDBRef::get(self, key, prefix).is_some()
+	}
+}
+
+impl<L: TrieLayout> HashDB<L::Hash, DBValue> for NodeStore<L> {
+	fn get(&self, key: &TrieHash<L>, prefix: Prefix) -> Option<DBValue> {
+		HashDBRef::get(self, key, prefix)
+	}
+
+	fn contains(&self, key: &TrieHash<L>, prefix: Prefix) -> bool {
+		HashDBRef::contains(self, key, prefix)
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> TrieHash<L> {
+		let key = L::Hash::hash(value);
+		self.emplace(key, prefix, value.to_vec());
+		key
+	}
+
+	fn emplace(&mut self, key: TrieHash<L>, _: Prefix, value: DBValue) {
+		let entry = self.nodes.entry(key).or_insert_with(|| (Vec::new(), 0));
+		if entry.1 <= 0 {
+			entry.0 = value;
+		}
+		entry.1 += 1;
+	}
+
+	fn remove(&mut self, key: &TrieHash<L>, _: Prefix) {
+		self.nodes.entry(*key).or_insert_with(|| (Vec::new(), 0)).1 -= 1;
+	}
+}
+
+impl<L: TrieLayout> AsHashDB<L::Hash, DBValue> for NodeStore<L> {
+	fn as_hash_db(&self) -> &dyn HashDB<L::Hash, DBValue> {
+		self
+	}
+
+	fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<L::Hash, DBValue> + 'a) {
+		self
+	}
+}
+
+mod envelope;
 mod generate;
+mod limits;
+mod ordered;
+mod recorded;
+mod values;
 mod verify;
+mod witness;
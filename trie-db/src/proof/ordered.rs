@@ -0,0 +1,92 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact proofs for tries built with `TrieConfiguration::ordered_trie_root`.
+
+use crate::rstd::vec::Vec;
+
+use crate::{
+	proof::{generate_proof, verify_proof_with_limits, NodeStore, VerifyError, VerifyLimits},
+	trie_visit, CError, NodeCodec, Result as TrieResult, TrieBuilder, TrieConfiguration, TrieDB,
+	TrieHash,
+};
+
+/// Generate a compact proof for the items at `indices` of the trie whose root is
+/// `L::ordered_trie_root(items)`.
+///
+/// The trie nodes are built in memory from `items`. Indices past the end of `items` are
+/// proven absent.
+pub fn generate_ordered_proof<'a, L, I, A, J>(
+	items: I,
+	indices: J,
+) -> TrieResult<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieConfiguration,
+	I: IntoIterator<Item = A>,
+	A: AsRef<[u8]>,
+	J: IntoIterator<Item = &'a u32>,
+{
+	let mut input: Vec<_> = items
+		.into_iter()
+		.enumerate()
+		.map(|(i, v)| (L::encode_index(i as u32), v))
+		.collect();
+	input.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+	let mut store = NodeStore::<L>::new();
+	let mut builder = TrieBuilder::<L, _>::new(&mut store);
+	trie_visit::<L, _, _, _, _>(input, &mut builder);
+	let root = builder.root.unwrap_or_else(L::Codec::hashed_null_node);
+
+	let keys: Vec<_> = indices.into_iter().map(|i| L::encode_index(*i)).collect();
+	let trie = TrieDB::<L>::new(&store, &root);
+	generate_proof(&trie, &keys)
+}
+
+/// Verify a compact proof generated by `generate_ordered_proof` against the root of an ordered
+/// trie.
+///
+/// `items` are pairs of an index and its expected value, or `None` if the index is expected to
+/// be absent from the trie.
+pub fn verify_ordered_proof<'a, L, I, V>(
+	root: &TrieHash<L>,
+	proof: &[Vec<u8>],
+	items: I,
+) -> Result<(), VerifyError<TrieHash<L>, CError<L>>>
+where
+	L: TrieConfiguration,
+	I: IntoIterator<Item = &'a (u32, Option<V>)>,
+	V: 'a + AsRef<[u8]>,
+{
+	verify_ordered_proof_with_limits::<L, _, _>(root, proof, items, &VerifyLimits::unlimited())
+}
+
+/// Verify a proof as `verify_ordered_proof`, failing with `VerifyError::LimitExceeded` if the
+/// proof or the encoded indices exceed `limits`.
+pub fn verify_ordered_proof_with_limits<'a, L, I, V>(
+	root: &TrieHash<L>,
+	proof: &[Vec<u8>],
+	items: I,
+	limits: &VerifyLimits,
+) -> Result<(), VerifyError<TrieHash<L>, CError<L>>>
+where
+	L: TrieConfiguration,
+	I: IntoIterator<Item = &'a (u32, Option<V>)>,
+	V: 'a + AsRef<[u8]>,
+{
+	let items: Vec<_> = items
+		.into_iter()
+		.map(|(i, v)| (L::encode_index(*i), v.as_ref().map(|v| v.as_ref())))
+		.collect();
+	verify_proof_with_limits::<L, _, _, _>(root, proof, &items, limits)
+}
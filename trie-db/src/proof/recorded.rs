@@ -0,0 +1,93 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion between recorded nodes and compact proofs.
+//!
+//! Recorded nodes are the raw nodes and values read from the database, as collected by a
+//! `Recorder` passed to `Trie::get_with` or shipped by eip-1186 proofs. Compact encodings omit
+//! the hashes of the nodes they include, and are much smaller.
+
+use crate::rstd::vec::Vec;
+
+use crate::{
+	decode_compact_with_limits, encode_compact,
+	proof::{generate_proof, NodeStore, RootAndNodes, VerifyLimits},
+	CError, Result as TrieResult, TrieDB, TrieHash, TrieLayout,
+};
+
+/// Compactly encode the partial trie of `root` formed by the recorded `nodes`, see
+/// `encode_compact`.
+///
+/// Fails with `TrieError::InvalidStateRoot` if the root node is not recorded. Subtrees of nodes
+/// not recorded are omitted, as are recorded nodes not reachable from `root`.
+pub fn compact_from_recorded<L, I, A>(
+	root: &TrieHash<L>,
+	nodes: I,
+) -> TrieResult<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = A>,
+	A: AsRef<[u8]>,
+{
+	let store = NodeStore::<L>::from_nodes(nodes);
+	encode_compact::<L>(&TrieDB::<L>::new(&store, root))
+}
+
+/// Expand a compact encoding back to the raw nodes and values it holds, and return them with
+/// the root of the trie, see `decode_compact`.
+///
+/// Nodes are returned sorted. Items of `encoded` following the first trie are ignored.
+pub fn recorded_from_compact<L>(encoded: &[Vec<u8>]) -> RootAndNodes<L>
+where
+	L: TrieLayout,
+{
+	recorded_from_compact_with_limits::<L>(encoded, &VerifyLimits::unlimited())
+}
+
+/// Variant of `recorded_from_compact` failing with `TrieError::LimitExceeded` if the items read
+/// exceed `limits`, see `decode_compact_with_limits`.
+pub fn recorded_from_compact_with_limits<L>(
+	encoded: &[Vec<u8>],
+	limits: &VerifyLimits,
+) -> RootAndNodes<L>
+where
+	L: TrieLayout,
+{
+	let mut store = NodeStore::<L>::new();
+	let (root, _) = decode_compact_with_limits::<L, _>(&mut store, encoded, limits)?;
+	let mut nodes: Vec<_> = store.drain().map(|(_, node, _)| node).collect();
+	nodes.sort();
+	Ok((root, nodes))
+}
+
+/// Generate the compact proof of `keys` accepted by `verify_proof` from the recorded `nodes` of
+/// the trie of `root`, see `generate_proof`.
+///
+/// The nodes must include those traversed by looking up `keys`, otherwise
+/// `TrieError::IncompleteDatabase` is returned.
+pub fn proof_from_recorded<'a, L, I, A, J, K>(
+	root: &TrieHash<L>,
+	nodes: I,
+	keys: J,
+) -> TrieResult<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = A>,
+	A: AsRef<[u8]>,
+	J: IntoIterator<Item = &'a K>,
+	K: 'a + AsRef<[u8]>,
+{
+	let store = NodeStore::<L>::from_nodes(nodes);
+	generate_proof::<_, L, _, _>(&TrieDB::<L>::new(&store, root), keys)
+}
@@ -0,0 +1,126 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Proofs of the values at a set of keys, verified without knowing the values.
+//!
+//! `generate_proof` omits the values of the proven keys, so its verifier has to be given them.
+//! These proofs are instead the `encode_compact` encoding of the nodes traversed by the lookups,
+//! values included, and the verifier returns the value found at each key.
+
+use crate::rstd::vec::Vec;
+
+use hashbrown::HashSet;
+
+use crate::{
+	decode_compact_with_limits, encode_compact,
+	proof::{NodeStore, VerifyError, VerifyLimits},
+	CError, DBValue, Recorder, Result as TrieResult, Trie, TrieDB, TrieError, TrieHash, TrieLayout,
+};
+
+/// Generate a proof of the values at `keys`, to be verified with `verify_value_proof`.
+pub fn generate_value_proof<'a, L, I, K>(
+	trie: &TrieDB<L>,
+	keys: I,
+) -> TrieResult<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = &'a K>,
+	K: 'a + AsRef<[u8]>,
+{
+	let mut recorder = Recorder::new();
+	for key in keys {
+		trie.get_with(key.as_ref(), &mut recorder)?;
+	}
+	let nodes = NodeStore::<L>::from_nodes(recorder.drain().into_iter().map(|record| record.data));
+	encode_compact::<L>(&TrieDB::<L>::new(&nodes, trie.root()))
+}
+
+type VerifyResult<L, T> = Result<T, VerifyError<TrieHash<L>, CError<L>>>;
+
+fn to_verify_error<L: TrieLayout>(
+	error: TrieError<TrieHash<L>, CError<L>>,
+) -> VerifyError<TrieHash<L>, CError<L>> {
+	match error {
+		TrieError::DecoderError(_, e) => VerifyError::DecodeError(e),
+		TrieError::InvalidHash(_, data) => VerifyError::InvalidChildReference(data),
+		TrieError::ValueAtIncompleteKey(key, _) => VerifyError::ValueMismatch(key),
+		TrieError::LimitExceeded(limit) => VerifyError::LimitExceeded(limit),
+		// Decoding and lookups have no budget, and the decoded proof holds its values.
+		TrieError::InvalidStateRoot(_) |
+		TrieError::IncompleteDatabase(_) |
+		TrieError::BudgetExceeded(_) |
+		TrieError::MissingValue(_) => VerifyError::IncompleteProof,
+	}
+}
+
+/// Verify a proof generated by `generate_value_proof` against `root`, and return the value at
+/// each of `keys`, in order, or `None` for keys proven absent.
+///
+/// Fails with `VerifyError::ExtraneousNode` if the proof holds nodes not needed to look up
+/// `keys`, and with `VerifyError::IncompleteProof` if a needed node is missing.
+pub fn verify_value_proof<'a, L, I, K>(
+	root: &TrieHash<L>,
+	proof: &[Vec<u8>],
+	keys: I,
+) -> VerifyResult<L, Vec<Option<DBValue>>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = &'a K>,
+	K: 'a + AsRef<[u8]>,
+{
+	verify_value_proof_with_limits::<L, _, _>(root, proof, keys, &VerifyLimits::unlimited())
+}
+
+/// Verify a proof as `verify_value_proof`, failing with `VerifyError::LimitExceeded` if the proof
+/// or `keys` exceed `limits`.
+pub fn verify_value_proof_with_limits<'a, L, I, K>(
+	root: &TrieHash<L>,
+	proof: &[Vec<u8>],
+	keys: I,
+	limits: &VerifyLimits,
+) -> VerifyResult<L, Vec<Option<DBValue>>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = &'a K>,
+	K: 'a + AsRef<[u8]>,
+{
+	let keys: Vec<_> = keys.into_iter().map(AsRef::as_ref).collect();
+	for key in keys.iter() {
+		limits.check_key_len(key.len()).map_err(VerifyError::LimitExceeded)?;
+	}
+
+	let mut nodes = NodeStore::<L>::new();
+	let (computed_root, used) = decode_compact_with_limits::<L, _>(&mut nodes, proof, limits)
+		.map_err(|e| to_verify_error::<L>(*e))?;
+	if used != proof.len() {
+		return Err(VerifyError::ExtraneousNode)
+	}
+	if computed_root != *root {
+		return Err(VerifyError::RootMismatch(computed_root))
+	}
+
+	let trie = TrieDB::<L>::new(&nodes, root);
+	let mut recorder = Recorder::new();
+	let mut values = Vec::new();
+	for key in keys {
+		let value = trie.get_with(key, &mut recorder).map_err(|e| to_verify_error::<L>(*e))?;
+		values.push(value);
+	}
+
+	let visited: HashSet<_> = recorder.drain().into_iter().map(|record| record.hash).collect();
+	if nodes.hashes().any(|hash| !visited.contains(hash)) {
+		return Err(VerifyError::ExtraneousNode)
+	}
+	Ok(values)
+}
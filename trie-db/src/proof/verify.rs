@@ -15,9 +15,9 @@
 //! Verification of compact proofs for Merkle-Patricia tries.
 
 use crate::{
-	nibble::LeftNibbleSlice,
-	nibble_ops::NIBBLE_LENGTH,
-	node::{Node, NodeHandle, Value},
+	nibble::{nibble_ops::NibbleOps, LeftNibbleSlice},
+	node::{Children, Node, NodeHandle, Value},
+	proof::{ExceededLimit, VerifyLimits},
 	rstd::{convert::TryInto, iter::Peekable, marker::PhantomData, result::Result, vec, vec::Vec},
 	CError, ChildReference, NodeCodec, TrieHash, TrieLayout,
 };
@@ -49,6 +49,8 @@ pub enum Error<HO, CE> {
 	RootMismatch(HO),
 	/// One of the proof nodes could not be decoded.
 	DecodeError(CE),
+	/// The proof exceeds one of the verification limits.
+	LimitExceeded(ExceededLimit),
 }
 
 #[cfg(feature = "std")]
@@ -72,6 +74,7 @@ impl<HO: std::fmt::Debug, CE: std::error::Error> std::fmt::Display for Error<HO,
 			Error::IncompleteProof => write!(f, "Proof is incomplete -- expected more nodes"),
 			Error::RootMismatch(hash) => write!(f, "Computed incorrect root {:?} from proof", hash),
 			Error::DecodeError(err) => write!(f, "Unable to decode proof node: {}", err),
+			Error::LimitExceeded(limit) => write!(f, "Proof exceeds limit: {}", limit),
 		}
 	}
 }
@@ -88,8 +91,8 @@ impl<HO: std::fmt::Debug, CE: std::error::Error + 'static> std::error::Error for
 
 struct StackEntry<'a, L: TrieLayout> {
 	/// The prefix is the nibble path to the node in the trie.
-	prefix: LeftNibbleSlice<'a>,
-	node: Node<'a>,
+	prefix: LeftNibbleSlice<'a, L::Nibble>,
+	node: Node<'a, L::Nibble>,
 	is_inline: bool,
 	/// The value associated with this trie node.
 	value: Option<Value<'a>>,
@@ -106,14 +109,14 @@ struct StackEntry<'a, L: TrieLayout> {
 impl<'a, L: TrieLayout> StackEntry<'a, L> {
 	fn new(
 		node_data: &'a [u8],
-		prefix: LeftNibbleSlice<'a>,
+		prefix: LeftNibbleSlice<'a, L::Nibble>,
 		is_inline: bool,
 	) -> Result<Self, Error<TrieHash<L>, CError<L>>> {
 		let node = L::Codec::decode(&node_data[..]).map_err(Error::DecodeError)?;
 		let children_len = match &node {
 			Node::Empty | Node::Leaf(..) => 0,
 			Node::Extension(..) => 1,
-			Node::Branch(..) | Node::NibbledBranch(..) => NIBBLE_LENGTH,
+			Node::Branch(..) | Node::NibbledBranch(..) => L::Nibble::NIBBLE_LENGTH,
 		};
 		let value = match &node {
 			Node::Empty | Node::Extension(_, _) => None,
@@ -170,7 +173,7 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 
 	fn advance_child_index<I>(
 		&mut self,
-		child_prefix: LeftNibbleSlice<'a>,
+		child_prefix: LeftNibbleSlice<'a, L::Nibble>,
 		proof_iter: &mut I,
 	) -> Result<Self, Error<TrieHash<L>, CError<L>>>
 	where
@@ -182,7 +185,7 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 				assert_eq!(self.child_index, 0);
 				Self::make_child_entry(proof_iter, child, child_prefix)
 			},
-			Node::Branch(children, _) | Node::NibbledBranch(_, children, _) => {
+			Node::Branch(ref children, _) | Node::NibbledBranch(_, ref children, _) => {
 				// because this is a branch
 				assert!(child_prefix.len() > 0);
 				let child_index = child_prefix
@@ -210,8 +213,8 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 				self.children[self.child_index] = Some(child_ref);
 				self.child_index += 1;
 			},
-			Node::Branch(children, _) | Node::NibbledBranch(_, children, _) => {
-				while self.child_index < NIBBLE_LENGTH {
+			Node::Branch(ref children, _) | Node::NibbledBranch(_, ref children, _) => {
+				while self.child_index < L::Nibble::NIBBLE_LENGTH {
 					if let Some(child) = children[self.child_index] {
 						let child_ref = child.try_into().map_err(Error::InvalidChildReference)?;
 						self.children[self.child_index] = Some(child_ref);
@@ -227,7 +230,7 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 	fn make_child_entry<I>(
 		proof_iter: &mut I,
 		child: NodeHandle<'a>,
-		prefix: LeftNibbleSlice<'a>,
+		prefix: LeftNibbleSlice<'a, L::Nibble>,
 	) -> Result<Self, Error<TrieHash<L>, CError<L>>>
 	where
 		I: Iterator<Item = &'a [u8]>,
@@ -262,10 +265,7 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 		};
 	}
 
-	fn advance_item<I>(
-		&mut self,
-		items_iter: &mut Peekable<I>,
-	) -> Result<Step<'a>, Error<TrieHash<L>, CError<L>>>
+	fn advance_item<I>(&mut self, items_iter: &mut Peekable<I>) -> StepResult<'a, L>
 	where
 		I: Iterator<Item = (&'a [u8], Option<&'a [u8]>)>,
 	{
@@ -305,7 +305,7 @@ impl<'a, L: TrieLayout> StackEntry<'a, L> {
 	}
 }
 
-enum ValueMatch<'a> {
+enum ValueMatch<'a, N> {
 	/// The key matches a leaf node, so the value at the key must be present.
 	MatchesLeaf,
 	/// The key matches a branch node, so the value at the key may or may not be present.
@@ -315,16 +315,16 @@ enum ValueMatch<'a> {
 	/// The key matches a location in trie, but the value was not omitted.
 	NotOmitted,
 	/// The key may match below a child of this node. Parameter is the prefix of the child node.
-	IsChild(LeftNibbleSlice<'a>),
+	IsChild(LeftNibbleSlice<'a, N>),
 }
 
 /// Determines whether a node on the stack carries a value at the given key or whether any nodes
 /// in the subtrie do. The prefix of the node is given by the first `prefix_len` nibbles of `key`.
-fn match_key_to_node<'a>(
-	key: &LeftNibbleSlice<'a>,
+fn match_key_to_node<'a, N: NibbleOps>(
+	key: &LeftNibbleSlice<'a, N>,
 	prefix_len: usize,
-	node: &Node,
-) -> ValueMatch<'a> {
+	node: &Node<N>,
+) -> ValueMatch<'a, N> {
 	match node {
 		Node::Empty => ValueMatch::NotFound,
 		Node::Leaf(partial, value) => {
@@ -362,12 +362,12 @@ fn match_key_to_node<'a>(
 /// Determines whether a branch node on the stack carries a value at the given key or whether any
 /// nodes in the subtrie do. The key of the branch node value is given by the first
 /// `prefix_plus_partial_len` nibbles of `key`.
-fn match_key_to_branch_node<'a>(
-	key: &LeftNibbleSlice<'a>,
+fn match_key_to_branch_node<'a, N: NibbleOps>(
+	key: &LeftNibbleSlice<'a, N>,
 	prefix_plus_partial_len: usize,
-	children: &[Option<NodeHandle>; NIBBLE_LENGTH],
+	children: &Children<N, Option<NodeHandle>>,
 	value: Option<&Value>,
-) -> ValueMatch<'a> {
+) -> ValueMatch<'a, N> {
 	if key.len() == prefix_plus_partial_len {
 		if value.is_none() {
 			ValueMatch::MatchesBranch
@@ -385,11 +385,13 @@ fn match_key_to_branch_node<'a>(
 	}
 }
 
-enum Step<'a> {
-	Descend(LeftNibbleSlice<'a>),
+enum Step<'a, N> {
+	Descend(LeftNibbleSlice<'a, N>),
 	UnwindStack,
 }
 
+type StepResult<'a, L> = Result<Step<'a, <L as TrieLayout>::Nibble>, Error<TrieHash<L>, CError<L>>>;
+
 /// Verify a compact proof for key-value pairs in a trie given a root hash.
 pub fn verify_proof<'a, L, I, K, V>(
 	root: &<L::Hash as Hasher>::Out,
@@ -402,6 +404,25 @@ where
 	K: 'a + AsRef<[u8]>,
 	V: 'a + AsRef<[u8]>,
 {
+	verify_proof_with_limits::<L, _, _, _>(root, proof, items, &VerifyLimits::unlimited())
+}
+
+/// Verify a compact proof as `verify_proof`, failing with `Error::LimitExceeded` if the proof or
+/// the keys of `items` exceed `limits`.
+pub fn verify_proof_with_limits<'a, L, I, K, V>(
+	root: &<L::Hash as Hasher>::Out,
+	proof: &[Vec<u8>],
+	items: I,
+	limits: &VerifyLimits,
+) -> Result<(), Error<TrieHash<L>, CError<L>>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = &'a (K, Option<V>)>,
+	K: 'a + AsRef<[u8]>,
+	V: 'a + AsRef<[u8]>,
+{
+	limits.check_proof(proof).map_err(Error::LimitExceeded)?;
+
 	// Sort items.
 	let mut items = items
 		.into_iter()
@@ -409,6 +430,10 @@ where
 		.collect::<Vec<_>>();
 	items.sort();
 
+	for (key, _) in items.iter() {
+		limits.check_key_len(key.len()).map_err(Error::LimitExceeded)?;
+	}
+
 	if items.is_empty() {
 		return if proof.is_empty() { Ok(()) } else { Err(Error::ExtraneousNode) }
 	}
@@ -438,6 +463,8 @@ where
 		// Insert omitted value.
 		match last_entry.advance_item(&mut items_iter)? {
 			Step::Descend(child_prefix) => {
+				// The root is at depth 1 and the child at the depth of the stack plus 2.
+				limits.check_depth(stack.len() + 2).map_err(Error::LimitExceeded)?;
 				let next_entry = last_entry.advance_child_index(child_prefix, &mut proof_iter)?;
 				stack.push(last_entry);
 				last_entry = next_entry;
@@ -0,0 +1,268 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Witnesses of trie updates, to compute the root resulting from changes without the full
+//! trie.
+//!
+//! The witness is the set of encoded nodes and values `TrieDBMut` reads from the database while
+//! applying the changes: nodes on the path of each changed key, and siblings fetched when a
+//! branch collapses after a removal. A verifier holding only the previous root replays the
+//! same changes over the witness nodes, so any node missing from the witness surfaces as
+//! `TrieError::IncompleteDatabase`.
+
+use crate::rstd::vec::Vec;
+
+use hash_db::{HashDB, HashDBRef, EMPTY_PREFIX};
+
+#[cfg(feature = "std")]
+use super::RootAndNodes;
+use super::{NodeStore, VerifyLimits};
+use crate::{
+	nibble::NibbleSlice,
+	node::{decode_hash, Node, NodeHandle},
+	rstd::boxed::Box,
+	CError, DBValue, NodeCodec, Result as TrieResult, TrieDBMut, TrieError, TrieHash, TrieLayout,
+	TrieMut,
+};
+
+#[cfg(feature = "std")]
+use hash_db::{AsHashDB, Prefix};
+#[cfg(feature = "std")]
+use hashbrown::HashMap;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+/// Database reading from `base` and writing to an overlay, recording the nodes read from
+/// `base`.
+#[cfg(feature = "std")]
+struct RecordingDB<'a, L: TrieLayout, D> {
+	base: &'a D,
+	overlay: NodeStore<L>,
+	recorded: Mutex<HashMap<TrieHash<L>, DBValue>>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, L, D> HashDB<L::Hash, DBValue> for RecordingDB<'a, L, D>
+where
+	L: TrieLayout,
+	D: HashDBRef<L::Hash, DBValue> + Sync,
+{
+	fn get(&self, key: &TrieHash<L>, prefix: Prefix) -> Option<DBValue> {
+		if let Some(value) = HashDB::get(&self.overlay, key, prefix) {
+			return Some(value)
+		}
+		let value = self.base.get(key, prefix)?;
+		self.recorded
+			.lock()
+			.expect("no panic while the lock is held; qed")
+			.insert(*key, value.clone());
+		Some(value)
+	}
+
+	fn contains(&self, key: &TrieHash<L>, prefix: Prefix) -> bool {
+		HashDB::get(self, key, prefix).is_some()
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> TrieHash<L> {
+		self.overlay.insert(prefix, value)
+	}
+
+	fn emplace(&mut self, key: TrieHash<L>, prefix: Prefix, value: DBValue) {
+		self.overlay.emplace(key, prefix, value);
+	}
+
+	fn remove(&mut self, key: &TrieHash<L>, prefix: Prefix) {
+		self.overlay.remove(key, prefix);
+	}
+}
+
+#[cfg(feature = "std")]
+impl<'a, L, D> AsHashDB<L::Hash, DBValue> for RecordingDB<'a, L, D>
+where
+	L: TrieLayout,
+	D: HashDBRef<L::Hash, DBValue> + Sync,
+{
+	fn as_hash_db(&self) -> &dyn HashDB<L::Hash, DBValue> {
+		self
+	}
+
+	fn as_hash_db_mut<'b>(&'b mut self) -> &'b mut (dyn HashDB<L::Hash, DBValue> + 'b) {
+		self
+	}
+}
+
+/// Apply `changes` to the trie of `root`, inserting `Some` values and removing keys with
+/// `None`, and return the new root.
+fn apply_changes<L, I, K, V>(
+	db: &mut dyn HashDB<L::Hash, DBValue>,
+	root: &TrieHash<L>,
+	changes: I,
+) -> TrieResult<TrieHash<L>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = (K, Option<V>)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	let mut new_root = *root;
+	{
+		let mut trie = TrieDBMut::<L>::from_existing(db, &mut new_root);
+		for (key, value) in changes {
+			match value {
+				Some(value) => trie.insert(key.as_ref(), value.as_ref())?,
+				None => trie.remove(key.as_ref())?,
+			};
+		}
+	}
+	Ok(new_root)
+}
+
+/// Apply `changes` to the trie of `root` in `db`, without writing to `db`, and return the new
+/// root together with the witness of the update.
+///
+/// Changes are applied in order: `Some` values are inserted and keys with `None` are removed.
+/// The witness nodes are sorted.
+#[cfg(feature = "std")]
+pub fn generate_update_witness<L, D, I, K, V>(
+	db: &D,
+	root: &TrieHash<L>,
+	changes: I,
+) -> RootAndNodes<L>
+where
+	L: TrieLayout,
+	D: HashDBRef<L::Hash, DBValue> + Sync,
+	I: IntoIterator<Item = (K, Option<V>)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	let mut recording =
+		RecordingDB::<L, D> { base: db, overlay: NodeStore::new(), recorded: Default::default() };
+	let new_root = apply_changes::<L, _, _, _>(&mut recording, root, changes)?;
+	let mut witness: Vec<_> = recording
+		.recorded
+		.into_inner()
+		.expect("no panic while the lock is held; qed")
+		.into_values()
+		.collect();
+	witness.sort();
+	Ok((new_root, witness))
+}
+
+/// Replay `changes` on the trie of `root` using only the `witness` nodes, and return the new
+/// root.
+///
+/// Changes must be the ones given to `generate_update_witness`, in the same order. Fails with
+/// `TrieError::IncompleteDatabase` if a node needed to apply them is not in the witness.
+pub fn verify_update_witness<L, I, K, V>(
+	root: &TrieHash<L>,
+	witness: &[Vec<u8>],
+	changes: I,
+) -> TrieResult<TrieHash<L>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = (K, Option<V>)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	verify_update_witness_with_limits::<L, _, _, _>(
+		root,
+		witness,
+		changes,
+		&VerifyLimits::unlimited(),
+	)
+}
+
+/// Verify a witness as `verify_update_witness`, failing with `TrieError::LimitExceeded` if the
+/// witness or the changed keys exceed `limits`.
+///
+/// The depth limit bounds the witness nodes on the path of each changed key, inline nodes
+/// included.
+pub fn verify_update_witness_with_limits<L, I, K, V>(
+	root: &TrieHash<L>,
+	witness: &[Vec<u8>],
+	changes: I,
+	limits: &VerifyLimits,
+) -> TrieResult<TrieHash<L>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	I: IntoIterator<Item = (K, Option<V>)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	let limit_exceeded = |limit| Box::new(TrieError::LimitExceeded(limit));
+	limits.check_proof(witness).map_err(limit_exceeded)?;
+	let mut db = NodeStore::<L>::from_nodes(witness);
+	let changes: Vec<_> = changes.into_iter().collect();
+	for (key, _) in changes.iter() {
+		limits.check_key_len(key.as_ref().len()).map_err(limit_exceeded)?;
+		check_path_depth::<L>(&db, root, key.as_ref(), limits)?;
+	}
+	apply_changes::<L, _, _, _>(&mut db, root, changes)
+}
+
+/// Check the depth of the nodes of `db` on the path of `key` from `root` against `limits`.
+///
+/// The walk stops at the first node missing from `db`, which fails when applying the changes.
+fn check_path_depth<L: TrieLayout>(
+	db: &NodeStore<L>,
+	root: &TrieHash<L>,
+	key: &[u8],
+	limits: &VerifyLimits,
+) -> TrieResult<(), TrieHash<L>, CError<L>> {
+	let mut key = NibbleSlice::new(key);
+	let mut hash = *root;
+	let mut depth = 0;
+	while let Some(node_data) = HashDBRef::get(db, &hash, EMPTY_PREFIX) {
+		// Iterates through inline children.
+		let mut node_data = &node_data[..];
+		loop {
+			depth += 1;
+			limits
+				.check_depth(depth)
+				.map_err(|limit| Box::new(TrieError::LimitExceeded(limit)))?;
+			let node = L::Codec::decode(node_data)
+				.map_err(|e| Box::new(TrieError::DecoderError(hash, e)))?;
+			let (partial, children) = match node {
+				Node::Empty | Node::Leaf(..) => return Ok(()),
+				Node::Extension(partial, child) => (partial, Err(child)),
+				Node::Branch(children, _) => (NibbleSlice::new(&[]), Ok(children)),
+				Node::NibbledBranch(partial, children, _) => (partial, Ok(children)),
+			};
+			if !key.starts_with(&partial) {
+				return Ok(())
+			}
+			key.advance(partial.len());
+			let child = match children {
+				Err(child) => child,
+				Ok(_) if key.is_empty() => return Ok(()),
+				Ok(children) => match children[key.at(0) as usize] {
+					Some(child) => {
+						key.advance(1);
+						child
+					},
+					None => return Ok(()),
+				},
+			};
+			match child {
+				NodeHandle::Hash(data) => {
+					hash = decode_hash::<L::Hash>(data)
+						.ok_or_else(|| Box::new(TrieError::InvalidHash(hash, data.to_vec())))?;
+					break
+				},
+				NodeHandle::Inline(data) => node_data = data,
+			}
+		}
+	}
+	Ok(())
+}
@@ -30,11 +30,43 @@ pub struct Record<HO> {
 	pub hash: HO,
 }
 
+/// Limits on the nodes put in a proof or recorded.
+///
+/// Nodes are counted with their full encoding, so a compact proof within the budget may be
+/// smaller than `max_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofBudget {
+	/// Maximum number of nodes and hashed values.
+	pub max_nodes: usize,
+	/// Maximum total size of the nodes and hashed values, in bytes.
+	pub max_bytes: usize,
+}
+
+impl ProofBudget {
+	/// Budget of at most `max_nodes` nodes totalling at most `max_bytes` bytes.
+	pub fn new(max_nodes: usize, max_bytes: usize) -> Self {
+		ProofBudget { max_nodes, max_bytes }
+	}
+
+	/// Budget without limits.
+	pub fn unlimited() -> Self {
+		ProofBudget::new(usize::MAX, usize::MAX)
+	}
+
+	/// Check whether `nodes` nodes totalling `bytes` bytes fit in the budget.
+	pub fn allows(&self, nodes: usize, bytes: usize) -> bool {
+		nodes <= self.max_nodes && bytes <= self.max_bytes
+	}
+}
+
 /// Records trie nodes as they pass it.
 #[cfg_attr(feature = "std", derive(Debug))]
 pub struct Recorder<HO> {
 	nodes: Vec<Record<HO>>,
 	min_depth: u32,
+	budget: ProofBudget,
+	bytes: usize,
+	exceeded: bool,
 }
 
 impl<HO: Copy> Default for Recorder<HO> {
@@ -52,18 +84,55 @@ impl<HO: Copy> Recorder<HO> {
 
 	/// Create a `Recorder` which only records nodes beyond a given depth.
 	pub fn with_depth(depth: u32) -> Self {
-		Recorder { nodes: Vec::new(), min_depth: depth }
+		Recorder {
+			nodes: Vec::new(),
+			min_depth: depth,
+			budget: ProofBudget::unlimited(),
+			bytes: 0,
+			exceeded: false,
+		}
+	}
+
+	/// Create a `Recorder` which records nodes until `budget` is exceeded.
+	///
+	/// Once a node does not fit in the budget, no more nodes are recorded until the next
+	/// `drain`, and lookups recorded with it fail with `TrieError::BudgetExceeded`. The records
+	/// then cover the lookups which succeeded, the failed one being the first left out.
+	pub fn with_budget(budget: ProofBudget) -> Self {
+		Recorder { budget, ..Recorder::new() }
 	}
 
 	/// Record a visited node, given its hash, data, and depth.
 	pub fn record(&mut self, hash: &HO, data: &[u8], depth: u32) {
-		if depth >= self.min_depth {
+		if depth >= self.min_depth && !self.exceeded {
+			if !self.budget.allows(self.nodes.len() + 1, self.bytes + data.len()) {
+				self.exceeded = true;
+				return
+			}
+			self.bytes += data.len();
 			self.nodes.push(Record { depth, data: data.into(), hash: *hash })
 		}
 	}
 
+	/// Whether a node was left out because of the budget since the last `drain`, in which case
+	/// the records do not cover all lookups.
+	pub fn budget_exceeded(&self) -> bool {
+		self.exceeded
+	}
+
+	/// Fail with the budget if a node was left out because of it since the last `drain`.
+	pub fn check_budget(&self) -> Result<(), ProofBudget> {
+		if self.exceeded {
+			Err(self.budget)
+		} else {
+			Ok(())
+		}
+	}
+
 	/// Drain all visited records.
 	pub fn drain(&mut self) -> Vec<Record<HO>> {
+		self.bytes = 0;
+		self.exceeded = false;
 		crate::rstd::mem::replace(&mut self.nodes, Vec::new())
 	}
 }
@@ -26,19 +26,23 @@
 //! trie.
 
 use crate::{
-	nibble_ops::NIBBLE_LENGTH,
-	node::{Node, NodeHandle, NodeHandlePlan, NodePlan, OwnedNode, ValuePlan},
-	rstd::{boxed::Box, convert::TryInto, marker::PhantomData, rc::Rc, result, vec, vec::Vec},
-	CError, ChildReference, DBValue, NibbleVec, NodeCodec, Result, TrieDB, TrieDBNodeIterator,
-	TrieError, TrieHash, TrieLayout,
+	nibble_ops::NibbleOps,
+	node::{Children, Node, NodeHandle, NodeHandlePlan, NodePlan, OwnedNode, Value, ValuePlan},
+	proof::VerifyLimits,
+	rstd::{
+		borrow::Borrow, boxed::Box, convert::TryInto, marker::PhantomData, rc::Rc, result, vec,
+		vec::Vec,
+	},
+	CError, ChildReference, DBValue, NibbleVec, NodeCodec, ProofBudget, Result, TrieDB,
+	TrieDBNodeIterator, TrieError, TrieHash, TrieLayout,
 };
-use hash_db::{HashDB, Prefix};
+use hash_db::{AsHashDB, HashDB, Hasher, Prefix};
 
 struct EncoderStackEntry<C: NodeCodec> {
 	/// The prefix is the nibble path to the node in the trie.
-	prefix: NibbleVec,
+	prefix: NibbleVec<C::Nibble>,
 	/// Node in memory content.
-	node: Rc<OwnedNode<DBValue>>,
+	node: Rc<OwnedNode<DBValue, C::Nibble>>,
 	/// The next entry in the stack is a child of the preceding entry at this index. For branch
 	/// nodes, the index is in [0, NIBBLE_LENGTH] and for extension nodes, the index is in [0, 1].
 	child_index: usize,
@@ -46,6 +50,8 @@ struct EncoderStackEntry<C: NodeCodec> {
 	omit_children: Vec<bool>,
 	/// Skip value if value node is after.
 	omit_value: bool,
+	/// Replace the value with an empty inline value, as the receiver already has it.
+	drop_value: bool,
 	/// The encoding of the subtrie nodes rooted at this entry, which is built up in
 	/// `encode_compact`.
 	output_index: usize,
@@ -63,7 +69,7 @@ impl<C: NodeCodec> EncoderStackEntry<C> {
 	/// - if self.node is a branch, then child_prefix must be longer than self.prefix + partial.
 	fn advance_child_index(
 		&mut self,
-		child_prefix: &NibbleVec,
+		child_prefix: &NibbleVec<C::Nibble>,
 	) -> result::Result<(), &'static str> {
 		match self.node.node_plan() {
 			NodePlan::Empty | NodePlan::Leaf { .. } =>
@@ -100,7 +106,7 @@ impl<C: NodeCodec> EncoderStackEntry<C> {
 	fn encode_node(&mut self) -> Result<Vec<u8>, C::HashOut, C::Error> {
 		let node_data = self.node.data();
 		let mut modified_node_plan;
-		let node_plan = if self.omit_value {
+		let node_plan = if self.omit_value || self.drop_value {
 			modified_node_plan = self.node.node_plan().clone();
 			if let Some(value) = modified_node_plan.value_plan_mut() {
 				// 0 length value.
@@ -111,6 +117,8 @@ impl<C: NodeCodec> EncoderStackEntry<C> {
 			self.node.node_plan()
 		};
 		let mut encoded = match node_plan {
+			NodePlan::Leaf { partial, value } if self.drop_value =>
+				C::leaf_node(partial.build(node_data).right(), value.build(node_data)),
 			NodePlan::Empty | NodePlan::Leaf { .. } => node_data.to_vec(),
 			NodePlan::Extension { partial, child: _ } =>
 				if !self.omit_children[0] {
@@ -152,12 +160,12 @@ impl<C: NodeCodec> EncoderStackEntry<C> {
 	/// - omit_children[i] is only true if child_handles[i] is Some
 	fn branch_children(
 		node_data: &[u8],
-		child_handles: &[Option<NodeHandlePlan>; NIBBLE_LENGTH],
+		child_handles: &Children<C::Nibble, Option<NodeHandlePlan>>,
 		omit_children: &[bool],
-	) -> Result<[Option<ChildReference<C::HashOut>>; NIBBLE_LENGTH], C::HashOut, C::Error> {
+	) -> Result<Children<C::Nibble, Option<ChildReference<C::HashOut>>>, C::HashOut, C::Error> {
 		let empty_child = ChildReference::Inline(C::HashOut::default(), 0);
-		let mut children = [None; NIBBLE_LENGTH];
-		for i in 0..NIBBLE_LENGTH {
+		let mut children = Children::default();
+		for i in 0..C::Nibble::NIBBLE_LENGTH {
 			children[i] = if omit_children[i] {
 				Some(empty_child)
 			} else if let Some(child_plan) = &child_handles[i] {
@@ -205,10 +213,51 @@ fn detached_value<L: TrieLayout>(
 /// This function makes the assumption that all child references in an inline trie node are inline
 /// references.
 pub fn encode_compact<L>(db: &TrieDB<L>) -> Result<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+{
+	encode_compact_with_budget(db, ProofBudget::unlimited())
+}
+
+/// Variant of `encode_compact` failing with `TrieError::BudgetExceeded` as soon as the encoded
+/// nodes do not fit in `budget`.
+pub fn encode_compact_with_budget<L>(
+	db: &TrieDB<L>,
+	budget: ProofBudget,
+) -> Result<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+{
+	encode_compact_nodes(db, budget, false)
+}
+
+/// Variant of `encode_compact` omitting all values, for a receiver which already has them. The
+/// values of nodes are replaced by empty inline values, and value nodes are left out. Inline
+/// nodes are kept in their parent as they are, values included.
+///
+/// The encoding is decoded by `decode_compact_with_values`, given the omitted values.
+pub fn encode_compact_without_values<L>(
+	db: &TrieDB<L>,
+) -> Result<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+{
+	encode_compact_nodes(db, ProofBudget::unlimited(), true)
+}
+
+/// Encode the nodes of `db` as `encode_compact_with_budget`, replacing all values with empty
+/// ones if `omit_values` is set.
+fn encode_compact_nodes<L>(
+	db: &TrieDB<L>,
+	budget: ProofBudget,
+	omit_values: bool,
+) -> Result<Vec<Vec<u8>>, TrieHash<L>, CError<L>>
 where
 	L: TrieLayout,
 {
 	let mut output = Vec::new();
+	// Bytes used from the budget, the nodes used being the length of `output`.
+	let mut used_bytes = 0;
 
 	// The stack of nodes through a path in the trie. Each entry is a child node of the preceding
 	// entry.
@@ -254,26 +303,35 @@ where
 					}
 				}
 
-				let (children_len, detached_value) = match node.node_plan() {
-					NodePlan::Empty => (0, None),
-					NodePlan::Leaf { value, .. } =>
-						(0, detached_value(value, node.data(), prefix.as_prefix(), &iter)),
-					NodePlan::Extension { .. } => (1, None),
-					NodePlan::NibbledBranch { value: Some(value), .. } |
-					NodePlan::Branch { value: Some(value), .. } => (
-						NIBBLE_LENGTH,
+				let children_len = match node.node_plan() {
+					NodePlan::Empty | NodePlan::Leaf { .. } => 0,
+					NodePlan::Extension { .. } => 1,
+					NodePlan::Branch { .. } | NodePlan::NibbledBranch { .. } =>
+						L::Nibble::NIBBLE_LENGTH,
+				};
+				let detached_value = match node.node_plan().value_plan() {
+					Some(value) if !omit_values =>
 						detached_value(value, node.data(), prefix.as_prefix(), &iter),
-					),
-					NodePlan::NibbledBranch { value: None, .. } |
-					NodePlan::Branch { value: None, .. } => (NIBBLE_LENGTH, None),
+					_ => None,
 				};
 
+				let nodes = output.len() + 1 + detached_value.is_some() as usize;
+				let bytes = used_bytes +
+					node.data().len() +
+					detached_value.as_ref().map_or(0, |value| value.len());
+				if !budget.allows(nodes, bytes) {
+					return Err(Box::new(TrieError::BudgetExceeded(budget)))
+				}
+				used_bytes = bytes;
+
+				let drop_value = omit_values && node.node_plan().value_plan().is_some();
 				stack.push(EncoderStackEntry {
 					prefix,
 					node,
 					child_index: 0,
 					omit_children: vec![false; children_len],
 					omit_value: detached_value.is_some(),
+					drop_value,
 					output_index: output.len(),
 					_marker: PhantomData::default(),
 				});
@@ -301,19 +359,41 @@ where
 	Ok(output)
 }
 
-struct DecoderStackEntry<'a, C: NodeCodec> {
-	node: Node<'a>,
+/// An item of a compact encoding, borrowed from a slice or owned after being read from a stream.
+trait EncodedItem: Borrow<[u8]> + Sized {
+	/// Drop the escape header at the start of the item.
+	fn skip_header(self) -> Self;
+}
+
+impl EncodedItem for &[u8] {
+	fn skip_header(self) -> Self {
+		&self[1..]
+	}
+}
+
+impl EncodedItem for Vec<u8> {
+	fn skip_header(mut self) -> Self {
+		self.remove(0);
+		self
+	}
+}
+
+struct DecoderStackEntry<C: NodeCodec, D: Borrow<[u8]>> {
+	node: OwnedNode<D, C::Nibble>,
 	/// The next entry in the stack is a child of the preceding entry at this index. For branch
 	/// nodes, the index is in [0, NIBBLE_LENGTH] and for extension nodes, the index is in [0, 1].
 	child_index: usize,
 	/// The reconstructed child references.
 	children: Vec<Option<ChildReference<C::HashOut>>>,
 	/// A value attached as a node. The node will need to use its hash as value.
-	attached_value: Option<&'a [u8]>,
+	attached_value: Option<D>,
+	/// A value omitted from the encoding and given back to the decoder, replacing the value of
+	/// the node.
+	given_value: Option<DBValue>,
 	_marker: PhantomData<C>,
 }
 
-impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
+impl<C: NodeCodec, D: Borrow<[u8]>> DecoderStackEntry<C, D> {
 	/// Advance the child index until either it exceeds the number of children or the child is
 	/// marked as omitted. Omitted children are indicated by an empty inline reference. For each
 	/// child that is passed over and not omitted, copy over the child reference from the node to
@@ -323,7 +403,7 @@ impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
 	/// list is complete. If this returns true and the entry is an extension node, then
 	/// `children[0]` is guaranteed to be Some.
 	fn advance_child_index(&mut self) -> Result<bool, C::HashOut, C::Error> {
-		match self.node {
+		match self.node.node() {
 			Node::Extension(_, child) if self.child_index == 0 => {
 				match child {
 					NodeHandle::Inline(data) if data.is_empty() => return Ok(false),
@@ -336,8 +416,8 @@ impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
 				}
 				self.child_index += 1;
 			},
-			Node::Branch(children, _) | Node::NibbledBranch(_, children, _) => {
-				while self.child_index < NIBBLE_LENGTH {
+			Node::Branch(ref children, _) | Node::NibbledBranch(_, ref children, _) => {
+				while self.child_index < C::Nibble::NIBBLE_LENGTH {
 					match children[self.child_index] {
 						Some(NodeHandle::Inline(data)) if data.is_empty() => return Ok(false),
 						Some(child) => {
@@ -358,8 +438,8 @@ impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
 
 	/// Push the partial key of this entry's node (including the branch nibble) to the given
 	/// prefix.
-	fn push_to_prefix(&self, prefix: &mut NibbleVec) {
-		match self.node {
+	fn push_to_prefix(&self, prefix: &mut NibbleVec<C::Nibble>) {
+		match self.node.node() {
 			Node::Empty => {},
 			Node::Leaf(partial, _) | Node::Extension(partial, _) => {
 				prefix.append_partial(partial.right());
@@ -376,8 +456,8 @@ impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
 
 	/// Pop the partial key of this entry's node (including the branch nibble) from the given
 	/// prefix.
-	fn pop_from_prefix(&self, prefix: &mut NibbleVec) {
-		match self.node {
+	fn pop_from_prefix(&self, prefix: &mut NibbleVec<C::Nibble>) {
+		match self.node.node() {
 			Node::Empty => {},
 			Node::Leaf(partial, _) | Node::Extension(partial, _) => {
 				prefix.drop_lasts(partial.len());
@@ -392,30 +472,29 @@ impl<'a, C: NodeCodec> DecoderStackEntry<'a, C> {
 		}
 	}
 
-	/// Reconstruct the encoded full trie node from the node and the entry's child references.
+	/// Reconstruct the encoded full trie node from the node and the entry's child references,
+	/// with `new_value` replacing the value of the node if given.
 	///
 	/// Preconditions:
 	/// - if node is an extension node, then `children[0]` is Some.
-	fn encode_node(self, attached_hash: Option<&[u8]>) -> Vec<u8> {
-		let attached_hash = attached_hash.map(|h| crate::node::Value::Node(h, None));
-		match self.node {
+	fn encode_node(self, new_value: Option<Value>) -> Vec<u8> {
+		let DecoderStackEntry { node, children, .. } = self;
+		let node = node.node();
+		match node {
 			Node::Empty => C::empty_node().to_vec(),
-			Node::Leaf(partial, value) =>
-				C::leaf_node(partial.right(), attached_hash.unwrap_or(value)),
+			Node::Leaf(partial, value) => C::leaf_node(partial.right(), new_value.unwrap_or(value)),
 			Node::Extension(partial, _) => C::extension_node(
 				partial.right_iter(),
 				partial.len(),
-				self.children[0].expect("required by method precondition; qed"),
-			),
-			Node::Branch(_, value) => C::branch_node(
-				self.children.into_iter(),
-				if attached_hash.is_some() { attached_hash } else { value },
+				children[0].expect("required by method precondition; qed"),
 			),
+			Node::Branch(_, value) =>
+				C::branch_node(children.iter(), if new_value.is_some() { new_value } else { value }),
 			Node::NibbledBranch(partial, _, value) => C::branch_node_nibbled(
 				partial.right_iter(),
 				partial.len(),
-				self.children.iter(),
-				if attached_hash.is_some() { attached_hash } else { value },
+				children.iter(),
+				if new_value.is_some() { new_value } else { value },
 			),
 		}
 	}
@@ -444,6 +523,20 @@ where
 	decode_compact_from_iter::<L, DB, _>(db, encoded.iter().map(Vec::as_slice))
 }
 
+/// Variant of `decode_compact` failing with `TrieError::LimitExceeded` if the items read exceed
+/// `limits`, see `decode_compact_from_iter_with_limits`.
+pub fn decode_compact_with_limits<L, DB>(
+	db: &mut DB,
+	encoded: &[Vec<u8>],
+	limits: &VerifyLimits,
+) -> Result<(TrieHash<L>, usize), TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+{
+	decode_compact_from_iter_with_limits::<L, DB, _>(db, encoded.iter().map(Vec::as_slice), limits)
+}
+
 /// Variant of 'decode_compact' that accept an iterator of encoded nodes as input.
 pub fn decode_compact_from_iter<'a, L, DB, I>(
 	db: &mut DB,
@@ -454,43 +547,243 @@ where
 	DB: HashDB<L::Hash, DBValue>,
 	I: IntoIterator<Item = &'a [u8]>,
 {
+	decode_compact_from_iter_with_limits::<L, DB, _>(db, encoded, &VerifyLimits::unlimited())
+}
+
+/// Variant of `decode_compact_from_iter` failing with `TrieError::LimitExceeded` if the items read
+/// exceed `limits`.
+///
+/// Only the items of the first trie are counted and checked. The depth counts the nodes of the
+/// encoding on the path from the root, and the key length bounds the path to every node; inline
+/// nodes are part of their parent item and are bounded by `max_node_size`.
+pub fn decode_compact_from_iter_with_limits<'a, L, DB, I>(
+	db: &mut DB,
+	encoded: I,
+	limits: &VerifyLimits,
+) -> Result<(TrieHash<L>, usize), TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	I: IntoIterator<Item = &'a [u8]>,
+{
+	decode_compact_items::<L, DB, _, _, _>(db, encoded.into_iter().map(Ok), limits, None)
+}
+
+/// Variant of `decode_compact` for encodings made by `encode_compact_without_values`, getting the
+/// value of each key back from `values`. Fails with `TrieError::MissingValue` if `values` does
+/// not have the value of a key of the encoding.
+///
+/// As the values are not authenticated by the encoding, the root of the reconstructed trie is
+/// checked against `root`, failing with `TrieError::InvalidStateRoot` of the reconstructed root
+/// if they differ. Nodes are only written to `db` once the root matches. Returns the number of
+/// items read.
+pub fn decode_compact_with_values<L, DB, F>(
+	db: &mut DB,
+	encoded: &[Vec<u8>],
+	root: &TrieHash<L>,
+	values: F,
+) -> Result<usize, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	F: FnMut(&[u8]) -> Option<DBValue>,
+{
+	decode_compact_with_values_with_limits::<L, DB, F>(
+		db,
+		encoded,
+		root,
+		values,
+		&VerifyLimits::unlimited(),
+	)
+}
+
+/// Variant of `decode_compact_with_values` failing with `TrieError::LimitExceeded` if
+/// `encoded` exceeds `limits`, see `decode_compact_from_iter_with_limits`.
+///
+/// Decoded nodes are held in memory until the root is checked, so `limits` also bound the
+/// memory used. Values given back by `values` are not counted.
+pub fn decode_compact_with_values_with_limits<L, DB, F>(
+	db: &mut DB,
+	encoded: &[Vec<u8>],
+	root: &TrieHash<L>,
+	mut values: F,
+	limits: &VerifyLimits,
+) -> Result<usize, TrieHash<L>, CError<L>>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	F: FnMut(&[u8]) -> Option<DBValue>,
+{
+	let mut pending = PendingNodes::<L::Hash> { nodes: Vec::new() };
+	let items = encoded.iter().map(|item| Ok(item.as_slice()));
+	let decoded: Result<_, TrieHash<L>, CError<L>> =
+		decode_compact_items::<L, _, _, _, _>(&mut pending, items, limits, Some(&mut values));
+	let (decoded_root, used) = decoded?;
+	if decoded_root != *root {
+		return Err(Box::new(TrieError::InvalidStateRoot(decoded_root)))
+	}
+	for (hash, (prefix, last), node) in pending.nodes {
+		db.emplace(hash, (&prefix, last), node);
+	}
+	Ok(used)
+}
+
+/// Hash, owned prefix and encoding of a node inserted in `PendingNodes`.
+type PendingNode<H> = (<H as Hasher>::Out, (Vec<u8>, Option<u8>), DBValue);
+
+/// Database holding the nodes inserted in it with their prefix, in order, to write them to
+/// another database later.
+struct PendingNodes<H: Hasher> {
+	nodes: Vec<PendingNode<H>>,
+}
+
+impl<H: Hasher> HashDB<H, DBValue> for PendingNodes<H> {
+	fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+		self.nodes
+			.iter()
+			.find(|(hash, (nibbles, last), _)| {
+				hash == key && nibbles.as_slice() == prefix.0 && *last == prefix.1
+			})
+			.map(|(_, _, node)| node.clone())
+	}
+
+	fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+		self.get(key, prefix).is_some()
+	}
+
+	fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+		let hash = H::hash(value);
+		self.emplace(hash, prefix, value.to_vec());
+		hash
+	}
+
+	fn emplace(&mut self, key: H::Out, prefix: Prefix, value: DBValue) {
+		self.nodes.push((key, (prefix.0.to_vec(), prefix.1), value));
+	}
+
+	fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+		let position = self.nodes.iter().position(|(hash, (nibbles, last), _)| {
+			hash == key && nibbles.as_slice() == prefix.0 && *last == prefix.1
+		});
+		if let Some(position) = position {
+			self.nodes.remove(position);
+		}
+	}
+}
+
+impl<H: Hasher> AsHashDB<H, DBValue> for PendingNodes<H> {
+	fn as_hash_db(&self) -> &dyn HashDB<H, DBValue> {
+		self
+	}
+
+	fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, DBValue> + 'a) {
+		self
+	}
+}
+
+/// Lookup of the values omitted from a compact encoding, by key.
+type ValueLookup<'a> = dyn FnMut(&[u8]) -> Option<DBValue> + 'a;
+
+/// Get the value of `node`, at `prefix`, from `values`, or `None` if the node has no value.
+fn given_value<L: TrieLayout>(
+	prefix: &NibbleVec<L::Nibble>,
+	node: Node<L::Nibble>,
+	values: &mut ValueLookup,
+) -> Result<Option<DBValue>, TrieHash<L>, CError<L>> {
+	let mut key = prefix.clone();
+	match node {
+		Node::Leaf(partial, _) | Node::NibbledBranch(partial, _, Some(_)) =>
+			key.append_partial(partial.right()),
+		Node::Branch(_, Some(_)) => {},
+		_ => return Ok(None),
+	}
+	let (key, extra_nibble) = key.as_prefix();
+	if let Some(extra_nibble) = extra_nibble {
+		return Err(Box::new(TrieError::ValueAtIncompleteKey(key.to_vec(), extra_nibble)))
+	}
+	match values(key) {
+		Some(value) => Ok(Some(value)),
+		None => Err(Box::new(TrieError::MissingValue(key.to_vec()))),
+	}
+}
+
+/// Decode the first trie of `items` as `decode_compact_from_iter_with_limits`, stopping at the
+/// first error of `items`. If `values` is given, the values of the nodes are replaced by the
+/// values it returns for their keys.
+fn decode_compact_items<L, DB, D, E, I>(
+	db: &mut DB,
+	items: I,
+	limits: &VerifyLimits,
+	mut values: Option<&mut ValueLookup>,
+) -> result::Result<(TrieHash<L>, usize), E>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	D: EncodedItem,
+	E: From<Box<TrieError<TrieHash<L>, CError<L>>>>,
+	I: Iterator<Item = result::Result<D, E>>,
+{
+	let limit_exceeded = |limit| Box::new(TrieError::LimitExceeded(limit));
 	// The stack of nodes through a path in the trie. Each entry is a child node of the preceding
 	// entry.
-	let mut stack: Vec<DecoderStackEntry<L::Codec>> = Vec::new();
+	let mut stack: Vec<DecoderStackEntry<L::Codec, D>> = Vec::new();
 
 	// The prefix of the next item to be read from the slice of encoded items.
 	let mut prefix = NibbleVec::new();
 
-	let mut iter = encoded.into_iter().enumerate();
-	while let Some((i, encoded_node)) = iter.next() {
-		let mut attached_node = 0;
+	let mut iter = items.enumerate();
+	while let Some((mut i, encoded_node)) = iter.next() {
+		let mut encoded_node = encoded_node?;
+		limits.check_nodes(i + 1).map_err(limit_exceeded)?;
+		limits.check_node_size(encoded_node.borrow().len()).map_err(limit_exceeded)?;
+		limits.check_depth(stack.len() + 1).map_err(limit_exceeded)?;
+		let mut attached_node = false;
 		if let Some(header) = L::Codec::ESCAPE_HEADER {
-			if encoded_node.starts_with(&[header]) {
-				attached_node = 1;
+			if encoded_node.borrow().starts_with(&[header]) {
+				attached_node = true;
+				encoded_node = encoded_node.skip_header();
 			}
 		}
-		let node = L::Codec::decode(&encoded_node[attached_node..])
+		let node = OwnedNode::new::<L::Codec>(encoded_node)
 			.map_err(|err| Box::new(TrieError::DecoderError(<TrieHash<L>>::default(), err)))?;
 
-		let children_len = match node {
+		let partial_len = match node.node() {
+			Node::Empty | Node::Branch(..) => 0,
+			Node::Leaf(partial, _) |
+			Node::Extension(partial, _) |
+			Node::NibbledBranch(partial, _, _) => partial.len(),
+		};
+		limits
+			.check_nibble_len::<L::Nibble>(prefix.len() + partial_len)
+			.map_err(limit_exceeded)?;
+
+		let children_len = match node.node() {
 			Node::Empty | Node::Leaf(..) => 0,
 			Node::Extension(..) => 1,
-			Node::Branch(..) | Node::NibbledBranch(..) => NIBBLE_LENGTH,
+			Node::Branch(..) | Node::NibbledBranch(..) => L::Nibble::NIBBLE_LENGTH,
 		};
 		let mut last_entry = DecoderStackEntry {
 			node,
 			child_index: 0,
 			children: vec![None; children_len],
 			attached_value: None,
+			given_value: None,
 			_marker: PhantomData::default(),
 		};
+		if let Some(values) = values.as_mut() {
+			last_entry.given_value = given_value::<L>(&prefix, last_entry.node.node(), *values)?;
+		}
 
-		if attached_node > 0 {
+		if attached_node {
 			// Read value
-			if let Some((_, fetched_value)) = iter.next() {
+			if let Some((value_index, fetched_value)) = iter.next() {
+				let fetched_value = fetched_value?;
+				limits.check_nodes(value_index + 1).map_err(limit_exceeded)?;
+				limits.check_node_size(fetched_value.borrow().len()).map_err(limit_exceeded)?;
+				i = value_index;
 				last_entry.attached_value = Some(fetched_value);
 			} else {
-				return Err(Box::new(TrieError::IncompleteDatabase(<TrieHash<L>>::default())))
+				return Err(Box::new(TrieError::IncompleteDatabase(<TrieHash<L>>::default())).into())
 			}
 		}
 
@@ -503,11 +796,19 @@ where
 
 			// Since `advance_child_index` returned true, the preconditions for `encode_node` are
 			// satisfied.
-			let hash = last_entry
-				.attached_value
-				.as_ref()
-				.map(|value| db.insert(prefix.as_prefix(), value));
-			let node_data = last_entry.encode_node(hash.as_ref().map(|h| h.as_ref()));
+			let given_value = last_entry.given_value.take();
+			let value_hash = match (&last_entry.attached_value, &given_value) {
+				(Some(value), _) => Some(db.insert(prefix.as_prefix(), value.borrow())),
+				(None, Some(value)) if Value::new_inline(value, L::MAX_INLINE_VALUE).is_none() =>
+					Some(db.insert(prefix.as_prefix(), value)),
+				_ => None,
+			};
+			let value = match (&value_hash, &given_value) {
+				(Some(hash), _) => Some(Value::Node(hash.as_ref(), None)),
+				(None, Some(value)) => Some(Value::Inline(value)),
+				(None, None) => None,
+			};
+			let node_data = last_entry.encode_node(value);
 			let node_hash = db.insert(prefix.as_prefix(), node_data.as_ref());
 
 			if let Some(entry) = stack.pop() {
@@ -521,5 +822,255 @@ where
 		}
 	}
 
-	Err(Box::new(TrieError::IncompleteDatabase(<TrieHash<L>>::default())))
+	Err(Box::new(TrieError::IncompleteDatabase(<TrieHash<L>>::default())).into())
+}
+
+/// Errors of the streaming compact encoding functions.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StreamError<T, E> {
+	/// Reading or writing the stream failed, or the stream holds an invalid length prefix.
+	Io(std::io::Error),
+	/// Encoding or decoding the trie failed.
+	Trie(Box<TrieError<T, E>>),
+}
+
+#[cfg(feature = "std")]
+impl<T, E> From<std::io::Error> for StreamError<T, E> {
+	fn from(err: std::io::Error) -> Self {
+		StreamError::Io(err)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<T, E> From<Box<TrieError<T, E>>> for StreamError<T, E> {
+	fn from(err: Box<TrieError<T, E>>) -> Self {
+		StreamError::Trie(err)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<T: std::fmt::Debug, E: std::fmt::Debug> std::fmt::Display for StreamError<T, E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			StreamError::Io(err) => write!(f, "Compact stream I/O error: {}", err),
+			StreamError::Trie(err) => write!(f, "Compact stream trie error: {}", err),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl<T: std::fmt::Debug + 'static, E: std::error::Error + 'static> std::error::Error
+	for StreamError<T, E>
+{
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			StreamError::Io(err) => Some(err),
+			StreamError::Trie(err) => Some(err.as_ref()),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+type StreamResult<L, T> = result::Result<T, StreamError<TrieHash<L>, CError<L>>>;
+
+/// Flags indicating which children of `node`, at `prefix`, are in the database of `db`, and so
+/// are omitted in the compact encoding.
+#[cfg(feature = "std")]
+fn present_children<L: TrieLayout>(
+	db: &TrieDB<L>,
+	prefix: &NibbleVec<L::Nibble>,
+	node: &OwnedNode<DBValue, L::Nibble>,
+) -> Vec<bool> {
+	let node_data = node.data();
+	let is_present = |child: &NodeHandlePlan, child_prefix: &NibbleVec<L::Nibble>| {
+		let hash = match child.build(node_data) {
+			NodeHandle::Hash(data) => crate::node::decode_hash::<L::Hash>(data),
+			NodeHandle::Inline(_) => None,
+		};
+		match hash {
+			Some(hash) => db.db().contains(&hash, child_prefix.as_prefix()),
+			None => false,
+		}
+	};
+
+	let mut child_prefix = prefix.clone();
+	let children = match node.node_plan() {
+		NodePlan::Empty | NodePlan::Leaf { .. } => return Vec::new(),
+		NodePlan::Extension { partial, child } => {
+			child_prefix.append_partial(partial.build(node_data).right());
+			return vec![is_present(child, &child_prefix)]
+		},
+		NodePlan::Branch { children, .. } => children,
+		NodePlan::NibbledBranch { partial, children, .. } => {
+			child_prefix.append_partial(partial.build(node_data).right());
+			children
+		},
+	};
+	let mut present = vec![false; L::Nibble::NIBBLE_LENGTH];
+	for (i, child) in children.iter().enumerate() {
+		if let Some(child) = child {
+			child_prefix.push(i as u8);
+			present[i] = is_present(child, &child_prefix);
+			child_prefix.pop();
+		}
+	}
+	present
+}
+
+#[cfg(feature = "std")]
+fn write_item<W: std::io::Write>(writer: &mut W, item: &[u8]) -> std::io::Result<()> {
+	let mut length = Vec::new();
+	crate::proof::encode_length(&mut length, item.len());
+	writer.write_all(&length)?;
+	writer.write_all(item)
+}
+
+/// Variant of `encode_compact` writing each item of the encoding to `writer` as soon as the
+/// node is read, instead of collecting them. Returns the number of items written.
+///
+/// Each item is prefixed with its length as a SCALE compact integer. Only the node being
+/// encoded is held in memory, so the size of the encoding is not bounded by memory.
+#[cfg(feature = "std")]
+pub fn encode_compact_to_writer<L, W>(db: &TrieDB<L>, writer: &mut W) -> StreamResult<L, usize>
+where
+	L: TrieLayout,
+	W: std::io::Write,
+{
+	let mut written = 0;
+	let mut iter = TrieDBNodeIterator::new(db)?;
+	while let Some(item) = iter.next() {
+		match item {
+			Ok((prefix, node_hash, node)) => {
+				// Skip inline nodes, as they cannot contain hash references to other nodes by
+				// assumption.
+				if node_hash.is_none() {
+					continue
+				}
+
+				// Unlike `encode_compact`, which omits the children the iterator yields, this
+				// omits the children in the database, which the iterator yields next.
+				let detached_value = match node.node_plan() {
+					NodePlan::Leaf { value, .. } |
+					NodePlan::Branch { value: Some(value), .. } |
+					NodePlan::NibbledBranch { value: Some(value), .. } =>
+						detached_value(value, node.data(), prefix.as_prefix(), &iter),
+					_ => None,
+				};
+				let mut entry = EncoderStackEntry::<L::Codec> {
+					omit_children: present_children(db, &prefix, &node),
+					prefix,
+					node,
+					child_index: 0,
+					omit_value: detached_value.is_some(),
+					drop_value: false,
+					output_index: written,
+					_marker: PhantomData,
+				};
+				write_item(writer, &entry.encode_node()?)?;
+				written += 1;
+				if let Some(value) = detached_value {
+					write_item(writer, &value)?;
+					written += 1;
+				}
+			},
+			Err(err) => match *err {
+				// Missing nodes are left as hash references, see `encode_compact`.
+				TrieError::IncompleteDatabase(_) => {},
+				_ => return Err(err.into()),
+			},
+		}
+	}
+	writer.flush()?;
+	Ok(written)
+}
+
+/// Items of a stream written by `encode_compact_to_writer`.
+#[cfg(feature = "std")]
+struct StreamItems<'r, R, T, E> {
+	reader: &'r mut R,
+	limits: &'r VerifyLimits,
+	_marker: PhantomData<(T, E)>,
+}
+
+#[cfg(feature = "std")]
+impl<'r, R: std::io::Read, T, E> StreamItems<'r, R, T, E> {
+	fn read_item(&mut self) -> result::Result<Option<Vec<u8>>, StreamError<T, E>> {
+		use std::io::{Error, ErrorKind, Read};
+
+		let mut length = [0; 9];
+		match self.reader.read_exact(&mut length[..1]) {
+			Ok(()) => {},
+			// The stream ends between items.
+			Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+			Err(err) => return Err(err.into()),
+		}
+		let length_len = match length[0] & 0b11 {
+			0b00 => 1,
+			0b01 => 2,
+			0b10 => 4,
+			_ => std::cmp::min(usize::from(length[0] >> 2) + 5, length.len()),
+		};
+		self.reader.read_exact(&mut length[1..length_len])?;
+		let len = crate::proof::decode_length(&mut &length[..length_len])
+			.map_err(|_| Error::new(ErrorKind::InvalidData, "invalid item length prefix"))?;
+		self.limits
+			.check_node_size(len)
+			.map_err(|limit| Box::new(TrieError::LimitExceeded(limit)))?;
+
+		// Reading through `take` only allocates as the data comes.
+		let mut item = Vec::new();
+		self.reader.by_ref().take(len as u64).read_to_end(&mut item)?;
+		if item.len() != len {
+			return Err(Error::from(ErrorKind::UnexpectedEof).into())
+		}
+		Ok(Some(item))
+	}
+}
+
+#[cfg(feature = "std")]
+impl<'r, R: std::io::Read, T, E> Iterator for StreamItems<'r, R, T, E> {
+	type Item = result::Result<Vec<u8>, StreamError<T, E>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.read_item().transpose()
+	}
+}
+
+/// Variant of `decode_compact` reading the items written by `encode_compact_to_writer` from
+/// `reader`, and inserting the nodes in `db` as they are decoded.
+///
+/// Only the nodes on the path to the node being decoded are held in memory. Reading stops after
+/// the first trie, which may be followed by more data. The stream ending before the trie is
+/// complete is `TrieError::IncompleteDatabase`, as for `decode_compact`.
+#[cfg(feature = "std")]
+pub fn decode_compact_from_reader<L, DB, R>(
+	db: &mut DB,
+	reader: &mut R,
+) -> StreamResult<L, (TrieHash<L>, usize)>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	R: std::io::Read,
+{
+	decode_compact_from_reader_with_limits::<L, DB, R>(db, reader, &VerifyLimits::unlimited())
+}
+
+/// Variant of `decode_compact_from_reader` failing with `TrieError::LimitExceeded` if the items
+/// read exceed `limits`, see `decode_compact_from_iter_with_limits`.
+///
+/// Item sizes are checked against their length prefix, before reading them.
+#[cfg(feature = "std")]
+pub fn decode_compact_from_reader_with_limits<L, DB, R>(
+	db: &mut DB,
+	reader: &mut R,
+	limits: &VerifyLimits,
+) -> StreamResult<L, (TrieHash<L>, usize)>
+where
+	L: TrieLayout,
+	DB: HashDB<L::Hash, DBValue>,
+	R: std::io::Read,
+{
+	let items = StreamItems { reader, limits, _marker: PhantomData };
+	decode_compact_items::<L, DB, _, _, _>(db, items, limits, None)
 }
@@ -88,7 +88,7 @@ where
 		parent_hash: TrieHash<L>,
 		node_handle: NodeHandle,
 		partial_key: Prefix,
-	) -> Result<(OwnedNode<DBValue>, Option<TrieHash<L>>), TrieHash<L>, CError<L>> {
+	) -> Result<(OwnedNode<DBValue, L::Nibble>, Option<TrieHash<L>>), TrieHash<L>, CError<L>> {
 		let (node_hash, node_data) = match node_handle {
 			NodeHandle::Hash(data) => {
 				let node_hash = decode_hash::<L::Hash>(data)
@@ -159,7 +159,7 @@ where
 {
 	trie: &'db TrieDB<'db, L>,
 	node_key: NodeHandle<'a>,
-	partial_key: NibbleVec,
+	partial_key: NibbleVec<L::Nibble>,
 	index: Option<u8>,
 }
 
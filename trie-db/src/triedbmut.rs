@@ -17,7 +17,7 @@
 use super::{
 	lookup::Lookup,
 	node::{
-		decode_hash, Node as EncodedNode, NodeHandle as EncodedNodeHandle, NodeKey,
+		decode_hash, Children, Node as EncodedNode, NodeHandle as EncodedNodeHandle, NodeKey,
 		Value as EncodedValue,
 	},
 	CError, DBValue, Result, TrieError, TrieHash, TrieLayout, TrieMut,
@@ -27,7 +27,7 @@ use hash_db::{HashDB, Hasher, Prefix, EMPTY_PREFIX};
 use hashbrown::HashSet;
 
 use crate::{
-	nibble::{nibble_ops, BackingByteVec, NibbleSlice, NibbleVec},
+	nibble::{nibble_ops::NibbleOps, BackingByteVec, NibbleSlice, NibbleVec},
 	node_codec::NodeCodec,
 	rstd::{boxed::Box, convert::TryFrom, mem, ops::Index, result, vec::Vec, VecDeque},
 };
@@ -58,16 +58,16 @@ impl<H> From<StorageHandle> for NodeHandle<H> {
 	}
 }
 
-fn empty_children<H>() -> Box<[Option<NodeHandle<H>>; nibble_ops::NIBBLE_LENGTH]> {
-	Box::new([
-		None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-		None,
-	])
+fn empty_children<N: NibbleOps, H>() -> Box<Children<N, Option<NodeHandle<H>>>> {
+	Box::new(Children::default())
 }
 
+/// Children of a branch node, one per nibble value of the layout.
+type BranchChildren<L> = Box<Children<<L as TrieLayout>::Nibble, Option<NodeHandle<TrieHash<L>>>>>;
+
 /// Type alias to indicate the nible covers a full key,
 /// therefore its left side is a full prefix.
-type NibbleFullKey<'key> = NibbleSlice<'key>;
+type NibbleFullKey<'key, N> = NibbleSlice<'key, N>;
 
 /// Value representation for Node.
 #[derive(Clone, Eq)]
@@ -134,13 +134,13 @@ impl<L: TrieLayout> Value<L> {
 
 	fn into_encoded<'a, F>(
 		&'a mut self,
-		partial: Option<&NibbleSlice>,
+		partial: Option<&NibbleSlice<L::Nibble>>,
 		f: &mut F,
 	) -> EncodedValue<'a>
 	where
 		F: FnMut(
 			NodeToEncode<TrieHash<L>>,
-			Option<&NibbleSlice>,
+			Option<&NibbleSlice<L::Nibble>>,
 			Option<u8>,
 		) -> ChildReference<TrieHash<L>>,
 	{
@@ -200,14 +200,10 @@ enum Node<L: TrieLayout> {
 	/// a flag indicating it is an extension.
 	/// The child node is always a branch.
 	Extension(NodeKey, NodeHandle<TrieHash<L>>),
-	/// A branch has up to 16 children and an optional value.
-	Branch(Box<[Option<NodeHandle<TrieHash<L>>>; nibble_ops::NIBBLE_LENGTH]>, Option<Value<L>>),
+	/// A branch has up to `NIBBLE_LENGTH` children and an optional value.
+	Branch(BranchChildren<L>, Option<Value<L>>),
 	/// Branch node with support for a nibble (to avoid extension node).
-	NibbledBranch(
-		NodeKey,
-		Box<[Option<NodeHandle<TrieHash<L>>>; nibble_ops::NIBBLE_LENGTH]>,
-		Option<Value<L>>,
-	),
+	NibbledBranch(NodeKey, BranchChildren<L>, Option<Value<L>>),
 }
 
 #[cfg(feature = "std")]
@@ -296,24 +292,10 @@ impl<L: TrieLayout> Node<L> {
 					None => Ok(None),
 				};
 
-				let children = Box::new([
-					child(0)?,
-					child(1)?,
-					child(2)?,
-					child(3)?,
-					child(4)?,
-					child(5)?,
-					child(6)?,
-					child(7)?,
-					child(8)?,
-					child(9)?,
-					child(10)?,
-					child(11)?,
-					child(12)?,
-					child(13)?,
-					child(14)?,
-					child(15)?,
-				]);
+				let mut children = empty_children();
+				for i in 0..L::Nibble::NIBBLE_LENGTH {
+					children[i] = child(i)?;
+				}
 
 				Node::Branch(children, val.map(Into::into))
 			},
@@ -323,24 +305,10 @@ impl<L: TrieLayout> Node<L> {
 					None => Ok(None),
 				};
 
-				let children = Box::new([
-					child(0)?,
-					child(1)?,
-					child(2)?,
-					child(3)?,
-					child(4)?,
-					child(5)?,
-					child(6)?,
-					child(7)?,
-					child(8)?,
-					child(9)?,
-					child(10)?,
-					child(11)?,
-					child(12)?,
-					child(13)?,
-					child(14)?,
-					child(15)?,
-				]);
+				let mut children = empty_children();
+				for i in 0..L::Nibble::NIBBLE_LENGTH {
+					children[i] = child(i)?;
+				}
 
 				Node::NibbledBranch(k.into(), children, val.map(Into::into))
 			},
@@ -351,30 +319,31 @@ impl<L: TrieLayout> Node<L> {
 	// TODO: parallelize
 	/// Here `child_cb` should process the first parameter to either insert an external
 	/// node value or to encode and add a new branch child node.
-	fn into_encoded<F>(self, mut child_cb: F) -> Vec<u8>
+	/// The encoded node is appended to `output`.
+	fn into_encoded<F>(self, mut child_cb: F, output: &mut Vec<u8>)
 	where
 		F: FnMut(
 			NodeToEncode<TrieHash<L>>,
-			Option<&NibbleSlice>,
+			Option<&NibbleSlice<L::Nibble>>,
 			Option<u8>,
 		) -> ChildReference<TrieHash<L>>,
 	{
 		match self {
-			Node::Empty => L::Codec::empty_node().to_vec(),
+			Node::Empty => output.extend_from_slice(L::Codec::empty_node()),
 			Node::Leaf(partial, mut value) => {
-				let pr = NibbleSlice::new_offset(&partial.1[..], partial.0);
+				let pr = NibbleSlice::<L::Nibble>::new_offset(&partial.1[..], partial.0);
 				let value = value.into_encoded::<F>(Some(&pr), &mut child_cb);
-				L::Codec::leaf_node(pr.right(), value)
+				L::Codec::leaf_node_into(pr.right(), value, output)
 			},
 			Node::Extension(partial, child) => {
-				let pr = NibbleSlice::new_offset(&partial.1[..], partial.0);
+				let pr = NibbleSlice::<L::Nibble>::new_offset(&partial.1[..], partial.0);
 				let it = pr.right_iter();
 				let c = child_cb(NodeToEncode::TrieNode(child), Some(&pr), None);
-				L::Codec::extension_node(it, pr.len(), c)
+				L::Codec::extension_node_into(it, pr.len(), c, output)
 			},
 			Node::Branch(mut children, mut value) => {
 				let value = value.as_mut().map(|v| v.into_encoded::<F>(None, &mut child_cb));
-				L::Codec::branch_node(
+				L::Codec::branch_node_into(
 					// map the `NodeHandle`s from the Branch to `ChildReferences`
 					children.iter_mut().map(Option::take).enumerate().map(|(i, maybe_child)| {
 						maybe_child.map(|child| {
@@ -382,24 +351,27 @@ impl<L: TrieLayout> Node<L> {
 						})
 					}),
 					value,
+					output,
 				)
 			},
 			Node::NibbledBranch(partial, mut children, mut value) => {
-				let pr = NibbleSlice::new_offset(&partial.1[..], partial.0);
+				let pr = NibbleSlice::<L::Nibble>::new_offset(&partial.1[..], partial.0);
 				let value = value.as_mut().map(|v| v.into_encoded::<F>(Some(&pr), &mut child_cb));
 				let it = pr.right_iter();
-				L::Codec::branch_node_nibbled(
+				L::Codec::branch_node_nibbled_into(
 					it,
 					pr.len(),
 					// map the `NodeHandle`s from the Branch to `ChildReferences`
 					children.iter_mut().map(Option::take).enumerate().map(|(i, maybe_child)| {
 						//let branch_index = [i as u8];
 						maybe_child.map(|child| {
-							let pr = NibbleSlice::new_offset(&partial.1[..], partial.0);
+							let pr =
+								NibbleSlice::<L::Nibble>::new_offset(&partial.1[..], partial.0);
 							child_cb(NodeToEncode::TrieNode(child), Some(&pr), Some(i as u8))
 						})
 					}),
 					value,
+					output,
 				)
 			},
 		}
@@ -485,6 +457,35 @@ where
 	}
 }
 
+/// Outcome of `TrieDBMut::remove_prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRemoval {
+	/// Number of keys removed.
+	pub removed: usize,
+	/// First key with the prefix left in the trie once the limit was reached, or `None` if all
+	/// keys with the prefix were removed.
+	pub resume_from: Option<Vec<u8>>,
+}
+
+impl PrefixRemoval {
+	/// Whether all keys with the prefix were removed.
+	pub fn all_removed(&self) -> bool {
+		self.resume_from.is_none()
+	}
+}
+
+/// State of the walk of `TrieDBMut::remove_prefix`.
+struct PrefixRemovalWalk<N> {
+	/// Path to the node being walked.
+	path: NibbleVec<N>,
+	/// Maximum number of keys to remove.
+	limit: Option<usize>,
+	removal: PrefixRemoval,
+}
+
+/// Node left after removing keys from it, if any, with whether it changed.
+type PrefixRemovedNode<L> = Option<(NodeHandle<TrieHash<L>>, bool)>;
+
 /// Compact and cache-friendly storage for Trie nodes.
 struct NodeStorage<L: TrieLayout> {
 	nodes: Vec<Stored<L>>,
@@ -567,6 +568,8 @@ where
 	/// The number of hash operations this trie has performed.
 	/// Note that none are performed until changes are committed.
 	hash_count: usize,
+	/// Cleared buffers reused to encode nodes across commits, at most one per trie depth.
+	encode_buffers: Vec<Vec<u8>>,
 }
 
 impl<'a, L> TrieDBMut<'a, L>
@@ -585,6 +588,7 @@ where
 			root_handle,
 			death_row: HashSet::new(),
 			hash_count: 0,
+			encode_buffers: Vec::new(),
 		}
 	}
 
@@ -604,6 +608,7 @@ where
 			root_handle,
 			death_row: HashSet::new(),
 			hash_count: 0,
+			encode_buffers: Vec::new(),
 		}
 	}
 
@@ -636,14 +641,14 @@ where
 	fn inspect<F>(
 		&mut self,
 		stored: Stored<L>,
-		key: &mut NibbleFullKey,
+		key: &mut NibbleFullKey<L::Nibble>,
 		inspector: F,
 	) -> Result<Option<(Stored<L>, bool)>, TrieHash<L>, CError<L>>
 	where
 		F: FnOnce(
 			&mut Self,
 			Node<L>,
-			&mut NibbleFullKey,
+			&mut NibbleFullKey<L::Nibble>,
 		) -> Result<Action<L>, TrieHash<L>, CError<L>>,
 	{
 		let current_key = *key;
@@ -670,7 +675,7 @@ where
 	// Walk the trie, attempting to find the key's node.
 	fn lookup<'x, 'key>(
 		&'x self,
-		mut partial: NibbleSlice<'key>,
+		mut partial: NibbleSlice<'key, L::Nibble>,
 		full_key: &'key [u8],
 		handle: &NodeHandle<TrieHash<L>>,
 	) -> Result<Option<DBValue>, TrieHash<L>, CError<L>>
@@ -691,13 +696,13 @@ where
 				NodeHandle::InMemory(handle) => match &self.storage[handle] {
 					Node::Empty => return Ok(None),
 					Node::Leaf(key, value) =>
-						if NibbleSlice::from_stored(key) == partial {
+						if NibbleSlice::<L::Nibble>::from_stored(key) == partial {
 							return Ok(value.in_memory_fetched_value(prefix, self.db)?)
 						} else {
 							return Ok(None)
 						},
 					Node::Extension(slice, child) => {
-						let slice = NibbleSlice::from_stored(slice);
+						let slice = NibbleSlice::<L::Nibble>::from_stored(slice);
 						if partial.starts_with(&slice) {
 							(slice.len(), child)
 						} else {
@@ -719,7 +724,7 @@ where
 							}
 						},
 					Node::NibbledBranch(slice, children, value) => {
-						let slice = NibbleSlice::from_stored(slice);
+						let slice = NibbleSlice::<L::Nibble>::from_stored(slice);
 						if slice == partial {
 							return Ok(if let Some(v) = value.as_ref() {
 								v.in_memory_fetched_value(prefix, self.db)?
@@ -748,7 +753,7 @@ where
 	fn insert_at(
 		&mut self,
 		handle: NodeHandle<TrieHash<L>>,
-		key: &mut NibbleFullKey,
+		key: &mut NibbleFullKey<L::Nibble>,
 		value: DBValue,
 		old_val: &mut Option<Value<L>>,
 	) -> Result<(StorageHandle, bool), TrieHash<L>, CError<L>> {
@@ -790,7 +795,7 @@ where
 	fn insert_inspector(
 		&mut self,
 		node: Node<L>,
-		key: &mut NibbleFullKey,
+		key: &mut NibbleFullKey<L::Nibble>,
 		value: DBValue,
 		old_val: &mut Option<Value<L>>,
 	) -> Result<InsertAction<L>, TrieHash<L>, CError<L>> {
@@ -849,7 +854,7 @@ where
 				debug_assert!(!L::USE_EXTENSION);
 				#[cfg(feature = "std")]
 				trace!(target: "trie", "branch: ROUTE,AUGMENT");
-				let existing_key = NibbleSlice::from_stored(&encoded);
+				let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 
 				let common = partial.common_prefix(&existing_key);
 				if common == existing_key.len() && common == partial.len() {
@@ -940,7 +945,7 @@ where
 				}
 			},
 			Node::Leaf(encoded, stored_value) => {
-				let existing_key = NibbleSlice::from_stored(&encoded);
+				let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 				let common = partial.common_prefix(&existing_key);
 				if common == existing_key.len() && common == partial.len() {
 					#[cfg(feature = "std")]
@@ -1051,7 +1056,7 @@ where
 			},
 			Node::Extension(encoded, child_branch) => {
 				debug_assert!(L::USE_EXTENSION);
-				let existing_key = NibbleSlice::from_stored(&encoded);
+				let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 				let common = partial.common_prefix(&existing_key);
 				if common == 0 {
 					#[cfg(feature = "std")]
@@ -1135,7 +1140,7 @@ where
 	fn remove_at(
 		&mut self,
 		handle: NodeHandle<TrieHash<L>>,
-		key: &mut NibbleFullKey,
+		key: &mut NibbleFullKey<L::Nibble>,
 		old_val: &mut Option<Value<L>>,
 	) -> Result<Option<(StorageHandle, bool)>, TrieHash<L>, CError<L>> {
 		let stored = match handle {
@@ -1157,7 +1162,7 @@ where
 	fn remove_inspector(
 		&mut self,
 		node: Node<L>,
-		key: &mut NibbleFullKey,
+		key: &mut NibbleFullKey<L::Nibble>,
 		old_val: &mut Option<Value<L>>,
 	) -> Result<Action<L>, TrieHash<L>, CError<L>> {
 		let partial = *key;
@@ -1213,7 +1218,7 @@ where
 			},
 			(Node::NibbledBranch(encoded, mut children, value), false) => {
 				let (common, existing_length) = {
-					let existing_key = NibbleSlice::from_stored(&encoded);
+					let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 					(existing_key.common_prefix(&partial), existing_key.len())
 				};
 				if common == existing_length && common == partial.len() {
@@ -1278,7 +1283,7 @@ where
 				}
 			},
 			(Node::Leaf(encoded, value), _) => {
-				let existing_key = NibbleSlice::from_stored(&encoded);
+				let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 				if existing_key == partial {
 					// this is the node we were looking for. Let's delete it.
 					let mut key_val = key.clone();
@@ -1292,14 +1297,14 @@ where
 						target: "trie",
 						"restoring leaf wrong partial, partial={:?}, existing={:?}",
 						partial,
-						NibbleSlice::from_stored(&encoded),
+						NibbleSlice::<L::Nibble>::from_stored(&encoded),
 					);
 					Action::Restore(Node::Leaf(encoded, value))
 				}
 			},
 			(Node::Extension(encoded, child_branch), _) => {
 				let (common, existing_length) = {
-					let existing_key = NibbleSlice::from_stored(&encoded);
+					let existing_key = NibbleSlice::<L::Nibble>::from_stored(&encoded);
 					(existing_key.common_prefix(&partial), existing_key.len())
 				};
 				if common == existing_length {
@@ -1334,19 +1339,213 @@ where
 		})
 	}
 
+	/// Removes the keys starting with `prefix` from the node at `handle`, at the path of `walk`,
+	/// in key order until its limit is reached. Once a key is left, following nodes are left
+	/// untouched.
+	fn remove_prefix_at(
+		&mut self,
+		handle: NodeHandle<TrieHash<L>>,
+		prefix: NibbleSlice<L::Nibble>,
+		walk: &mut PrefixRemovalWalk<L::Nibble>,
+	) -> Result<PrefixRemovedNode<L>, TrieHash<L>, CError<L>> {
+		if walk.removal.resume_from.is_some() {
+			return Ok(Some((handle, false)))
+		}
+
+		let stored = match handle {
+			NodeHandle::InMemory(h) => self.storage.destroy(h),
+			NodeHandle::Hash(h) => {
+				let handle = self.cache(h, walk.path.as_prefix())?;
+				self.storage.destroy(handle)
+			},
+		};
+		let (node, hash) = match stored {
+			Stored::New(node) => (node, None),
+			Stored::Cached(node, hash) => (node, Some(hash)),
+		};
+
+		let action = self.remove_prefix_inspector(node, prefix, walk)?;
+		if let (Action::Replace(_) | Action::Delete, Some(hash)) = (&action, hash) {
+			let (start, end) = walk.path.as_prefix();
+			self.death_row.insert((hash, (start.into(), end)));
+		}
+		Ok(match action {
+			Action::Restore(node) => {
+				let stored = match hash {
+					Some(hash) => Stored::Cached(node, hash),
+					None => Stored::New(node),
+				};
+				Some((self.storage.alloc(stored).into(), false))
+			},
+			Action::Replace(node) => Some((self.storage.alloc(Stored::New(node)).into(), true)),
+			Action::Delete => None,
+		})
+	}
+
+	/// The prefix removal inspector.
+	fn remove_prefix_inspector(
+		&mut self,
+		node: Node<L>,
+		prefix: NibbleSlice<L::Nibble>,
+		walk: &mut PrefixRemovalWalk<L::Nibble>,
+	) -> Result<Action<L>, TrieHash<L>, CError<L>> {
+		Ok(match node {
+			Node::Empty => Action::Restore(Node::Empty),
+			Node::Leaf(encoded, value) => {
+				let partial = NibbleSlice::<L::Nibble>::from_stored(&encoded);
+				if !partial.starts_with(&prefix) {
+					return Ok(Action::Restore(Node::Leaf(encoded, value)))
+				}
+				walk.path.append_partial(partial.right());
+				let value = self.remove_prefixed_value(value, walk)?;
+				walk.path.drop_lasts(partial.len());
+				match value {
+					Some(value) => Action::Restore(Node::Leaf(encoded, value)),
+					None => Action::Delete,
+				}
+			},
+			Node::Extension(encoded, child) => {
+				let partial = NibbleSlice::<L::Nibble>::from_stored(&encoded);
+				let common = partial.common_prefix(&prefix);
+				let child_prefix = if common == prefix.len() {
+					NibbleSlice::<L::Nibble>::new(&[])
+				} else if common == partial.len() {
+					prefix.mid(common)
+				} else {
+					// The prefix diverges from the extension.
+					return Ok(Action::Restore(Node::Extension(encoded, child)))
+				};
+				let partial_len = partial.len();
+				walk.path.append_partial(partial.right());
+				let action = match self.remove_prefix_at(child, child_prefix, walk)? {
+					Some((child, false)) => Action::Restore(Node::Extension(encoded, child)),
+					Some((child, true)) => {
+						let key = NibbleSlice::<L::Nibble>::new_offset(
+							walk.path.inner(),
+							walk.path.len() - partial_len,
+						);
+						Action::Replace(self.fix(Node::Extension(encoded, child), key)?)
+					},
+					// The whole branch got deleted, so is this extension.
+					None => Action::Delete,
+				};
+				walk.path.drop_lasts(partial_len);
+				action
+			},
+			Node::Branch(children, value) =>
+				self.remove_prefix_from_branch(None, children, value, prefix, walk)?,
+			Node::NibbledBranch(encoded, children, value) =>
+				self.remove_prefix_from_branch(Some(encoded), children, value, prefix, walk)?,
+		})
+	}
+
+	/// Removes the keys starting with `prefix` from a branch, with a partial key if nibbled.
+	fn remove_prefix_from_branch(
+		&mut self,
+		encoded: Option<NodeKey>,
+		mut children: BranchChildren<L>,
+		mut value: Option<Value<L>>,
+		prefix: NibbleSlice<L::Nibble>,
+		walk: &mut PrefixRemovalWalk<L::Nibble>,
+	) -> Result<Action<L>, TrieHash<L>, CError<L>> {
+		let partial = encoded
+			.as_ref()
+			.map_or(NibbleSlice::<L::Nibble>::new(&[]), NibbleSlice::from_stored);
+		let common = partial.common_prefix(&prefix);
+		let partial_len = partial.len();
+		let rebuild = |encoded, children, value| match encoded {
+			Some(encoded) => Node::NibbledBranch(encoded, children, value),
+			None => Node::Branch(children, value),
+		};
+		if common < partial_len && common < prefix.len() {
+			// The prefix diverges from the branch partial key.
+			return Ok(Action::Restore(rebuild(encoded, children, value)))
+		}
+
+		walk.path.append_partial(partial.right());
+		let mut changed = false;
+		// Either the whole branch is under the prefix, or the subtrie of a single child is.
+		let (children_range, child_prefix) = if common == prefix.len() {
+			if let Some(stored_value) = value.take() {
+				value = self.remove_prefixed_value(stored_value, walk)?;
+				changed |= value.is_none();
+			}
+			(0..L::Nibble::NIBBLE_LENGTH, NibbleSlice::<L::Nibble>::new(&[]))
+		} else {
+			let index = prefix.at(common) as usize;
+			(index..index + 1, prefix.mid(common + 1))
+		};
+		for index in children_range {
+			if let Some(child) = children[index].take() {
+				walk.path.push(index as u8);
+				match self.remove_prefix_at(child, child_prefix, walk)? {
+					Some((child, child_changed)) => {
+						children[index] = Some(child);
+						changed |= child_changed;
+					},
+					None => changed = true,
+				}
+				walk.path.pop();
+			}
+		}
+
+		let action = if !changed {
+			Action::Restore(rebuild(encoded, children, value))
+		} else if value.is_none() && children.iter().all(Option::is_none) {
+			Action::Delete
+		} else {
+			let key = NibbleSlice::<L::Nibble>::new_offset(
+				walk.path.inner(),
+				walk.path.len() - partial_len,
+			);
+			Action::Replace(self.fix(rebuild(encoded, children, value), key)?)
+		};
+		walk.path.drop_lasts(partial_len);
+		Ok(action)
+	}
+
+	/// Removes `value`, at the path of `walk`, unless its limit is reached, in which case the path
+	/// is the key to resume from and the value is returned.
+	fn remove_prefixed_value(
+		&mut self,
+		value: Value<L>,
+		walk: &mut PrefixRemovalWalk<L::Nibble>,
+	) -> Result<Option<Value<L>>, TrieHash<L>, CError<L>> {
+		let (key, extra_nibble) = walk.path.as_prefix();
+		if let Some(limit) = walk.limit {
+			if walk.removal.removed >= limit {
+				if let Some(extra_nibble) = extra_nibble {
+					return Err(Box::new(TrieError::ValueAtIncompleteKey(
+						key.to_vec(),
+						extra_nibble,
+					)))
+				}
+				walk.removal.resume_from = Some(key.to_vec());
+				return Ok(Some(value))
+			}
+		}
+		self.replace_old_value(&mut None, Some(value), (key, extra_nibble));
+		walk.removal.removed += 1;
+		Ok(None)
+	}
+
 	/// Given a node which may be in an _invalid state_, fix it such that it is then in a valid
 	/// state.
 	///
 	/// _invalid state_ means:
 	/// - Branch node where there is only a single entry;
 	/// - Extension node followed by anything other than a Branch node.
-	fn fix(&mut self, node: Node<L>, key: NibbleSlice) -> Result<Node<L>, TrieHash<L>, CError<L>> {
+	fn fix(
+		&mut self,
+		node: Node<L>,
+		key: NibbleSlice<L::Nibble>,
+	) -> Result<Node<L>, TrieHash<L>, CError<L>> {
 		self.fix_inner(node, key, false)
 	}
 	fn fix_inner(
 		&mut self,
 		node: Node<L>,
-		key: NibbleSlice,
+		key: NibbleSlice<L::Nibble>,
 		recurse_extension: bool,
 	) -> Result<Node<L>, TrieHash<L>, CError<L>> {
 		match node {
@@ -1359,7 +1558,7 @@ where
 					Many,
 				}
 				let mut used_index = UsedIndex::None;
-				for i in 0..16 {
+				for i in 0..L::Nibble::NIBBLE_LENGTH {
 					match (children[i].is_none(), &used_index) {
 						(false, &UsedIndex::None) => used_index = UsedIndex::One(i as u8),
 						(false, &UsedIndex::One(_)) => {
@@ -1376,7 +1575,11 @@ where
 					(UsedIndex::One(a), None) => {
 						// only one onward node. make an extension.
 
-						let new_partial = NibbleSlice::new_offset(&[a], 1).to_stored();
+						let new_partial = NibbleSlice::<L::Nibble>::new_offset(
+							&[a],
+							L::Nibble::NIBBLE_PER_BYTE - 1,
+						)
+						.to_stored();
 						let child = children[a as usize]
 							.take()
 							.expect("used_index only set if occupied; qed");
@@ -1387,7 +1590,7 @@ where
 						// make a leaf.
 						#[cfg(feature = "std")]
 						trace!(target: "trie", "fixing: branch -> leaf");
-						Ok(Node::Leaf(NibbleSlice::new(&[]).to_stored(), value))
+						Ok(Node::Leaf(NibbleSlice::<L::Nibble>::new(&[]).to_stored(), value))
 					},
 					(_, value) => {
 						// all is well.
@@ -1406,7 +1609,7 @@ where
 					Many,
 				}
 				let mut used_index = UsedIndex::None;
-				for i in 0..16 {
+				for i in 0..L::Nibble::NIBBLE_LENGTH {
 					match (children[i].is_none(), &used_index) {
 						(false, &UsedIndex::None) => used_index = UsedIndex::One(i as u8),
 						(false, &UsedIndex::One(_)) => {
@@ -1427,20 +1630,11 @@ where
 							.expect("used_index only set if occupied; qed");
 						let mut key2 = key.clone();
 						key2.advance(
-							(enc_nibble.1.len() * nibble_ops::NIBBLE_PER_BYTE) - enc_nibble.0,
-						);
-						let (start, alloc_start, prefix_end) = match key2.left() {
-							(start, None) => (start, None, Some(nibble_ops::push_at_left(0, a, 0))),
-							(start, Some(v)) => {
-								let mut so: BackingByteVec = start.into();
-								so.push(nibble_ops::pad_left(v) | a);
-								(start, Some(so), None)
-							},
-						};
-						let child_prefix = (
-							alloc_start.as_ref().map(|start| &start[..]).unwrap_or(start),
-							prefix_end,
+							(enc_nibble.1.len() * L::Nibble::NIBBLE_PER_BYTE) - enc_nibble.0,
 						);
+						let mut child_key = NibbleVec::from_left(&key2);
+						child_key.push(a);
+						let child_prefix = child_key.as_prefix();
 						let stored = match child {
 							NodeHandle::InMemory(h) => self.storage.destroy(h),
 							NodeHandle::Hash(h) => {
@@ -1459,20 +1653,26 @@ where
 						match child_node {
 							Node::Leaf(sub_partial, value) => {
 								let mut enc_nibble = enc_nibble;
-								combine_key(
+								combine_key::<L::Nibble>(
 									&mut enc_nibble,
-									(nibble_ops::NIBBLE_PER_BYTE - 1, &[a][..]),
+									(L::Nibble::NIBBLE_PER_BYTE - 1, &[a][..]),
+								);
+								combine_key::<L::Nibble>(
+									&mut enc_nibble,
+									(sub_partial.0, &sub_partial.1[..]),
 								);
-								combine_key(&mut enc_nibble, (sub_partial.0, &sub_partial.1[..]));
 								Ok(Node::Leaf(enc_nibble, value))
 							},
 							Node::NibbledBranch(sub_partial, ch_children, ch_value) => {
 								let mut enc_nibble = enc_nibble;
-								combine_key(
+								combine_key::<L::Nibble>(
+									&mut enc_nibble,
+									(L::Nibble::NIBBLE_PER_BYTE - 1, &[a][..]),
+								);
+								combine_key::<L::Nibble>(
 									&mut enc_nibble,
-									(nibble_ops::NIBBLE_PER_BYTE - 1, &[a][..]),
+									(sub_partial.0, &sub_partial.1[..]),
 								);
-								combine_key(&mut enc_nibble, (sub_partial.0, &sub_partial.1[..]));
 								Ok(Node::NibbledBranch(enc_nibble, ch_children, ch_value))
 							},
 							_ => unreachable!(),
@@ -1494,37 +1694,24 @@ where
 			},
 			Node::Extension(partial, child) => {
 				let mut key2 = key.clone();
-				let (start, alloc_start, prefix_end) = if !recurse_extension {
+				let child_key = if !recurse_extension {
 					// We could advance key, but this code can also be called
 					// recursively, so there might be some prefix from branch.
-					let last = partial.1[partial.1.len() - 1] & (255 >> 4);
-					key2.advance((partial.1.len() * nibble_ops::NIBBLE_PER_BYTE) - partial.0 - 1);
-					match key2.left() {
-						(start, None) => (start, None, Some(nibble_ops::push_at_left(0, last, 0))),
-						(start, Some(v)) => {
-							let mut so: BackingByteVec = start.into();
-							// Complete last byte with `last`.
-							so.push(nibble_ops::pad_left(v) | last);
-							(start, Some(so), None)
-						},
-					}
+					let last = partial.1[partial.1.len() - 1] & L::Nibble::PADDING_BITMASK;
+					key2.advance((partial.1.len() * L::Nibble::NIBBLE_PER_BYTE) - partial.0 - 1);
+					let mut so = NibbleVec::from_left(&key2);
+					// Complete the prefix with `last`.
+					so.push(last);
+					so
 				} else {
-					let k2 = key2.left();
-
-					let mut so: NibbleVec = Default::default();
-					so.append_optional_slice_and_nibble(Some(&NibbleSlice::new(k2.0)), None);
-					if let Some(n) = k2.1 {
-						so.push(n >> nibble_ops::BIT_PER_NIBBLE);
-					}
+					let mut so = NibbleVec::from_left(&key2);
 					so.append_optional_slice_and_nibble(
-						Some(&NibbleSlice::from_stored(&partial)),
+						Some(&NibbleSlice::<L::Nibble>::from_stored(&partial)),
 						None,
 					);
-					let so = so.as_prefix();
-					(k2.0, Some(so.0.into()), so.1)
+					so
 				};
-				let child_prefix =
-					(alloc_start.as_ref().map(|start| &start[..]).unwrap_or(start), prefix_end);
+				let child_prefix = child_key.as_prefix();
 
 				let stored = match child {
 					NodeHandle::InMemory(h) => self.storage.destroy(h),
@@ -1549,7 +1736,7 @@ where
 						}
 						// subpartial
 						let mut partial = partial;
-						combine_key(&mut partial, (sub_partial.0, &sub_partial.1[..]));
+						combine_key::<L::Nibble>(&mut partial, (sub_partial.0, &sub_partial.1[..]));
 						#[cfg(feature = "std")]
 						trace!(
 							target: "trie",
@@ -1568,7 +1755,7 @@ where
 						}
 						// subpartial oly
 						let mut partial = partial;
-						combine_key(&mut partial, (sub_partial.0, &sub_partial.1[..]));
+						combine_key::<L::Nibble>(&mut partial, (sub_partial.0, &sub_partial.1[..]));
 						#[cfg(feature = "std")]
 						trace!(
 							target: "trie",
@@ -1596,6 +1783,48 @@ where
 		}
 	}
 
+	/// Remove the keys starting with `prefix`, in key order, stopping once `limit` keys are
+	/// removed if given.
+	///
+	/// The subtrie under the prefix is walked once, dropping removed subtrees whole and queueing
+	/// their nodes for deletion from the database on commit, rather than looking up and fixing
+	/// the path to each key. When keys with the prefix are left, the first of them is returned to
+	/// resume from, which is also where another call with the same prefix starts.
+	pub fn remove_prefix(
+		&mut self,
+		prefix: &[u8],
+		limit: Option<usize>,
+	) -> Result<PrefixRemoval, TrieHash<L>, CError<L>> {
+		#[cfg(feature = "std")]
+		trace!(target: "trie", "remove_prefix: prefix={:?}, limit={:?}", ToHex(prefix), limit);
+
+		let root_handle = self.root_handle();
+		let mut walk = PrefixRemovalWalk {
+			path: NibbleVec::<L::Nibble>::new(),
+			limit,
+			removal: PrefixRemoval { removed: 0, resume_from: None },
+		};
+		match self.remove_prefix_at(
+			root_handle,
+			NibbleSlice::<L::Nibble>::new(prefix),
+			&mut walk,
+		)? {
+			Some((handle, _changed)) => {
+				#[cfg(feature = "std")]
+				trace!(target: "trie", "remove_prefix: altered trie={}", _changed);
+				self.root_handle = handle;
+			},
+			None => {
+				#[cfg(feature = "std")]
+				trace!(target: "trie", "remove_prefix: obliterated trie");
+				self.root_handle = NodeHandle::Hash(L::Codec::hashed_null_node());
+				*self.root = L::Codec::hashed_null_node();
+			},
+		}
+
+		Ok(walk.removal)
+	}
+
 	/// Commit the in-memory changes to disk, freeing their storage and
 	/// updating the state root.
 	pub fn commit(&mut self) {
@@ -1616,28 +1845,34 @@ where
 
 		match self.storage.destroy(handle) {
 			Stored::New(node) => {
-				let mut k = NibbleVec::new();
-
-				let encoded_root = node.into_encoded(|node, o_slice, o_index| {
-					let mov = k.append_optional_slice_and_nibble(o_slice, o_index);
-					match node {
-						NodeToEncode::Node(value) => {
-							let value_hash = self.db.insert(k.as_prefix(), value);
-							k.drop_lasts(mov);
-							ChildReference::Hash(value_hash)
-						},
-						NodeToEncode::TrieNode(child) => {
-							let result = self.commit_child(child, &mut k);
-							k.drop_lasts(mov);
-							result
-						},
-					}
-				});
+				let mut k = NibbleVec::<L::Nibble>::new();
+
+				let mut encoded_root = self.encode_buffers.pop().unwrap_or_default();
+				node.into_encoded(
+					|node, o_slice, o_index| {
+						let mov = k.append_optional_slice_and_nibble(o_slice, o_index);
+						match node {
+							NodeToEncode::Node(value) => {
+								let value_hash = self.db.insert(k.as_prefix(), value);
+								k.drop_lasts(mov);
+								ChildReference::Hash(value_hash)
+							},
+							NodeToEncode::TrieNode(child) => {
+								let result = self.commit_child(child, &mut k);
+								k.drop_lasts(mov);
+								result
+							},
+						}
+					},
+					&mut encoded_root,
+				);
 				#[cfg(feature = "std")]
 				trace!(target: "trie", "encoded root node: {:?}", ToHex(&encoded_root[..]));
 
 				*self.root = self.db.insert(EMPTY_PREFIX, &encoded_root[..]);
 				self.hash_count += 1;
+				encoded_root.clear();
+				self.encode_buffers.push(encoded_root);
 
 				self.root_handle = NodeHandle::Hash(*self.root);
 			},
@@ -1658,7 +1893,7 @@ where
 	fn commit_child(
 		&mut self,
 		handle: NodeHandle<TrieHash<L>>,
-		prefix: &mut NibbleVec,
+		prefix: &mut NibbleVec<L::Nibble>,
 	) -> ChildReference<TrieHash<L>> {
 		match handle {
 			NodeHandle::Hash(hash) => ChildReference::Hash(hash),
@@ -1666,9 +1901,10 @@ where
 				match self.storage.destroy(storage_handle) {
 					Stored::Cached(_, hash) => ChildReference::Hash(hash),
 					Stored::New(node) => {
-						let encoded = {
+						let mut encoded = self.encode_buffers.pop().unwrap_or_default();
+						{
 							let commit_child = |node: NodeToEncode<TrieHash<L>>,
-							                    o_slice: Option<&NibbleSlice>,
+							                    o_slice: Option<&NibbleSlice<L::Nibble>>,
 							                    o_index: Option<u8>| {
 								let mov = prefix.append_optional_slice_and_nibble(o_slice, o_index);
 								match node {
@@ -1684,9 +1920,9 @@ where
 									},
 								}
 							};
-							node.into_encoded(commit_child)
-						};
-						if encoded.len() >= L::Hash::LENGTH {
+							node.into_encoded(commit_child, &mut encoded);
+						}
+						let result = if encoded.len() >= L::Hash::LENGTH {
 							let hash = self.db.insert(prefix.as_prefix(), &encoded[..]);
 							self.hash_count += 1;
 							ChildReference::Hash(hash)
@@ -1697,7 +1933,10 @@ where
 							let len = encoded.len();
 							h.as_mut()[..len].copy_from_slice(&encoded[..len]);
 							ChildReference::Inline(h, len)
-						}
+						};
+						encoded.clear();
+						self.encode_buffers.push(encoded);
+						result
 					},
 				}
 			},
@@ -1736,7 +1975,7 @@ where
 	where
 		'x: 'key,
 	{
-		self.lookup(NibbleSlice::new(key), key, &self.root_handle)
+		self.lookup(NibbleSlice::<L::Nibble>::new(key), key, &self.root_handle)
 	}
 
 	fn insert(
@@ -1754,8 +1993,12 @@ where
 		trace!(target: "trie", "insert: key={:?}, value={:?}", ToHex(key), ToHex(&value));
 
 		let root_handle = self.root_handle();
-		let (new_handle, _changed) =
-			self.insert_at(root_handle, &mut NibbleSlice::new(key), value.to_vec(), &mut old_val)?;
+		let (new_handle, _changed) = self.insert_at(
+			root_handle,
+			&mut NibbleSlice::<L::Nibble>::new(key),
+			value.to_vec(),
+			&mut old_val,
+		)?;
 
 		#[cfg(feature = "std")]
 		trace!(target: "trie", "insert: altered trie={}", _changed);
@@ -1769,7 +2012,7 @@ where
 		trace!(target: "trie", "remove: key={:?}", ToHex(key));
 
 		let root_handle = self.root_handle();
-		let mut key_slice = NibbleSlice::new(key);
+		let mut key_slice = NibbleSlice::<L::Nibble>::new(key);
 		let mut old_val = None;
 
 		match self.remove_at(root_handle, &mut key_slice, &mut old_val)? {
@@ -1800,14 +2043,14 @@ where
 }
 
 /// combine two NodeKeys
-fn combine_key(start: &mut NodeKey, end: (usize, &[u8])) {
-	debug_assert!(start.0 < nibble_ops::NIBBLE_PER_BYTE);
-	debug_assert!(end.0 < nibble_ops::NIBBLE_PER_BYTE);
-	let final_offset = (start.0 + end.0) % nibble_ops::NIBBLE_PER_BYTE;
-	let _shifted = nibble_ops::shift_key(start, final_offset);
+fn combine_key<N: NibbleOps>(start: &mut NodeKey, end: (usize, &[u8])) {
+	debug_assert!(start.0 < N::NIBBLE_PER_BYTE);
+	debug_assert!(end.0 < N::NIBBLE_PER_BYTE);
+	let final_offset = (start.0 + end.0) % N::NIBBLE_PER_BYTE;
+	let _shifted = N::shift_key(start, final_offset);
 	let st = if end.0 > 0 {
 		let sl = start.1.len();
-		start.1[sl - 1] |= nibble_ops::pad_right(end.1[0]);
+		start.1[sl - 1] |= N::pad_right(N::NIBBLE_PER_BYTE - end.0, end.1[0]);
 		1
 	} else {
 		0
@@ -1817,7 +2060,7 @@ fn combine_key(start: &mut NodeKey, end: (usize, &[u8])) {
 
 #[cfg(test)]
 mod tests {
-	use crate::nibble::BackingByteVec;
+	use crate::nibble::{nibble_ops::Radix16, BackingByteVec};
 
 	#[test]
 	fn combine_test() {
@@ -1825,7 +2068,7 @@ mod tests {
 		let b: &[u8] = [0x56, 0x78][..].into();
 		let test_comb = |a: (_, &BackingByteVec), b, c| {
 			let mut a = (a.0, a.1.clone());
-			super::combine_key(&mut a, b);
+			super::combine_key::<Radix16>(&mut a, b);
 			assert_eq!((a.0, &a.1[..]), c);
 		};
 		test_comb((0, &a), (0, &b), (0, &[0x12, 0x34, 0x56, 0x78][..]));
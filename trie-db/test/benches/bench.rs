@@ -16,6 +16,7 @@ use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
 
 use reference_trie::ExtensionLayout as Layout;
 use trie_db::{
+	nibble_ops::Radix16,
 	proof::{generate_proof, verify_proof},
 	NibbleSlice, Trie,
 };
@@ -56,7 +57,7 @@ fn nibble_common_prefix(b: &mut Criterion) {
 		let mixed: Vec<_> = keys
 			.iter()
 			.zip(values.iter().rev())
-			.map(|pair| (NibbleSlice::new(pair.0), NibbleSlice::new(pair.1)))
+			.map(|pair| (NibbleSlice::<Radix16>::new(pair.0), NibbleSlice::<Radix16>::new(pair.1)))
 			.collect();
 
 		b.iter(&mut || {
@@ -0,0 +1,118 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::BTreeMap, fs};
+
+use memory_db::{HashKey, MemoryDB};
+use reference_trie::test_layouts;
+use trie_db::{
+	trie_build_unsorted, trie_root_unsorted, trie_visit, DBValue, ExternalSort, NodeCodec, Trie,
+	TrieDB, TrieHash, TrieLayout, TrieRoot,
+};
+use trie_standardmap::{Alphabet, StandardMap, ValueMode};
+
+fn unsorted_input() -> Vec<(Vec<u8>, Vec<u8>)> {
+	let mut input = StandardMap {
+		alphabet: Alphabet::Low,
+		min_key: 1,
+		journal_key: 2,
+		value_mode: ValueMode::Random,
+		count: 400,
+	}
+	.make();
+	// Overwritten keys keep their last value.
+	let overwrites: Vec<_> = input
+		.iter()
+		.step_by(7)
+		.map(|(k, v)| (k.clone(), [v.as_slice(), b"x"].concat()))
+		.collect();
+	input.extend(overwrites);
+	input
+}
+
+fn sorted_root<T: TrieLayout>(input: &[(Vec<u8>, Vec<u8>)]) -> TrieHash<T> {
+	let sorted: BTreeMap<_, _> = input.iter().cloned().collect();
+	let mut cb = TrieRoot::<T>::default();
+	trie_visit::<T, _, _, _, _>(sorted, &mut cb);
+	cb.root.unwrap_or_default()
+}
+
+test_layouts!(unsorted_root_in_memory, unsorted_root_in_memory_internal);
+fn unsorted_root_in_memory_internal<T: TrieLayout>() {
+	let dir = tempfile::tempdir().unwrap();
+	let input = unsorted_input();
+	let root =
+		trie_root_unsorted::<T, _, _, _>(input.clone(), &ExternalSort::new(dir.path())).unwrap();
+	assert_eq!(root, sorted_root::<T>(&input));
+	assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+test_layouts!(unsorted_root_spills_runs, unsorted_root_spills_runs_internal);
+fn unsorted_root_spills_runs_internal<T: TrieLayout>() {
+	let dir = tempfile::tempdir().unwrap();
+	let input = unsorted_input();
+	for run_size in [1, 100, 1000, 10_000] {
+		let sort = ExternalSort::new(dir.path()).with_run_size(run_size);
+		let root = trie_root_unsorted::<T, _, _, _>(input.clone(), &sort).unwrap();
+		assert_eq!(root, sorted_root::<T>(&input));
+		assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+	}
+}
+
+test_layouts!(unsorted_root_merge_passes, unsorted_root_merge_passes_internal);
+fn unsorted_root_merge_passes_internal<T: TrieLayout>() {
+	let dir = tempfile::tempdir().unwrap();
+	let input = unsorted_input();
+	for max_merge_runs in [0, 2, 3, 16] {
+		let sort = ExternalSort::new(dir.path())
+			.with_run_size(200)
+			.with_max_merge_runs(max_merge_runs);
+		let root = trie_root_unsorted::<T, _, _, _>(input.clone(), &sort).unwrap();
+		assert_eq!(root, sorted_root::<T>(&input));
+		assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+	}
+}
+
+test_layouts!(unsorted_build, unsorted_build_internal);
+fn unsorted_build_internal<T: TrieLayout>() {
+	let dir = tempfile::tempdir().unwrap();
+	let input = unsorted_input();
+	let sort = ExternalSort::new(dir.path()).with_run_size(500);
+	let mut db = MemoryDB::<T::Hash, HashKey<_>, DBValue>::default();
+	let root = trie_build_unsorted::<T, _, _, _, _>(&mut db, input.clone(), &sort).unwrap();
+	assert_eq!(root, sorted_root::<T>(&input));
+
+	let sorted: BTreeMap<_, _> = input.into_iter().collect();
+	let trie = TrieDB::<T>::new(&db, &root);
+	let items: Vec<_> = trie.iter().unwrap().map(|item| item.unwrap()).collect();
+	assert_eq!(items, sorted.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn unsorted_empty_input() {
+	type T = reference_trie::ExtensionLayout;
+	let dir = tempfile::tempdir().unwrap();
+	let sort = ExternalSort::new(dir.path()).with_run_size(1);
+	let input: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+	let root = trie_root_unsorted::<T, _, _, _>(input, &sort).unwrap();
+	assert_eq!(root, <T as TrieLayout>::Codec::hashed_null_node());
+}
+
+#[test]
+fn unsorted_missing_directory() {
+	type T = reference_trie::ExtensionLayout;
+	let dir = tempfile::tempdir().unwrap();
+	let sort = ExternalSort::new(dir.path().join("missing")).with_run_size(1);
+	assert!(trie_root_unsorted::<T, _, _, _>(unsorted_input(), &sort).is_err());
+}
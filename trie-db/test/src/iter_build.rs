@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use memory_db::{HashKey, MemoryDB, PrefixedKey};
+use memory_db::{HashKey, MemoryDB, PrefixedKey, ShardedMemoryDB};
 use reference_trie::{
 	test_layouts, ExtensionLayout, HashedValueNoExt, HashedValueNoExtThreshold, NoExtensionLayout,
 	RefHasher,
@@ -288,3 +288,36 @@ fn polka_re_test() {
 		(vec![105, 97, 48, 77, 101, 105, 121, 101], vec![69, 109, 111, 111, 82, 49, 97, 105]),
 	]);
 }
+
+test_layouts!(parallel_builds_into_sharded_db, parallel_builds_into_sharded_db_internal);
+fn parallel_builds_into_sharded_db_internal<T: TrieLayout>() {
+	use trie_db::{Trie, TrieBuilder, TrieDB};
+
+	let inputs: Vec<Vec<(Vec<u8>, Vec<u8>)>> = (0u8..4)
+		.map(|t| (0u8..64).map(|i| (vec![i, t, i], vec![t; 1 + i as usize])).collect())
+		.collect();
+	let db = ShardedMemoryDB::<T::Hash, PrefixedKey<_>, DBValue>::default();
+	let roots: Vec<_> = std::thread::scope(|s| {
+		let handles: Vec<_> = inputs
+			.iter()
+			.map(|input| {
+				let mut db = &db;
+				s.spawn(move || {
+					let mut cb = TrieBuilder::<T, _>::new(&mut db);
+					trie_db::trie_visit::<T, _, _, _, _>(input.iter().cloned(), &mut cb);
+					cb.root.unwrap()
+				})
+			})
+			.collect();
+		handles.into_iter().map(|h| h.join().unwrap()).collect()
+	});
+
+	let db = db.into_memory_db();
+	for (input, root) in inputs.iter().zip(roots.iter()) {
+		assert_eq!(*root, reference_trie::calc_root::<T, _, _, _>(input.clone()));
+		let trie = TrieDB::<T>::new(&db, root);
+		for (k, v) in input {
+			assert_eq!(trie.get(k).unwrap().as_ref(), Some(v));
+		}
+	}
+}
@@ -16,6 +16,7 @@ use hash_db::{HashDB, Hasher};
 use hex_literal::hex;
 use reference_trie::test_layouts;
 use trie_db::{
+	nibble_ops::NibbleOps,
 	node::{Node, Value},
 	DBValue, NibbleSlice, NibbleVec, TrieDB, TrieDBNodeIterator, TrieError, TrieIterator,
 	TrieLayout, TrieMut,
@@ -41,8 +42,8 @@ fn build_trie_db<T: TrieLayout>(
 	(memdb, root)
 }
 
-fn nibble_vec<T: AsRef<[u8]>>(bytes: T, len: usize) -> NibbleVec {
-	let slice = NibbleSlice::new(bytes.as_ref());
+fn nibble_vec<N: NibbleOps, T: AsRef<[u8]>>(bytes: T, len: usize) -> NibbleVec<N> {
+	let slice = NibbleSlice::<N>::new(bytes.as_ref());
 
 	let mut v = NibbleVec::new();
 	for i in 0..len {
@@ -51,6 +52,17 @@ fn nibble_vec<T: AsRef<[u8]>>(bytes: T, len: usize) -> NibbleVec {
 	v
 }
 
+/// Depth of the branch of the tries built from the keys `01`, `0123` and `02`, the first
+/// nibble where `01` and `02` differ.
+fn branch_depth<T: TrieLayout>() -> usize {
+	T::Nibble::biggest_depth(&hex!("01"), &hex!("02"))
+}
+
+/// Check that a node is inline exactly when its encoding is shorter than a hash.
+fn check_inline<T: TrieLayout>(hash: Option<impl AsRef<[u8]>>, data: &[u8]) {
+	assert_eq!(hash.is_none(), data.len() < T::Hash::LENGTH);
+}
+
 test_layouts!(iterator_works, iterator_works_internal);
 fn iterator_works_internal<T: TrieLayout>() {
 	let pairs = vec![
@@ -123,14 +135,16 @@ fn iterator_works_internal<T: TrieLayout>() {
 
 		assert!(iter.next().is_none());
 	} else {
-		let can_expand =
-			T::MAX_INLINE_VALUE.unwrap_or(T::Hash::LENGTH as u32) < T::Hash::LENGTH as u32;
+		let depth = branch_depth::<T>();
+		let nibble_per_byte = T::Nibble::NIBBLE_PER_BYTE;
 		match iter.next() {
 			Some(Ok((prefix, Some(_), node))) => {
 				assert_eq!(prefix, nibble_vec(hex!(""), 0));
 				match node.node() {
-					Node::NibbledBranch(partial, _, _) =>
-						assert_eq!(partial, NibbleSlice::new_offset(&hex!("00")[..], 1)),
+					Node::NibbledBranch(partial, _, _) => assert_eq!(
+						partial,
+						NibbleSlice::new_offset(&hex!("00")[..], nibble_per_byte - depth)
+					),
 					_ => panic!("unexpected node"),
 				}
 			},
@@ -139,13 +153,11 @@ fn iterator_works_internal<T: TrieLayout>() {
 
 		match iter.next() {
 			Some(Ok((prefix, hash, node))) => {
-				if !can_expand {
-					assert!(hash.is_none());
-				}
-				assert_eq!(prefix, nibble_vec(hex!("01"), 2));
+				check_inline::<T>(hash, node.data());
+				assert_eq!(prefix, nibble_vec(hex!("01"), depth + 1));
 				match node.node() {
 					Node::NibbledBranch(partial, _, _) =>
-						assert_eq!(partial, NibbleSlice::new(&hex!("")[..])),
+						assert_eq!(partial, NibbleSlice::new_offset(&hex!("01")[..], depth + 1)),
 					_ => panic!("unexpected node"),
 				}
 			},
@@ -154,13 +166,13 @@ fn iterator_works_internal<T: TrieLayout>() {
 
 		match iter.next() {
 			Some(Ok((prefix, hash, node))) => {
-				if !can_expand {
-					assert!(hash.is_none());
-				}
-				assert_eq!(prefix, nibble_vec(hex!("0120"), 3));
+				check_inline::<T>(hash, node.data());
+				assert_eq!(prefix, nibble_vec(hex!("0123"), nibble_per_byte + 1));
 				match node.node() {
-					Node::Leaf(partial, _) =>
-						assert_eq!(partial, NibbleSlice::new_offset(&hex!("03")[..], 1)),
+					Node::Leaf(partial, _) => assert_eq!(
+						partial,
+						NibbleSlice::new_offset(&hex!("0123")[..], nibble_per_byte + 1)
+					),
 					_ => panic!("unexpected node"),
 				}
 			},
@@ -170,9 +182,10 @@ fn iterator_works_internal<T: TrieLayout>() {
 
 		match iter.next() {
 			Some(Ok((prefix, Some(_), node))) => {
-				assert_eq!(prefix, nibble_vec(hex!("02"), 2));
+				assert_eq!(prefix, nibble_vec(hex!("02"), depth + 1));
 				match node.node() {
-					Node::Leaf(partial, _) => assert_eq!(partial, NibbleSlice::new(&hex!("")[..])),
+					Node::Leaf(partial, _) =>
+						assert_eq!(partial, NibbleSlice::new_offset(&hex!("02")[..], depth + 1)),
 					_ => panic!("unexpected node"),
 				}
 			},
@@ -221,21 +234,23 @@ fn seek_works_internal<T: TrieLayout>() {
 		_ => panic!("unexpected item"),
 	}
 
+	let depth = branch_depth::<T>();
+
 	TrieIterator::seek(&mut iter, &hex!("00")[..]).unwrap();
 	match iter.next() {
-		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("01"), 2)),
+		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("01"), depth + 1)),
 		_ => panic!("unexpected item"),
 	}
 
 	TrieIterator::seek(&mut iter, &hex!("01")[..]).unwrap();
 	match iter.next() {
-		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("01"), 2)),
+		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("01"), depth + 1)),
 		_ => panic!("unexpected item"),
 	}
 
 	TrieIterator::seek(&mut iter, &hex!("02")[..]).unwrap();
 	match iter.next() {
-		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("02"), 2)),
+		Some(Ok((prefix, _, _))) => assert_eq!(prefix, nibble_vec(hex!("02"), depth + 1)),
 		_ => panic!("unexpected item"),
 	}
 
@@ -315,6 +330,16 @@ fn iterate_over_incomplete_db_internal<T: TrieLayout>() {
 		let mut iter = TrieDBNodeIterator::new(&trie).unwrap();
 
 		TrieIterator::seek(&mut iter, &hex!("0130")[..]).unwrap();
+		// In a binary trie, `02` and `03` have their own branch.
+		if T::Nibble::biggest_depth(&hex!("02"), &hex!("03")) > branch_depth::<T>() {
+			match iter.next() {
+				Some(Ok((_, _, node))) => match node.node() {
+					Node::NibbledBranch(..) => {},
+					_ => panic!("unexpected node"),
+				},
+				_ => panic!("unexpected item"),
+			}
+		}
 		match iter.next() {
 			Some(Err(e)) =>
 				if let TrieError::IncompleteDatabase(err_hash) = *e {
@@ -339,7 +364,6 @@ fn iterate_over_incomplete_db_internal<T: TrieLayout>() {
 
 test_layouts!(prefix_works, prefix_works_internal);
 fn prefix_works_internal<T: TrieLayout>() {
-	let can_expand = T::MAX_INLINE_VALUE.unwrap_or(T::Hash::LENGTH as u32) < T::Hash::LENGTH as u32;
 	let pairs = vec![
 		(hex!("01").to_vec(), b"aaaa".to_vec()),
 		(hex!("0123").to_vec(), b"bbbb".to_vec()),
@@ -364,15 +388,14 @@ fn prefix_works_internal<T: TrieLayout>() {
 			_ => panic!("unexpected item"),
 		}
 	} else {
+		let depth = branch_depth::<T>();
 		match iter.next() {
 			Some(Ok((prefix, hash, node))) => {
-				if !can_expand {
-					debug_assert!(hash.is_none());
-				}
-				assert_eq!(prefix, nibble_vec(hex!("01"), 2));
+				check_inline::<T>(hash, node.data());
+				assert_eq!(prefix, nibble_vec(hex!("01"), depth + 1));
 				match node.node() {
 					Node::NibbledBranch(partial, _, _) =>
-						assert_eq!(partial, NibbleSlice::new_offset(&hex!("")[..], 0)),
+						assert_eq!(partial, NibbleSlice::new_offset(&hex!("01")[..], depth + 1)),
 					_ => panic!("unexpected node"),
 				}
 			},
@@ -380,15 +403,16 @@ fn prefix_works_internal<T: TrieLayout>() {
 		}
 	}
 
+	let nibble_per_byte = T::Nibble::NIBBLE_PER_BYTE;
 	match iter.next() {
 		Some(Ok((prefix, hash, node))) => {
-			if !can_expand {
-				debug_assert!(hash.is_none());
-			}
-			assert_eq!(prefix, nibble_vec(hex!("0120"), 3));
+			check_inline::<T>(hash, node.data());
+			assert_eq!(prefix, nibble_vec(hex!("0123"), nibble_per_byte + 1));
 			match node.node() {
-				Node::Leaf(partial, _) =>
-					assert_eq!(partial, NibbleSlice::new_offset(&hex!("03")[..], 1)),
+				Node::Leaf(partial, _) => assert_eq!(
+					partial,
+					NibbleSlice::new_offset(&hex!("0123")[..], nibble_per_byte + 1)
+				),
 				_ => panic!("unexpected node"),
 			}
 		},
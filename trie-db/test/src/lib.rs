@@ -14,6 +14,8 @@
 
 //! Tests for trie-db crate.
 
+#[cfg(test)]
+mod external_sort;
 #[cfg(test)]
 mod fatdb;
 #[cfg(test)]
@@ -23,8 +25,14 @@ mod iter_build;
 #[cfg(test)]
 mod iterator;
 #[cfg(test)]
+mod ordered_root;
+#[cfg(test)]
+mod partial_trie;
+#[cfg(test)]
 mod proof;
 #[cfg(test)]
+mod proof_envelope;
+#[cfg(test)]
 mod recorder;
 #[cfg(test)]
 mod sectriedb;
@@ -0,0 +1,84 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ethereum_trie::EthereumLayout;
+use keccak_hasher::KeccakHasher;
+use memory_db::{MemoryDB, PrefixedKey};
+use reference_trie::{ExtensionLayout, NoExtensionLayout};
+use substrate_trie::{LayoutV0, LayoutV1};
+use trie_db::{
+	DBValue, NodeCodec, OrderedRootBuilder, Trie, TrieConfiguration, TrieDB, TrieLayout,
+};
+
+type PrefixedMemoryDB<L> =
+	MemoryDB<<L as TrieLayout>::Hash, PrefixedKey<<L as TrieLayout>::Hash>, DBValue>;
+
+fn values(count: usize) -> Vec<Vec<u8>> {
+	(0..count).map(|i| vec![i as u8; 1 + i % 40]).collect()
+}
+
+fn check_pushes<L: TrieConfiguration>() {
+	let values = values(300);
+	let mut builder =
+		OrderedRootBuilder::<L, _>::new(PrefixedMemoryDB::<L>::new(L::Codec::empty_node()));
+	assert!(builder.is_empty());
+	assert_eq!(builder.root().unwrap(), L::Codec::hashed_null_node());
+	for (i, value) in values.iter().enumerate() {
+		builder.push(value);
+		assert_eq!(builder.len(), i + 1);
+		if i < 70 || i % 17 == 0 || (126..=130).contains(&i) {
+			assert_eq!(builder.root().unwrap(), L::ordered_trie_root(&values[..=i]));
+		}
+	}
+	let (db, root) = builder.into_inner().unwrap();
+	assert_eq!(root, L::ordered_trie_root(&values));
+	let trie = TrieDB::<L>::new(&db, &root);
+	for (i, value) in values.iter().enumerate() {
+		assert_eq!(trie.get(&L::encode_index(i as u32)).unwrap().as_ref(), Some(value));
+	}
+	assert_eq!(trie.iter().unwrap().count(), values.len());
+}
+
+fn check_batches<L: TrieConfiguration>() {
+	let values = values(200);
+	let mut builder =
+		OrderedRootBuilder::<L, _>::new(PrefixedMemoryDB::<L>::new(L::Codec::empty_node()));
+	for (i, value) in values.iter().enumerate() {
+		builder.push(value);
+		if i % 50 == 49 {
+			assert_eq!(builder.root().unwrap(), L::ordered_trie_root(&values[..=i]));
+		}
+	}
+	assert_eq!(builder.root().unwrap(), builder.root().unwrap());
+}
+
+#[test]
+fn ordered_root_reference_layouts() {
+	check_pushes::<ExtensionLayout>();
+	check_pushes::<NoExtensionLayout>();
+	check_batches::<ExtensionLayout>();
+}
+
+#[test]
+fn ordered_root_rlp_index() {
+	check_pushes::<EthereumLayout>();
+	check_batches::<EthereumLayout>();
+}
+
+#[test]
+fn ordered_root_compact_index() {
+	check_pushes::<LayoutV0<KeccakHasher>>();
+	check_pushes::<LayoutV1<KeccakHasher>>();
+	check_batches::<LayoutV1<KeccakHasher>>();
+}
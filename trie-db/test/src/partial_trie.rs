@@ -0,0 +1,142 @@
+// Copyright 2017, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use hash_db::{HashDB, EMPTY_PREFIX};
+use reference_trie::test_layouts;
+use trie_db::{
+	decode_compact, encode_compact, DBValue, NibbleOps, PartialTrie, Proven, Recorder, Trie,
+	TrieDB, TrieDBMut, TrieError, TrieLayout, TrieMut,
+};
+
+type MemoryDB<T> = memory_db::MemoryDB<
+	<T as TrieLayout>::Hash,
+	memory_db::HashKey<<T as TrieLayout>::Hash>,
+	DBValue,
+>;
+
+const ENTRIES: &[(&[u8], &[u8])] = &[
+	// "alfa" is at a hash-referenced leaf node, never recorded.
+	(b"alfa", &[0; 32]),
+	// "bravo" is at a leaf node, never recorded.
+	(b"bravo", b"bravo"),
+	(b"do", b"verb"),
+	(b"dog", b"puppy"),
+	(b"doge", &[0; 32]),
+	(b"horse", b"stallion"),
+	(b"house", b"building"),
+];
+
+/// Full trie and the nodes recorded while looking up "do", "dogs" and "horse".
+fn recorded_db<T: TrieLayout>() -> (MemoryDB<T>, MemoryDB<T>, <T::Hash as hash_db::Hasher>::Out) {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in ENTRIES {
+			trie.insert(key, value).unwrap();
+		}
+	}
+
+	let mut recorder = Recorder::new();
+	{
+		let trie = <TrieDB<T>>::new(&db, &root);
+		for key in [&b"do"[..], b"dogs", b"horse"] {
+			trie.get_with(key, &mut recorder).unwrap();
+		}
+	}
+	let mut partial_db = <MemoryDB<T>>::default();
+	for record in recorder.drain() {
+		partial_db.insert(EMPTY_PREFIX, &record.data);
+	}
+	(db, partial_db, root)
+}
+
+fn check_partial_trie<T: TrieLayout>(trie: &PartialTrie<T>) {
+	assert_eq!(trie.get(b"do").unwrap(), Proven::Known(Some(b"verb".to_vec())));
+	assert_eq!(trie.get(b"horse").unwrap(), Proven::Known(Some(b"stallion".to_vec())));
+	assert_eq!(trie.get(b"dogs").unwrap(), Proven::Known(None));
+	assert_eq!(trie.contains(b"zulu").unwrap(), Proven::Known(false));
+
+	match trie.get(b"alfa").unwrap() {
+		Proven::Unknown(prefix) => {
+			// The first node not recorded is the child of the branch where "alfa" diverges
+			// from the recorded "do".
+			assert_eq!(prefix.len(), T::Nibble::biggest_depth(b"alfa", b"do") + 1);
+			for i in 0..prefix.len() {
+				assert_eq!(prefix.at(i), T::Nibble::left_nibble_at(b"alfa", i));
+			}
+		},
+		result => panic!("expected Proven::Unknown, got {:?}", result),
+	}
+	assert!(matches!(trie.contains(b"alfa").unwrap(), Proven::Unknown(_)));
+
+	let known: Vec<_> = ENTRIES
+		.iter()
+		.filter(|(key, _)| matches!(trie.get(key).unwrap(), Proven::Known(_)))
+		.map(|(key, value)| (key.to_vec(), value.to_vec()))
+		.collect();
+	assert!(known.len() < ENTRIES.len());
+	let iterated: Vec<_> = trie.iter().map(|item| item.unwrap()).collect();
+	assert_eq!(iterated, known);
+}
+
+test_layouts!(partial_trie_from_recorder, partial_trie_from_recorder_internal);
+fn partial_trie_from_recorder_internal<T: TrieLayout>() {
+	let (_, partial_db, root) = recorded_db::<T>();
+	check_partial_trie(&PartialTrie::<T>::new(&partial_db, &root));
+}
+
+test_layouts!(partial_trie_from_compact, partial_trie_from_compact_internal);
+fn partial_trie_from_compact_internal<T: TrieLayout>() {
+	let (_, partial_db, root) = recorded_db::<T>();
+	let encoded = encode_compact::<T>(&TrieDB::<T>::new(&partial_db, &root)).unwrap();
+
+	let mut db = <MemoryDB<T>>::default();
+	let (decoded_root, _) = decode_compact::<T, _>(&mut db, &encoded).unwrap();
+	assert_eq!(decoded_root, root);
+	check_partial_trie(&PartialTrie::<T>::new(&db, &decoded_root));
+}
+
+test_layouts!(partial_trie_full_and_empty, partial_trie_full_and_empty_internal);
+fn partial_trie_full_and_empty_internal<T: TrieLayout>() {
+	let (db, _, root) = recorded_db::<T>();
+	let trie = PartialTrie::<T>::new(&db, &root);
+	let iterated: Vec<_> = trie.iter().map(|item| item.unwrap()).collect();
+	let expected: Vec<_> =
+		ENTRIES.iter().map(|(key, value)| (key.to_vec(), value.to_vec())).collect();
+	assert_eq!(iterated, expected);
+
+	let empty = <MemoryDB<T>>::default();
+	let trie = PartialTrie::<T>::new(&empty, &root);
+	assert_eq!(trie.iter().count(), 0);
+	match trie.get(b"do").unwrap() {
+		Proven::Unknown(prefix) => assert!(prefix.is_empty()),
+		result => panic!("expected Proven::Unknown, got {:?}", result),
+	}
+}
+
+test_layouts!(partial_trie_undecodable_root, partial_trie_undecodable_root_internal);
+fn partial_trie_undecodable_root_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let root = db.insert(EMPTY_PREFIX, &[0xff; 40]);
+	let trie = PartialTrie::<T>::new(&db, &root);
+
+	let mut iter = trie.iter();
+	match iter.next() {
+		Some(Err(e)) => assert!(matches!(*e, TrieError::DecoderError(..))),
+		item => panic!("expected a decoder error, got {:?}", item),
+	}
+	assert!(iter.next().is_none());
+	assert!(trie.get(b"do").is_err());
+}
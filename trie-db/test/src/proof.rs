@@ -16,8 +16,17 @@ use hash_db::Hasher;
 use reference_trie::{test_layouts, NoExtensionLayout};
 
 use trie_db::{
-	proof::{generate_proof, verify_proof, VerifyError},
-	DBValue, Trie, TrieDB, TrieDBMut, TrieLayout, TrieMut,
+	decode_compact,
+	proof::{
+		compact_from_recorded, generate_ordered_proof, generate_proof, generate_proof_with_budget,
+		generate_update_witness, generate_value_proof, proof_from_recorded, recorded_from_compact,
+		recorded_from_compact_with_limits, verify_ordered_proof, verify_ordered_proof_with_limits,
+		verify_proof, verify_proof_with_limits, verify_update_witness,
+		verify_update_witness_with_limits, verify_value_proof, verify_value_proof_with_limits,
+		ExceededLimit, VerifyError, VerifyLimits,
+	},
+	DBValue, NibbleOps, NodeCodec, ProofBudget, Recorder, Trie, TrieConfiguration, TrieDB,
+	TrieDBMut, TrieError, TrieLayout, TrieMut,
 };
 
 type MemoryDB<T> = memory_db::MemoryDB<
@@ -240,3 +249,387 @@ fn test_verify_decode_error_internal<T: TrieLayout>() {
 		result => panic!("expected VerifyError::DecodeError, got {:?}", result),
 	}
 }
+
+fn test_ordered_proof_internal<L: TrieConfiguration>() {
+	for count in [0usize, 1, 5, 70, 130, 300] {
+		let items: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8; 1 + i % 40]).collect();
+		let root = L::ordered_trie_root(&items);
+		let indices: Vec<u32> =
+			(0..count as u32).filter(|i| i % 7 == 0 || *i == 127 || *i == 128).collect();
+
+		let mut statement: Vec<_> =
+			indices.iter().map(|i| (*i, Some(items[*i as usize].clone()))).collect();
+		statement.push((count as u32, None));
+		let proven: Vec<_> = statement.iter().map(|(i, _)| *i).collect();
+		let proof = generate_ordered_proof::<L, _, _, _>(&items, &proven).unwrap();
+		verify_ordered_proof::<L, _, _>(&root, &proof, &statement).unwrap();
+
+		if let Some(last) = indices.last() {
+			let wrong = [(*last, Some(b"wrong".to_vec()))];
+			let proof = generate_ordered_proof::<L, _, _, _>(&items, &[*last]).unwrap();
+			assert!(verify_ordered_proof::<L, _, _>(&root, &proof, &wrong).is_err());
+			let absent = [(*last, None::<Vec<u8>>)];
+			assert!(verify_ordered_proof::<L, _, _>(&root, &proof, &absent).is_err());
+		}
+	}
+}
+
+fn test_ordered_proof_limits_internal<L: TrieConfiguration>() {
+	let items: Vec<Vec<u8>> = (0..300usize).map(|i| vec![i as u8; 1 + i % 40]).collect();
+	let root = L::ordered_trie_root(&items);
+	let statement: Vec<_> = [0u32, 7, 128, 299]
+		.iter()
+		.map(|i| (*i, Some(items[*i as usize].clone())))
+		.chain(Some((300, None)))
+		.collect();
+	let proven: Vec<_> = statement.iter().map(|(i, _)| *i).collect();
+	let proof = generate_ordered_proof::<L, _, _, _>(&items, &proven).unwrap();
+	let max_key_len = proven.iter().map(|i| L::encode_index(*i).len()).max().unwrap();
+	check_tightest_limits(
+		&proof,
+		max_key_len,
+		|limits| verify_ordered_proof_with_limits::<L, _, _>(&root, &proof, &statement, limits),
+		|err| match err {
+			VerifyError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+}
+
+#[test]
+fn test_ordered_proof_limits() {
+	test_ordered_proof_limits_internal::<reference_trie::ExtensionLayout>();
+	test_ordered_proof_limits_internal::<reference_trie::NoExtensionLayout>();
+	test_ordered_proof_limits_internal::<ethereum_trie::EthereumLayout>();
+	test_ordered_proof_limits_internal::<substrate_trie::LayoutV0<keccak_hasher::KeccakHasher>>();
+	test_ordered_proof_limits_internal::<substrate_trie::LayoutV1<keccak_hasher::KeccakHasher>>();
+}
+
+#[test]
+fn test_ordered_proof() {
+	test_ordered_proof_internal::<reference_trie::ExtensionLayout>();
+	test_ordered_proof_internal::<reference_trie::NoExtensionLayout>();
+	test_ordered_proof_internal::<ethereum_trie::EthereumLayout>();
+	test_ordered_proof_internal::<substrate_trie::LayoutV0<keccak_hasher::KeccakHasher>>();
+	test_ordered_proof_internal::<substrate_trie::LayoutV1<keccak_hasher::KeccakHasher>>();
+}
+
+fn test_update_witness_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::new(T::Codec::empty_node());
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in test_entries() {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let changes: Vec<(&[u8], Option<&[u8]>)> = vec![
+		(b"bravo", Some(b"charlie")),
+		(b"dogs", Some(&[1; 40])),
+		// Collapses the branch of "dog" and the one of "horse" and "house".
+		(b"doge", None),
+		(b"horse", None),
+		(b"absent", None),
+	];
+
+	let mut expected_db = db.clone();
+	let mut expected_root = root;
+	{
+		let mut trie = <TrieDBMut<T>>::from_existing(&mut expected_db, &mut expected_root);
+		for (key, value) in changes.iter() {
+			match value {
+				Some(value) => trie.insert(key, value).unwrap(),
+				None => trie.remove(key).unwrap(),
+			};
+		}
+	}
+
+	let (new_root, witness) =
+		generate_update_witness::<T, _, _, _, _>(&db, &root, changes.clone()).unwrap();
+	assert_eq!(new_root, expected_root);
+	assert!(!witness.is_empty());
+	assert_eq!(
+		verify_update_witness::<T, _, _, _>(&root, &witness, changes.clone()).unwrap(),
+		expected_root,
+	);
+
+	for i in 0..witness.len() {
+		let mut incomplete = witness.clone();
+		incomplete.remove(i);
+		match verify_update_witness::<T, _, _, _>(&root, &incomplete, changes.clone()) {
+			Err(e) if matches!(*e, TrieError::IncompleteDatabase(_)) => {},
+			result => panic!("expected TrieError::IncompleteDatabase, got {:?}", result),
+		}
+	}
+
+	check_tightest_limits(
+		&witness,
+		b"absent".len(),
+		|limits| {
+			let new_root = verify_update_witness_with_limits::<T, _, _, _>(
+				&root,
+				&witness,
+				changes.clone(),
+				limits,
+			)?;
+			assert_eq!(new_root, expected_root);
+			Ok(())
+		},
+		|err: Box<TrieError<_, _>>| match *err {
+			TrieError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+}
+
+test_layouts!(test_update_witness, test_update_witness_internal);
+
+#[test]
+fn test_update_witness_external_layouts() {
+	test_update_witness_internal::<ethereum_trie::EthereumLayout>();
+	test_update_witness_internal::<substrate_trie::LayoutV0<keccak_hasher::KeccakHasher>>();
+	test_update_witness_internal::<substrate_trie::LayoutV1<keccak_hasher::KeccakHasher>>();
+}
+
+test_layouts!(test_value_proof, test_value_proof_internal);
+fn test_value_proof_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in test_entries() {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let keys: Vec<&[u8]> = vec![b"horse", b"do", b"dogs", b"alfa", b"doge"];
+	let proof = generate_value_proof(&trie, &keys).unwrap();
+
+	let values = verify_value_proof::<T, _, _>(&root, &proof, &keys).unwrap();
+	let expected: Vec<_> = keys.iter().map(|key| trie.get(key).unwrap()).collect();
+	assert_eq!(values, expected);
+	assert_eq!(values[2], None);
+
+	// Nodes only needed for "alfa" are extraneous without it.
+	assert!(matches!(
+		verify_value_proof::<T, _, _>(&root, &proof, &keys[..3]),
+		Err(VerifyError::ExtraneousNode),
+	));
+	let mut extended = proof.clone();
+	extended.push(proof[0].clone());
+	assert!(matches!(
+		verify_value_proof::<T, _, _>(&root, &extended, &keys),
+		Err(VerifyError::ExtraneousNode),
+	));
+
+	assert!(matches!(
+		verify_value_proof::<T, _, _>(&root, &proof[..proof.len() - 1], &keys),
+		Err(VerifyError::IncompleteProof),
+	));
+
+	let other_root = T::Hash::hash(b"other");
+	assert!(matches!(
+		verify_value_proof::<T, _, _>(&other_root, &proof, &keys),
+		Err(VerifyError::RootMismatch(_)),
+	));
+
+	let empty_root = T::Codec::hashed_null_node();
+	let empty_db = <MemoryDB<T>>::new(T::Codec::empty_node());
+	let empty = <TrieDB<T>>::new(&empty_db, &empty_root);
+	let proof = generate_value_proof(&empty, &keys).unwrap();
+	let values = verify_value_proof::<T, _, _>(&empty_root, &proof, &keys).unwrap();
+	assert!(values.iter().all(Option::is_none));
+}
+
+test_layouts!(test_recorded_compact_conversion, test_recorded_compact_conversion_internal);
+fn test_recorded_compact_conversion_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in test_entries() {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let keys: Vec<&[u8]> = vec![b"alfa", b"do", b"dogs", b"horse"];
+	let mut recorder = Recorder::new();
+	let mut items = Vec::new();
+	for key in keys.iter() {
+		items.push((*key, trie.get_with(key, &mut recorder).unwrap()));
+	}
+	let mut recorded: Vec<_> = recorder.drain().into_iter().map(|record| record.data).collect();
+	recorded.sort();
+	recorded.dedup();
+
+	let compact = compact_from_recorded::<T, _, _>(&root, &recorded).unwrap();
+	let size = |nodes: &[Vec<u8>]| nodes.iter().map(Vec::len).sum::<usize>();
+	assert!(size(&compact) < size(&recorded));
+	let mut decoded_db = <MemoryDB<T>>::default();
+	let (decoded_root, used) = decode_compact::<T, _>(&mut decoded_db, &compact).unwrap();
+	assert_eq!((decoded_root, used), (root, compact.len()));
+	let decoded = <TrieDB<T>>::new(&decoded_db, &root);
+	for (key, value) in items.iter() {
+		assert_eq!(&decoded.get(key).unwrap(), value);
+	}
+
+	let (expanded_root, expanded) = recorded_from_compact::<T>(&compact).unwrap();
+	assert_eq!(expanded_root, root);
+	assert_eq!(expanded, recorded);
+	// Paths in the compact encoding depend on the layout, and are at most the longest key.
+	let max_key_len = (1..=b"horse".len())
+		.find(|max_key_len| {
+			let limits = VerifyLimits { max_key_len: *max_key_len, ..VerifyLimits::unlimited() };
+			recorded_from_compact_with_limits::<T>(&compact, &limits).is_ok()
+		})
+		.unwrap();
+	check_tightest_limits(
+		&compact,
+		max_key_len,
+		|limits| {
+			let (expanded_root, expanded) =
+				recorded_from_compact_with_limits::<T>(&compact, limits)?;
+			assert_eq!((expanded_root, expanded), (root, recorded.clone()));
+			Ok(())
+		},
+		|err: Box<TrieError<_, _>>| match *err {
+			TrieError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+
+	let proof = proof_from_recorded::<T, _, _, _, _>(&root, &recorded, &keys).unwrap();
+	assert_eq!(proof, generate_proof::<_, T, _, _>(&trie, &keys).unwrap());
+	verify_proof::<T, _, _, _>(&root, &proof, items.iter()).unwrap();
+
+	assert!(compact_from_recorded::<T, _, _>(&root, &recorded[..0]).is_err());
+}
+
+test_layouts!(test_proof_budget, test_proof_budget_internal);
+fn test_proof_budget_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for i in 0..64u8 {
+			trie.insert(&[i, i], &[i; 40]).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let keys: Vec<Vec<u8>> = (0..16u8).map(|i| vec![i * 5, i * 5]).chain(Some(vec![7])).collect();
+
+	let unlimited = generate_proof_with_budget(&trie, &keys, ProofBudget::unlimited()).unwrap();
+	assert_eq!(unlimited.resume_from, None);
+	assert_eq!(unlimited.proof, generate_proof::<_, T, _, _>(&trie, &keys).unwrap());
+
+	// Prove keys in chunks of a few nodes, resuming from the first key left out. The keys differ
+	// in their first byte, so the path of a key holds at most a branch per nibble of that byte
+	// and a leaf.
+	let budget = ProofBudget::new(T::Nibble::NIBBLE_PER_BYTE + 2, usize::MAX);
+	let mut sorted = keys.clone();
+	sorted.sort();
+	let mut remaining = &sorted[..];
+	let mut chunks = 0;
+	while !remaining.is_empty() {
+		let chunk = generate_proof_with_budget(&trie, remaining, budget).unwrap();
+		let proven = match &chunk.resume_from {
+			Some(key) => remaining.iter().position(|k| k == key).unwrap(),
+			None => remaining.len(),
+		};
+		assert!(proven > 0);
+		let items: Vec<_> =
+			remaining[..proven].iter().map(|key| (key, trie.get(key).unwrap())).collect();
+		verify_proof::<T, _, _, _>(&root, &chunk.proof, items.iter()).unwrap();
+		remaining = &remaining[proven..];
+		chunks += 1;
+	}
+	assert!(chunks > 1);
+
+	match generate_proof_with_budget(&trie, &keys, ProofBudget::new(1, usize::MAX)) {
+		Err(err) => assert!(matches!(*err, TrieError::BudgetExceeded(b) if b.max_nodes == 1)),
+		Ok(proof) => panic!("proof unexpectedly fit in budget: {:?}", proof),
+	}
+	assert!(generate_proof_with_budget(&trie, &keys, ProofBudget::new(100, 10)).is_err());
+}
+
+/// Check that `verify` accepts the tightest limits for `proof` and keys of at most `max_key_len`
+/// bytes, and that lowering any of them makes it fail with that limit, as returned by `exceeded`.
+pub(crate) fn check_tightest_limits<E: std::fmt::Debug>(
+	proof: &[Vec<u8>],
+	max_key_len: usize,
+	verify: impl Fn(&VerifyLimits) -> Result<(), E>,
+	exceeded: impl Fn(E) -> Option<ExceededLimit>,
+) {
+	let mut limits = VerifyLimits {
+		max_nodes: proof.len(),
+		max_node_size: proof.iter().map(Vec::len).max().unwrap(),
+		max_key_len,
+		max_depth: 1,
+	};
+	while verify(&limits).is_err() {
+		assert!(limits.max_depth < proof.len() * 2, "{:?}", verify(&limits));
+		limits.max_depth += 1;
+	}
+	assert!(limits.max_depth > 1);
+
+	let lowered = [
+		(
+			VerifyLimits { max_nodes: limits.max_nodes - 1, ..limits },
+			ExceededLimit::Nodes(limits.max_nodes - 1),
+		),
+		(
+			VerifyLimits { max_node_size: limits.max_node_size - 1, ..limits },
+			ExceededLimit::NodeSize(limits.max_node_size - 1),
+		),
+		(
+			VerifyLimits { max_key_len: limits.max_key_len - 1, ..limits },
+			ExceededLimit::KeyLength(limits.max_key_len - 1),
+		),
+		(
+			VerifyLimits { max_depth: limits.max_depth - 1, ..limits },
+			ExceededLimit::Depth(limits.max_depth - 1),
+		),
+	];
+	for (limits, limit) in lowered.iter() {
+		assert_eq!(verify(limits).err().and_then(&exceeded), Some(*limit));
+	}
+}
+
+test_layouts!(test_verify_limits, test_verify_limits_internal);
+fn test_verify_limits_internal<T: TrieLayout>() {
+	let keys: Vec<&[u8]> = vec![b"alfa", b"do", b"dogs", b"horse"];
+	let (root, proof, items) = test_generate_proof::<T>(test_entries(), keys.clone());
+	verify_proof_with_limits::<T, _, _, _>(&root, &proof, items.iter(), &VerifyLimits::unlimited())
+		.unwrap();
+	check_tightest_limits(
+		&proof,
+		5,
+		|limits| verify_proof_with_limits::<T, _, _, _>(&root, &proof, items.iter(), limits),
+		|err| match err {
+			VerifyError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in test_entries() {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let proof = generate_value_proof(&trie, &keys).unwrap();
+	check_tightest_limits(
+		&proof,
+		5,
+		|limits| {
+			verify_value_proof_with_limits::<T, _, _>(&root, &proof, &keys, limits).map(|_| ())
+		},
+		|err| match err {
+			VerifyError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+}
@@ -0,0 +1,189 @@
+// Copyright 2019, 2021 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use parity_scale_codec::{Decode, Encode};
+use reference_trie::{
+	test_layouts, ExtensionLayout, HashedValueNoExt, HashedValueNoExtThreshold, NoExtensionLayout,
+};
+use trie_db::{
+	proof::{
+		generate_proof, layout_tag, verify_proof, EnvelopeError, LayoutTag, ProofEnvelope,
+		ProofKind, ENVELOPE_VERSION,
+	},
+	DBValue, Trie, TrieDB, TrieDBMut, TrieLayout, TrieMut,
+};
+
+type MemoryDB<T> = memory_db::MemoryDB<
+	<T as TrieLayout>::Hash,
+	memory_db::HashKey<<T as TrieLayout>::Hash>,
+	DBValue,
+>;
+
+/// The SCALE type of the binary encoding.
+type ScaleEnvelope = (u8, LayoutTag, u8, Vec<u8>, Vec<Vec<u8>>);
+
+type Items = Vec<(&'static [u8], Option<DBValue>)>;
+
+fn test_envelope<T: TrieLayout>() -> (ProofEnvelope<T>, Items) {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in [(&b"do"[..], &b"verb"[..]), (b"dog", b"puppy"), (b"doge", &[0; 32])] {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let keys: Vec<&'static [u8]> = vec![b"dog", b"dogs"];
+	let proof = generate_proof::<_, T, _, _>(&trie, keys.iter()).unwrap();
+	let items = keys.into_iter().map(|key| (key, trie.get(key).unwrap())).collect();
+	(ProofEnvelope::new(root, ProofKind::Compact, proof), items)
+}
+
+test_layouts!(envelope_roundtrip, envelope_roundtrip_internal);
+fn envelope_roundtrip_internal<T: TrieLayout>() {
+	let (envelope, items) = test_envelope::<T>();
+
+	let encoded = envelope.encode();
+	let decoded = ProofEnvelope::<T>::decode(&encoded).unwrap();
+	assert_eq!(decoded, envelope);
+	verify_proof::<T, _, _, _>(&decoded.root, &decoded.nodes, items.iter()).unwrap();
+
+	let (version, tag, kind, root, nodes) = ScaleEnvelope::decode(&mut &encoded[..]).unwrap();
+	assert_eq!((version, tag, kind), (ENVELOPE_VERSION, layout_tag::<T>(), 0));
+	assert_eq!((&root[..], &nodes), (envelope.root.as_ref(), &envelope.nodes));
+
+	let json = envelope.to_hex_json();
+	assert_eq!(ProofEnvelope::<T>::from_hex_json(&json).unwrap(), envelope);
+	let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+	assert_eq!(value["version"], ENVELOPE_VERSION);
+	assert_eq!(value["kind"], "compact");
+	assert_eq!(value["nodes"].as_array().unwrap().len(), envelope.nodes.len());
+	let pretty = serde_json::to_string_pretty(&value).unwrap();
+	assert_eq!(ProofEnvelope::<T>::from_hex_json(&pretty).unwrap(), envelope);
+
+	for kind in [ProofKind::Eip1186, ProofKind::Range] {
+		let envelope = ProofEnvelope::<T>::new(envelope.root, kind, Vec::new());
+		assert_eq!(ProofEnvelope::<T>::decode(&envelope.encode()).unwrap(), envelope);
+		assert_eq!(ProofEnvelope::<T>::from_hex_json(&envelope.to_hex_json()).unwrap(), envelope);
+	}
+}
+
+#[test]
+fn envelope_layout_tags() {
+	let tags = [
+		layout_tag::<ExtensionLayout>(),
+		layout_tag::<NoExtensionLayout>(),
+		layout_tag::<HashedValueNoExtThreshold>(),
+		layout_tag::<ethereum_trie::EthereumLayout>(),
+		layout_tag::<substrate_trie::LayoutV0<keccak_hasher::KeccakHasher>>(),
+		layout_tag::<substrate_trie::LayoutV1<keccak_hasher::KeccakHasher>>(),
+	];
+	for (i, tag) in tags.iter().enumerate() {
+		assert!(!tags[..i].contains(tag), "duplicate tag {:?} at {}", tag, i);
+	}
+	// Without a threshold, the two layouts encode nodes the same way.
+	assert_eq!(layout_tag::<HashedValueNoExt>(), layout_tag::<NoExtensionLayout>());
+
+	let (envelope, _) = test_envelope::<NoExtensionLayout>();
+	let tag = layout_tag::<NoExtensionLayout>();
+	assert_eq!(
+		ProofEnvelope::<HashedValueNoExtThreshold>::decode(&envelope.encode()),
+		Err(EnvelopeError::LayoutMismatch(tag)),
+	);
+	assert_eq!(
+		ProofEnvelope::<ExtensionLayout>::from_hex_json(&envelope.to_hex_json()),
+		Err(EnvelopeError::LayoutMismatch(tag)),
+	);
+}
+
+#[test]
+fn envelope_decoding_errors() {
+	type L = NoExtensionLayout;
+	let (envelope, _) = test_envelope::<L>();
+	let decode = |scale: ScaleEnvelope| ProofEnvelope::<L>::decode(&scale.encode());
+	let scale: ScaleEnvelope = (
+		ENVELOPE_VERSION,
+		layout_tag::<L>(),
+		0,
+		envelope.root.as_ref().to_vec(),
+		envelope.nodes.clone(),
+	);
+	assert_eq!(decode(scale.clone()), Ok(envelope.clone()));
+
+	// Long nodes take the four byte and big integer length modes.
+	let long_nodes = vec![vec![1; 1 << 14], vec![2; 1 << 6]];
+	let mut long = scale.clone();
+	long.4 = long_nodes.clone();
+	assert_eq!(
+		long.encode(),
+		ProofEnvelope::<L>::new(envelope.root, ProofKind::Compact, long_nodes).encode()
+	);
+	assert_eq!(decode(long).unwrap().nodes[0].len(), 1 << 14);
+
+	let mut bad = scale.clone();
+	bad.0 = ENVELOPE_VERSION + 1;
+	assert_eq!(decode(bad), Err(EnvelopeError::UnsupportedVersion(ENVELOPE_VERSION + 1)));
+	let mut bad = scale.clone();
+	bad.2 = 3;
+	assert_eq!(decode(bad), Err(EnvelopeError::UnknownKind));
+	let mut bad = scale.clone();
+	bad.3.pop();
+	assert_eq!(decode(bad), Err(EnvelopeError::InvalidRootLength(31)));
+
+	let encoded = envelope.encode();
+	for len in 0..encoded.len() {
+		assert_eq!(ProofEnvelope::<L>::decode(&encoded[..len]), Err(EnvelopeError::UnexpectedEnd));
+	}
+	let mut trailing = encoded.clone();
+	trailing.push(0);
+	assert_eq!(ProofEnvelope::<L>::decode(&trailing), Err(EnvelopeError::TrailingData));
+
+	// The root length, 32, in the two byte mode instead of the single byte one.
+	let mut non_canonical = encoded[..10].to_vec();
+	non_canonical.extend_from_slice(&[32 << 2 | 0b01, 0]);
+	non_canonical.extend_from_slice(&encoded[11..]);
+	assert_eq!(ProofEnvelope::<L>::decode(&non_canonical), Err(EnvelopeError::InvalidLength));
+
+	// A node count larger than the input.
+	let mut huge = encoded[..43].to_vec();
+	huge.extend_from_slice(&[0b11, 0xff, 0xff, 0xff, 0xff]);
+	assert_eq!(ProofEnvelope::<L>::decode(&huge), Err(EnvelopeError::UnexpectedEnd));
+
+	let json = envelope.to_hex_json();
+	let from_json = |json: &str| ProofEnvelope::<L>::from_hex_json(json);
+	assert_eq!(
+		from_json(&json.replace("\"compact\"", "\"other\"")),
+		Err(EnvelopeError::UnknownKind)
+	);
+	assert_eq!(
+		from_json(&json.replace("\"version\":1", "\"version\":2")),
+		Err(EnvelopeError::UnsupportedVersion(2))
+	);
+	assert_eq!(
+		from_json(&json.replace("\"root\":\"0x", "\"root\":\"")),
+		Err(EnvelopeError::InvalidHex)
+	);
+	assert_eq!(
+		from_json(&json.replace("\"root\":\"0x", "\"root\":\"0xzz")),
+		Err(EnvelopeError::InvalidHex)
+	);
+	assert_eq!(from_json(&json.replace("\"kind\"", "\"other\"")), Err(EnvelopeError::InvalidJson));
+	assert_eq!(
+		from_json(&json.replace("{", "{\"kind\":\"compact\",")),
+		Err(EnvelopeError::InvalidJson),
+	);
+	assert_eq!(from_json(&json[..json.len() - 1]), Err(EnvelopeError::InvalidJson));
+	assert_eq!(from_json(&format!("{} {{}}", json)), Err(EnvelopeError::TrailingData));
+}
@@ -17,7 +17,7 @@
 use hash_db::Hasher;
 use memory_db::{HashKey, MemoryDB};
 use reference_trie::{RefHasher, RefTrieDB, RefTrieDBMut};
-use trie_db::{Record, Recorder, Trie, TrieMut};
+use trie_db::{ProofBudget, Record, Recorder, Trie, TrieError, TrieMut};
 
 #[test]
 fn basic_recorder() {
@@ -56,6 +56,35 @@ fn basic_recorder_min_depth() {
 	assert_eq!(records[0].clone(), Record { data: node2, hash: hash2, depth: 456 });
 }
 
+#[test]
+fn basic_recorder_budget() {
+	let mut basic = Recorder::with_budget(ProofBudget::new(2, 10));
+
+	let node1 = vec![1, 2, 3, 4];
+	let node2 = vec![4, 5, 6, 7, 8, 9, 10];
+	let node3 = vec![11];
+
+	let (hash1, hash2, hash3) =
+		(RefHasher::hash(&node1), RefHasher::hash(&node2), RefHasher::hash(&node3));
+	basic.record(&hash1, &node1, 0);
+	assert!(!basic.budget_exceeded());
+	basic.record(&hash2, &node2, 1);
+	assert!(basic.budget_exceeded());
+	assert_eq!(basic.check_budget(), Err(ProofBudget::new(2, 10)));
+	// Nothing is recorded once the budget is exceeded, even if it fits.
+	basic.record(&hash3, &node3, 2);
+
+	assert_eq!(basic.drain(), vec![Record { data: node1.clone(), hash: hash1, depth: 0 }]);
+	assert!(!basic.budget_exceeded());
+	assert_eq!(basic.check_budget(), Ok(()));
+
+	basic.record(&hash1, &node1, 0);
+	basic.record(&hash3, &node3, 1);
+	basic.record(&hash3, &node3, 2);
+	assert!(basic.budget_exceeded());
+	assert_eq!(basic.drain().len(), 2);
+}
+
 #[test]
 fn trie_record() {
 	let mut db = MemoryDB::<RefHasher, HashKey<_>, _>::default();
@@ -118,3 +147,34 @@ fn trie_record() {
 		]
 	);
 }
+
+#[test]
+fn trie_record_budget() {
+	let mut db = MemoryDB::<RefHasher, HashKey<_>, _>::default();
+	let mut root = Default::default();
+	{
+		let mut x = RefTrieDBMut::new(&mut db, &mut root);
+		x.insert(b"dog", b"cat").unwrap();
+		x.insert(b"letter", b"confusion").unwrap();
+		x.insert(b"pirate", b"aargh!").unwrap();
+		x.insert(b"yo ho ho", b"and a bottle of rum").unwrap();
+	}
+	let trie = RefTrieDB::new(&db, &root);
+	let mut unlimited = Recorder::new();
+	trie.get_with(b"pirate", &mut unlimited).unwrap().unwrap();
+	let pirate_nodes = unlimited.drain();
+	assert_eq!(pirate_nodes.len(), 2);
+
+	// The lookup of "letter" exceeds the budget and fails, keeping the records of "pirate".
+	let budget = ProofBudget::new(pirate_nodes.len(), usize::MAX);
+	let mut recorder = Recorder::with_budget(budget);
+	trie.get_with(b"pirate", &mut recorder).unwrap().unwrap();
+	match trie.get_with(b"letter", &mut recorder) {
+		Err(err) => assert_eq!(*err, TrieError::BudgetExceeded(budget)),
+		Ok(value) => panic!("lookup unexpectedly fit in budget: {:?}", value),
+	}
+	// Later lookups fail until the records are drained.
+	assert!(trie.get_with(b"pirate", &mut recorder).is_err());
+	assert_eq!(recorder.drain(), pirate_nodes);
+	assert_eq!(trie.get_with(b"dog", &mut recorder).unwrap(), Some(b"cat".to_vec()));
+}
@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use hash_db::Hasher;
 use memory_db::{HashKey, MemoryDB};
 use reference_trie::{RefHasher, RefSecTrieDBMut, RefTrieDB};
-use trie_db::{DBValue, Trie, TrieMut};
+use trie_db::{DBValue, NodeCodec, SecTrieDB, SecTrieDBMut, Trie, TrieConfiguration, TrieMut};
 
 #[test]
 fn sectrie_to_trie() {
@@ -28,3 +30,75 @@ fn sectrie_to_trie() {
 	let t = RefTrieDB::new(&memdb, &root);
 	assert_eq!(t.get(&RefHasher::hash(&[0x01u8, 0x23])).unwrap().unwrap(), vec![0x01u8, 0x23],);
 }
+
+/// Key hasher differing from the node hasher: Keccak-256 of the reversed key.
+#[derive(Debug)]
+struct ReversedKeyHasher;
+
+impl Hasher for ReversedKeyHasher {
+	type Out = [u8; 32];
+	type StdHasher = <RefHasher as Hasher>::StdHasher;
+	const LENGTH: usize = 32;
+
+	fn hash(x: &[u8]) -> Self::Out {
+		let reversed: Vec<u8> = x.iter().rev().cloned().collect();
+		RefHasher::hash(&reversed)
+	}
+}
+
+fn sec_entries() -> Vec<(Vec<u8>, Vec<u8>)> {
+	let mut entries: Vec<_> =
+		(0..100u8).map(|i| (vec![i, i / 3], vec![i; 1 + i as usize % 40])).collect();
+	// Duplicated key, the last value is kept.
+	entries.push((vec![7, 2], b"last".to_vec()));
+	entries
+}
+
+fn sec_trie_root_matches_internal<T: TrieConfiguration>()
+where
+	<T::Hash as Hasher>::Out: Ord,
+{
+	let entries = sec_entries();
+	let mut memdb = MemoryDB::<T::Hash, HashKey<_>, DBValue>::new(T::Codec::empty_node());
+	let mut root = Default::default();
+	{
+		let mut t = SecTrieDBMut::<T>::new(&mut memdb, &mut root);
+		for (k, v) in &entries {
+			t.insert(k, v).unwrap();
+		}
+	}
+	assert_eq!(T::sec_trie_root(entries.clone()), root);
+
+	let mut db = MemoryDB::<T::Hash, HashKey<_>, DBValue>::new(T::Codec::empty_node());
+	assert_eq!(T::sec_trie_build(&mut db, entries.clone()), root);
+	let mut db_with_key_hasher =
+		MemoryDB::<T::Hash, HashKey<_>, DBValue>::new(T::Codec::empty_node());
+	assert_eq!(
+		T::sec_trie_build_with_key_hasher::<T::Hash, _, _, _, _>(
+			&mut db_with_key_hasher,
+			entries.clone()
+		),
+		root
+	);
+	let t = SecTrieDB::<T>::new(&db, &root);
+	assert_eq!(t.get(&[7, 2]).unwrap(), Some(b"last".to_vec()));
+	assert_eq!(t.get(&[8, 2]).unwrap(), Some(vec![8; 9]));
+
+	let hashed: BTreeMap<_, _> = entries
+		.iter()
+		.map(|(k, v)| (ReversedKeyHasher::hash(k).to_vec(), v.clone()))
+		.collect();
+	let other_root =
+		T::sec_trie_root_with_key_hasher::<ReversedKeyHasher, _, _, _>(entries.clone());
+	assert_eq!(other_root, T::trie_root(hashed));
+	assert_ne!(other_root, root);
+}
+
+#[test]
+fn sec_trie_root_matches_sectriedbmut() {
+	sec_trie_root_matches_internal::<reference_trie::ExtensionLayout>();
+	sec_trie_root_matches_internal::<reference_trie::NoExtensionLayout>();
+	sec_trie_root_matches_internal::<ethereum_trie::EthereumLayout>();
+	sec_trie_root_matches_internal::<substrate_trie::LayoutV0<RefHasher>>();
+	sec_trie_root_matches_internal::<substrate_trie::LayoutV1<RefHasher>>();
+}
@@ -14,9 +14,15 @@
 
 use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
 use reference_trie::test_layouts;
+use std::{collections::BTreeMap, io::ErrorKind};
 use trie_db::{
-	decode_compact, encode_compact, DBValue, Recorder, Trie, TrieDB, TrieDBMut, TrieError,
-	TrieLayout, TrieMut,
+	decode_compact, decode_compact_from_reader, decode_compact_from_reader_with_limits,
+	decode_compact_with_limits, decode_compact_with_values, decode_compact_with_values_with_limits,
+	encode_compact, encode_compact_to_writer, encode_compact_with_budget,
+	encode_compact_without_values,
+	proof::{ExceededLimit, VerifyLimits},
+	DBValue, ProofBudget, Recorder, StreamError, Trie, TrieDB, TrieDBMut, TrieError, TrieLayout,
+	TrieMut,
 };
 
 type MemoryDB<T> = memory_db::MemoryDB<
@@ -24,6 +30,11 @@ type MemoryDB<T> = memory_db::MemoryDB<
 	memory_db::HashKey<<T as TrieLayout>::Hash>,
 	DBValue,
 >;
+type PrefixedMemoryDB<T> = memory_db::MemoryDB<
+	<T as TrieLayout>::Hash,
+	memory_db::PrefixedKey<<T as TrieLayout>::Hash>,
+	DBValue,
+>;
 
 fn test_encode_compact<L: TrieLayout>(
 	entries: Vec<(&'static [u8], &'static [u8])>,
@@ -138,3 +149,276 @@ fn trie_decoding_fails_with_incomplete_database_internal<T: TrieLayout>() {
 		_ => panic!("decode was unexpectedly successful"),
 	}
 }
+
+test_layouts!(trie_compact_encoding_budget, trie_compact_encoding_budget_internal);
+fn trie_compact_encoding_budget_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for i in 0..64u8 {
+			trie.insert(&[i, i], &[i; 40]).unwrap();
+		}
+	}
+	let trie = <TrieDB<T>>::new(&db, &root);
+	let encoded = encode_compact::<T>(&trie).unwrap();
+	let size = encoded.iter().map(Vec::len).sum::<usize>();
+
+	let budget = ProofBudget::new(encoded.len(), usize::MAX);
+	assert_eq!(encode_compact_with_budget::<T>(&trie, budget).unwrap(), encoded);
+	for budget in [ProofBudget::new(encoded.len() - 1, usize::MAX), ProofBudget::new(1000, size)] {
+		match encode_compact_with_budget::<T>(&trie, budget) {
+			Err(err) => assert!(matches!(*err, TrieError::BudgetExceeded(b) if b == budget)),
+			Ok(_) => panic!("encoding unexpectedly fit in {:?}", budget),
+		}
+	}
+}
+
+test_layouts!(trie_decoding_limits, trie_decoding_limits_internal);
+fn trie_decoding_limits_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for i in 0..64u8 {
+			trie.insert(&[i, i, i], &[i; 40]).unwrap();
+		}
+	}
+	let encoded = encode_compact::<T>(&TrieDB::<T>::new(&db, &root)).unwrap();
+
+	crate::proof::check_tightest_limits(
+		&encoded,
+		3,
+		|limits| {
+			let mut db = <MemoryDB<T>>::default();
+			let (decoded_root, _) = decode_compact_with_limits::<T, _>(&mut db, &encoded, limits)?;
+			assert_eq!(decoded_root, root);
+			Ok(())
+		},
+		|err: Box<TrieError<_, _>>| match *err {
+			TrieError::LimitExceeded(limit) => Some(limit),
+			_ => None,
+		},
+	);
+}
+
+fn frame_items(items: &[Vec<u8>]) -> Vec<u8> {
+	use parity_scale_codec::{Compact, Encode};
+
+	let mut framed = Vec::new();
+	for item in items {
+		Compact(item.len() as u32).encode_to(&mut framed);
+		framed.extend_from_slice(item);
+	}
+	framed
+}
+
+test_layouts!(trie_compact_stream, trie_compact_stream_internal);
+fn trie_compact_stream_internal<T: TrieLayout>() {
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for i in 0..64u8 {
+			trie.insert(&[i, i], &[i; 40]).unwrap();
+		}
+		trie.insert(b"do", b"verb").unwrap();
+		trie.insert(b"dog", b"puppy").unwrap();
+	}
+
+	// A partial trie, with the nodes on the path to some keys.
+	let mut recorder = Recorder::new();
+	{
+		let trie = <TrieDB<T>>::new(&db, &root);
+		for key in [&b"dog"[..], &[3, 3], &[40, 41]] {
+			trie.get_with(key, &mut recorder).unwrap();
+		}
+	}
+	let mut partial_db = <MemoryDB<T>>::default();
+	for record in recorder.drain() {
+		partial_db.insert(EMPTY_PREFIX, &record.data);
+	}
+
+	for db in [&db, &partial_db] {
+		let trie = <TrieDB<T>>::new(db, &root);
+		let encoded = encode_compact::<T>(&trie).unwrap();
+		let mut stream = Vec::new();
+		assert_eq!(encode_compact_to_writer::<T, _>(&trie, &mut stream).unwrap(), encoded.len());
+		assert_eq!(stream, frame_items(&encoded));
+
+		// Data after the trie is not read.
+		stream.extend_from_slice(b"trailing");
+		let mut reader = &stream[..];
+		let mut decoded_db = <MemoryDB<T>>::default();
+		let decoded = decode_compact_from_reader::<T, _, _>(&mut decoded_db, &mut reader).unwrap();
+		assert_eq!(decoded, (root, encoded.len()));
+		assert_eq!(reader, b"trailing");
+		let mut expected_db = <MemoryDB<T>>::default();
+		decode_compact::<T, _>(&mut expected_db, &encoded).unwrap();
+		assert_eq!(decoded_db.keys(), expected_db.keys());
+
+		// The stream ends between two items.
+		let framed = frame_items(&encoded[..encoded.len() - 1]);
+		match decode_compact_from_reader::<T, _, _>(&mut <MemoryDB<T>>::default(), &mut &framed[..])
+		{
+			Err(StreamError::Trie(err)) =>
+				assert!(matches!(*err, TrieError::IncompleteDatabase(_))),
+			_ => panic!("decoding an incomplete trie unexpectedly succeeded"),
+		}
+
+		// The stream ends within an item.
+		let framed = &stream[..stream.len() - b"trailing".len() - 1];
+		match decode_compact_from_reader::<T, _, _>(&mut <MemoryDB<T>>::default(), &mut &framed[..])
+		{
+			Err(StreamError::Io(err)) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+			_ => panic!("decoding a truncated item unexpectedly succeeded"),
+		}
+
+		// Items larger than the limit are rejected before they are read.
+		let max_node_size = encoded.iter().map(Vec::len).max().unwrap() - 1;
+		let limits = VerifyLimits { max_node_size, ..VerifyLimits::unlimited() };
+		match decode_compact_from_reader_with_limits::<T, _, _>(
+			&mut <MemoryDB<T>>::default(),
+			&mut &stream[..],
+			&limits,
+		) {
+			Err(StreamError::Trie(err)) => assert!(matches!(
+				*err,
+				TrieError::LimitExceeded(ExceededLimit::NodeSize(max)) if max == max_node_size
+			)),
+			_ => panic!("decoding unexpectedly fit in {:?}", limits),
+		}
+	}
+}
+
+test_layouts!(trie_compact_encoding_without_values, trie_compact_encoding_without_values_internal);
+fn trie_compact_encoding_without_values_internal<T: TrieLayout>() {
+	let mut values = BTreeMap::new();
+	values.insert(b"do".to_vec(), b"verb".repeat(10));
+	// "dog" is at an inline leaf node, which keeps its value.
+	values.insert(b"dog".to_vec(), b"puppy".to_vec());
+	for i in 0..32u8 {
+		values.insert(vec![i, i], vec![i; 40]);
+	}
+	let mut db = <MemoryDB<T>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = <TrieDBMut<T>>::new(&mut db, &mut root);
+		for (key, value) in values.iter() {
+			trie.insert(key, value).unwrap();
+		}
+	}
+
+	// A partial trie, with the nodes on the path to some keys.
+	let mut recorder = Recorder::new();
+	{
+		let trie = <TrieDB<T>>::new(&db, &root);
+		for key in [&b"dog"[..], &[3, 3], &[4, 5]] {
+			trie.get_with(key, &mut recorder).unwrap();
+		}
+	}
+	let mut partial_db = <MemoryDB<T>>::default();
+	for record in recorder.drain() {
+		partial_db.insert(EMPTY_PREFIX, &record.data);
+	}
+
+	for db in [&db, &partial_db] {
+		let trie = <TrieDB<T>>::new(db, &root);
+		let encoded = encode_compact_without_values::<T>(&trie).unwrap();
+		let size = encoded.iter().map(Vec::len).sum::<usize>();
+		let full_size = encode_compact::<T>(&trie).unwrap().iter().map(Vec::len).sum::<usize>();
+		assert!(size < full_size);
+
+		let mut decoded_db = <MemoryDB<T>>::default();
+		let lookup = |key: &[u8]| values.get(key).cloned();
+		let used = decode_compact_with_values::<T, _, _>(&mut decoded_db, &encoded, &root, lookup)
+			.unwrap();
+		assert_eq!(used, encoded.len());
+		// The decoded trie has the nodes of the trie, and value nodes from the given values.
+		let mut expected_db = <MemoryDB<T>>::default();
+		decode_compact::<T, _>(&mut expected_db, &encode_compact::<T>(&trie).unwrap()).unwrap();
+		for key in expected_db.keys().keys() {
+			assert!(decoded_db.contains(key, EMPTY_PREFIX));
+		}
+		let decoded = <TrieDB<T>>::new(&decoded_db, &root);
+		for key in [&b"do"[..], b"dog", &[3, 3]] {
+			assert_eq!(decoded.get(key).unwrap().as_ref(), values.get(key));
+		}
+		// Nodes keep their prefix when written to `db`.
+		let mut prefixed_db = <PrefixedMemoryDB<T>>::default();
+		decode_compact_with_values::<T, _, _>(&mut prefixed_db, &encoded, &root, lookup).unwrap();
+		let mut expected_db = <PrefixedMemoryDB<T>>::default();
+		decode_compact::<T, _>(&mut expected_db, &encode_compact::<T>(&trie).unwrap()).unwrap();
+		let prefixed_keys = prefixed_db.keys();
+		assert!(expected_db.keys().keys().all(|key| prefixed_keys.contains_key(key)));
+
+		// Values missing from the lookup are reported.
+		let lookup = |key: &[u8]| if key == b"do" { None } else { values.get(key).cloned() };
+		match decode_compact_with_values::<T, _, _>(
+			&mut <MemoryDB<T>>::default(),
+			&encoded,
+			&root,
+			lookup,
+		) {
+			Err(err) => assert!(matches!(*err, TrieError::MissingValue(ref key) if key == b"do")),
+			Ok(_) => panic!("decoding unexpectedly succeeded without a value"),
+		}
+
+		// Wrong values give another root.
+		let lookup = |key: &[u8]| {
+			if key == b"do" {
+				Some(b"noun".to_vec())
+			} else {
+				values.get(key).cloned()
+			}
+		};
+		let mut rejected_db = <MemoryDB<T>>::default();
+		match decode_compact_with_values::<T, _, _>(&mut rejected_db, &encoded, &root, lookup) {
+			Err(err) => assert!(matches!(*err, TrieError::InvalidStateRoot(r) if r != root)),
+			Ok(_) => panic!("decoding unexpectedly succeeded with a wrong value"),
+		}
+		// Nodes of the rejected trie are not written.
+		assert!(rejected_db.keys().is_empty());
+
+		// Limits are checked before any node is written.
+		let lookup = |key: &[u8]| values.get(key).cloned();
+		let max_node_size = encoded.iter().map(Vec::len).max().unwrap();
+		let fitting =
+			VerifyLimits { max_nodes: encoded.len(), max_node_size, ..VerifyLimits::unlimited() };
+		let lowered = [
+			(
+				VerifyLimits { max_nodes: encoded.len() - 1, ..fitting },
+				ExceededLimit::Nodes(encoded.len() - 1),
+			),
+			(
+				VerifyLimits { max_node_size: max_node_size - 1, ..fitting },
+				ExceededLimit::NodeSize(max_node_size - 1),
+			),
+			(VerifyLimits { max_depth: 1, ..fitting }, ExceededLimit::Depth(1)),
+		];
+		let mut limited_db = <MemoryDB<T>>::default();
+		decode_compact_with_values_with_limits::<T, _, _>(
+			&mut limited_db,
+			&encoded,
+			&root,
+			lookup,
+			&fitting,
+		)
+		.unwrap();
+		assert!(!limited_db.keys().is_empty());
+		for (limits, exceeded) in lowered {
+			let mut limited_db = <MemoryDB<T>>::default();
+			match decode_compact_with_values_with_limits::<T, _, _>(
+				&mut limited_db,
+				&encoded,
+				&root,
+				lookup,
+				&limits,
+			) {
+				Err(err) => assert!(matches!(*err, TrieError::LimitExceeded(l) if l == exceeded)),
+				Ok(_) => panic!("decoding unexpectedly fit in {:?}", limits),
+			}
+			assert!(limited_db.keys().is_empty());
+		}
+	}
+}
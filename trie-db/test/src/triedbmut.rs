@@ -21,6 +21,7 @@ use reference_trie::{
 	HashedValueNoExt, HashedValueNoExtThreshold, NoExtensionLayout, RefHasher, ReferenceNodeCodec,
 	ReferenceNodeCodecNoExt,
 };
+use std::collections::BTreeMap;
 use trie_db::{DBValue, NodeCodec, TrieDBMut, TrieError, TrieLayout, TrieMut, Value};
 use trie_standardmap::*;
 
@@ -650,3 +651,120 @@ fn register_proof_without_value() {
 		));
 	}
 }
+
+test_layouts!(journaled_pruning, journaled_pruning_internal);
+fn journaled_pruning_internal<T: TrieLayout>() {
+	use memory_db::JournalDB;
+	use trie_db::{Trie, TrieDB};
+
+	let mut db = JournalDB::new(PrefixedMemoryDB::<T>::default(), 2);
+	let mut roots = Vec::new();
+	let mut root = Default::default();
+	for era in 0u8..6 {
+		{
+			let mut t = if era == 0 {
+				TrieDBMut::<T>::new(&mut db, &mut root)
+			} else {
+				TrieDBMut::<T>::from_existing(&mut db, &mut root)
+			};
+			t.insert(b"counter", &[era; 40]).unwrap();
+			t.insert(&[era], &[era; 8]).unwrap();
+		}
+		db.journal_under(era as u64, root);
+		roots.push(root);
+		if era >= 2 {
+			db.mark_canonical(era as u64 - 2, &roots[era as usize - 2]).unwrap();
+		}
+	}
+
+	// The last `history_size + 1` states are readable, older ones are pruned.
+	for (era, root) in roots.iter().enumerate() {
+		let trie = TrieDB::<T>::new(&db, root);
+		let value = trie.get(b"counter");
+		if era >= 3 {
+			assert_eq!(value.unwrap(), Some(vec![era as u8; 40]));
+		} else {
+			assert!(value.is_err());
+		}
+	}
+
+	// A competing block is reverted when its era is canonicalized.
+	let mut fork_root = roots[4];
+	{
+		let mut t = TrieDBMut::<T>::from_existing(&mut db, &mut fork_root);
+		t.insert(b"counter", &[0xff; 40]).unwrap();
+	}
+	db.journal_under(5, fork_root);
+	assert_eq!(TrieDB::<T>::new(&db, &fork_root).get(b"counter").unwrap(), Some(vec![0xff; 40]));
+	db.journal_under(6, roots[5]);
+	db.journal_under(7, roots[5]);
+	db.mark_canonical(4, &roots[4]).unwrap();
+	db.mark_canonical(5, &roots[5]).unwrap();
+	assert!(TrieDB::<T>::new(&db, &fork_root).get(b"counter").is_err());
+	assert_eq!(TrieDB::<T>::new(&db, &roots[5]).get(b"counter").unwrap(), Some(vec![5; 40]));
+}
+
+test_layouts!(remove_prefix, remove_prefix_internal);
+fn remove_prefix_internal<T: TrieLayout>() {
+	let mut entries = BTreeMap::new();
+	for a in 0..3u8 {
+		entries.insert(vec![a], vec![a; 2]);
+		for b in 0..3u8 {
+			entries.insert(vec![a, b], vec![a; 16 * (b as usize + 1)]);
+			entries.insert(vec![a, b, 0x12, a], vec![b; 4]);
+		}
+	}
+	let entries: Vec<_> = entries.into_iter().collect();
+
+	let prefixes: [&[u8]; 7] =
+		[&[], &[1], &[1, 1], &[1, 1, 0x12], &[1, 1, 0x13], &[5], &[2, 0, 0x12]];
+	for prefix in prefixes {
+		let matching: Vec<_> = entries
+			.iter()
+			.map(|(key, _)| key)
+			.filter(|key| key.starts_with(prefix))
+			.collect();
+		let limits = (0..=matching.len()).map(Some).chain(std::iter::once(None));
+		for limit in limits {
+			let mut db = PrefixedMemoryDB::<T>::default();
+			let mut root = Default::default();
+			populate_trie::<T>(&mut db, &mut root, &entries);
+			let removal = TrieDBMut::<T>::from_existing(&mut db, &mut root)
+				.remove_prefix(prefix, limit)
+				.unwrap();
+
+			let removed = limit.map_or(matching.len(), |limit| limit.min(matching.len()));
+			assert_eq!(removal.removed, removed);
+			assert_eq!(removal.resume_from.as_ref(), matching.get(removed).copied());
+			assert_eq!(removal.all_removed(), removed == matching.len());
+
+			let remaining: Vec<_> = entries
+				.iter()
+				.filter(|(key, _)| !matching[..removed].contains(&key))
+				.cloned()
+				.collect();
+			assert_eq!(root, reference_trie_root::<T, _, _, _>(remaining.clone()));
+
+			// The nodes of the removed keys are pruned from the database.
+			let mut expected_db = PrefixedMemoryDB::<T>::default();
+			let mut expected_root = Default::default();
+			populate_trie::<T>(&mut expected_db, &mut expected_root, &remaining);
+			db.purge();
+			expected_db.purge();
+			assert_eq!(db.keys(), expected_db.keys());
+		}
+	}
+
+	// Removing in steps from uncommitted nodes ends as a single removal.
+	let mut db = PrefixedMemoryDB::<T>::default();
+	let mut root = Default::default();
+	let mut t = populate_trie::<T>(&mut db, &mut root, &entries);
+	let mut calls = 1;
+	while !t.remove_prefix(&[1], Some(2)).unwrap().all_removed() {
+		calls += 1;
+	}
+	// Seven keys start with the prefix.
+	assert_eq!(calls, 4);
+	let remaining = entries.iter().filter(|(key, _)| !key.starts_with(&[1])).cloned();
+	assert_eq!(*t.root(), reference_trie_root::<T, _, _, _>(remaining));
+}
@@ -1,7 +1,9 @@
 use crate::rstd::{result::Result, vec::Vec};
 use hash_db::Hasher;
 use trie_db::{
-	node::{decode_hash, Node, NodeHandle, Value},
+	nibble_ops::NibbleOps,
+	node::{decode_hash, Children, Node, NodeHandle, Value},
+	proof::{ExceededLimit, VerifyLimits},
 	recorder::Recorder,
 	CError, NibbleSlice, NodeCodec, Result as TrieResult, Trie, TrieHash, TrieLayout,
 };
@@ -26,10 +28,10 @@ where
 /// be used for debugging.
 #[derive(PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(Debug))]
-pub enum VerifyError<'a, HO, CE> {
+pub enum VerifyError<'a, HO, CE, N: NibbleOps> {
 	/// The proof does not contain any value for the given key
 	/// the error carries the nibbles left after traversing the trie
-	NonExistingValue(NibbleSlice<'a>),
+	NonExistingValue(NibbleSlice<'a, N>),
 	/// The proof contains a value for the given key
 	/// while we were expecting to find a non-existence proof
 	ExistingValue(Vec<u8>),
@@ -44,10 +46,16 @@ pub enum VerifyError<'a, HO, CE> {
 	DecodeError(CE),
 	/// Error in converting a plain hash into a HO
 	HashDecodeError(&'a [u8]),
+	/// The proof contains nodes after the ones needed to look up the key.
+	ExtraneousNode,
+	/// The proof exceeds one of the verification limits.
+	LimitExceeded(ExceededLimit),
 }
 
 #[cfg(feature = "std")]
-impl<'a, HO: std::fmt::Debug, CE: std::error::Error> std::fmt::Display for VerifyError<'a, HO, CE> {
+impl<'a, HO: std::fmt::Debug, CE: std::error::Error, N: NibbleOps> std::fmt::Display
+	for VerifyError<'a, HO, CE, N>
+{
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
 		match self {
 			VerifyError::NonExistingValue(key) => {
@@ -65,13 +73,15 @@ impl<'a, HO: std::fmt::Debug, CE: std::error::Error> std::fmt::Display for Verif
 			VerifyError::HashDecodeError(plain_hash) => {
 				write!(f, "Unable to decode hash value plain_hash: {:?}", plain_hash)
 			},
+			VerifyError::ExtraneousNode => write!(f, "Extraneous node found in proof"),
+			VerifyError::LimitExceeded(limit) => write!(f, "Proof exceeds limit: {}", limit),
 		}
 	}
 }
 
 #[cfg(feature = "std")]
-impl<'a, HO: std::fmt::Debug, CE: std::error::Error + 'static> std::error::Error
-	for VerifyError<'a, HO, CE>
+impl<'a, HO: std::fmt::Debug, CE: std::error::Error + 'static, N: NibbleOps> std::error::Error
+	for VerifyError<'a, HO, CE, N>
 {
 	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
 		match self {
@@ -87,195 +97,195 @@ pub fn verify_proof<'a, L>(
 	proof: &'a [Vec<u8>],
 	raw_key: &'a [u8],
 	expected_value: Option<&[u8]>,
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+) -> VerifyResult<'a, L, ()>
 where
 	L: TrieLayout,
 {
-	if proof.is_empty() {
-		return Err(VerifyError::IncompleteProof)
-	}
-	let key = NibbleSlice::new(raw_key);
-	process_node::<L>(Some(root), &proof[0], key, expected_value, &proof[1..])
+	verify_proof_with_limits::<L>(root, proof, raw_key, expected_value, &VerifyLimits::unlimited())
 }
 
-fn process_node<'a, L>(
-	expected_node_hash: Option<&<L::Hash as Hasher>::Out>,
-	encoded_node: &'a [u8],
-	key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
+type VerifyResult<'a, L, T> =
+	Result<T, VerifyError<'a, TrieHash<L>, CError<L>, <L as TrieLayout>::Nibble>>;
+
+/// Verify a proof as `verify_proof`, failing with `VerifyError::LimitExceeded` if the proof or
+/// `raw_key` exceed `limits`.
+pub fn verify_proof_with_limits<'a, L>(
+	root: &<L::Hash as Hasher>::Out,
 	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+	raw_key: &'a [u8],
+	expected_value: Option<&[u8]>,
+	limits: &VerifyLimits,
+) -> VerifyResult<'a, L, ()>
 where
 	L: TrieLayout,
 {
-	if let Some(value) = expected_value {
-		if encoded_node == value {
-			return Ok(())
-		}
-	}
-	if let Some(expected) = expected_node_hash {
-		let calculated_node_hash = <L::Hash as Hasher>::hash(encoded_node);
-		if calculated_node_hash != *expected {
-			return Err(VerifyError::HashMismatch(calculated_node_hash))
-		}
-	}
-	let node = <L::Codec as NodeCodec>::decode(encoded_node).map_err(VerifyError::DecodeError)?;
-	match node {
-		Node::Empty => process_empty::<L>(key, expected_value, proof),
-		Node::Leaf(nib, data) => process_leaf::<L>(nib, data, key, expected_value, proof),
-		Node::Extension(nib, handle) =>
-			process_extension::<L>(&nib, handle, key, expected_value, proof),
-		Node::Branch(children, maybe_data) =>
-			process_branch::<L>(children, maybe_data, key, expected_value, proof),
-		Node::NibbledBranch(nib, children, maybe_data) =>
-			process_nibbledbranch::<L>(nib, children, maybe_data, key, expected_value, proof),
-	}
+	check_limits(proof, raw_key, limits).map_err(VerifyError::LimitExceeded)?;
+	let Lookup { value, key, rest } = process_node::<L>(root, proof, raw_key, limits)?;
+	match_value::<L>(value, key, expected_value, rest)
 }
 
-fn process_empty<'a, L>(
-	key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
-	_: &[Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
-where
-	L: TrieLayout,
-{
-	if expected_value.is_none() {
-		Ok(())
-	} else {
-		Err(VerifyError::NonExistingValue(key))
-	}
+fn check_limits(
+	proof: &[Vec<u8>],
+	raw_key: &[u8],
+	limits: &VerifyLimits,
+) -> Result<(), ExceededLimit> {
+	limits.check_proof(proof)?;
+	limits.check_key_len(raw_key.len())
 }
 
-fn process_leaf<'a, L>(
-	nib: NibbleSlice,
-	data: Value<'a>,
-	key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
+/// Verify a proof generated by `generate_proof` for `raw_key` and return the proven value, or
+/// `None` if the key is proven absent.
+///
+/// Unlike `verify_proof`, the proof must not contain nodes past the ones on the path of the key.
+pub fn verify_proof_value<'a, L>(
+	root: &<L::Hash as Hasher>::Out,
 	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+	raw_key: &'a [u8],
+) -> VerifyResult<'a, L, Option<Vec<u8>>>
 where
 	L: TrieLayout,
 {
-	if key != nib && expected_value.is_none() {
-		return Ok(())
-	} else if key != nib {
-		return Err(VerifyError::NonExistingValue(key))
-	}
-	match_value::<L>(Some(data), key, expected_value, proof)
+	verify_proof_value_with_limits::<L>(root, proof, raw_key, &VerifyLimits::unlimited())
 }
-fn process_extension<'a, L>(
-	nib: &NibbleSlice,
-	handle: NodeHandle<'a>,
-	mut key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
+
+/// Verify a proof as `verify_proof_value`, failing with `VerifyError::LimitExceeded` if the proof
+/// or `raw_key` exceed `limits`.
+pub fn verify_proof_value_with_limits<'a, L>(
+	root: &<L::Hash as Hasher>::Out,
 	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+	raw_key: &'a [u8],
+	limits: &VerifyLimits,
+) -> VerifyResult<'a, L, Option<Vec<u8>>>
 where
 	L: TrieLayout,
 {
-	if !key.starts_with(nib) && expected_value.is_none() {
-		return Ok(())
-	} else if !key.starts_with(nib) {
-		return Err(VerifyError::NonExistingValue(key))
-	}
-	key.advance(nib.len());
-
-	match handle {
-		NodeHandle::Inline(encoded_node) =>
-			process_node::<L>(None, encoded_node, key, expected_value, proof),
-		NodeHandle::Hash(plain_hash) => {
-			let new_root = decode_hash::<L::Hash>(plain_hash)
+	check_limits(proof, raw_key, limits).map_err(VerifyError::LimitExceeded)?;
+	let Lookup { value, mut rest, .. } = process_node::<L>(root, proof, raw_key, limits)?;
+	let value = match value {
+		None => None,
+		Some(Value::Inline(value)) => Some(value.to_vec()),
+		Some(Value::Node(plain_hash, _)) => {
+			let value_hash = decode_hash::<L::Hash>(plain_hash)
 				.ok_or(VerifyError::HashDecodeError(plain_hash))?;
-			process_node::<L>(Some(&new_root), &proof[0], key, expected_value, &proof[1..])
+			let (value, others) = rest.split_first().ok_or(VerifyError::IncompleteProof)?;
+			let calculated_value_hash = <L::Hash as Hasher>::hash(value);
+			if calculated_value_hash != value_hash {
+				return Err(VerifyError::HashMismatch(calculated_value_hash))
+			}
+			rest = others;
+			Some(value.to_vec())
 		},
+	};
+	if !rest.is_empty() {
+		return Err(VerifyError::ExtraneousNode)
 	}
+	Ok(value)
 }
 
-fn process_nibbledbranch<'a, L>(
-	nib: NibbleSlice,
-	children: [Option<NodeHandle<'a>>; 16],
-	maybe_data: Option<Value<'a>>,
-	mut key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
-	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
-where
-	L: TrieLayout,
-{
-	if !key.starts_with(&nib) && expected_value.is_none() {
-		return Ok(())
-	} else if !key.starts_with(&nib) && expected_value.is_some() {
-		return Err(VerifyError::NonExistingValue(key))
-	}
-	key.advance(nib.len());
-
-	if key.is_empty() {
-		match_value::<L>(maybe_data, key, expected_value, proof)
-	} else {
-		match_children::<L>(children, key, expected_value, proof)
-	}
+/// End of the lookup of a key in the proof nodes.
+struct Lookup<'a, N> {
+	/// Value at the key, `None` if the key is proven absent.
+	value: Option<Value<'a>>,
+	/// Nibbles of the key left when the lookup ended.
+	key: NibbleSlice<'a, N>,
+	/// Proof items after the nodes on the path of the key.
+	rest: &'a [Vec<u8>],
 }
 
-fn process_branch<'a, L>(
-	children: [Option<NodeHandle<'a>>; 16],
-	maybe_data: Option<Value<'a>>,
-	key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
+/// Look up `raw_key` through the proof nodes, starting from the node of hash `root`.
+///
+/// Each decoded node, including inline ones, counts towards the depth limit.
+fn process_node<'a, L>(
+	root: &<L::Hash as Hasher>::Out,
 	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+	raw_key: &'a [u8],
+	limits: &VerifyLimits,
+) -> VerifyResult<'a, L, Lookup<'a, L::Nibble>>
 where
 	L: TrieLayout,
 {
+	let mut key = NibbleSlice::new(raw_key);
+	let mut rest = proof;
+	let mut expected_node_hash = *root;
+	let mut depth = 0;
+	let value = 'nodes: loop {
+		let (encoded_node, others) = rest.split_first().ok_or(VerifyError::IncompleteProof)?;
+		rest = others;
+		let calculated_node_hash = <L::Hash as Hasher>::hash(encoded_node);
+		if calculated_node_hash != expected_node_hash {
+			return Err(VerifyError::HashMismatch(calculated_node_hash))
+		}
+		// Iterates through inline children.
+		let mut encoded_node = &encoded_node[..];
+		loop {
+			depth += 1;
+			limits.check_depth(depth).map_err(VerifyError::LimitExceeded)?;
+			let node =
+				<L::Codec as NodeCodec>::decode(encoded_node).map_err(VerifyError::DecodeError)?;
+			let child = match node {
+				Node::Empty => break 'nodes None,
+				Node::Leaf(nib, value) => break 'nodes if key == nib { Some(value) } else { None },
+				Node::Extension(nib, child) => {
+					if !key.starts_with(&nib) {
+						break 'nodes None
+					}
+					key.advance(nib.len());
+					Ok(child)
+				},
+				Node::Branch(children, value) => match_children(children, value, &mut key),
+				Node::NibbledBranch(nib, children, value) => {
+					if !key.starts_with(&nib) {
+						break 'nodes None
+					}
+					key.advance(nib.len());
+					match_children(children, value, &mut key)
+				},
+			};
+			match child {
+				Ok(NodeHandle::Inline(data)) => encoded_node = data,
+				Ok(NodeHandle::Hash(plain_hash)) => {
+					expected_node_hash = decode_hash::<L::Hash>(plain_hash)
+						.ok_or(VerifyError::HashDecodeError(plain_hash))?;
+					continue 'nodes
+				},
+				Err(value) => break 'nodes value,
+			}
+		}
+	};
+	Ok(Lookup { value, key, rest })
+}
+
+/// Follow the child of a branch at the first nibble of `key`, or return the value of the
+/// branch if `key` ends there.
+fn match_children<'a, N: NibbleOps>(
+	children: Children<N, Option<NodeHandle<'a>>>,
+	value: Option<Value<'a>>,
+	key: &mut NibbleSlice<'a, N>,
+) -> Result<NodeHandle<'a>, Option<Value<'a>>> {
 	if key.is_empty() {
-		match_value::<L>(maybe_data, key, expected_value, proof)
-	} else {
-		match_children::<L>(children, key, expected_value, proof)
+		return Err(value)
 	}
-}
-fn match_children<'a, L>(
-	children: [Option<NodeHandle<'a>>; 16],
-	mut key: NibbleSlice<'a>,
-	expected_value: Option<&[u8]>,
-	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
-where
-	L: TrieLayout,
-{
-	match children.get(key.at(0) as usize) {
-		Some(Some(NodeHandle::Hash(hash))) =>
-			if proof.is_empty() {
-				Err(VerifyError::IncompleteProof)
-			} else {
-				key.advance(1);
-				let new_root =
-					decode_hash::<L::Hash>(hash).ok_or(VerifyError::HashDecodeError(hash))?;
-				process_node::<L>(Some(&new_root), &proof[0], key, expected_value, &proof[1..])
-			},
-		Some(Some(NodeHandle::Inline(encoded_node))) => {
+	match children[key.at(0) as usize] {
+		Some(child) => {
 			key.advance(1);
-			process_node::<L>(None, encoded_node, key, expected_value, proof)
+			Ok(child)
 		},
-		Some(None) =>
-			if expected_value.is_none() {
-				Ok(())
-			} else {
-				Err(VerifyError::NonExistingValue(key))
-			},
-		None => panic!("key index is out of range in children array"),
+		None => Err(None),
 	}
 }
 
+/// Check the value found by the lookup against `expected_value`, the value of a hashed value
+/// node being the first of the `rest` of the proof items.
 fn match_value<'a, L>(
 	maybe_data: Option<Value<'a>>,
-	key: NibbleSlice<'a>,
+	key: NibbleSlice<'a, L::Nibble>,
 	expected_value: Option<&[u8]>,
-	proof: &'a [Vec<u8>],
-) -> Result<(), VerifyError<'a, TrieHash<L>, CError<L>>>
+	rest: &'a [Vec<u8>],
+) -> VerifyResult<'a, L, ()>
 where
 	L: TrieLayout,
 {
-	match (maybe_data, proof.first(), expected_value) {
+	match (maybe_data, rest.first(), expected_value) {
 		(None, _, None) => Ok(()),
 		(None, _, Some(_)) => Err(VerifyError::NonExistingValue(key)),
 		(Some(Value::Inline(inline_data)), _, Some(value)) =>
@@ -27,4 +27,7 @@ mod rstd {
 }
 
 mod eip1186;
-pub use eip1186::{generate_proof, verify_proof, VerifyError};
+pub use eip1186::{
+	generate_proof, verify_proof, verify_proof_value, verify_proof_value_with_limits,
+	verify_proof_with_limits, VerifyError,
+};
@@ -14,8 +14,14 @@
 
 use hash_db::Hasher;
 use reference_trie::test_layouts;
-use trie_db::{DBValue, TrieDB, TrieDBMut, TrieLayout, TrieMut};
-use trie_eip1186::{generate_proof, verify_proof, VerifyError};
+use trie_db::{
+	proof::{ExceededLimit, VerifyLimits},
+	DBValue, TrieDB, TrieDBMut, TrieLayout, TrieMut,
+};
+use trie_eip1186::{
+	generate_proof, verify_proof, verify_proof_value, verify_proof_value_with_limits,
+	verify_proof_with_limits, VerifyError,
+};
 
 type MemoryDB<T> = memory_db::MemoryDB<
 	<T as TrieLayout>::Hash,
@@ -161,6 +167,20 @@ fn test_verify_value_mismatch_some_to_none_internal<T: TrieLayout>() {
 	assert!(is_ok);
 }
 
+test_layouts!(test_verify_value_as_proof_item, test_verify_value_as_proof_item_internal);
+fn test_verify_value_as_proof_item_internal<T: TrieLayout>() {
+	let (root, mut proof, _) = test_generate_proof::<T>(test_entries(), b"horse");
+
+	// A proof item equal to the expected value is decoded and hash checked as any other node,
+	// it does not prove the value on its own.
+	let value_only = vec![b"stallion".to_vec()];
+	let res = verify_proof::<T>(&root, &value_only, b"horse", Some(b"stallion"));
+	assert!(matches!(res, Err(VerifyError::HashMismatch(_))));
+	proof.insert(0, b"stallion".to_vec());
+	let res = verify_proof::<T>(&root, &proof, b"horse", Some(b"stallion"));
+	assert!(matches!(res, Err(VerifyError::HashMismatch(_))));
+}
+
 test_layouts!(test_verify_incomplete_proof, test_verify_incomplete_proof_internal);
 fn test_verify_incomplete_proof_internal<T: TrieLayout>() {
 	let (root, mut proof, item) = test_generate_proof::<T>(test_entries(), b"alfa");
@@ -180,3 +200,79 @@ fn test_verify_decode_error_internal<T: TrieLayout>() {
 	let res = verify_proof::<T>(&fake_root, &proof, b"bravo", item.as_deref());
 	assert!(matches!(res, Err(VerifyError::DecodeError(_))));
 }
+
+test_layouts!(test_verify_proof_value, test_verify_proof_value_internal);
+fn test_verify_proof_value_internal<T: TrieLayout>() {
+	let keys: [&[u8]; 9] =
+		[b"alfa", b"bravo", b"do", b"dog", b"doge", b"horse", b"house", b"halp", b"do\x10"];
+	for key in keys {
+		let (root, proof, item) = test_generate_proof::<T>(test_entries(), key);
+		assert_eq!(verify_proof_value::<T>(&root, &proof, key).unwrap(), item);
+
+		let mut extended = proof.clone();
+		extended.push(proof[0].clone());
+		let res = verify_proof_value::<T>(&root, &extended, key);
+		assert!(matches!(res, Err(VerifyError::ExtraneousNode)));
+
+		let res = verify_proof_value::<T>(&root, &proof[..proof.len() - 1], key);
+		assert!(matches!(res, Err(VerifyError::IncompleteProof)));
+	}
+
+	let (root, proof, item) = test_generate_proof::<T>(vec![], b"alfa");
+	assert!(item.is_none());
+	assert_eq!(verify_proof_value::<T>(&root, &proof, b"alfa").unwrap(), None);
+
+	let (_, proof, _) = test_generate_proof::<T>(test_entries(), b"horse");
+	let other_root = T::Hash::hash(b"other");
+	let res = verify_proof_value::<T>(&other_root, &proof, b"horse");
+	assert!(matches!(res, Err(VerifyError::HashMismatch(_))));
+}
+
+test_layouts!(test_verify_limits, test_verify_limits_internal);
+fn test_verify_limits_internal<T: TrieLayout>() {
+	// Nodes of this trie are small enough to be inlined in the root, unless values are hashed.
+	let inline_entries: Vec<(&'static [u8], &'static [u8])> = vec![(b"a", b"1"), (b"b", b"2")];
+	for (entries, key) in [(test_entries(), &b"doge"[..]), (inline_entries, b"a")] {
+		let (root, proof, item) = test_generate_proof::<T>(entries, key);
+		let mut limits = VerifyLimits {
+			max_nodes: proof.len(),
+			max_node_size: proof.iter().map(Vec::len).max().unwrap(),
+			max_key_len: key.len(),
+			max_depth: 1,
+		};
+		let verify =
+			|limits| verify_proof_with_limits::<T>(&root, &proof, key, item.as_deref(), &limits);
+		let verify_value =
+			|limits| verify_proof_value_with_limits::<T>(&root, &proof, key, &limits);
+		while verify(limits).is_err() {
+			// At most a branch and an extension or leaf per nibble of the key.
+			assert!(limits.max_depth <= key.len() * 4, "{:?}", verify(limits));
+			limits.max_depth += 1;
+		}
+		assert_eq!(verify_value(limits).unwrap(), item);
+		if key == b"a" && T::MAX_INLINE_VALUE.is_none() {
+			// Inline nodes count towards the depth.
+			assert_eq!(proof.len(), 1);
+			assert!(limits.max_depth > 1);
+		}
+
+		let lowered = [
+			VerifyLimits { max_nodes: limits.max_nodes - 1, ..limits },
+			VerifyLimits { max_node_size: limits.max_node_size - 1, ..limits },
+			VerifyLimits { max_key_len: limits.max_key_len - 1, ..limits },
+			VerifyLimits { max_depth: limits.max_depth - 1, ..limits },
+		];
+		let exceeded = [
+			ExceededLimit::Nodes(limits.max_nodes - 1),
+			ExceededLimit::NodeSize(limits.max_node_size - 1),
+			ExceededLimit::KeyLength(limits.max_key_len - 1),
+			ExceededLimit::Depth(limits.max_depth - 1),
+		];
+		for (lowered, exceeded) in lowered.iter().zip(exceeded) {
+			let res = verify(*lowered);
+			assert!(matches!(res, Err(VerifyError::LimitExceeded(e)) if e == exceeded));
+			let res = verify_value(*lowered);
+			assert!(matches!(res, Err(VerifyError::LimitExceeded(e)) if e == exceeded));
+		}
+	}
+}
@@ -41,7 +41,7 @@ mod rstd {
 
 use self::rstd::*;
 
-pub use hash_db::Hasher;
+pub use hash_db::{Hasher, Prefix};
 
 /// Different possible value to use for node encoding.
 #[derive(Clone)]
@@ -53,10 +53,17 @@ pub enum Value<'a> {
 }
 
 impl<'a> Value<'a> {
-	fn new<H: Hasher>(value: &'a [u8], threshold: Option<u32>) -> Value<'a> {
+	fn new<H: Hasher, N: NodeSink<H::Out>>(
+		value: &'a [u8],
+		threshold: Option<u32>,
+		key: &[u8],
+		sink: &mut N,
+	) -> Value<'a> {
 		if let Some(threshold) = threshold {
 			if value.len() >= threshold as usize {
-				Value::Node(H::hash(value).as_ref().to_vec())
+				let hash = H::hash(value);
+				sink.value(key, value, &hash);
+				Value::Node(hash.as_ref().to_vec())
 			} else {
 				Value::Inline(value)
 			}
@@ -90,11 +97,72 @@ pub trait TrieStream {
 	/// Append an Extension node
 	fn append_extension(&mut self, key: &[u8]);
 	/// Append a Branch of Extension substream
-	fn append_substream<H: Hasher>(&mut self, other: Self);
+	fn append_substream<H: Hasher>(&mut self, other: Self)
+	where
+		Self: Sized,
+	{
+		self.append_encoded_substream::<H>(&other.out());
+	}
+	/// Append a Branch or Extension child from its encoding, returning its hash if it
+	/// is referenced by hash rather than inlined.
+	fn append_encoded_substream<H: Hasher>(&mut self, encoded: &[u8]) -> Option<H::Out>;
 	/// Return the finished `TrieStream` as a vector of bytes.
 	fn out(self) -> Vec<u8>;
 }
 
+/// Callback for the nodes of a trie built by `trie_visit`, similar to the trie-db
+/// `ProcessEncodedNode` trait.
+pub trait ProcessEncodedNode<HO> {
+	/// Called with the prefix, encoding and hash of each node referenced by hash, and of the
+	/// root. Children are processed before their parent, so the root comes last.
+	fn process(&mut self, prefix: Prefix, encoded_node: &[u8], hash: &HO);
+
+	/// Called with the key, value and hash of each value stored in its own node.
+	fn process_inner_hashed_value(&mut self, prefix: Prefix, value: &[u8], hash: &HO);
+}
+
+/// Receiver of the nodes of `build_trie`, with prefixes as unpacked nibbles.
+trait NodeSink<HO> {
+	fn node(&mut self, nibbles: &[u8], encoded_node: &[u8], hash: &HO);
+	fn value(&mut self, nibbles: &[u8], value: &[u8], hash: &HO);
+}
+
+/// Sink ignoring nodes, when only the root is needed.
+impl<HO> NodeSink<HO> for () {
+	fn node(&mut self, _: &[u8], _: &[u8], _: &HO) {}
+	fn value(&mut self, _: &[u8], _: &[u8], _: &HO) {}
+}
+
+/// Sink packing prefixes for a `ProcessEncodedNode`.
+struct Packed<'a, F> {
+	callback: &'a mut F,
+	prefix: Vec<u8>,
+}
+
+impl<'a, F> Packed<'a, F> {
+	fn pack(&mut self, nibbles: &[u8]) -> Option<u8> {
+		self.prefix.clear();
+		self.prefix.extend(nibbles.chunks_exact(2).map(|n| n[0] << 4 | n[1]));
+		if nibbles.len() % 2 == 1 {
+			Some(nibbles[nibbles.len() - 1] << 4)
+		} else {
+			None
+		}
+	}
+}
+
+impl<'a, HO, F: ProcessEncodedNode<HO>> NodeSink<HO> for Packed<'a, F> {
+	fn node(&mut self, nibbles: &[u8], encoded_node: &[u8], hash: &HO) {
+		let last = self.pack(nibbles);
+		self.callback.process((&self.prefix, last), encoded_node, hash);
+	}
+
+	fn value(&mut self, nibbles: &[u8], value: &[u8], hash: &HO) {
+		let last = self.pack(nibbles);
+		self.callback.process_inner_hashed_value((&self.prefix, last), value, hash);
+	}
+}
+
 fn shared_prefix_length<T: Eq>(first: &[T], second: &[T]) -> usize {
 	first
 		.iter()
@@ -138,6 +206,20 @@ where
 	B: AsRef<[u8]>,
 	H: Hasher,
 	S: TrieStream,
+{
+	H::hash(&build::<H, S, I, A, B, _>(input, no_extension, threshold, &mut ()).out())
+}
+
+/// Sort `input`, keeping the last value of duplicated keys, and encode its trie, returning
+/// the root node stream.
+fn build<H, S, I, A, B, N>(input: I, no_extension: bool, threshold: Option<u32>, sink: &mut N) -> S
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]> + Ord,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	S: TrieStream,
+	N: NodeSink<H::Out>,
 {
 	// first put elements into btree to sort them and to remove duplicates
 	let input = input.into_iter().collect::<BTreeMap<_, _>>();
@@ -162,8 +244,8 @@ where
 		.collect::<Vec<_>>();
 
 	let mut stream = S::new();
-	build_trie::<H, S, _, _>(&input, 0, &mut stream, no_extension, threshold);
-	H::hash(&stream.out())
+	build_trie::<H, S, _, _, _>(&input, 0, &mut stream, no_extension, threshold, sink);
+	stream
 }
 
 /// Variant of `trie_root` for patricia trie without extension node.
@@ -206,30 +288,7 @@ where
 	H: Hasher,
 	S: TrieStream,
 {
-	// first put elements into btree to sort them and to remove duplicates
-	let input = input.into_iter().collect::<BTreeMap<_, _>>();
-
-	let mut nibbles = Vec::with_capacity(input.keys().map(|k| k.as_ref().len()).sum::<usize>() * 2);
-	let mut lens = Vec::with_capacity(input.len() + 1);
-	lens.push(0);
-	for k in input.keys() {
-		for &b in k.as_ref() {
-			nibbles.push(b >> 4);
-			nibbles.push(b & 0x0F);
-		}
-		lens.push(nibbles.len());
-	}
-
-	// then move them to a vector
-	let input = input
-		.into_iter()
-		.zip(lens.windows(2))
-		.map(|((_, v), w)| (&nibbles[w[0]..w[1]], v))
-		.collect::<Vec<_>>();
-
-	let mut stream = S::new();
-	build_trie::<H, S, _, _>(&input, 0, &mut stream, no_extension, threshold);
-	stream.out()
+	build::<H, S, I, A, B, _>(input, no_extension, threshold, &mut ()).out()
 }
 
 /// Variant of `unhashed_trie` for patricia trie without extension node.
@@ -245,6 +304,59 @@ where
 	unhashed_trie_inner::<H, S, I, A, B>(input, true, threshold)
 }
 
+/// Variant of `trie_root` reporting the hashed nodes of the trie to `callback`, for instance
+/// to insert them in a database. Returns the root hash.
+pub fn trie_visit<H, S, I, A, B, F>(input: I, threshold: Option<u32>, callback: &mut F) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]> + Ord,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	S: TrieStream,
+	F: ProcessEncodedNode<H::Out>,
+{
+	trie_visit_inner::<H, S, I, A, B, F>(input, false, threshold, callback)
+}
+
+/// Variant of `trie_visit` for patricia trie without extension node.
+/// See [`trie_visit`].
+pub fn trie_visit_no_extension<H, S, I, A, B, F>(
+	input: I,
+	threshold: Option<u32>,
+	callback: &mut F,
+) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]> + Ord,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	S: TrieStream,
+	F: ProcessEncodedNode<H::Out>,
+{
+	trie_visit_inner::<H, S, I, A, B, F>(input, true, threshold, callback)
+}
+
+fn trie_visit_inner<H, S, I, A, B, F>(
+	input: I,
+	no_extension: bool,
+	threshold: Option<u32>,
+	callback: &mut F,
+) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]> + Ord,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	S: TrieStream,
+	F: ProcessEncodedNode<H::Out>,
+{
+	let mut sink = Packed { callback, prefix: Vec::new() };
+	let root = build::<H, S, I, A, B, _>(input, no_extension, threshold, &mut sink).out();
+	let hash = H::hash(&root);
+	sink.node(&[], &root, &hash);
+	hash
+}
+
 /// Generates a key-hashed (secure) trie root hash for a vector of key-value tuples.
 ///
 /// ```ignore
@@ -274,26 +386,152 @@ where
 	trie_root::<H, S, _, _, _>(input.into_iter().map(|(k, v)| (H::hash(k.as_ref()), v)), threshold)
 }
 
+/// Variant of `sec_trie_root` hashing keys with `K` instead of the node hasher `H`.
+///
+/// `K` is the key hasher of the layout for tries accessed through trie-db's `SecTrieDB` and
+/// `FatDB`.
+pub fn sec_trie_root_with_key_hasher<H, K, S, I, A, B>(input: I, threshold: Option<u32>) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	K: Hasher,
+	K::Out: Ord,
+	S: TrieStream,
+{
+	trie_root::<H, S, _, _, _>(hash_keys::<K, _, _, _>(input), threshold)
+}
+
+/// Variant of `sec_trie_root` for patricia trie without extension node.
+/// See [`sec_trie_root`].
+pub fn sec_trie_root_no_extension<H, S, I, A, B>(input: I, threshold: Option<u32>) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	H::Out: Ord,
+	S: TrieStream,
+{
+	sec_trie_root_no_extension_with_key_hasher::<H, H, S, _, _, _>(input, threshold)
+}
+
+/// Variant of `sec_trie_root_with_key_hasher` for patricia trie without extension node.
+/// See [`sec_trie_root_with_key_hasher`].
+pub fn sec_trie_root_no_extension_with_key_hasher<H, K, S, I, A, B>(
+	input: I,
+	threshold: Option<u32>,
+) -> H::Out
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	K: Hasher,
+	K::Out: Ord,
+	S: TrieStream,
+{
+	trie_root_no_extension::<H, S, _, _, _>(hash_keys::<K, _, _, _>(input), threshold)
+}
+
+/// Method similar to `sec_trie_root` but returning the root encoded
+/// node instead of its hash.
+/// Mainly use for testing or debugging.
+pub fn sec_unhashed_trie<H, S, I, A, B>(input: I, threshold: Option<u32>) -> Vec<u8>
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	H::Out: Ord,
+	S: TrieStream,
+{
+	sec_unhashed_trie_with_key_hasher::<H, H, S, _, _, _>(input, threshold)
+}
+
+/// Variant of `sec_unhashed_trie` hashing keys with `K` instead of the node hasher `H`.
+/// See [`sec_trie_root_with_key_hasher`].
+pub fn sec_unhashed_trie_with_key_hasher<H, K, S, I, A, B>(
+	input: I,
+	threshold: Option<u32>,
+) -> Vec<u8>
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	K: Hasher,
+	K::Out: Ord,
+	S: TrieStream,
+{
+	unhashed_trie::<H, S, _, _, _>(hash_keys::<K, _, _, _>(input), threshold)
+}
+
+/// Variant of `sec_unhashed_trie` for patricia trie without extension node.
+/// See [`sec_unhashed_trie`].
+pub fn sec_unhashed_trie_no_extension<H, S, I, A, B>(input: I, threshold: Option<u32>) -> Vec<u8>
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	H::Out: Ord,
+	S: TrieStream,
+{
+	sec_unhashed_trie_no_extension_with_key_hasher::<H, H, S, _, _, _>(input, threshold)
+}
+
+/// Variant of `sec_unhashed_trie_with_key_hasher` for patricia trie without extension node.
+/// See [`sec_unhashed_trie_with_key_hasher`].
+pub fn sec_unhashed_trie_no_extension_with_key_hasher<H, K, S, I, A, B>(
+	input: I,
+	threshold: Option<u32>,
+) -> Vec<u8>
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	B: AsRef<[u8]>,
+	H: Hasher,
+	K: Hasher,
+	K::Out: Ord,
+	S: TrieStream,
+{
+	unhashed_trie_no_extension::<H, S, _, _, _>(hash_keys::<K, _, _, _>(input), threshold)
+}
+
+fn hash_keys<K, I, A, B>(input: I) -> impl Iterator<Item = (K::Out, B)>
+where
+	I: IntoIterator<Item = (A, B)>,
+	A: AsRef<[u8]>,
+	K: Hasher,
+{
+	input.into_iter().map(|(k, v)| (K::hash(k.as_ref()), v))
+}
+
 /// Takes a slice of key/value tuples where the key is a slice of nibbles
 /// and encodes it into the provided `Stream`.
-fn build_trie<H, S, A, B>(
+fn build_trie<H, S, A, B, N>(
 	input: &[(A, B)],
 	cursor: usize,
 	stream: &mut S,
 	no_extension: bool,
 	threshold: Option<u32>,
+	sink: &mut N,
 ) where
 	A: AsRef<[u8]>,
 	B: AsRef<[u8]>,
 	H: Hasher,
 	S: TrieStream,
+	N: NodeSink<H::Out>,
 {
 	match input.len() {
 		// No input, just append empty data.
 		0 => stream.append_empty_data(),
 		// Leaf node; append the remainder of the key and the value. Done.
 		1 => {
-			let value = Value::new::<H>(input[0].1.as_ref(), threshold);
+			let value =
+				Value::new::<H, _>(input[0].1.as_ref(), threshold, input[0].0.as_ref(), sink);
 			stream.append_leaf(&input[0].0.as_ref()[cursor..], value)
 		},
 		// We have multiple items in the input. Figure out if we should add an
@@ -318,12 +556,13 @@ fn build_trie<H, S, A, B>(
 				}
 			} else if shared_nibble_count > cursor {
 				stream.append_extension(&key[cursor..shared_nibble_count]);
-				build_trie_trampoline::<H, _, _, _>(
+				build_trie_trampoline::<H, _, _, _, _>(
 					input,
 					shared_nibble_count,
 					stream,
 					no_extension,
 					threshold,
+					sink,
 				);
 				return
 			} else {
@@ -358,7 +597,7 @@ fn build_trie<H, S, A, B>(
 			}
 
 			// Put out the node header:
-			let value = value.map(|v| Value::new::<H>(v, threshold));
+			let value = value.map(|v| Value::new::<H, _>(v, threshold, key, sink));
 			stream.begin_branch(
 				o_branch_slice,
 				value.clone(),
@@ -373,12 +612,13 @@ fn build_trie<H, S, A, B>(
 			};
 			for &count in &shared_nibble_counts {
 				if count > 0 {
-					build_trie_trampoline::<H, S, _, _>(
+					build_trie_trampoline::<H, S, _, _, _>(
 						&input[begin..(begin + count)],
 						cursor + 1,
 						stream,
 						no_extension,
 						threshold.clone(),
+						sink,
 					);
 					begin += count;
 				} else {
@@ -391,19 +631,24 @@ fn build_trie<H, S, A, B>(
 	}
 }
 
-fn build_trie_trampoline<H, S, A, B>(
+fn build_trie_trampoline<H, S, A, B, N>(
 	input: &[(A, B)],
 	cursor: usize,
 	stream: &mut S,
 	no_extension: bool,
 	threshold: Option<u32>,
+	sink: &mut N,
 ) where
 	A: AsRef<[u8]>,
 	B: AsRef<[u8]>,
 	H: Hasher,
 	S: TrieStream,
+	N: NodeSink<H::Out>,
 {
 	let mut substream = S::new();
-	build_trie::<H, _, _, _>(input, cursor, &mut substream, no_extension, threshold);
-	stream.append_substream::<H>(substream);
+	build_trie::<H, _, _, _, _>(input, cursor, &mut substream, no_extension, threshold, sink);
+	let encoded = substream.out();
+	if let Some(hash) = stream.append_encoded_substream::<H>(&encoded) {
+		sink.node(&input[0].0.as_ref()[..cursor], &encoded, &hash);
+	}
 }
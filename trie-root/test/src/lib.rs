@@ -16,10 +16,27 @@
 
 #[cfg(test)]
 mod test {
+	use std::collections::BTreeMap;
+
+	use hash_db::{HashDB, Hasher, Prefix};
 	use hex_literal::hex;
 	use keccak_hasher::KeccakHasher;
-	use reference_trie::ReferenceTrieStream;
-	use trie_root::{sec_trie_root, trie_root};
+	use memory_db::{MemoryDB, PrefixedKey};
+	use reference_trie::{
+		calc_root_build, ExtensionLayout, HashedValueNoExtThreshold, ReferenceTrieStream,
+		ReferenceTrieStreamNoExt,
+	};
+	use trie_db::{DBValue, Trie, TrieDB, TrieLayout};
+	use trie_root::{
+		sec_trie_root, sec_trie_root_no_extension, sec_trie_root_no_extension_with_key_hasher,
+		sec_trie_root_with_key_hasher, sec_unhashed_trie, sec_unhashed_trie_no_extension,
+		sec_unhashed_trie_no_extension_with_key_hasher, sec_unhashed_trie_with_key_hasher,
+		trie_root, trie_root_no_extension, trie_visit, trie_visit_no_extension, unhashed_trie,
+		ProcessEncodedNode,
+	};
+	use trie_standardmap::{Alphabet, StandardMap, ValueMode};
+
+	type H256 = <KeccakHasher as Hasher>::Out;
 
 	#[test]
 	fn previous_doc_test_1() {
@@ -42,4 +59,203 @@ mod test {
 			root
 		);
 	}
+
+	/// Prefix, padded last prefix nibble, encoded node and hash.
+	type NodeEntry = (Vec<u8>, Option<u8>, Vec<u8>, H256);
+
+	struct Nodes(Vec<NodeEntry>);
+
+	impl ProcessEncodedNode<H256> for Nodes {
+		fn process(&mut self, prefix: Prefix, encoded_node: &[u8], hash: &H256) {
+			self.0.push((prefix.0.to_vec(), prefix.1, encoded_node.to_vec(), *hash));
+		}
+
+		fn process_inner_hashed_value(&mut self, prefix: Prefix, value: &[u8], hash: &H256) {
+			self.process(prefix, value, hash)
+		}
+	}
+
+	struct Db<'a, T>(&'a mut T);
+
+	impl<'a, T: HashDB<KeccakHasher, DBValue>> ProcessEncodedNode<H256> for Db<'a, T> {
+		fn process(&mut self, prefix: Prefix, encoded_node: &[u8], hash: &H256) {
+			self.0.emplace(*hash, prefix, encoded_node.to_vec());
+		}
+
+		fn process_inner_hashed_value(&mut self, prefix: Prefix, value: &[u8], hash: &H256) {
+			self.0.emplace(*hash, prefix, value.to_vec());
+		}
+	}
+
+	fn input() -> Vec<(Vec<u8>, Vec<u8>)> {
+		let mut input = StandardMap {
+			alphabet: Alphabet::Low,
+			min_key: 1,
+			journal_key: 3,
+			value_mode: ValueMode::Mirror,
+			count: 200,
+		}
+		.make();
+		input.push((b"do".to_vec(), b"verb".to_vec()));
+		input.push((b"dog".to_vec(), b"puppy".to_vec()));
+		input
+	}
+
+	#[test]
+	fn trie_visit_reports_nodes() {
+		let mut nodes = Nodes(Vec::new());
+		let root =
+			trie_visit::<KeccakHasher, ReferenceTrieStream, _, _, _, _>(input(), None, &mut nodes);
+		assert_eq!(root, trie_root::<KeccakHasher, ReferenceTrieStream, _, _, _>(input(), None));
+		for (_, _, node, hash) in &nodes.0 {
+			assert_eq!(KeccakHasher::hash(node), *hash);
+		}
+		let (prefix, last, root_node, root_hash) = nodes.0.last().unwrap();
+		assert_eq!((prefix.as_slice(), *last, *root_hash), (&[][..], None, root));
+		assert_eq!(
+			root_node,
+			&unhashed_trie::<KeccakHasher, ReferenceTrieStream, _, _, _>(input(), None)
+		);
+		assert!(nodes.0.len() > 1);
+
+		let mut nodes = Nodes(Vec::new());
+		let empty: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+		let root = trie_visit::<KeccakHasher, ReferenceTrieStream, _, _, _, _>(
+			empty.clone(),
+			None,
+			&mut nodes,
+		);
+		assert_eq!(root, trie_root::<KeccakHasher, ReferenceTrieStream, _, _, _>(empty, None));
+		assert_eq!(nodes.0.len(), 1);
+	}
+
+	#[test]
+	fn trie_visit_matches_trie_db_builder() {
+		type PrefixedMemoryDB = MemoryDB<KeccakHasher, PrefixedKey<KeccakHasher>, DBValue>;
+		let sorted: BTreeMap<_, _> = input().into_iter().collect();
+
+		let mut expected = PrefixedMemoryDB::default();
+		calc_root_build::<ExtensionLayout, _, _, _, _>(sorted.clone(), &mut expected);
+		let mut db = PrefixedMemoryDB::default();
+		trie_visit::<KeccakHasher, ReferenceTrieStream, _, _, _, _>(
+			input(),
+			None,
+			&mut Db(&mut db),
+		);
+		assert_eq!(db.keys(), expected.keys());
+
+		let threshold = HashedValueNoExtThreshold::MAX_INLINE_VALUE;
+		let mut expected = PrefixedMemoryDB::default();
+		calc_root_build::<HashedValueNoExtThreshold, _, _, _, _>(sorted, &mut expected);
+		let mut db = PrefixedMemoryDB::default();
+		let root = trie_visit_no_extension::<KeccakHasher, ReferenceTrieStreamNoExt, _, _, _, _>(
+			input(),
+			threshold,
+			&mut Db(&mut db),
+		);
+		assert_eq!(db.keys(), expected.keys());
+
+		let trie = TrieDB::<HashedValueNoExtThreshold>::new(&db, &root);
+		for (key, value) in input() {
+			assert_eq!(trie.get(&key).unwrap(), Some(value));
+		}
+	}
+
+	#[test]
+	fn sec_variants_hash_keys() {
+		type K = KeccakHasher;
+		let hashed: BTreeMap<_, _> = input().into_iter().map(|(k, v)| (K::hash(&k), v)).collect();
+
+		let root = sec_trie_root::<K, ReferenceTrieStream, _, _, _>(input(), None);
+		assert_eq!(root, trie_root::<K, ReferenceTrieStream, _, _, _>(hashed.clone(), None));
+		assert_eq!(
+			sec_trie_root_with_key_hasher::<K, K, ReferenceTrieStream, _, _, _>(input(), None),
+			root
+		);
+		let node = sec_unhashed_trie::<K, ReferenceTrieStream, _, _, _>(input(), None);
+		assert_eq!(K::hash(&node), root);
+		assert_eq!(
+			sec_unhashed_trie_with_key_hasher::<K, K, ReferenceTrieStream, _, _, _>(input(), None),
+			node
+		);
+
+		let threshold = HashedValueNoExtThreshold::MAX_INLINE_VALUE;
+		let root =
+			sec_trie_root_no_extension::<K, ReferenceTrieStreamNoExt, _, _, _>(input(), threshold);
+		assert_eq!(
+			root,
+			trie_root_no_extension::<K, ReferenceTrieStreamNoExt, _, _, _>(hashed, threshold)
+		);
+		assert_eq!(
+			sec_trie_root_no_extension_with_key_hasher::<K, K, ReferenceTrieStreamNoExt, _, _, _>(
+				input(),
+				threshold,
+			),
+			root
+		);
+		let node = sec_unhashed_trie_no_extension::<K, ReferenceTrieStreamNoExt, _, _, _>(
+			input(),
+			threshold,
+		);
+		assert_eq!(K::hash(&node), root);
+		assert_eq!(
+			sec_unhashed_trie_no_extension_with_key_hasher::<K, K, ReferenceTrieStreamNoExt, _, _, _>(
+				input(),
+				threshold,
+			),
+			node
+		);
+	}
+
+	/// Key hasher differing from the node hasher: Keccak-256 of the reversed key.
+	#[derive(Debug)]
+	struct ReversedKeyHasher;
+
+	impl Hasher for ReversedKeyHasher {
+		type Out = [u8; 32];
+		type StdHasher = <KeccakHasher as Hasher>::StdHasher;
+		const LENGTH: usize = 32;
+
+		fn hash(x: &[u8]) -> Self::Out {
+			let reversed: Vec<u8> = x.iter().rev().cloned().collect();
+			KeccakHasher::hash(&reversed)
+		}
+	}
+
+	#[test]
+	fn sec_trie_root_key_hasher() {
+		type K = ReversedKeyHasher;
+		let hashed: BTreeMap<_, _> = input().into_iter().map(|(k, v)| (K::hash(&k), v)).collect();
+		let root = sec_trie_root_with_key_hasher::<KeccakHasher, K, ReferenceTrieStream, _, _, _>(
+			input(),
+			None,
+		);
+		assert_eq!(
+			root,
+			trie_root::<KeccakHasher, ReferenceTrieStream, _, _, _>(hashed.clone(), None)
+		);
+		let node = sec_unhashed_trie_with_key_hasher::<KeccakHasher, K, ReferenceTrieStream, _, _, _>(
+			input(),
+			None,
+		);
+		assert_eq!(KeccakHasher::hash(&node), root);
+		let threshold = HashedValueNoExtThreshold::MAX_INLINE_VALUE;
+		assert_eq!(
+			sec_trie_root_no_extension_with_key_hasher::<
+				KeccakHasher,
+				K,
+				ReferenceTrieStreamNoExt,
+				_,
+				_,
+				_,
+			>(input(), threshold),
+			trie_root_no_extension::<KeccakHasher, ReferenceTrieStreamNoExt, _, _, _>(
+				hashed, threshold
+			)
+		);
+		assert_ne!(
+			root,
+			sec_trie_root::<KeccakHasher, ReferenceTrieStream, _, _, _>(input(), None)
+		);
+	}
 }